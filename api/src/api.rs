@@ -1,24 +1,68 @@
-use std::str::FromStr;
+use std::{collections::BTreeSet, str::FromStr, sync::Mutex, time::Instant};
 
 use crate::{
-    db::{Dao, PageOptions, Pagination},
+    db::{Dao, PageOptions, Pagination, TransferStatsModel},
     error::ApiError,
     spec::TransactionList,
-    types::Transaction,
+    types::{DateBound, Transaction},
 };
-use chrono::NaiveDate;
+use chrono::Utc;
 use common::db::setup_database_connection;
 use solana_sdk::pubkey::Pubkey;
 
 use crate::config::ApiConfig;
 
+pub const MAX_MINTS_PER_REQUEST: usize = 100;
+
 pub fn validate_pubkey(str_pubkey: String) -> Result<Pubkey, ApiError> {
     Pubkey::from_str(&str_pubkey).map_err(|_| ApiError::PubkeyValidationError(str_pubkey))
 }
 
+/// Checks the mints a request filters on, and returns them deduplicated as they are stored.
+pub fn validate_mint_addresses(mint_addresses: Vec<String>) -> Result<Vec<Vec<u8>>, ApiError> {
+    if mint_addresses.len() > MAX_MINTS_PER_REQUEST {
+        return Err(ApiError::InvalidInput(format!(
+            "at most {} mint addresses can be provided",
+            MAX_MINTS_PER_REQUEST
+        )));
+    }
+    let mints = mint_addresses
+        .into_iter()
+        .map(|mint| validate_pubkey(mint).map(|mint| mint.to_bytes().to_vec()))
+        .collect::<Result<BTreeSet<_>, _>>()?;
+    Ok(mints.into_iter().collect())
+}
+
+/// Checks an inclusive block height range, and returns its bounds as they are stored.
+pub fn validate_block_heights(
+    from_height: Option<u64>,
+    to_height: Option<u64>,
+) -> Result<(Option<i64>, Option<i64>), ApiError> {
+    if let (Some(from_height), Some(to_height)) = (from_height, to_height) {
+        if from_height > to_height {
+            return Err(ApiError::InvalidInput(format!(
+                "from_height {} is greater than to_height {}",
+                from_height, to_height
+            )));
+        }
+    }
+    let stored_height = |height: Option<u64>| {
+        height
+            .map(|height| {
+                i64::try_from(height).map_err(|_| {
+                    ApiError::InvalidInput(format!("block height {} is out of range", height))
+                })
+            })
+            .transpose()
+    };
+    Ok((stored_height(from_height)?, stored_height(to_height)?))
+}
+
 pub struct Api {
     pub config: ApiConfig,
     pub dao: Dao,
+    // The transfer counts of `getStats`, with when they were computed.
+    stats_cache: Mutex<Option<(Instant, TransferStatsModel)>>,
 }
 
 impl Api {
@@ -26,13 +70,46 @@ impl Api {
         Api {
             config: config.clone(),
             dao: Dao::new(
-                setup_database_connection(config.get_database_url(), config.max_connections)
+                setup_database_connection(config.get_database_url(), &config.get_pool_config())
                     .await
                     .into(),
             ),
+            stats_cache: Mutex::new(None),
         }
     }
 
+    pub async fn transfer_stats(&self) -> Result<TransferStatsModel, ApiError> {
+        if let Some((computed_at, stats)) = *self.stats_cache.lock().unwrap() {
+            if computed_at.elapsed() < self.config.get_stats_cache_ttl() {
+                return Ok(stats);
+            }
+        }
+        let stats = self.dao.get_transfer_stats().await?;
+        *self.stats_cache.lock().unwrap() = Some((Instant::now(), stats));
+        Ok(stats)
+    }
+
+    // The highest slot that is old enough to be served under `slot_safety_margin`.
+    pub async fn max_readable_slot(&self) -> Result<Option<i64>, ApiError> {
+        self.dao
+            .max_readable_slot(self.config.slot_safety_margin)
+            .await
+    }
+
+    // The page size and pagination of a request, shared by every endpoint that returns a
+    // `TransactionList` so their limits and pagination modes behave the same.
+    pub fn paginate_request(
+        &self,
+        limit: &Option<u32>,
+        page: &Option<u32>,
+        before: &Option<String>,
+        after: &Option<String>,
+    ) -> Result<(PageOptions, Pagination), ApiError> {
+        let page_opt = self.validate_pagination(limit, page, before, after)?;
+        let pagination = self.create_pagination(page_opt.clone())?;
+        Ok((page_opt, pagination))
+    }
+
     pub fn create_pagination(&self, page_opt: PageOptions) -> Result<Pagination, ApiError> {
         match (
             page_opt.before.as_ref(),
@@ -56,11 +133,13 @@ impl Api {
     ) -> Result<PageOptions, ApiError> {
         let mut page_opt = PageOptions::default();
 
+        let max_limit = self.config.get_max_limit();
         if let Some(limit) = limit {
-            if *limit > 1000 {
-                return Err(ApiError::PaginationExceededError);
+            if *limit > max_limit {
+                return Err(ApiError::PaginationExceededError(max_limit));
             }
         }
+        let default_limit = self.config.get_default_limit();
 
         if let Some(page) = page {
             if *page == 0 {
@@ -71,48 +150,77 @@ impl Api {
                 return Err(ApiError::PaginationError);
             }
 
-            let current_limit = limit.unwrap_or(1000);
-            let offset = (*page - 1) * current_limit;
-            if offset > 500_000 {
-                return Err(ApiError::OffsetLimitExceededError);
+            let current_limit = limit.unwrap_or(default_limit);
+            let offset = (*page - 1) as u64 * current_limit as u64;
+            let max_offset = self.config.get_max_offset();
+            if offset > max_offset as u64 {
+                return Err(ApiError::OffsetLimitExceededError(max_offset));
             }
         }
 
         if let Some(before) = before {
-            match NaiveDate::parse_from_str(before, "%d/%m/%Y") {
-                Ok(date) => page_opt.before = Some(date),
-                Err(_) => return Err(ApiError::InvalidDate("before".to_string())),
+            match DateBound::parse(before) {
+                Some(date) => page_opt.before = Some(date),
+                None => return Err(ApiError::InvalidDate("before".to_string())),
             }
         }
 
         if let Some(after) = after {
-            match NaiveDate::parse_from_str(after, "%d/%m/%Y") {
-                Ok(date) => page_opt.after = Some(date),
-                Err(_) => return Err(ApiError::InvalidDate("after".to_string())),
+            match DateBound::parse(after) {
+                Some(date) => page_opt.after = Some(date),
+                None => return Err(ApiError::InvalidDate("after".to_string())),
             }
         }
 
-        page_opt.limit = limit.map(|x| x as u64).unwrap_or(1000);
+        // Days are inclusive, so a range of a single day has `after` equal to `before`.
+        if let (Some(before), Some(after)) = (page_opt.before, page_opt.after) {
+            if after.start() > before.end() {
+                return Err(ApiError::InvalidDate(
+                    "after must not be later than before".to_string(),
+                ));
+            }
+        }
+        if let Some(after) = page_opt.after {
+            if after.start() > Utc::now() {
+                return Err(ApiError::InvalidDate(
+                    "after must not be in the future".to_string(),
+                ));
+            }
+        }
+
+        page_opt.limit = limit.unwrap_or(default_limit) as u64;
         page_opt.page = page.map(|x| x as u64);
         Ok(page_opt)
     }
 
     pub fn build_transaction_response(
         transactions: Vec<Transaction>,
+        total: Option<u64>,
         limit: u64,
         pagination: &Pagination,
     ) -> TransactionList {
-        let total = transactions.len() as u32;
+        let count = transactions.len() as u32;
+        // An empty first page shows that nothing matches without counting, e.g. for an address
+        // that was never seen.
+        let total = match pagination {
+            Pagination::Page { page: 1 } | Pagination::Keyset { .. }
+                if total.is_none() && transactions.is_empty() =>
+            {
+                Some(0)
+            }
+            _ => total,
+        };
         let (page, before, after) = match pagination {
             Pagination::Keyset { before, after } => {
-                let bef = before.map(|x| x.format("%d/%m/%Y").to_string());
-                let aft = after.map(|x| x.format("%d/%m/%Y").to_string());
+                let bef = before.map(|x| x.to_string());
+                let aft = after.map(|x| x.to_string());
                 (None, bef, aft)
             }
             Pagination::Page { page } => (Some(*page), None, None),
         };
 
         TransactionList {
+            count,
             total,
             limit: limit as u32,
             page: page.map(|x| x as u32),
@@ -2,7 +2,10 @@ use jsonrpsee::RpcModule;
 use log::debug;
 
 use crate::error::ApiError;
-use crate::spec::{ApiContract, GetTransactionsByAddress};
+use crate::spec::{
+    ApiContract, GetActivityHistogram, GetBlock, GetCounterparties, GetTransactionsByAddress,
+    GetTransactionsByAddresses, GetTransactionsByMint, GetTransactionsByProgram, GetTransferVolume,
+};
 
 pub struct RpcApiBuilder;
 
@@ -22,6 +25,11 @@ impl RpcApiBuilder {
             rpc_context.readiness().await.map_err(Into::into)
         })?;
 
+        module.register_async_method("get_config", |_rpc_params, rpc_context| async move {
+            rpc_context.get_config().await.map_err(Into::into)
+        })?;
+        module.register_alias("getConfig", "get_config")?;
+
         // get_transactions_by_address
         module.register_async_method(
             "get_transactions_by_address",
@@ -35,6 +43,103 @@ impl RpcApiBuilder {
         )?;
         module.register_alias("getTransactionsByAddress", "get_transactions_by_address")?;
 
+        module.register_async_method(
+            "get_transactions_by_addresses",
+            |rpc_params, rpc_context| async move {
+                let payload = rpc_params.parse::<GetTransactionsByAddresses>()?;
+                rpc_context
+                    .get_transactions_by_addresses(payload)
+                    .await
+                    .map_err(Into::into)
+            },
+        )?;
+        module.register_alias(
+            "getTransactionsByAddresses",
+            "get_transactions_by_addresses",
+        )?;
+
+        module.register_async_method(
+            "get_transactions_by_mint",
+            |rpc_params, rpc_context| async move {
+                let payload = rpc_params.parse::<GetTransactionsByMint>()?;
+                rpc_context
+                    .get_transactions_by_mint(payload)
+                    .await
+                    .map_err(Into::into)
+            },
+        )?;
+        module.register_alias("getTransactionsByMint", "get_transactions_by_mint")?;
+
+        module.register_async_method(
+            "get_transactions_by_program",
+            |rpc_params, rpc_context| async move {
+                let payload = rpc_params.parse::<GetTransactionsByProgram>()?;
+                rpc_context
+                    .get_transactions_by_program(payload)
+                    .await
+                    .map_err(Into::into)
+            },
+        )?;
+        module.register_alias("getTransactionsByProgram", "get_transactions_by_program")?;
+
+        module.register_async_method(
+            "get_counterparties",
+            |rpc_params, rpc_context| async move {
+                let payload = rpc_params.parse::<GetCounterparties>()?;
+                rpc_context
+                    .get_counterparties(payload)
+                    .await
+                    .map_err(Into::into)
+            },
+        )?;
+        module.register_alias("getCounterparties", "get_counterparties")?;
+
+        module.register_async_method("get_block", |rpc_params, rpc_context| async move {
+            let payload = rpc_params.parse::<GetBlock>()?;
+            rpc_context.get_block(payload).await.map_err(Into::into)
+        })?;
+        module.register_alias("getBlock", "get_block")?;
+
+        module.register_async_method(
+            "get_latest_indexed_block",
+            |_rpc_params, rpc_context| async move {
+                rpc_context
+                    .get_latest_indexed_block()
+                    .await
+                    .map_err(Into::into)
+            },
+        )?;
+        module.register_alias("getLatestIndexedBlock", "get_latest_indexed_block")?;
+
+        module.register_async_method(
+            "get_transfer_volume",
+            |rpc_params, rpc_context| async move {
+                let payload = rpc_params.parse::<GetTransferVolume>()?;
+                rpc_context
+                    .get_transfer_volume(payload)
+                    .await
+                    .map_err(Into::into)
+            },
+        )?;
+        module.register_alias("getTransferVolume", "get_transfer_volume")?;
+
+        module.register_async_method(
+            "get_activity_histogram",
+            |rpc_params, rpc_context| async move {
+                let payload = rpc_params.parse::<GetActivityHistogram>()?;
+                rpc_context
+                    .get_activity_histogram(payload)
+                    .await
+                    .map_err(Into::into)
+            },
+        )?;
+        module.register_alias("getActivityHistogram", "get_activity_histogram")?;
+
+        module.register_async_method("get_stats", |_rpc_params, rpc_context| async move {
+            rpc_context.get_stats().await.map_err(Into::into)
+        })?;
+        module.register_alias("getStats", "get_stats")?;
+
         module.register_async_method("schema", |_, rpc_context| async move {
             Ok(rpc_context.schema())
         })?;
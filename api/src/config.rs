@@ -1,5 +1,13 @@
-use common::config::load_config_using_env_prefix;
-use serde::Deserialize;
+use std::{net::IpAddr, time::Duration};
+
+use clap::Parser;
+use common::{
+    config::{load_config_with_overrides, redact_url},
+    db::PoolConfig,
+};
+use figment::value::Value;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
 use crate::error::ApiError;
 
@@ -9,10 +17,86 @@ pub struct ApiConfig {
     pub env: Option<String>,
     pub metrics_port: Option<u16>,
     pub metrics_host: Option<String>,
+    // Serves the metrics on `/metrics` of this port for Prometheus, next to statsd.
+    pub prometheus_port: Option<u16>,
     #[serde(default = "default_server_port")]
     pub server_port: u16,
     #[serde(default = "default_max_connections")]
     pub max_connections: u32,
+    // How the pool keeps and hands out connections, see `get_pool_config` for the defaults.
+    pub min_connections: Option<u32>,
+    pub acquire_timeout_ms: Option<u64>,
+    pub idle_timeout_seconds: Option<u64>,
+    #[serde(default)]
+    pub enable_admin_methods: bool,
+    // Serves `/export`, which streams every matching transfer in one response, see
+    // `export::ExportLayer`. Off by default, as a broad export scans a large part of the table.
+    #[serde(default)]
+    pub enable_export: bool,
+    // How long the statements of an export may run and how many exports run at once, see
+    // `get_export_timeout` and `get_max_concurrent_exports`.
+    pub export_timeout_ms: Option<u64>,
+    pub max_concurrent_exports: Option<u32>,
+    // When set, transfers within this many slots of the latest indexed slot are not served,
+    // since they may still be rolled back.
+    pub slot_safety_margin: Option<u64>,
+    // Tokio worker threads for the server, one per CPU core when unset.
+    pub worker_threads: Option<usize>,
+    // Pagination limits, see the getters for their defaults.
+    pub max_limit: Option<u32>,
+    pub default_limit: Option<u32>,
+    pub max_offset: Option<u32>,
+    // Statements running longer than this are cancelled by Postgres, see `get_query_timeout`.
+    pub query_timeout_ms: Option<u64>,
+    // How long `getStats` reuses its transfer counts, see `get_stats_cache_ttl`.
+    pub stats_cache_ttl_seconds: Option<u64>,
+    // PEM files to serve TLS with. Both or neither have to be set, and without them the server
+    // speaks plain HTTP.
+    pub tls_cert_path: Option<String>,
+    pub tls_key_path: Option<String>,
+    // Requests a client may make per second, and in a burst, see `get_rate_limit`.
+    pub requests_per_second: Option<u32>,
+    pub burst: Option<u32>,
+    // Proxies trusted to name the client they forward for, see `rate_limit::client_ip`.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+    // Origins allowed to call the API from a browser, any origin when empty. See
+    // `cors::origin_allowed` for the wildcard syntax.
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+}
+
+// The effective configuration as returned by `getConfig`, with credentials redacted.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SanitizedApiConfig {
+    pub database_url: String,
+    pub env: Option<String>,
+    pub metrics_port: Option<u16>,
+    pub metrics_host: Option<String>,
+    pub prometheus_port: Option<u16>,
+    pub server_port: u16,
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub acquire_timeout_ms: u64,
+    pub idle_timeout_seconds: Option<u64>,
+    pub enable_admin_methods: bool,
+    pub enable_export: bool,
+    pub export_timeout_ms: u64,
+    pub max_concurrent_exports: u32,
+    pub slot_safety_margin: Option<u64>,
+    pub worker_threads: Option<usize>,
+    pub max_limit: u32,
+    pub default_limit: u32,
+    pub max_offset: u32,
+    pub query_timeout_ms: u64,
+    pub stats_cache_ttl_seconds: u64,
+    pub tls_cert_path: Option<String>,
+    pub tls_key_path: Option<String>,
+    pub requests_per_second: Option<u32>,
+    pub burst: Option<u32>,
+    pub trusted_proxies: Vec<IpAddr>,
+    pub allowed_origins: Vec<String>,
 }
 
 fn default_max_connections() -> u32 {
@@ -33,6 +117,124 @@ impl ApiConfig {
             })
             .unwrap()
     }
+
+    // The most transfers a single page can hold.
+    pub fn get_max_limit(&self) -> u32 {
+        self.max_limit.unwrap_or(1000)
+    }
+
+    // The page size when a request doesn't set `limit`, at most `max_limit`.
+    pub fn get_default_limit(&self) -> u32 {
+        self.default_limit.unwrap_or(1000).min(self.get_max_limit())
+    }
+
+    // How far page based pagination can skip into the results. Offsets are expensive on large
+    // tables, so deeper results are paginated by date.
+    pub fn get_max_offset(&self) -> u32 {
+        self.max_offset.unwrap_or(500_000)
+    }
+
+    // How long a single database statement may run, so one slow query can't hold a pooled
+    // connection indefinitely.
+    pub fn get_query_timeout(&self) -> Duration {
+        Duration::from_millis(self.query_timeout_ms.unwrap_or(30_000))
+    }
+
+    // An export is a single statement reading every matching transfer, so it gets far longer
+    // than a query, an hour by default. 0 lets it run for as long as it takes.
+    pub fn get_export_timeout(&self) -> Duration {
+        Duration::from_millis(self.export_timeout_ms.unwrap_or(3_600_000))
+    }
+
+    // Each export holds a pooled connection until it is done, so only a few run at once and
+    // the queries keep the rest of the pool.
+    pub fn get_max_concurrent_exports(&self) -> u32 {
+        self.max_concurrent_exports.unwrap_or(2).max(1)
+    }
+
+    // One connection is kept open by default, and idle ones beyond it are closed after 10
+    // minutes. An idle timeout of 0 keeps them open.
+    pub fn get_pool_config(&self) -> PoolConfig {
+        PoolConfig {
+            max_connections: self.max_connections,
+            min_connections: self.min_connections.unwrap_or(1),
+            acquire_timeout: Duration::from_millis(self.acquire_timeout_ms.unwrap_or(30_000)),
+            idle_timeout: match self.idle_timeout_seconds.unwrap_or(600) {
+                0 => None,
+                seconds => Some(Duration::from_secs(seconds)),
+            },
+            statement_timeout: Some(self.get_query_timeout()),
+        }
+    }
+
+    // Counting every transfer is slow on a large table, so `getStats` serves counts up to this
+    // old.
+    pub fn get_stats_cache_ttl(&self) -> Duration {
+        Duration::from_secs(self.stats_cache_ttl_seconds.unwrap_or(60))
+    }
+
+    // The requests per second and burst of each client when rate limiting is enabled. The burst
+    // defaults to one second's worth of requests.
+    pub fn get_rate_limit(&self) -> Option<(u32, u32)> {
+        self.requests_per_second
+            .filter(|requests_per_second| *requests_per_second > 0)
+            .map(|requests_per_second| {
+                (
+                    requests_per_second,
+                    self.burst.unwrap_or(requests_per_second),
+                )
+            })
+    }
+
+    // The certificate and key paths when TLS is enabled.
+    pub fn get_tls_paths(&self) -> Result<Option<(&str, &str)>, ApiError> {
+        match (&self.tls_cert_path, &self.tls_key_path) {
+            (Some(cert_path), Some(key_path)) => Ok(Some((cert_path, key_path))),
+            (None, None) => Ok(None),
+            _ => Err(ApiError::ConfigurationError {
+                msg: "tls_cert_path and tls_key_path must be set together".to_string(),
+            }),
+        }
+    }
+
+    pub fn sanitized(&self) -> SanitizedApiConfig {
+        let database_url = self
+            .database_config
+            .get(DATABASE_URL_KEY)
+            .and_then(|u| u.clone().into_string())
+            .map(|url| redact_url(&url))
+            .unwrap_or_default();
+        let pool_config = self.get_pool_config();
+        SanitizedApiConfig {
+            database_url,
+            env: self.env.clone(),
+            metrics_port: self.metrics_port,
+            metrics_host: self.metrics_host.clone(),
+            prometheus_port: self.prometheus_port,
+            server_port: self.server_port,
+            max_connections: self.max_connections,
+            min_connections: pool_config.min_connections,
+            acquire_timeout_ms: pool_config.acquire_timeout.as_millis() as u64,
+            idle_timeout_seconds: pool_config.idle_timeout.map(|timeout| timeout.as_secs()),
+            enable_admin_methods: self.enable_admin_methods,
+            enable_export: self.enable_export,
+            export_timeout_ms: self.get_export_timeout().as_millis() as u64,
+            max_concurrent_exports: self.get_max_concurrent_exports(),
+            slot_safety_margin: self.slot_safety_margin,
+            worker_threads: self.worker_threads,
+            max_limit: self.get_max_limit(),
+            default_limit: self.get_default_limit(),
+            max_offset: self.get_max_offset(),
+            query_timeout_ms: self.get_query_timeout().as_millis() as u64,
+            stats_cache_ttl_seconds: self.get_stats_cache_ttl().as_secs(),
+            tls_cert_path: self.tls_cert_path.clone(),
+            tls_key_path: self.tls_key_path.clone(),
+            requests_per_second: self.requests_per_second,
+            burst: self.burst,
+            trusted_proxies: self.trusted_proxies.clone(),
+            allowed_origins: self.allowed_origins.clone(),
+        }
+    }
 }
 
 // Types and constants used for Figment configuration items.
@@ -40,6 +242,32 @@ pub type DatabaseConfig = figment::value::Dict;
 
 pub const DATABASE_URL_KEY: &str = "url";
 
+/// Command-line flags, which take precedence over the environment and the local config.
+#[derive(Parser, Debug, Clone, Default)]
+#[clap(about = "Serves the indexed token transfers over JSON-RPC")]
+pub struct ApiArgs {
+    #[clap(long)]
+    pub database_url: Option<String>,
+}
+
+impl ApiArgs {
+    fn overrides(&self) -> Vec<(String, Value)> {
+        self.database_url
+            .iter()
+            .map(|database_url| {
+                (
+                    format!("database_config.{}", DATABASE_URL_KEY),
+                    database_url.clone().into(),
+                )
+            })
+            .collect()
+    }
+}
+
 pub fn setup_config() -> ApiConfig {
-    load_config_using_env_prefix("API_")
+    setup_config_with_args(&ApiArgs::default())
+}
+
+pub fn setup_config_with_args(args: &ApiArgs) -> ApiConfig {
+    load_config_with_overrides("API_", args.overrides())
 }
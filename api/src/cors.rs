@@ -0,0 +1,33 @@
+use hyper::{header::CONTENT_TYPE, Method};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+/// Whether `origin` is in `allowed_origins`. Entries are compared to the whole origin, except
+/// that `*.` in e.g. `https://*.example.com` stands for one or more subdomains.
+pub fn origin_allowed(allowed_origins: &[String], origin: &str) -> bool {
+    allowed_origins
+        .iter()
+        .any(|allowed| match allowed.split_once("*.") {
+            Some((scheme, domain)) => origin
+                .strip_prefix(scheme)
+                .and_then(|host| host.strip_suffix(domain))
+                .and_then(|subdomain| subdomain.strip_suffix('.'))
+                .is_some_and(|subdomain| !subdomain.is_empty() && !subdomain.contains(['/', ':'])),
+            None => allowed == origin,
+        })
+}
+
+// Any origin is allowed when the list is empty.
+pub fn cors_layer(allowed_origins: &[String]) -> CorsLayer {
+    let cors = CorsLayer::new()
+        .allow_methods([Method::POST, Method::GET])
+        .allow_headers([CONTENT_TYPE]);
+    if allowed_origins.is_empty() {
+        return cors.allow_origin(Any);
+    }
+    let allowed_origins = allowed_origins.to_vec();
+    cors.allow_origin(AllowOrigin::predicate(move |origin, _| {
+        origin
+            .to_str()
+            .is_ok_and(|origin| origin_allowed(&allowed_origins, origin))
+    }))
+}
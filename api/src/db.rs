@@ -1,19 +1,32 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use crate::error::ApiError;
+use crate::types::DateBound;
 use chrono::DateTime;
-use chrono::NaiveDate;
-use chrono::TimeZone;
 use chrono::Utc;
-use dao::generated::token_transfers;
+use dao::generated::{blocks, sea_orm_active_enums::TokenType, token_transfers, transactions};
+use futures::Stream;
 use schemars::JsonSchema;
+use sea_orm::prelude::DateTimeWithTimeZone;
+use sea_orm::sea_query::Expr;
+use sea_orm::sea_query::Query;
 use sea_orm::ColumnTrait;
+use sea_orm::Condition;
+use sea_orm::ConnectionTrait;
 use sea_orm::DatabaseConnection;
+use sea_orm::DatabaseTransaction;
+use sea_orm::DbBackend;
+use sea_orm::DbErr;
 use sea_orm::EntityTrait;
+use sea_orm::FromQueryResult;
 use sea_orm::Order;
+use sea_orm::PaginatorTrait;
 use sea_orm::QueryFilter;
 use sea_orm::QueryOrder;
 use sea_orm::QuerySelect;
+use sea_orm::Select;
+use sea_orm::Statement;
+use sea_orm::TransactionTrait;
 use serde::Deserialize;
 use serde::Serialize;
 
@@ -22,40 +35,108 @@ pub struct Dao {
     pub db: Arc<DatabaseConnection>,
 }
 
+#[derive(FromQueryResult)]
+struct SlotModel {
+    slot: Option<i64>,
+}
+
+#[derive(FromQueryResult, Clone, Copy, Debug)]
+pub struct TransferStatsModel {
+    pub total_transfers: i64,
+    pub distinct_mints: i64,
+}
+
+#[derive(FromQueryResult)]
+pub struct BlockStatsModel {
+    pub earliest_block_time: Option<i64>,
+    pub latest_block_time: Option<i64>,
+    pub last_indexed_slot: Option<i64>,
+}
+
+#[derive(FromQueryResult)]
+pub struct VolumeBucketModel {
+    pub bucket: DateTimeWithTimeZone,
+    pub amount: String,
+    pub count: i64,
+}
+
+#[derive(FromQueryResult)]
+pub struct ActivityBucketModel {
+    pub bucket: DateTimeWithTimeZone,
+    pub count: i64,
+}
+
+#[derive(FromQueryResult)]
+pub struct CounterpartyModel {
+    pub counterparty: Vec<u8>,
+    pub mint_address: Option<Vec<u8>>,
+    pub outgoing_count: i64,
+    pub outgoing_amount: String,
+    pub incoming_count: i64,
+    pub incoming_amount: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TransferFilter {
+    pub source: Option<Vec<u8>>,
+    pub destination: Option<Vec<u8>>,
+    // Either the source or the destination.
+    pub participant: Option<Vec<u8>>,
+    // Either the source or the destination is one of these, when not empty.
+    pub participants: Vec<Vec<u8>>,
+    pub mint: Option<Vec<u8>>,
+    // The mint is one of these, when not empty.
+    pub mints: Vec<Vec<u8>>,
+    // The token program that executed the transfer.
+    pub program: Option<Vec<u8>>,
+    pub token_type: Option<TokenType>,
+    // Only transfers at or below this slot are returned.
+    pub max_slot: Option<i64>,
+    // Only transfers of successful transactions when true, of failed ones when false.
+    pub success: Option<bool>,
+    // Only transfers in the indexed blocks with a block height in this inclusive range.
+    pub min_block_height: Option<i64>,
+    pub max_block_height: Option<i64>,
+}
+
 pub enum Pagination {
     Keyset {
-        before: Option<NaiveDate>,
-        after: Option<NaiveDate>,
+        before: Option<DateBound>,
+        after: Option<DateBound>,
     },
     Page {
         page: u64,
     },
 }
 
-pub fn paginate<T, C>(pagination: &Pagination, limit: u64, stmt: T, column: C) -> T
+// Only the date bounds of keyset pagination, which also apply when counting a query.
+fn filter_dates<T, C>(pagination: &Pagination, stmt: T, column: C) -> T
 where
-    T: QueryFilter + QuerySelect,
+    T: QueryFilter,
     C: ColumnTrait,
 {
     let mut stmt = stmt;
-    match pagination {
-        Pagination::Keyset { before, after } => {
-            if let Some(before) = before {
-                let before_datetime = before.and_hms_opt(23, 59, 59).unwrap();
-                let before_utc: DateTime<Utc> = Utc.from_utc_datetime(&before_datetime);
-                stmt = stmt.filter(column.lt(before_utc));
-            }
+    if let Pagination::Keyset { before, after } = pagination {
+        if let Some(before) = before {
+            stmt = stmt.filter(column.lt(before.end()));
+        }
 
-            if let Some(after) = after {
-                let after_datetime = after.and_hms_opt(0, 0, 0).unwrap();
-                let after_utc: DateTime<Utc> = Utc.from_utc_datetime(&after_datetime);
-                stmt = stmt.filter(column.gt(after_utc));
-            }
+        if let Some(after) = after {
+            stmt = stmt.filter(column.gt(after.start()));
         }
-        Pagination::Page { page } => {
-            if *page > 0 {
-                stmt = stmt.offset((page - 1) * limit)
-            }
+    }
+    stmt
+}
+
+pub fn paginate<T, C>(pagination: &Pagination, limit: u64, stmt: T, column: C) -> T
+where
+    T: QueryFilter + QuerySelect,
+    C: ColumnTrait,
+{
+    let mut stmt = filter_dates(pagination, stmt, column);
+    if let Pagination::Page { page } = pagination {
+        if *page > 0 {
+            stmt = stmt.offset((page - 1) * limit)
         }
     }
     stmt.limit(limit)
@@ -63,7 +144,6 @@ where
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
-
 pub struct TransactionSorting {
     pub sort_by: TransactionSortBy,
     pub sort_direction: Option<TransactionSortDirection>,
@@ -79,7 +159,6 @@ impl Default for TransactionSorting {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
-
 pub enum TransactionSortBy {
     #[serde(rename = "created")]
     Created,
@@ -87,11 +166,12 @@ pub enum TransactionSortBy {
     Slot,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema, Default)]
 pub enum TransactionSortDirection {
     #[serde(rename = "asc")]
     Asc,
     #[serde(rename = "desc")]
+    #[default]
     Desc,
 }
 
@@ -99,14 +179,8 @@ pub enum TransactionSortDirection {
 pub struct PageOptions {
     pub limit: u64,
     pub page: Option<u64>,
-    pub before: Option<NaiveDate>,
-    pub after: Option<NaiveDate>,
-}
-
-impl Default for TransactionSortDirection {
-    fn default() -> TransactionSortDirection {
-        TransactionSortDirection::Desc
-    }
+    pub before: Option<DateBound>,
+    pub after: Option<DateBound>,
 }
 
 pub fn create_sorting(
@@ -132,66 +206,419 @@ impl Dao {
         &self.db
     }
 
+    pub async fn get_latest_indexed_slot(&self) -> Result<Option<i64>, ApiError> {
+        let slot = blocks::Entity::find()
+            .select_only()
+            .column_as(Expr::col(blocks::Column::Slot).max(), "slot")
+            .into_model::<SlotModel>()
+            .one(self.get_db())
+            .await
+            .map_err(ApiError::from)?;
+        Ok(slot.and_then(|s| s.slot))
+    }
+
+    // The highest slot at least `slot_safety_margin` slots below the latest indexed one.
+    pub async fn max_readable_slot(
+        &self,
+        slot_safety_margin: Option<u64>,
+    ) -> Result<Option<i64>, ApiError> {
+        let margin = match slot_safety_margin {
+            Some(margin) => margin as i64,
+            None => return Ok(None),
+        };
+        let latest_slot = self.get_latest_indexed_slot().await?;
+        Ok(latest_slot.map(|slot| slot - margin))
+    }
+
+    // Scans every transfer, callers are expected to cache the result.
+    pub async fn get_transfer_stats(&self) -> Result<TransferStatsModel, ApiError> {
+        let statement = Statement::from_string(
+            DbBackend::Postgres,
+            "SELECT COUNT(*) AS total_transfers, COUNT(DISTINCT mint_address) AS distinct_mints \
+             FROM token_transfers"
+                .to_string(),
+        );
+        let stats = TransferStatsModel::find_by_statement(statement)
+            .one(self.get_db())
+            .await
+            .map_err(ApiError::from)?;
+        Ok(stats.unwrap_or(TransferStatsModel {
+            total_transfers: 0,
+            distinct_mints: 0,
+        }))
+    }
+
+    pub async fn get_block_stats(&self) -> Result<BlockStatsModel, ApiError> {
+        let statement = Statement::from_string(
+            DbBackend::Postgres,
+            "SELECT MIN(block_time) AS earliest_block_time, MAX(block_time) AS latest_block_time, \
+             MAX(slot) AS last_indexed_slot FROM blocks"
+                .to_string(),
+        );
+        let stats = BlockStatsModel::find_by_statement(statement)
+            .one(self.get_db())
+            .await
+            .map_err(ApiError::from)?;
+        Ok(stats.unwrap_or(BlockStatsModel {
+            earliest_block_time: None,
+            latest_block_time: None,
+            last_indexed_slot: None,
+        }))
+    }
+
+    pub async fn get_block(&self, slot: i64) -> Result<Option<blocks::Model>, ApiError> {
+        blocks::Entity::find()
+            .filter(blocks::Column::Slot.eq(slot))
+            .one(self.get_db())
+            .await
+            .map_err(ApiError::from)
+    }
+
+    pub async fn get_latest_indexed_block(&self) -> Result<Option<blocks::Model>, ApiError> {
+        blocks::Entity::find()
+            .order_by(blocks::Column::Slot, Order::Desc)
+            .one(self.get_db())
+            .await
+            .map_err(ApiError::from)
+    }
+
     pub async fn get_transactions_by_address(
         &self,
-        source: Option<Vec<u8>>,
-        destination: Option<Vec<u8>>,
-        mint: Option<Vec<u8>>,
+        filter: &TransferFilter,
         pagination: &Pagination,
         limit: u64,
         sort_direction: Order,
         sort_by: Option<token_transfers::Column>,
-    ) -> Result<Vec<token_transfers::Model>, ApiError> {
+    ) -> Result<Vec<(token_transfers::Model, Option<transactions::Model>)>, ApiError> {
+        let mut query = Self::find_transfers(filter).find_also_related(transactions::Entity);
+
+        if let Some(col) = sort_by {
+            query = query
+                .order_by(col, sort_direction.clone())
+                .order_by(token_transfers::Column::Slot, sort_direction.clone());
+        }
+
+        let transactions = paginate(pagination, limit, query, token_transfers::Column::BlockTime)
+            .all(self.get_db())
+            .await
+            .map_err(ApiError::from)?;
+
+        Ok(transactions)
+    }
+
+    // Opens the transaction an export is read in, with the statement timeout of the pool
+    // replaced by `timeout`, none when it is zero. The timeout lasts until the transaction ends.
+    pub async fn begin_export(&self, timeout: Duration) -> Result<DatabaseTransaction, ApiError> {
+        let txn = self.get_db().begin().await.map_err(ApiError::from)?;
+        txn.execute(Statement::from_string(
+            DbBackend::Postgres,
+            format!("SET LOCAL statement_timeout = {}", timeout.as_millis()),
+        ))
+        .await
+        .map_err(ApiError::from)?;
+        Ok(txn)
+    }
+
+    // Every transfer matching `filter` within the dates of `pagination`, oldest first. Rows are
+    // read from a cursor of `txn`, opened with `begin_export`, as the stream is polled, so an
+    // export of any size is never held in memory at once.
+    pub async fn stream_transactions_by_address<'a>(
+        txn: &'a DatabaseTransaction,
+        filter: &TransferFilter,
+        pagination: &Pagination,
+    ) -> Result<
+        impl Stream<Item = Result<(token_transfers::Model, Option<transactions::Model>), DbErr>>
+            + Send
+            + 'a,
+        ApiError,
+    > {
+        let query = Self::find_transfers(filter)
+            .find_also_related(transactions::Entity)
+            .order_by(token_transfers::Column::Slot, Order::Asc)
+            .order_by(token_transfers::Column::InstructionIndex, Order::Asc);
+        filter_dates(pagination, query, token_transfers::Column::BlockTime)
+            .stream(txn)
+            .await
+            .map_err(ApiError::from)
+    }
+
+    pub async fn count_transactions_by_address(
+        &self,
+        filter: &TransferFilter,
+        pagination: &Pagination,
+    ) -> Result<u64, ApiError> {
+        let query = filter_dates(
+            pagination,
+            Self::find_transfers(filter),
+            token_transfers::Column::BlockTime,
+        );
+        let count = query.count(self.get_db()).await.map_err(ApiError::from)?;
+        Ok(count as u64)
+    }
+
+    fn find_transfers(filter: &TransferFilter) -> Select<token_transfers::Entity> {
         let mut query = token_transfers::Entity::find();
 
-        if let Some(source_address) = source {
-            query = query.filter(token_transfers::Column::SourceAddress.eq(source_address));
+        if let Some(source_address) = &filter.source {
+            query = query.filter(token_transfers::Column::SourceAddress.eq(source_address.clone()));
         }
 
-        if let Some(dest_address) = destination {
-            query = query.filter(token_transfers::Column::DestinationAddress.eq(dest_address));
+        if let Some(dest_address) = &filter.destination {
+            query =
+                query.filter(token_transfers::Column::DestinationAddress.eq(dest_address.clone()));
         }
 
-        if let Some(mint_address) = mint {
-            query = query.filter(token_transfers::Column::MintAddress.eq(mint_address));
+        if let Some(participant) = &filter.participant {
+            query = query.filter(
+                Condition::any()
+                    .add(token_transfers::Column::SourceAddress.eq(participant.clone()))
+                    .add(token_transfers::Column::DestinationAddress.eq(participant.clone())),
+            );
         }
 
-        if let Some(col) = sort_by {
-            query = query
-                .order_by(col, sort_direction.clone())
-                .order_by(token_transfers::Column::Slot, sort_direction.clone());
+        if !filter.participants.is_empty() {
+            query = query.filter(
+                Condition::any()
+                    .add(token_transfers::Column::SourceAddress.is_in(filter.participants.clone()))
+                    .add(
+                        token_transfers::Column::DestinationAddress
+                            .is_in(filter.participants.clone()),
+                    ),
+            );
         }
 
-        let transactions = paginate(pagination, limit, query, token_transfers::Column::BlockTime)
+        if let Some(mint_address) = &filter.mint {
+            query = query.filter(token_transfers::Column::MintAddress.eq(mint_address.clone()));
+        }
+
+        if !filter.mints.is_empty() {
+            query = query.filter(token_transfers::Column::MintAddress.is_in(filter.mints.clone()));
+        }
+
+        if let Some(program) = &filter.program {
+            query = query.filter(token_transfers::Column::ProgramId.eq(program.clone()));
+        }
+
+        if let Some(token_type) = filter.token_type {
+            query = query.filter(token_transfers::Column::TokenType.eq(token_type));
+        }
+
+        if let Some(max_slot) = filter.max_slot {
+            query = query.filter(token_transfers::Column::Slot.lte(max_slot));
+        }
+
+        // A subquery rather than a join, so counting the transfers doesn't need the join too.
+        if let Some(success) = filter.success {
+            let failed_signatures = Query::select()
+                .column(transactions::Column::Signature)
+                .from(transactions::Entity)
+                .and_where(transactions::Column::Error.is_not_null())
+                .to_owned();
+            query = query.filter(if success {
+                token_transfers::Column::Signature.not_in_subquery(failed_signatures)
+            } else {
+                token_transfers::Column::Signature.in_subquery(failed_signatures)
+            });
+        }
+
+        if filter.min_block_height.is_some() || filter.max_block_height.is_some() {
+            let mut slots = Query::select()
+                .column(blocks::Column::Slot)
+                .from(blocks::Entity)
+                .to_owned();
+            if let Some(min_block_height) = filter.min_block_height {
+                slots.and_where(blocks::Column::BlockHeight.gte(min_block_height));
+            }
+            if let Some(max_block_height) = filter.max_block_height {
+                slots.and_where(blocks::Column::BlockHeight.lte(max_block_height));
+            }
+            query = query.filter(token_transfers::Column::Slot.in_subquery(slots));
+        }
+
+        query
+    }
+
+    // Buckets are read with `time_bucket`, which Timescale can serve chunk by chunk.
+    pub async fn get_transfer_volume(
+        &self,
+        mint: Vec<u8>,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        interval: String,
+        max_slot: Option<i64>,
+        limit: u64,
+    ) -> Result<Vec<VolumeBucketModel>, ApiError> {
+        let statement = Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            "
+            SELECT
+                time_bucket($1::interval, block_time) AS bucket,
+                SUM(amount)::text AS amount,
+                COUNT(*) AS count
+            FROM token_transfers
+            WHERE mint_address = $2
+                AND block_time >= $3
+                AND block_time < $4
+                AND NOT EXISTS (
+                    SELECT 1 FROM transactions t
+                    WHERE t.signature = token_transfers.signature
+                        AND t.block_time = token_transfers.block_time
+                        AND t.error IS NOT NULL
+                )
+                AND ($5::bigint IS NULL OR slot <= $5)
+            GROUP BY bucket
+            ORDER BY bucket ASC
+            LIMIT $6
+            ",
+            vec![
+                interval.into(),
+                mint.into(),
+                start.into(),
+                end.into(),
+                max_slot.into(),
+                (limit as i64).into(),
+            ],
+        );
+        VolumeBucketModel::find_by_statement(statement)
             .all(self.get_db())
             .await
-            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+            .map_err(ApiError::from)
+    }
 
-        Ok(transactions)
+    // One row per day of `[start, end)`, zero for the days without transfers. A self transfer
+    // counts once. Failed transfers are left out, as they are from the volume.
+    pub async fn get_activity_histogram(
+        &self,
+        address: Vec<u8>,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        max_slot: Option<i64>,
+    ) -> Result<Vec<ActivityBucketModel>, ApiError> {
+        let statement = Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            "
+            SELECT day AS bucket, COALESCE(counts.count, 0) AS count
+            FROM generate_series(
+                $2::timestamptz,
+                $3::timestamptz - INTERVAL '1 day',
+                INTERVAL '1 day'
+            ) AS day
+            LEFT JOIN (
+                SELECT time_bucket(INTERVAL '1 day', block_time) AS bucket, COUNT(*) AS count
+                FROM token_transfers
+                WHERE (source_address = $1 OR destination_address = $1)
+                    AND block_time >= $2
+                    AND block_time < $3
+                    AND NOT EXISTS (
+                        SELECT 1 FROM transactions t
+                        WHERE t.signature = token_transfers.signature
+                            AND t.block_time = token_transfers.block_time
+                            AND t.error IS NOT NULL
+                    )
+                    AND ($4::bigint IS NULL OR slot <= $4)
+                GROUP BY bucket
+            ) counts ON counts.bucket = day
+            ORDER BY day ASC
+            ",
+            vec![address.into(), start.into(), end.into(), max_slot.into()],
+        );
+        ActivityBucketModel::find_by_statement(statement)
+            .all(self.get_db())
+            .await
+            .map_err(ApiError::from)
+    }
+
+    // Transfers to an address are grouped by their source and transfers from it by their
+    // destination, per mint, since amounts of different mints don't add up. Failed transfers
+    // moved nothing and are left out.
+    pub async fn get_counterparties(
+        &self,
+        address: Vec<u8>,
+        mint: Option<Vec<u8>>,
+        max_slot: Option<i64>,
+        limit: u64,
+        offset: u64,
+    ) -> Result<Vec<CounterpartyModel>, ApiError> {
+        let statement = Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            "
+            SELECT
+                counterparty,
+                mint_address,
+                COUNT(*) FILTER (WHERE outgoing) AS outgoing_count,
+                COALESCE(SUM(amount) FILTER (WHERE outgoing), 0)::text AS outgoing_amount,
+                COUNT(*) FILTER (WHERE NOT outgoing) AS incoming_count,
+                COALESCE(SUM(amount) FILTER (WHERE NOT outgoing), 0)::text AS incoming_amount
+            FROM (
+                SELECT destination_address AS counterparty, TRUE AS outgoing, *
+                FROM token_transfers
+                WHERE source_address = $1
+                UNION ALL
+                SELECT source_address AS counterparty, FALSE AS outgoing, *
+                FROM token_transfers
+                WHERE destination_address = $1
+            ) transfers
+            WHERE counterparty <> $1
+                AND ($2::bytea IS NULL OR mint_address = $2)
+                AND ($3::bigint IS NULL OR slot <= $3)
+                AND NOT EXISTS (
+                    SELECT 1 FROM transactions t
+                    WHERE t.signature = transfers.signature
+                        AND t.block_time = transfers.block_time
+                        AND t.error IS NOT NULL
+                )
+            GROUP BY counterparty, mint_address
+            ORDER BY COUNT(*) DESC, counterparty, mint_address
+            LIMIT $4
+            OFFSET $5
+            ",
+            vec![
+                address.into(),
+                mint.into(),
+                max_slot.into(),
+                (limit as i64).into(),
+                (offset as i64).into(),
+            ],
+        );
+        CounterpartyModel::find_by_statement(statement)
+            .all(self.get_db())
+            .await
+            .map_err(ApiError::from)
     }
 
     pub async fn get_transactions_by_mint(
         &self,
-        mint: Vec<u8>,
+        mints: Vec<Vec<u8>>,
+        max_slot: Option<i64>,
         pagination: &Pagination,
         limit: u64,
         sort_direction: Order,
         sort_by: Option<token_transfers::Column>,
-    ) -> Result<Vec<token_transfers::Model>, ApiError> {
-        let mut query = token_transfers::Entity::find()
-            .filter(token_transfers::Column::MintAddress.eq(mint.clone()));
-
-        if let Some(col) = sort_by {
-            query = query
-                .order_by(col, sort_direction.clone())
-                .order_by(token_transfers::Column::Slot, sort_direction.clone());
-        }
-
-        let transactions = paginate(pagination, limit, query, token_transfers::Column::BlockTime)
-            .all(self.get_db())
+    ) -> Result<Vec<(token_transfers::Model, Option<transactions::Model>)>, ApiError> {
+        let filter = TransferFilter {
+            mints,
+            max_slot,
+            ..Default::default()
+        };
+        self.get_transactions_by_address(&filter, pagination, limit, sort_direction, sort_by)
             .await
-            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    }
 
-        Ok(transactions)
+    pub async fn get_transactions_by_program(
+        &self,
+        program: Vec<u8>,
+        max_slot: Option<i64>,
+        pagination: &Pagination,
+        limit: u64,
+        sort_direction: Order,
+        sort_by: Option<token_transfers::Column>,
+    ) -> Result<Vec<(token_transfers::Model, Option<transactions::Model>)>, ApiError> {
+        let filter = TransferFilter {
+            program: Some(program),
+            max_slot,
+            ..Default::default()
+        };
+        self.get_transactions_by_address(&filter, pagination, limit, sort_direction, sort_by)
+            .await
     }
 }
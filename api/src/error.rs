@@ -1,17 +1,31 @@
-use {jsonrpsee::core::Error as RpcError, jsonrpsee::types::error::CallError, thiserror::Error};
+use {
+    jsonrpsee::core::Error as RpcError,
+    jsonrpsee::types::error::{CallError, ErrorObject},
+    sea_orm::{error::DbErr, RuntimeErr},
+    serde_json::json,
+    thiserror::Error,
+};
+
+// JSON-RPC has no 429, so a saturated pool is reported with the "limit exceeded" code used by
+// EIP-1474, with the suggested backoff in the error data.
+pub const POOL_EXHAUSTED_CODE: i32 = -32005;
+pub const POOL_EXHAUSTED_RETRY_AFTER_SECONDS: u64 = 1;
+
+// SQLSTATE of a statement cancelled by `statement_timeout`.
+const QUERY_CANCELED: &str = "57014";
 
 #[derive(Error, Debug)]
 pub enum ApiError {
-    #[error("Pagination Error. Limit should not be greater than 1000.")]
-    PaginationExceededError,
+    #[error("Pagination Error. Limit should not be greater than {0}.")]
+    PaginationExceededError(u32),
     #[error("Pagination Error. No Pagination Method Selected.")]
     PaginationEmptyError,
     #[error("Pagination Error. Only one pagination parameter supported per query.")]
     PaginationError,
     #[error(
-        "Paginating beyond 500000 items is not supported. Please use date based pagination instead"
+        "Paginating beyond {0} items is not supported. Please use date based pagination instead"
     )]
-    OffsetLimitExceededError,
+    OffsetLimitExceededError(u32),
     #[error("Server Failed to Start")]
     ServerStartError(#[from] RpcError),
     #[error("Pubkey Validation Err: {0} is invalid")]
@@ -20,22 +34,51 @@ pub enum ApiError {
     ConfigurationError { msg: String },
     #[error("Database Error: {0}")]
     DatabaseError(String),
+    #[error("Server is busy, no database connection became available. Retry later.")]
+    PoolExhausted,
+    #[error("Query timed out. Narrow the request, e.g. with a smaller limit or date range.")]
+    QueryTimeout,
     #[error("Transaction not found: {0}")]
     TransactionNotFound(String),
+    #[error("Block not found: {0}")]
+    BlockNotFound(u64),
     #[error("Invalid date: {0}")]
     InvalidDate(String),
     #[error("Invalid input: {0}")]
     InvalidInput(String),
+    #[error("Admin methods are disabled")]
+    AdminMethodsDisabled,
 }
 
-impl From<sea_orm::error::DbErr> for ApiError {
-    fn from(err: sea_orm::error::DbErr) -> Self {
-        ApiError::DatabaseError(format!("DatabaseError: {}", err))
+impl From<DbErr> for ApiError {
+    fn from(err: DbErr) -> Self {
+        match err {
+            DbErr::ConnectionAcquire
+            | DbErr::Conn(RuntimeErr::SqlxError(sqlx::Error::PoolTimedOut))
+            | DbErr::Exec(RuntimeErr::SqlxError(sqlx::Error::PoolTimedOut))
+            | DbErr::Query(RuntimeErr::SqlxError(sqlx::Error::PoolTimedOut)) => {
+                ApiError::PoolExhausted
+            }
+            DbErr::Exec(RuntimeErr::SqlxError(sqlx::Error::Database(ref e)))
+            | DbErr::Query(RuntimeErr::SqlxError(sqlx::Error::Database(ref e)))
+                if e.code().as_deref() == Some(QUERY_CANCELED) =>
+            {
+                ApiError::QueryTimeout
+            }
+            err => ApiError::DatabaseError(format!("DatabaseError: {}", err)),
+        }
     }
 }
 
 impl From<ApiError> for RpcError {
     fn from(val: ApiError) -> Self {
-        RpcError::Call(CallError::from_std_error(val))
+        match val {
+            ApiError::PoolExhausted => RpcError::Call(CallError::Custom(ErrorObject::owned(
+                POOL_EXHAUSTED_CODE,
+                val.to_string(),
+                Some(json!({ "retryAfter": POOL_EXHAUSTED_RETRY_AFTER_SECONDS })),
+            ))),
+            val => RpcError::Call(CallError::from_std_error(val)),
+        }
     }
 }
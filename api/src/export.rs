@@ -0,0 +1,369 @@
+use std::{
+    error::Error,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use cadence_macros::statsd_count;
+use common::metrics::safe_metric;
+use dao::generated::sea_orm_active_enums::TokenType;
+use futures::StreamExt;
+use hyper::{
+    body::{Bytes, HttpBody},
+    header::{HeaderValue, CONTENT_TYPE},
+    Body, Method, Request, Response, StatusCode,
+};
+use log::error;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+use tower::{Layer, Service};
+
+use crate::{
+    api::{validate_mint_addresses, validate_pubkey},
+    db::{Dao, Pagination, TransferFilter},
+    error::ApiError,
+    types::{DateBound, Transaction},
+};
+
+pub const EXPORT_PATH: &str = "/export";
+
+// A filter is a few hundred bytes, anything past this isn't one.
+const MAX_REQUEST_BYTES: usize = 64 * 1024;
+
+const CSV_HEADER: &str = "signature,slot,block_time,block_time_unix,program_address,mint_address,\
+source_address,destination_address,source_ata,destination_ata,amount,decimals,ui_amount,fee,\
+net_amount,fee_payer,transaction_fee,memo,error,inner_instruction_index\n";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    // One JSON transfer per line, as `getTransactionsByAddress` returns them.
+    #[default]
+    Ndjson,
+    Csv,
+}
+
+impl ExportFormat {
+    fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Ndjson => "application/x-ndjson",
+            ExportFormat::Csv => "text/csv",
+        }
+    }
+}
+
+/// The body of an export request. The filters are those of `getTransactionsByAddress`, without
+/// pagination: every matching transfer is returned, oldest first.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct ExportTransfers {
+    #[serde(alias = "sourceAddress")]
+    pub source: Option<String>,
+    #[serde(alias = "destinationAddress")]
+    pub destination: Option<String>,
+    pub participant_address: Option<String>,
+    #[serde(alias = "mintAddress")]
+    pub mint: Option<String>,
+    pub mint_addresses: Option<Vec<String>>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub success_only: Option<bool>,
+    pub token_type: Option<TokenType>,
+    pub format: Option<ExportFormat>,
+}
+
+fn optional_pubkey(address: Option<String>) -> Result<Option<Vec<u8>>, ApiError> {
+    address
+        .map(|address| validate_pubkey(address).map(|pubkey| pubkey.to_bytes().to_vec()))
+        .transpose()
+}
+
+fn optional_date(date: Option<String>, name: &str) -> Result<Option<DateBound>, ApiError> {
+    date.map(|date| DateBound::parse(&date).ok_or(ApiError::InvalidDate(name.to_string())))
+        .transpose()
+}
+
+impl ExportTransfers {
+    /// Checks the request the way `getTransactionsByAddress` does, and returns what to query.
+    pub fn into_filter(self) -> Result<(TransferFilter, Pagination, ExportFormat), ApiError> {
+        let mint_addresses = self.mint_addresses.unwrap_or_default();
+        if self.source.is_none()
+            && self.destination.is_none()
+            && self.participant_address.is_none()
+            && self.mint.is_none()
+            && mint_addresses.is_empty()
+        {
+            return Err(ApiError::InvalidInput(
+                "source, destination, participant_address, mint or mint_addresses must be provided"
+                    .to_string(),
+            ));
+        }
+        if self.mint.is_some() && !mint_addresses.is_empty() {
+            return Err(ApiError::InvalidInput(
+                "mint can't be combined with mint_addresses".to_string(),
+            ));
+        }
+        if self.participant_address.is_some()
+            && (self.source.is_some() || self.destination.is_some())
+        {
+            return Err(ApiError::InvalidInput(
+                "participant_address can't be combined with source or destination".to_string(),
+            ));
+        }
+        let before = optional_date(self.before, "before")?;
+        let after = optional_date(self.after, "after")?;
+        if let (Some(before), Some(after)) = (before, after) {
+            if after.start() > before.end() {
+                return Err(ApiError::InvalidDate(
+                    "after must not be later than before".to_string(),
+                ));
+            }
+        }
+
+        let filter = TransferFilter {
+            source: optional_pubkey(self.source)?,
+            destination: optional_pubkey(self.destination)?,
+            participant: optional_pubkey(self.participant_address)?,
+            mint: optional_pubkey(self.mint)?,
+            mints: validate_mint_addresses(mint_addresses)?,
+            success: self.success_only,
+            token_type: self.token_type,
+            ..Default::default()
+        };
+        Ok((
+            filter,
+            Pagination::Keyset { before, after },
+            self.format.unwrap_or_default(),
+        ))
+    }
+}
+
+// Quotes the fields that contain a separator, a quote or a line break, as RFC 4180 does.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn csv_optional<T: ToString>(field: &Option<T>) -> String {
+    field
+        .as_ref()
+        .map(|field| csv_field(&field.to_string()))
+        .unwrap_or_default()
+}
+
+/// A transfer as a line of the CSV export, in the order of its header.
+pub fn csv_row(transfer: &Transaction) -> String {
+    let fields = [
+        csv_field(&transfer.signature),
+        transfer.slot.to_string(),
+        transfer.block_time.to_rfc3339(),
+        transfer.block_time_unix.to_string(),
+        csv_field(&transfer.program_address),
+        csv_optional(&transfer.mint_address),
+        csv_field(&transfer.source_address),
+        csv_field(&transfer.destination_address),
+        csv_optional(&transfer.source_ata),
+        csv_optional(&transfer.destination_ata),
+        transfer.amount.to_string(),
+        csv_optional(&transfer.decimals),
+        csv_optional(&transfer.ui_amount),
+        csv_optional(&transfer.fee),
+        csv_optional(&transfer.net_amount),
+        csv_optional(&transfer.fee_payer),
+        csv_optional(&transfer.transaction_fee),
+        csv_optional(&transfer.memo),
+        csv_optional(&transfer.error),
+        csv_optional(&transfer.inner_instruction_index),
+    ];
+    format!("{}\n", fields.join(","))
+}
+
+fn export_line(transfer: &Transaction, format: ExportFormat) -> String {
+    match format {
+        ExportFormat::Ndjson => format!("{}\n", serde_json::to_string(transfer).unwrap()),
+        ExportFormat::Csv => csv_row(transfer),
+    }
+}
+
+fn error_response(status: StatusCode, message: String) -> Response<Body> {
+    let mut response = Response::new(Body::from(message));
+    *response.status_mut() = status;
+    response
+}
+
+async fn read_body(mut body: Body) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| e.to_string())?;
+        if bytes.len() + chunk.len() > MAX_REQUEST_BYTES {
+            return Err("Request body is too large".to_string());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+// Answers an export request. The transfers are written to the response as they are read, and a
+// failure midway aborts it, so a client can't take a cut off export for a complete one. Requests
+// past the exports `limits` lets run at once are turned away.
+async fn export(dao: Dao, limits: ExportLimits, request: Request<Body>) -> Response<Body> {
+    let body = match read_body(request.into_body()).await {
+        Ok(body) => body,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    let payload = match serde_json::from_slice::<ExportTransfers>(&body) {
+        Ok(payload) => payload,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e.to_string()),
+    };
+    let (mut filter, pagination, format) = match payload.into_filter() {
+        Ok(query) => query,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e.to_string()),
+    };
+    // Held until the export is sent.
+    let Ok(permit) = limits.exports.clone().try_acquire_owned() else {
+        safe_metric(|| {
+            statsd_count!("api_export_rejected", 1);
+        });
+        return error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Too many exports in progress, try again later".to_string(),
+        );
+    };
+    filter.max_slot = match dao.max_readable_slot(limits.slot_safety_margin).await {
+        Ok(max_slot) => max_slot,
+        Err(e) => return error_response(StatusCode::SERVICE_UNAVAILABLE, e.to_string()),
+    };
+    safe_metric(|| {
+        statsd_count!("api_export", 1);
+    });
+
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let _permit = permit;
+        let txn = match dao.begin_export(limits.timeout).await {
+            Ok(txn) => txn,
+            Err(e) => {
+                error!("Failed to start export: {}", e);
+                sender.abort();
+                return;
+            }
+        };
+        let mut rows = match Dao::stream_transactions_by_address(&txn, &filter, &pagination).await {
+            Ok(rows) => rows,
+            Err(e) => {
+                error!("Failed to start export: {}", e);
+                sender.abort();
+                return;
+            }
+        };
+        if format == ExportFormat::Csv && sender.send_data(Bytes::from(CSV_HEADER)).await.is_err() {
+            return;
+        }
+        while let Some(row) = rows.next().await {
+            let line = match row {
+                Ok(row) => export_line(&Transaction::from(row), format),
+                Err(e) => {
+                    error!("Export failed: {}", ApiError::from(e));
+                    sender.abort();
+                    return;
+                }
+            };
+            // The client went away.
+            if sender.send_data(Bytes::from(line)).await.is_err() {
+                return;
+            }
+        }
+    });
+    let mut response = Response::new(body);
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static(format.content_type()),
+    );
+    response
+}
+
+// What an export may take from the database, shared by every connection the layer serves.
+#[derive(Clone)]
+struct ExportLimits {
+    slot_safety_margin: Option<u64>,
+    timeout: Duration,
+    exports: Arc<Semaphore>,
+}
+
+/// Serves `POST /export` with a streamed export of the transfers matching an `ExportTransfers`
+/// body, and passes every other request on. Exports run in their own transaction, whose
+/// statements may take `timeout` rather than the query timeout, none when it is zero, and at
+/// most `max_concurrent_exports` of them run at once.
+#[derive(Clone)]
+pub struct ExportLayer {
+    dao: Dao,
+    limits: ExportLimits,
+}
+
+impl ExportLayer {
+    pub fn new(
+        dao: Dao,
+        slot_safety_margin: Option<u64>,
+        timeout: Duration,
+        max_concurrent_exports: u32,
+    ) -> Self {
+        ExportLayer {
+            dao,
+            limits: ExportLimits {
+                slot_safety_margin,
+                timeout,
+                exports: Arc::new(Semaphore::new(max_concurrent_exports as usize)),
+            },
+        }
+    }
+}
+
+impl<S> Layer<S> for ExportLayer {
+    type Service = Export<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Export {
+            inner,
+            dao: self.dao.clone(),
+            limits: self.limits.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Export<S> {
+    inner: S,
+    dao: Dao,
+    limits: ExportLimits,
+}
+
+impl<S> Service<Request<Body>> for Export<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Error: Into<Box<dyn Error + Send + Sync>> + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = Box<dyn Error + Send + Sync + 'static>;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        if request.uri().path() == EXPORT_PATH && request.method() == Method::POST {
+            let (dao, limits) = (self.dao.clone(), self.limits.clone());
+            return Box::pin(async move { Ok(export(dao, limits, request).await) });
+        }
+        let response = self.inner.call(request);
+        Box::pin(async move { response.await.map_err(Into::into) })
+    }
+}
@@ -1,7 +1,13 @@
 pub mod api;
 pub mod builder;
 pub mod config;
+pub mod cors;
 pub mod db;
 pub mod error;
+pub mod export;
+pub mod rate_limit;
+pub mod request_id;
+pub mod runtime;
 pub mod spec;
+pub mod tls;
 pub mod types;
@@ -1,30 +1,30 @@
 use api::api::Api;
 use api::builder::RpcApiBuilder;
-use api::config::setup_config;
+use api::config::{setup_config_with_args, ApiArgs, ApiConfig};
+use api::cors::cors_layer;
 use api::error::ApiError;
+use api::export::ExportLayer;
+use api::rate_limit::{serve_rate_limited, RateLimitLayer};
+use api::request_id::{current_request_id, RequestIdLayer};
+use api::runtime::build_runtime;
+use api::tls::{load_tls_config, serve_tls};
 use cadence_macros::statsd_time;
+use clap::Parser;
 use common::metrics::safe_metric;
 use common::metrics::setup_metrics;
-use hyper::Method;
 use jsonrpsee::server::{
     logger::{Logger, TransportProtocol},
     middleware::proxy_get_request::ProxyGetRequestLayer,
     ServerBuilder,
 };
 use log::info;
-use serde::Serialize;
 use std::env;
 use std::net::SocketAddr;
-use tower_http::cors::{Any, CorsLayer};
+use tokio::net::TcpListener;
 
 use log::debug;
 use std::time::Instant;
 
-#[derive(Serialize)]
-struct Message {
-    message: String,
-}
-
 // Using jemallocator because default allocator holds onto
 // memory too easily. This causes OOM when large accounts (>100MB)
 // are processed. jemallocator frees up memory much more aggressively.
@@ -42,33 +42,39 @@ static GLOBAL: Jemalloc = Jemalloc;
 #[derive(Clone)]
 struct MetricMiddleware;
 
+// Ties together the events logged for one request, including the calls of a batch. The id is
+// left out of the metric tags, which would otherwise grow a series per request.
+#[derive(Clone, Copy, Debug)]
+struct RequestContext {
+    id: u64,
+    started_at: Instant,
+}
+
 impl Logger for MetricMiddleware {
-    type Instant = Instant;
+    type Instant = RequestContext;
 
     fn on_request(&self, _t: TransportProtocol) -> Self::Instant {
-        Instant::now()
+        RequestContext {
+            id: current_request_id().unwrap_or_default(),
+            started_at: Instant::now(),
+        }
     }
 
-    fn on_result(
-        &self,
-        name: &str,
-        success: bool,
-        started_at: Self::Instant,
-        _t: TransportProtocol,
-    ) {
+    fn on_result(&self, name: &str, success: bool, request: Self::Instant, _t: TransportProtocol) {
         let stat = match success {
             true => "success",
             false => "failure",
         };
         debug!(
-            "Call to '{}' {} took {:?}",
+            "[request {}] Call to '{}' {} took {:?}",
+            request.id,
             name,
             stat,
-            started_at.elapsed()
+            request.started_at.elapsed()
         );
         safe_metric(|| {
             let success = success.to_string();
-            statsd_time!("api_call", started_at.elapsed(), "method" => name, "success" => &success);
+            statsd_time!("api_call", request.started_at.elapsed(), "method" => name, "success" => &success);
         });
     }
 
@@ -88,11 +94,21 @@ impl Logger for MetricMiddleware {
         _kind: jsonrpsee::server::logger::MethodKind,
         _transport: TransportProtocol,
     ) {
-        debug!("Call: {} {:?}", method_name, params);
+        debug!(
+            "[request {}] Call: {} {:?}",
+            current_request_id().unwrap_or_default(),
+            method_name,
+            params
+        );
     }
 
-    fn on_response(&self, result: &str, _started_at: Self::Instant, _transport: TransportProtocol) {
-        debug!("Response: {}", result);
+    fn on_response(&self, result: &str, request: Self::Instant, _transport: TransportProtocol) {
+        debug!(
+            "[request {}] Response after {:?}: {}",
+            request.id,
+            request.started_at.elapsed(),
+            result
+        );
     }
 
     fn on_disconnect(&self, remote_addr: SocketAddr, _transport: TransportProtocol) {
@@ -100,8 +116,7 @@ impl Logger for MetricMiddleware {
     }
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<(), ApiError> {
+fn main() -> anyhow::Result<(), ApiError> {
     env::set_var(
         env_logger::DEFAULT_FILTER_ENV,
         env::var_os(env_logger::DEFAULT_FILTER_ENV)
@@ -109,33 +124,77 @@ async fn main() -> anyhow::Result<(), ApiError> {
     );
     env_logger::init();
 
-    let config = setup_config();
+    let config = setup_config_with_args(&ApiArgs::parse());
+    build_runtime(config.worker_threads)?.block_on(serve(config))
+}
 
+async fn serve(config: ApiConfig) -> anyhow::Result<(), ApiError> {
     let addr = SocketAddr::from(([0, 0, 0, 0], config.server_port));
-    let cors = CorsLayer::new()
-        .allow_methods([Method::POST, Method::GET])
-        .allow_origin(Any)
-        .allow_headers([hyper::header::CONTENT_TYPE]);
+    let cors = cors_layer(&config.allowed_origins);
 
     setup_metrics(
         "api",
         config.metrics_host.clone(),
         config.metrics_port,
         config.env.clone(),
+        config.prometheus_port,
     );
+    let rate_limit = config.get_rate_limit().map(|(requests_per_second, burst)| {
+        RateLimitLayer::new(requests_per_second, burst, config.trusted_proxies.clone())
+    });
+    let api = Api::new(config.clone()).await;
+    let export = config.enable_export.then(|| {
+        ExportLayer::new(
+            api.dao.clone(),
+            config.slot_safety_margin,
+            config.get_export_timeout(),
+            config.get_max_concurrent_exports(),
+        )
+    });
     let middleware = tower::ServiceBuilder::new()
+        .layer(RequestIdLayer)
         .layer(cors)
+        .option_layer(export)
         .layer(ProxyGetRequestLayer::new("/readiness", "readiness")?)
         .layer(ProxyGetRequestLayer::new("/liveness", "liveness")?)
         .layer(ProxyGetRequestLayer::new("/health", "healthz")?);
 
+    let tls_config = match config.get_tls_paths()? {
+        Some((cert_path, key_path)) => Some(load_tls_config(cert_path, key_path)?),
+        None => None,
+    };
+    // With TLS or rate limiting the server itself only listens on loopback, behind the listener
+    // that accepts the connections.
+    let relayed = tls_config.is_some() || rate_limit.is_some();
+    let server_addr = match relayed {
+        true => SocketAddr::from(([127, 0, 0, 1], 0)),
+        false => addr,
+    };
     let server = ServerBuilder::default()
         .set_middleware(middleware)
         .set_logger(MetricMiddleware)
-        .build(addr)
+        .build(server_addr)
         .await?;
+    if relayed {
+        let backend = server.local_addr()?;
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|e| ApiError::ConfigurationError {
+                msg: format!("Failed to listen on {}: {}", addr, e),
+            })?;
+        match (rate_limit, tls_config) {
+            (Some(rate_limit), tls_config) => {
+                tokio::spawn(serve_rate_limited(
+                    listener, tls_config, rate_limit, backend,
+                ));
+            }
+            (None, Some(tls_config)) => {
+                tokio::spawn(serve_tls(listener, tls_config, backend));
+            }
+            (None, None) => {}
+        }
+    }
 
-    let api = Api::new(config).await;
     let rpc = RpcApiBuilder::build(Box::new(api))?;
     info!("Server Started");
     let server_handle = server.start(rpc)?;
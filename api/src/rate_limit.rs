@@ -0,0 +1,279 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use cadence_macros::statsd_count;
+use common::metrics::safe_metric;
+use hyper::{
+    client::HttpConnector,
+    header::{HeaderValue, RETRY_AFTER},
+    server::conn::Http,
+    service::service_fn,
+    Body, Client, HeaderMap, Request, Response, StatusCode,
+};
+use log::debug;
+use tokio::net::TcpListener;
+use tokio_rustls::{rustls::ServerConfig, TlsAcceptor};
+use tower::{Layer, Service, ServiceBuilder};
+use tower_http::add_extension::AddExtensionLayer;
+
+// Buckets of clients that have been idle long enough to refill are dropped past this many.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+// The bucket shared by requests whose client isn't known, and by new clients while the tracked
+// ones are all active.
+const SHARED_BUCKET: &str = "";
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// A token bucket per client. Each holds up to `burst` requests and refills at
+/// `requests_per_second`.
+pub struct RateLimiter {
+    requests_per_second: f64,
+    burst: f64,
+    buckets: Mutex<Buckets>,
+}
+
+struct Buckets {
+    buckets: HashMap<String, Bucket>,
+    swept_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_second: u32, burst: u32) -> Self {
+        RateLimiter {
+            requests_per_second: requests_per_second as f64,
+            burst: burst.max(1) as f64,
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                swept_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes a token from the bucket of `client`, false when it is empty.
+    pub fn try_acquire(&self, client: &str, now: Instant) -> bool {
+        let mut state = self.buckets.lock().unwrap();
+        let Buckets { buckets, swept_at } = &mut *state;
+        let mut client = client;
+        if buckets.len() >= MAX_TRACKED_CLIENTS && !buckets.contains_key(client) {
+            // Idle buckets are only full again after this long, so sweeping more often than that
+            // would scan the buckets of active clients for nothing.
+            let full_after = self.burst / self.requests_per_second;
+            if now.duration_since(*swept_at).as_secs_f64() >= full_after {
+                buckets.retain(|_, bucket| {
+                    now.duration_since(bucket.updated_at).as_secs_f64() < full_after
+                });
+                *swept_at = now;
+            }
+            if buckets.len() >= MAX_TRACKED_CLIENTS {
+                client = SHARED_BUCKET;
+            }
+        }
+        let bucket = buckets.entry(client.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            updated_at: now,
+        });
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.requests_per_second).min(self.burst);
+        bucket.updated_at = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+}
+
+/// The address of the peer a connection was accepted from.
+#[derive(Clone, Copy, Debug)]
+pub struct PeerAddr(pub SocketAddr);
+
+/// The client of a request from `peer`. Only proxies in `trusted_proxies` may name the client
+/// they forward for, in `X-Forwarded-For` or else `X-Real-IP`. Every proxy appends the address
+/// it got the request from to `X-Forwarded-For`, so the client is the last address in it that
+/// isn't of a trusted proxy, and whatever a client wrote before that is ignored.
+pub fn client_ip(headers: &HeaderMap, peer: IpAddr, trusted_proxies: &[IpAddr]) -> IpAddr {
+    if !trusted_proxies.contains(&peer) {
+        return peer;
+    }
+    let forwarded_for = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|address| address.trim().parse::<IpAddr>().ok())
+        .collect::<Vec<_>>();
+    forwarded_for
+        .into_iter()
+        .rev()
+        .map_while(|address| address)
+        .find(|address| !trusted_proxies.contains(address))
+        .or_else(|| {
+            headers
+                .get("x-real-ip")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse().ok())
+        })
+        .unwrap_or(peer)
+}
+
+fn too_many_requests() -> Response<Body> {
+    let mut response = Response::new(Body::from("Too many requests"));
+    *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from_static("1"));
+    response
+}
+
+/// Answers requests over their client's limit with a 429 instead of passing them on. Clients
+/// are found by `client_ip` from the request's `PeerAddr` extension, and requests without one
+/// share a limit.
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    limiter: Arc<RateLimiter>,
+    trusted_proxies: Arc<Vec<IpAddr>>,
+}
+
+impl RateLimitLayer {
+    pub fn new(requests_per_second: u32, burst: u32, trusted_proxies: Vec<IpAddr>) -> Self {
+        RateLimitLayer {
+            limiter: Arc::new(RateLimiter::new(requests_per_second, burst)),
+            trusted_proxies: Arc::new(trusted_proxies),
+        }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            limiter: self.limiter.clone(),
+            trusted_proxies: self.trusted_proxies.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimit<S> {
+    inner: S,
+    limiter: Arc<RateLimiter>,
+    trusted_proxies: Arc<Vec<IpAddr>>,
+}
+
+impl<S> RateLimit<S> {
+    fn client_key(&self, request: &Request<Body>) -> String {
+        request
+            .extensions()
+            .get::<PeerAddr>()
+            .map(|PeerAddr(peer)| {
+                client_ip(request.headers(), peer.ip(), &self.trusted_proxies).to_string()
+            })
+            .unwrap_or_else(|| SHARED_BUCKET.to_string())
+    }
+}
+
+impl<S> Service<Request<Body>> for RateLimit<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Error: Into<Box<dyn Error + Send + Sync>> + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = Box<dyn Error + Send + Sync + 'static>;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        if !self
+            .limiter
+            .try_acquire(&self.client_key(&request), Instant::now())
+        {
+            safe_metric(|| {
+                statsd_count!("api_rate_limited", 1);
+            });
+            return Box::pin(async { Ok(too_many_requests()) });
+        }
+        let response = self.inner.call(request);
+        Box::pin(async move { response.await.map_err(Into::into) })
+    }
+}
+
+async fn relay(
+    client: Client<HttpConnector>,
+    backend: SocketAddr,
+    mut request: Request<Body>,
+) -> Result<Response<Body>, Box<dyn Error + Send + Sync>> {
+    let uri = format!(
+        "http://{}{}",
+        backend,
+        request
+            .uri()
+            .path_and_query()
+            .map_or("/", |path| path.as_str())
+    );
+    *request.uri_mut() = uri.parse()?;
+    Ok(client.request(request).await?)
+}
+
+// The JSON-RPC server doesn't hand the peer address to middleware, so rate limited requests are
+// served here first, where each connection is accepted, and relayed to `backend` on the
+// loopback interface. TLS is terminated here too when `tls` is set.
+pub async fn serve_rate_limited(
+    listener: TcpListener,
+    tls: Option<ServerConfig>,
+    rate_limit: RateLimitLayer,
+    backend: SocketAddr,
+) {
+    let acceptor = tls.map(|config| TlsAcceptor::from(Arc::new(config)));
+    let client = Client::new();
+    loop {
+        let (stream, remote_addr) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                debug!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+        let client = client.clone();
+        let service = ServiceBuilder::new()
+            .layer(AddExtensionLayer::new(PeerAddr(remote_addr)))
+            .layer(rate_limit.clone())
+            .service(service_fn(move |request| {
+                relay(client.clone(), backend, request)
+            }));
+        let acceptor = acceptor.clone();
+        tokio::spawn(async move {
+            let served = match acceptor {
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(tls_stream) => Http::new().serve_connection(tls_stream, service).await,
+                    Err(e) => {
+                        debug!("TLS handshake with {} failed: {}", remote_addr, e);
+                        return;
+                    }
+                },
+                None => Http::new().serve_connection(stream, service).await,
+            };
+            if let Err(e) = served {
+                debug!("Connection from {} closed: {}", remote_addr, e);
+            }
+        });
+    }
+}
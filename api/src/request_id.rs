@@ -0,0 +1,61 @@
+use std::{
+    error::Error,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll},
+};
+
+use hyper::{Body, Request, Response};
+use tower::{Layer, Service};
+
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+tokio::task_local! {
+    static CURRENT_REQUEST_ID: u64;
+}
+
+/// The id of the HTTP request being served by the current task, if any.
+pub fn current_request_id() -> Option<u64> {
+    CURRENT_REQUEST_ID.try_with(|id| *id).ok()
+}
+
+/// Gives every request an id that is readable through [`current_request_id`] for as long as
+/// the request is being handled, so the events logged for it can be tied together.
+#[derive(Clone, Default)]
+pub struct RequestIdLayer;
+
+impl<S> Layer<S> for RequestIdLayer {
+    type Service = RequestId<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestId { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct RequestId<S> {
+    inner: S,
+}
+
+impl<S> Service<Request<Body>> for RequestId<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Error: Into<Box<dyn Error + Send + Sync>> + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = Box<dyn Error + Send + Sync + 'static>;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+        let response = CURRENT_REQUEST_ID.scope(id, self.inner.call(request));
+        Box::pin(async move { response.await.map_err(Into::into) })
+    }
+}
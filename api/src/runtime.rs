@@ -0,0 +1,23 @@
+use tokio::runtime::{Builder, Runtime};
+
+use crate::error::ApiError;
+
+// The multi-threaded runtime the server runs on. Without `worker_threads` tokio starts one
+// worker per CPU core.
+pub fn build_runtime(worker_threads: Option<usize>) -> Result<Runtime, ApiError> {
+    let mut builder = Builder::new_multi_thread();
+    if let Some(worker_threads) = worker_threads {
+        if worker_threads == 0 {
+            return Err(ApiError::ConfigurationError {
+                msg: "worker_threads must be greater than 0".to_string(),
+            });
+        }
+        builder.worker_threads(worker_threads);
+    }
+    builder
+        .enable_all()
+        .build()
+        .map_err(|e| ApiError::ConfigurationError {
+            msg: format!("Failed to build runtime: {}", e),
+        })
+}
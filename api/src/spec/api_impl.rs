@@ -1,17 +1,31 @@
+use std::collections::{BTreeMap, BTreeSet};
+
 use crate::{
-    api::{validate_pubkey, Api},
-    db::create_sorting,
+    api::{validate_block_heights, validate_mint_addresses, validate_pubkey, Api},
+    config::SanitizedApiConfig,
+    db::{create_sorting, TransferFilter},
     error::ApiError,
-    types::Transaction,
+    types::{parse_day, ActivityBucket, Block, Counterparty, Transaction, VolumeBucket},
 };
+use chrono::{Duration, TimeZone, Utc};
 use open_rpc_derive::document_rpc;
 use open_rpc_schema::document::OpenrpcDocument;
 use sea_orm::{ConnectionTrait, DbBackend, Statement};
 
-use super::{ApiContract, GetTransactionsByAddress, TransactionList};
+use super::{
+    ActivityHistogram, ApiContract, Counterparties, GetActivityHistogram, GetBlock,
+    GetCounterparties, GetTransactionsByAddress, GetTransactionsByAddresses, GetTransactionsByMint,
+    GetTransactionsByProgram, GetTransferVolume, IndexStats, TransactionList,
+    TransactionsByAddresses, TransferVolume,
+};
 
 use async_trait::async_trait;
 
+const DEFAULT_VOLUME_INTERVAL: &str = "1 day";
+const MAX_VOLUME_BUCKETS: u64 = 1000;
+const MAX_ACTIVITY_DAYS: i64 = 1000;
+const MAX_ADDRESSES_PER_REQUEST: usize = 100;
+
 #[document_rpc]
 #[async_trait]
 impl ApiContract for Api {
@@ -33,24 +47,60 @@ impl ApiContract for Api {
         Ok(())
     }
 
+    async fn get_config(self: &Api) -> Result<SanitizedApiConfig, ApiError> {
+        if !self.config.enable_admin_methods {
+            return Err(ApiError::AdminMethodsDisabled);
+        }
+        Ok(self.config.sanitized())
+    }
+
     async fn get_transactions_by_address(
         self: &Api,
         payload: GetTransactionsByAddress,
     ) -> Result<TransactionList, ApiError> {
         let GetTransactionsByAddress {
-            source_address,
-            destination_address,
-            mint_address,
+            source: source_address,
+            destination: destination_address,
+            participant_address,
+            mint: mint_address,
+            mint_addresses,
             before,
             after,
             limit,
             page,
             sort_by,
+            count_total,
+            success_only,
+            from_height,
+            to_height,
+            include_raw,
+            token_type,
         } = payload;
+        let mint_addresses = mint_addresses.unwrap_or_default();
+
+        if source_address.is_none()
+            && destination_address.is_none()
+            && participant_address.is_none()
+            && mint_address.is_none()
+            && mint_addresses.is_empty()
+        {
+            return Err(ApiError::InvalidInput(
+                "source, destination, participant_address, mint or mint_addresses must be provided"
+                    .to_string(),
+            ));
+        }
 
-        if source_address.is_none() && destination_address.is_none() && mint_address.is_none() {
+        if mint_address.is_some() && !mint_addresses.is_empty() {
             return Err(ApiError::InvalidInput(
-                "source_address, destination_address or mint_address must be provided".to_string(),
+                "mint can't be combined with mint_addresses".to_string(),
+            ));
+        }
+
+        if participant_address.is_some()
+            && (source_address.is_some() || destination_address.is_some())
+        {
+            return Err(ApiError::InvalidInput(
+                "participant_address can't be combined with source or destination".to_string(),
             ));
         }
 
@@ -66,22 +116,272 @@ impl ApiContract for Api {
             None
         };
 
+        let participant = if let Some(participant) = participant_address {
+            Some(validate_pubkey(participant)?.to_bytes().to_vec())
+        } else {
+            None
+        };
+
+        let mint = if let Some(mint) = mint_address {
+            Some(validate_pubkey(mint)?.to_bytes().to_vec())
+        } else {
+            None
+        };
+        let mints = validate_mint_addresses(mint_addresses)?;
+
+        let (min_block_height, max_block_height) = validate_block_heights(from_height, to_height)?;
+
+        let (page, pagination) = self.paginate_request(&limit, &page, &before, &after)?;
+        let (sort_direction, sort_column) = create_sorting(sort_by.unwrap_or_default());
+
+        let filter = TransferFilter {
+            source,
+            destination,
+            participant,
+            mint,
+            mints,
+            max_slot: self.max_readable_slot().await?,
+            success: success_only,
+            min_block_height,
+            max_block_height,
+            token_type,
+            ..Default::default()
+        };
+
+        let models = self
+            .dao
+            .get_transactions_by_address(
+                &filter,
+                &pagination,
+                page.limit,
+                sort_direction,
+                sort_column,
+            )
+            .await?;
+        let total = if count_total.unwrap_or(false) {
+            Some(
+                self.dao
+                    .count_transactions_by_address(&filter, &pagination)
+                    .await?,
+            )
+        } else {
+            None
+        };
+        let include_raw = include_raw.unwrap_or(false);
+        let transactions: Vec<Transaction> = models
+            .into_iter()
+            .map(|(model, transaction)| {
+                let data = model.data.clone().filter(|_| include_raw);
+                Transaction {
+                    data: data.map(|data| bs58::encode(data).into_string()),
+                    ..Transaction::from((model, transaction))
+                }
+            })
+            .collect();
+        Ok(Api::build_transaction_response(
+            transactions,
+            total,
+            page.limit,
+            &pagination,
+        ))
+    }
+
+    async fn get_transactions_by_addresses(
+        self: &Api,
+        payload: GetTransactionsByAddresses,
+    ) -> Result<TransactionsByAddresses, ApiError> {
+        let GetTransactionsByAddresses {
+            addresses,
+            mint_address,
+            limit,
+            page,
+            before,
+            after,
+            sort_by,
+        } = payload;
+
+        if addresses.is_empty() || addresses.len() > MAX_ADDRESSES_PER_REQUEST {
+            return Err(ApiError::InvalidInput(format!(
+                "between 1 and {} addresses must be provided",
+                MAX_ADDRESSES_PER_REQUEST
+            )));
+        }
+        let addresses = addresses
+            .into_iter()
+            .map(validate_pubkey)
+            .collect::<Result<BTreeSet<_>, _>>()?;
+
         let mint = if let Some(mint) = mint_address {
             Some(validate_pubkey(mint)?.to_bytes().to_vec())
         } else {
             None
         };
 
-        let page = self.validate_pagination(&limit, &page, &before, &after)?;
-        let pagination = self.create_pagination(page.clone())?;
+        let (page, pagination) = self.paginate_request(&limit, &page, &before, &after)?;
         let (sort_direction, sort_column) = create_sorting(sort_by.unwrap_or_default());
 
+        let filter = TransferFilter {
+            participants: addresses
+                .iter()
+                .map(|address| address.to_bytes().to_vec())
+                .collect(),
+            mint,
+            max_slot: self.max_readable_slot().await?,
+            ..Default::default()
+        };
+
         let models = self
             .dao
             .get_transactions_by_address(
-                source,
-                destination,
+                &filter,
+                &pagination,
+                page.limit,
+                sort_direction,
+                sort_column,
+            )
+            .await?;
+        let transactions: Vec<Transaction> = models.into_iter().map(Transaction::from).collect();
+        let TransactionList {
+            count,
+            limit,
+            page,
+            before,
+            after,
+            items,
+            ..
+        } = Api::build_transaction_response(transactions, None, page.limit, &pagination);
+
+        // Every requested address is listed, with no transfers if it had none in this page.
+        let mut transactions_by_address: BTreeMap<String, Vec<Transaction>> = addresses
+            .iter()
+            .map(|address| (address.to_string(), vec![]))
+            .collect();
+        for transaction in items {
+            let mut involved = vec![&transaction.source_address];
+            if transaction.destination_address != transaction.source_address {
+                involved.push(&transaction.destination_address);
+            }
+            for address in involved {
+                if let Some(transactions) = transactions_by_address.get_mut(address) {
+                    transactions.push(transaction.clone());
+                }
+            }
+        }
+
+        Ok(TransactionsByAddresses {
+            count,
+            limit,
+            page,
+            before,
+            after,
+            items: transactions_by_address,
+        })
+    }
+
+    async fn get_transactions_by_mint(
+        self: &Api,
+        payload: GetTransactionsByMint,
+    ) -> Result<TransactionList, ApiError> {
+        let GetTransactionsByMint {
+            mint_address,
+            mint_addresses,
+            limit,
+            page,
+            before,
+            after,
+            sort_by,
+        } = payload;
+
+        let mut mint_addresses = mint_addresses.unwrap_or_default();
+        mint_addresses.insert(0, mint_address);
+        let mints = validate_mint_addresses(mint_addresses)?;
+        let (page, pagination) = self.paginate_request(&limit, &page, &before, &after)?;
+        let (sort_direction, sort_column) = create_sorting(sort_by.unwrap_or_default());
+
+        let models = self
+            .dao
+            .get_transactions_by_mint(
+                mints,
+                self.max_readable_slot().await?,
+                &pagination,
+                page.limit,
+                sort_direction,
+                sort_column,
+            )
+            .await?;
+        let transactions: Vec<Transaction> = models.into_iter().map(Transaction::from).collect();
+        Ok(Api::build_transaction_response(
+            transactions,
+            None,
+            page.limit,
+            &pagination,
+        ))
+    }
+
+    async fn get_counterparties(
+        self: &Api,
+        payload: GetCounterparties,
+    ) -> Result<Counterparties, ApiError> {
+        let GetCounterparties {
+            address,
+            mint_address,
+            limit,
+            page,
+        } = payload;
+
+        let address_bytes = validate_pubkey(address.clone())?.to_bytes().to_vec();
+        let mint = if let Some(mint) = mint_address {
+            Some(validate_pubkey(mint)?.to_bytes().to_vec())
+        } else {
+            None
+        };
+
+        // Groups have no time to page on, so only page numbers are supported.
+        let page_opt = self.validate_pagination(&limit, &page, &None, &None)?;
+        let page = page_opt.page.unwrap_or(1);
+        let models = self
+            .dao
+            .get_counterparties(
+                address_bytes,
                 mint,
+                self.max_readable_slot().await?,
+                page_opt.limit,
+                (page - 1) * page_opt.limit,
+            )
+            .await?;
+        let items: Vec<Counterparty> = models.into_iter().map(Counterparty::from).collect();
+
+        Ok(Counterparties {
+            address,
+            count: items.len() as u32,
+            limit: page_opt.limit as u32,
+            page: page as u32,
+            items,
+        })
+    }
+
+    async fn get_transactions_by_program(
+        self: &Api,
+        payload: GetTransactionsByProgram,
+    ) -> Result<TransactionList, ApiError> {
+        let GetTransactionsByProgram {
+            program_id,
+            limit,
+            page,
+            before,
+            after,
+            sort_by,
+        } = payload;
+
+        let program = validate_pubkey(program_id)?.to_bytes().to_vec();
+        let (page, pagination) = self.paginate_request(&limit, &page, &before, &after)?;
+        let (sort_direction, sort_column) = create_sorting(sort_by.unwrap_or_default());
+
+        let models = self
+            .dao
+            .get_transactions_by_program(
+                program,
+                self.max_readable_slot().await?,
                 &pagination,
                 page.limit,
                 sort_direction,
@@ -91,8 +391,132 @@ impl ApiContract for Api {
         let transactions: Vec<Transaction> = models.into_iter().map(Transaction::from).collect();
         Ok(Api::build_transaction_response(
             transactions,
+            None,
             page.limit,
             &pagination,
         ))
     }
+
+    async fn get_block(self: &Api, payload: GetBlock) -> Result<Block, ApiError> {
+        let GetBlock { slot } = payload;
+        self.dao
+            .get_block(slot as i64)
+            .await?
+            .map(Block::from)
+            .ok_or(ApiError::BlockNotFound(slot))
+    }
+
+    async fn get_latest_indexed_block(self: &Api) -> Result<Option<Block>, ApiError> {
+        Ok(self.dao.get_latest_indexed_block().await?.map(Block::from))
+    }
+
+    async fn get_transfer_volume(
+        self: &Api,
+        payload: GetTransferVolume,
+    ) -> Result<TransferVolume, ApiError> {
+        let GetTransferVolume {
+            mint_address,
+            start,
+            end,
+            interval,
+        } = payload;
+
+        let mint = validate_pubkey(mint_address.clone())?.to_bytes().to_vec();
+        let start = parse_day(&start).ok_or_else(|| ApiError::InvalidDate("start".to_string()))?;
+        let end = parse_day(&end).ok_or_else(|| ApiError::InvalidDate("end".to_string()))?;
+        if start > end {
+            return Err(ApiError::InvalidInput(
+                "start must not be after end".to_string(),
+            ));
+        }
+        let interval = interval.unwrap_or_else(|| DEFAULT_VOLUME_INTERVAL.to_string());
+        let interval = interval.trim().to_string();
+        if interval.is_empty()
+            || !interval
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == ' ')
+        {
+            return Err(ApiError::InvalidInput(format!(
+                "Invalid interval: '{}'",
+                interval
+            )));
+        }
+
+        let start = Utc.from_utc_datetime(&start.and_hms_opt(0, 0, 0).unwrap());
+        let end = Utc.from_utc_datetime(&end.and_hms_opt(0, 0, 0).unwrap()) + Duration::days(1);
+        // One extra bucket is read to tell a full response from a truncated one.
+        let models = self
+            .dao
+            .get_transfer_volume(
+                mint,
+                start,
+                end,
+                interval.clone(),
+                self.max_readable_slot().await?,
+                MAX_VOLUME_BUCKETS + 1,
+            )
+            .await?;
+        if models.len() as u64 > MAX_VOLUME_BUCKETS {
+            return Err(ApiError::InvalidInput(format!(
+                "More than {} buckets, use a larger interval or a shorter range",
+                MAX_VOLUME_BUCKETS
+            )));
+        }
+
+        Ok(TransferVolume {
+            mint_address,
+            interval,
+            buckets: models.into_iter().map(VolumeBucket::from).collect(),
+        })
+    }
+
+    async fn get_activity_histogram(
+        self: &Api,
+        payload: GetActivityHistogram,
+    ) -> Result<ActivityHistogram, ApiError> {
+        let GetActivityHistogram {
+            address,
+            start,
+            end,
+        } = payload;
+
+        let address_bytes = validate_pubkey(address.clone())?.to_bytes().to_vec();
+        let start = parse_day(&start).ok_or_else(|| ApiError::InvalidDate("start".to_string()))?;
+        let end = parse_day(&end).ok_or_else(|| ApiError::InvalidDate("end".to_string()))?;
+        if start > end {
+            return Err(ApiError::InvalidInput(
+                "start must not be after end".to_string(),
+            ));
+        }
+        if (end - start).num_days() >= MAX_ACTIVITY_DAYS {
+            return Err(ApiError::InvalidInput(format!(
+                "More than {} days, use a shorter range",
+                MAX_ACTIVITY_DAYS
+            )));
+        }
+
+        let start = Utc.from_utc_datetime(&start.and_hms_opt(0, 0, 0).unwrap());
+        let end = Utc.from_utc_datetime(&end.and_hms_opt(0, 0, 0).unwrap()) + Duration::days(1);
+        let models = self
+            .dao
+            .get_activity_histogram(address_bytes, start, end, self.max_readable_slot().await?)
+            .await?;
+
+        Ok(ActivityHistogram {
+            address,
+            buckets: models.into_iter().map(ActivityBucket::from).collect(),
+        })
+    }
+
+    async fn get_stats(self: &Api) -> Result<IndexStats, ApiError> {
+        let transfer_stats = self.transfer_stats().await?;
+        let block_stats = self.dao.get_block_stats().await?;
+        Ok(IndexStats {
+            total_transfers: transfer_stats.total_transfers as u64,
+            distinct_mints: transfer_stats.distinct_mints as u64,
+            earliest_block_time: block_stats.earliest_block_time,
+            latest_block_time: block_stats.latest_block_time,
+            last_indexed_slot: block_stats.last_indexed_slot,
+        })
+    }
 }
@@ -1,7 +1,11 @@
+use std::collections::BTreeMap;
+
+use crate::config::SanitizedApiConfig;
 use crate::db::TransactionSorting;
 use crate::error::ApiError;
-use crate::types::Transaction;
+use crate::types::{ActivityBucket, Block, Counterparty, Transaction, VolumeBucket};
 use async_trait::async_trait;
+use dao::generated::sea_orm_active_enums::TokenType;
 use open_rpc_derive::{document_rpc, rpc};
 use open_rpc_schema::schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
@@ -11,8 +15,44 @@ mod api_impl;
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema, Default)]
 #[serde(deny_unknown_fields, rename_all = "camelCase")]
 pub struct GetTransactionsByAddress {
-    pub source_address: Option<String>,
-    pub destination_address: Option<String>,
+    // The `_address` names these fields had before are still accepted.
+    #[serde(alias = "sourceAddress")]
+    pub source: Option<String>,
+    #[serde(alias = "destinationAddress")]
+    pub destination: Option<String>,
+    // Matches transfers in either direction, can't be combined with the two fields above.
+    pub participant_address: Option<String>,
+    #[serde(alias = "mintAddress")]
+    pub mint: Option<String>,
+    // Transfers of any of these mints, at most 100 per request. Can't be combined with `mint`.
+    pub mint_addresses: Option<Vec<String>>,
+    pub limit: Option<u32>,
+    pub page: Option<u32>,
+    // `YYYY-MM-DD` days, RFC3339 timestamps, or legacy `DD/MM/YYYY` days. `after` must not be
+    // later than `before`, days are inclusive.
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub sort_by: Option<TransactionSorting>,
+    // Also counts every matching transfer into `total`, which can be slow for broad filters.
+    pub count_total: Option<bool>,
+    // `true` returns only transfers of successful transactions, `false` only those of failed
+    // ones. Both are returned when unset.
+    pub success_only: Option<bool>,
+    // Inclusive block height bounds, checked against the indexed blocks. `from_height` must not
+    // be greater than `to_height`.
+    pub from_height: Option<u64>,
+    pub to_height: Option<u64>,
+    // Also returns the raw data of each transfer's instruction, for decoding it client side.
+    pub include_raw: Option<bool>,
+    // Only transfers of this kind, e.g. `token-2022` or `native`.
+    pub token_type: Option<TokenType>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema, Default)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct GetTransactionsByAddresses {
+    // Transfers from or to any of these, at most 100 per request.
+    pub addresses: Vec<String>,
     pub mint_address: Option<String>,
     pub limit: Option<u32>,
     pub page: Option<u32>,
@@ -21,10 +61,105 @@ pub struct GetTransactionsByAddress {
     pub sort_by: Option<TransactionSorting>,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema, Default)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct GetTransactionsByMint {
+    pub mint_address: String,
+    // More mints whose transfers are listed along those of `mint_address`, at most 100 mints in
+    // all.
+    pub mint_addresses: Option<Vec<String>>,
+    pub limit: Option<u32>,
+    pub page: Option<u32>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub sort_by: Option<TransactionSorting>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema, Default)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct GetCounterparties {
+    pub address: String,
+    pub mint_address: Option<String>,
+    pub limit: Option<u32>,
+    pub page: Option<u32>,
+}
+
+// The addresses `address` exchanged tokens with, those with the most transfers first.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Counterparties {
+    pub address: String,
+    // The number of counterparties in this page.
+    pub count: u32,
+    pub limit: u32,
+    pub page: u32,
+    pub items: Vec<Counterparty>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema, Default)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct GetTransactionsByProgram {
+    // The token program that executed the transfers, e.g. Token-2022. Transfers a DEX routes
+    // through a token program are listed under the token program.
+    pub program_id: String,
+    pub limit: Option<u32>,
+    pub page: Option<u32>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub sort_by: Option<TransactionSorting>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct GetBlock {
+    pub slot: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct GetTransferVolume {
+    pub mint_address: String,
+    // Both days are inclusive, as `YYYY-MM-DD` or `DD/MM/YYYY`.
+    pub start: String,
+    pub end: String,
+    // A Postgres interval such as "1 hour", defaults to "1 day".
+    pub interval: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferVolume {
+    pub mint_address: String,
+    pub interval: String,
+    pub buckets: Vec<VolumeBucket>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct GetActivityHistogram {
+    pub address: String,
+    // Both days are inclusive, as `YYYY-MM-DD` or `DD/MM/YYYY`.
+    pub start: String,
+    pub end: String,
+}
+
+// Every day from `start` to `end`, including those without transfers.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ActivityHistogram {
+    pub address: String,
+    pub buckets: Vec<ActivityBucket>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default, JsonSchema)]
 #[serde(default)]
 pub struct TransactionList {
-    pub total: u32,
+    // The number of items in this page.
+    pub count: u32,
+    // The number of transfers matching the query across all pages, only set with `count_total`
+    // or when the first page is empty.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
     pub limit: u32,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub page: Option<u32>,
@@ -35,11 +170,41 @@ pub struct TransactionList {
     pub items: Vec<Transaction>,
 }
 
+// A single page of transfers across all the requested addresses, listed under each address
+// they involve. A transfer between two of the addresses is listed under both.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default, JsonSchema)]
+#[serde(default)]
+pub struct TransactionsByAddresses {
+    // The number of transfers in this page.
+    pub count: u32,
+    pub limit: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub before: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after: Option<String>,
+    pub items: BTreeMap<String, Vec<Transaction>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct IndexStats {
+    // Both counts can be up to `stats_cache_ttl_seconds` old.
+    pub total_transfers: u64,
+    pub distinct_mints: u64,
+    // Unix timestamps of the oldest and newest indexed blocks.
+    pub earliest_block_time: Option<i64>,
+    pub latest_block_time: Option<i64>,
+    pub last_indexed_slot: Option<i64>,
+}
+
 #[document_rpc]
 #[async_trait]
 pub trait ApiContract: Send + Sync + 'static {
     async fn liveness(&self) -> Result<(), ApiError>;
     async fn readiness(&self) -> Result<(), ApiError>;
+    // Admin only, requires `enable_admin_methods`.
+    async fn get_config(&self) -> Result<SanitizedApiConfig, ApiError>;
 
     #[rpc(
         name = "getTransactionsByAddress",
@@ -50,4 +215,85 @@ pub trait ApiContract: Send + Sync + 'static {
         &self,
         payload: GetTransactionsByAddress,
     ) -> Result<TransactionList, ApiError>;
+
+    #[rpc(
+        name = "getTransactionsByAddresses",
+        params = "named",
+        summary = "Get the transactions of several addresses at once"
+    )]
+    async fn get_transactions_by_addresses(
+        &self,
+        payload: GetTransactionsByAddresses,
+    ) -> Result<TransactionsByAddresses, ApiError>;
+
+    #[rpc(
+        name = "getTransactionsByMint",
+        params = "named",
+        summary = "Get the transactions of a mint"
+    )]
+    async fn get_transactions_by_mint(
+        &self,
+        payload: GetTransactionsByMint,
+    ) -> Result<TransactionList, ApiError>;
+
+    #[rpc(
+        name = "getTransactionsByProgram",
+        params = "named",
+        summary = "Get the transactions of a program"
+    )]
+    async fn get_transactions_by_program(
+        &self,
+        payload: GetTransactionsByProgram,
+    ) -> Result<TransactionList, ApiError>;
+
+    #[rpc(
+        name = "getCounterparties",
+        params = "named",
+        summary = "Get the addresses an address sent tokens to or received them from"
+    )]
+    async fn get_counterparties(
+        &self,
+        payload: GetCounterparties,
+    ) -> Result<Counterparties, ApiError>;
+
+    #[rpc(
+        name = "getBlock",
+        params = "named",
+        summary = "Get the metadata of an indexed block"
+    )]
+    async fn get_block(&self, payload: GetBlock) -> Result<Block, ApiError>;
+
+    #[rpc(
+        name = "getLatestIndexedBlock",
+        params = "named",
+        summary = "Get the metadata of the most recently indexed block"
+    )]
+    async fn get_latest_indexed_block(&self) -> Result<Option<Block>, ApiError>;
+
+    #[rpc(
+        name = "getTransferVolume",
+        params = "named",
+        summary = "Get the transfer volume of a mint per time bucket"
+    )]
+    async fn get_transfer_volume(
+        &self,
+        payload: GetTransferVolume,
+    ) -> Result<TransferVolume, ApiError>;
+
+    #[rpc(
+        name = "getActivityHistogram",
+        params = "named",
+        summary = "Get the number of transfers of an address per day"
+    )]
+    async fn get_activity_histogram(
+        &self,
+        payload: GetActivityHistogram,
+    ) -> Result<ActivityHistogram, ApiError>;
+
+    #[rpc(
+        name = "getStats",
+        params = "named",
+        summary = "Get the coverage and freshness of the index"
+    )]
+    async fn get_stats(&self) -> Result<IndexStats, ApiError>;
 }
@@ -0,0 +1,97 @@
+use std::{fs::File, io::BufReader, net::SocketAddr, sync::Arc};
+
+use log::{debug, info};
+use rustls_pemfile::Item;
+use tokio::{
+    io::copy_bidirectional,
+    net::{TcpListener, TcpStream},
+};
+use tokio_rustls::{
+    rustls::{Certificate, PrivateKey, ServerConfig},
+    TlsAcceptor,
+};
+
+use crate::error::ApiError;
+
+fn tls_error(path: &str, msg: impl std::fmt::Display) -> ApiError {
+    ApiError::ConfigurationError {
+        msg: format!("Invalid TLS file {}: {}", path, msg),
+    }
+}
+
+fn read_pem(path: &str) -> Result<Vec<Item>, ApiError> {
+    let file = File::open(path).map_err(|e| tls_error(path, e))?;
+    let mut reader = BufReader::new(file);
+    let mut items = vec![];
+    while let Some(item) = rustls_pemfile::read_one(&mut reader).map_err(|e| tls_error(path, e))? {
+        items.push(item);
+    }
+    Ok(items)
+}
+
+/// Reads the certificate chain, leaf first, from the PEM `CERTIFICATE` blocks of `cert_path`,
+/// and the private key from the first `PRIVATE KEY` (PKCS#8), `RSA PRIVATE KEY` (PKCS#1) or
+/// `EC PRIVATE KEY` (SEC1) block of `key_path`.
+pub fn load_tls_config(cert_path: &str, key_path: &str) -> Result<ServerConfig, ApiError> {
+    let certs = read_pem(cert_path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(cert) => Some(Certificate(cert)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if certs.is_empty() {
+        return Err(tls_error(cert_path, "no certificate found"));
+    }
+    let key = read_pem(key_path)?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| tls_error(key_path, "no private key found"))?;
+
+    ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| tls_error(cert_path, e))
+}
+
+// The server only listens over plain TCP, so TLS is terminated here and every connection is
+// relayed to `backend` on the loopback interface.
+pub async fn serve_tls(listener: TcpListener, config: ServerConfig, backend: SocketAddr) {
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+    if let Ok(addr) = listener.local_addr() {
+        info!("Serving TLS on {}", addr);
+    }
+    loop {
+        let (stream, remote_addr) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                debug!("Failed to accept TLS connection: {}", e);
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        tokio::spawn(async move {
+            let mut tls_stream = match acceptor.accept(stream).await {
+                Ok(tls_stream) => tls_stream,
+                Err(e) => {
+                    debug!("TLS handshake with {} failed: {}", remote_addr, e);
+                    return;
+                }
+            };
+            let mut backend_stream = match TcpStream::connect(backend).await {
+                Ok(backend_stream) => backend_stream,
+                Err(e) => {
+                    debug!("Failed to relay TLS connection from {}: {}", remote_addr, e);
+                    return;
+                }
+            };
+            if let Err(e) = copy_bidirectional(&mut tls_stream, &mut backend_stream).await {
+                debug!("TLS connection from {} closed: {}", remote_addr, e);
+            }
+        });
+    }
+}
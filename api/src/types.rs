@@ -1,17 +1,135 @@
-use chrono::NaiveDate;
-use chrono::{DateTime, Utc};
-use dao::generated::token_transfers;
+use std::fmt;
+
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use dao::generated::{blocks, token_transfers, transactions};
+
+use crate::db::{ActivityBucketModel, CounterpartyModel, VolumeBucketModel};
 use schemars::JsonSchema;
-use serde::{Deserialize, Deserializer, Serialize};
+use sea_orm::prelude::Decimal;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+const ISO_8601_FORMAT: &str = "%Y-%m-%d";
+// Accepted for compatibility with clients written before ISO-8601 dates were supported.
+const LEGACY_FORMAT: &str = "%d/%m/%Y";
 
-const FORMAT: &str = "%d/%m/%Y";
+/// Parses a day given as `YYYY-MM-DD` or the legacy `DD/MM/YYYY`.
+pub fn parse_day(s: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(s, ISO_8601_FORMAT)
+        .or_else(|_| NaiveDate::parse_from_str(s, LEGACY_FORMAT))
+        .ok()
+}
 
 fn deserialize_date<'de, D>(deserializer: D) -> Result<NaiveDate, D::Error>
 where
     D: Deserializer<'de>,
 {
     let s = String::deserialize(deserializer)?;
-    NaiveDate::parse_from_str(&s, FORMAT).map_err(serde::de::Error::custom)
+    parse_day(&s).ok_or_else(|| serde::de::Error::custom(format!("invalid date: {}", s)))
+}
+
+/// A `before` or `after` pagination bound. It keeps the format it was given in, so that
+/// responses echo it back the same way.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DateBound {
+    // A whole day, `legacy` when it was given as `DD/MM/YYYY` rather than `YYYY-MM-DD`.
+    Day { date: NaiveDate, legacy: bool },
+    // An RFC3339 timestamp, for bounds within a day.
+    Timestamp(DateTime<Utc>),
+}
+
+impl DateBound {
+    pub fn parse(s: &str) -> Option<DateBound> {
+        if let Ok(timestamp) = DateTime::parse_from_rfc3339(s) {
+            return Some(DateBound::Timestamp(timestamp.with_timezone(&Utc)));
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(s, ISO_8601_FORMAT) {
+            return Some(DateBound::Day {
+                date,
+                legacy: false,
+            });
+        }
+        NaiveDate::parse_from_str(s, LEGACY_FORMAT)
+            .ok()
+            .map(|date| DateBound::Day { date, legacy: true })
+    }
+
+    /// The earliest instant covered by the bound.
+    pub fn start(&self) -> DateTime<Utc> {
+        match self {
+            DateBound::Day { date, .. } => {
+                Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+            }
+            DateBound::Timestamp(timestamp) => *timestamp,
+        }
+    }
+
+    /// The latest instant covered by the bound.
+    pub fn end(&self) -> DateTime<Utc> {
+        match self {
+            DateBound::Day { date, .. } => {
+                Utc.from_utc_datetime(&date.and_hms_opt(23, 59, 59).unwrap())
+            }
+            DateBound::Timestamp(timestamp) => *timestamp,
+        }
+    }
+}
+
+impl fmt::Display for DateBound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DateBound::Day {
+                date,
+                legacy: false,
+            } => write!(f, "{}", date.format(ISO_8601_FORMAT)),
+            DateBound::Day { date, legacy: true } => write!(f, "{}", date.format(LEGACY_FORMAT)),
+            DateBound::Timestamp(timestamp) => write!(f, "{}", timestamp.to_rfc3339()),
+        }
+    }
+}
+
+// Raw token amounts are u64s, and are sent as decimal strings since JavaScript numbers lose
+// precision above 2^53.
+fn serialize_amount<S>(amount: &u64, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_str(amount)
+}
+
+// Accepts the decimal strings `serialize_amount` writes, as well as plain numbers.
+fn deserialize_amount<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Amount {
+        String(String),
+        Number(u64),
+    }
+    match Amount::deserialize(deserializer)? {
+        Amount::String(s) => s.parse::<u64>().map_err(serde::de::Error::custom),
+        Amount::Number(amount) => Ok(amount),
+    }
+}
+
+fn serialize_optional_amount<S>(amount: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match amount {
+        Some(amount) => serialize_amount(amount, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn deserialize_optional_amount<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct Amount(#[serde(deserialize_with = "deserialize_amount")] u64);
+    Ok(Option::<Amount>::deserialize(deserializer)?.map(|Amount(amount)| amount))
 }
 
 #[derive(Deserialize)]
@@ -37,15 +155,78 @@ pub struct Transaction {
     pub source_ata: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub destination_ata: Option<String>,
-    pub amount: i64,
+    #[serde(
+        serialize_with = "serialize_amount",
+        deserialize_with = "deserialize_amount"
+    )]
+    #[schemars(with = "String")]
+    pub amount: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decimals: Option<i16>,
+    // `amount` in whole tokens, as a wallet would display it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ui_amount: Option<f64>,
+    // For Token-2022 mints with a transfer fee, the part of `amount` withheld from the
+    // destination, and what it received.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_optional_amount",
+        deserialize_with = "deserialize_optional_amount"
+    )]
+    #[schemars(with = "Option<String>")]
+    pub fee: Option<u64>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_optional_amount",
+        deserialize_with = "deserialize_optional_amount"
+    )]
+    #[schemars(with = "Option<String>")]
+    pub net_amount: Option<u64>,
+    // Who signed and paid for the transaction, and its fee in lamports. Not stored for
+    // transfers indexed before they were.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee_payer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transaction_fee: Option<i64>,
+    // The transaction's SPL Memo, e.g. the reference of an exchange withdrawal.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
     pub slot: i64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<String>,
     pub block_time: DateTime<Utc>,
+    // `block_time` as the unix timestamp the block reported on chain.
+    pub block_time_unix: i64,
+    // Set for transfers made through CPI, their position among the instruction's inner
+    // instructions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inner_instruction_index: Option<i32>,
+    // The base58 encoded data of the transfer instruction, only returned when asked for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
 }
 
-impl From<token_transfers::Model> for Transaction {
-    fn from(model: token_transfers::Model) -> Self {
+// Amounts are stored as `numeric(20, 0)`, and only ever written from u64s.
+fn raw_amount(amount: Decimal) -> u64 {
+    u64::try_from(amount).unwrap_or_default()
+}
+
+// A transfer along with its row in `transactions`, which is left joined.
+impl From<(token_transfers::Model, Option<transactions::Model>)> for Transaction {
+    fn from((model, transaction): (token_transfers::Model, Option<transactions::Model>)) -> Self {
+        let (error, fee_payer, transaction_fee, memo) = match transaction {
+            Some(transaction) => (
+                transaction.error,
+                transaction.fee_payer,
+                transaction.fee,
+                transaction.memo,
+            ),
+            None => (None, None, None, None),
+        };
+        let amount = raw_amount(model.amount);
+        let fee = model.fee.map(raw_amount);
         Transaction {
             signature: bs58::encode(model.signature).into_string(),
             source_address: bs58::encode(model.source_address).into_string(),
@@ -58,10 +239,113 @@ impl From<token_transfers::Model> for Transaction {
                 .mint_address
                 .map(|mint| bs58::encode(mint).into_string()),
             slot: model.slot,
-            amount: model.amount,
-            error: model.error,
+            amount,
+            decimals: model.decimals,
+            ui_amount: model.ui_amount,
+            fee,
+            net_amount: fee.map(|fee| amount.saturating_sub(fee)),
+            fee_payer: fee_payer.map(|fee_payer| bs58::encode(fee_payer).into_string()),
+            transaction_fee,
+            memo,
+            error,
             block_time: model.block_time.into(),
+            block_time_unix: model.block_time.timestamp(),
             program_address: bs58::encode(model.program_id).into_string(),
+            inner_instruction_index: (model.inner_instruction_index >= 0)
+                .then_some(model.inner_instruction_index),
+            data: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Block {
+    pub slot: i64,
+    pub parent_slot: i64,
+    pub block_height: i64,
+    pub block_time: i64,
+    // Not stored for blocks indexed before the hashes were.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blockhash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_blockhash: Option<String>,
+}
+
+impl From<blocks::Model> for Block {
+    fn from(model: blocks::Model) -> Self {
+        Block {
+            slot: model.slot,
+            parent_slot: model.parent_slot,
+            block_height: model.block_height,
+            block_time: model.block_time,
+            blockhash: model.blockhash.map(|hash| bs58::encode(hash).into_string()),
+            parent_blockhash: model
+                .parent_blockhash
+                .map(|hash| bs58::encode(hash).into_string()),
+        }
+    }
+}
+
+// Only successful transfers are counted. `amount` is a decimal string, since the sum of raw
+// amounts can exceed 64 bits.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct VolumeBucket {
+    pub bucket: DateTime<Utc>,
+    pub amount: String,
+    pub count: i64,
+}
+
+impl From<VolumeBucketModel> for VolumeBucket {
+    fn from(model: VolumeBucketModel) -> Self {
+        VolumeBucket {
+            bucket: model.bucket.into(),
+            amount: model.amount,
+            count: model.count,
+        }
+    }
+}
+
+// The successful transfers from or to an address on the day starting at `bucket`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ActivityBucket {
+    pub bucket: DateTime<Utc>,
+    pub count: i64,
+}
+
+impl From<ActivityBucketModel> for ActivityBucket {
+    fn from(model: ActivityBucketModel) -> Self {
+        ActivityBucket {
+            bucket: model.bucket.into(),
+            count: model.count,
+        }
+    }
+}
+
+// Amounts are raw token amounts of `mint_address`, as decimal strings.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Counterparty {
+    pub address: String,
+    pub mint_address: Option<String>,
+    // Transfers from the queried address to this one.
+    pub outgoing_count: i64,
+    pub outgoing_amount: String,
+    // Transfers from this address to the queried one.
+    pub incoming_count: i64,
+    pub incoming_amount: String,
+}
+
+impl From<CounterpartyModel> for Counterparty {
+    fn from(model: CounterpartyModel) -> Self {
+        Counterparty {
+            address: bs58::encode(model.counterparty).into_string(),
+            mint_address: model
+                .mint_address
+                .map(|mint| bs58::encode(mint).into_string()),
+            outgoing_count: model.outgoing_count,
+            outgoing_amount: model.outgoing_amount,
+            incoming_count: model.incoming_count,
+            incoming_amount: model.incoming_amount,
         }
     }
 }
@@ -1,32 +1,75 @@
 use figment::providers::Format;
-use figment::providers::{Env, Json};
+use figment::providers::{Env, Json, Serialized};
+use figment::value::Value;
 use figment::Figment;
 use git2::Repository;
 use serde::de::DeserializeOwned;
 use std::path::{Path, PathBuf};
+use url::Url;
 
-fn get_relative_git_path(file_path: &str) -> PathBuf {
-    let repo = Repository::discover(".").expect("Failed to discover Git repository");
-    let git_root = repo.workdir().expect("Failed to get Git repository root");
+pub const REDACTED: &str = "REDACTED";
 
-    git_root.join(file_path)
-}
+pub const CONFIG_PATH_ENV: &str = "ITIHAS_CONFIG_PATH";
+const LOCAL_CONFIG_FILE: &str = "local_config.json";
 
+// `ITIHAS_CONFIG_PATH` when set, the config of the repository when run from a checkout, and
+// otherwise `local_config.json` in the current directory, e.g. for a built binary in a container.
 fn get_local_config_file_path() -> PathBuf {
-    let file_path = get_relative_git_path("common/src/local_config.json");
-    if !Path::new(&file_path).exists() {
+    let file_path = match std::env::var_os(CONFIG_PATH_ENV) {
+        Some(path) => PathBuf::from(path),
+        None => match Repository::discover(".")
+            .ok()
+            .and_then(|repo| repo.workdir().map(Path::to_path_buf))
+        {
+            Some(git_root) => git_root.join("common/src").join(LOCAL_CONFIG_FILE),
+            None => PathBuf::from(LOCAL_CONFIG_FILE),
+        },
+    };
+    if !file_path.exists() {
         panic!(
-            "Configuration file does not exist: {}",
-            file_path.to_string_lossy()
+            "ENV=local requires a configuration file, but {} does not exist. Set {} to its path.",
+            file_path.to_string_lossy(),
+            CONFIG_PATH_ENV
         );
     }
     file_path
 }
 
 pub fn load_config_using_env_prefix<T: DeserializeOwned>(env_prefix: &str) -> T {
+    load_config_with_overrides(env_prefix, vec![])
+}
+
+// Like `load_config_using_env_prefix`, with `overrides` (e.g. command-line flags) taking
+// precedence. Their keys are dotted paths such as `database_config.url`.
+pub fn load_config_with_overrides<T: DeserializeOwned>(
+    env_prefix: &str,
+    overrides: Vec<(String, Value)>,
+) -> T {
     let mut config = Figment::new().join(Env::prefixed(env_prefix));
     if let Ok("local") = std::env::var("ENV").as_deref() {
         config = config.join(Json::file(get_local_config_file_path()));
     }
+    for (key, value) in overrides {
+        config = config.merge(Serialized::default(&key, value));
+    }
     config.extract::<T>().unwrap()
 }
+
+// Strips credentials from a connection string before it is logged or returned to a client:
+// the password and every query parameter value (RPC providers pass api keys that way).
+pub fn redact_url(url: &str) -> String {
+    let mut url = match Url::parse(url) {
+        Ok(url) => url,
+        Err(_) => return REDACTED.to_string(),
+    };
+    if url.password().is_some() {
+        let _ = url.set_password(Some(REDACTED));
+    }
+    if url.query().is_some() {
+        let keys: Vec<String> = url.query_pairs().map(|(key, _)| key.into_owned()).collect();
+        url.query_pairs_mut()
+            .clear()
+            .extend_pairs(keys.iter().map(|key| (key.as_str(), REDACTED)));
+    }
+    url.to_string()
+}
@@ -1,18 +1,44 @@
+use std::time::Duration;
+
 use sea_orm::{DatabaseConnection, SqlxPostgresConnector};
 use sqlx::{
     postgres::{PgConnectOptions, PgPoolOptions},
     PgPool,
 };
 
-async fn setup_pg_pool(database_url: &str, max_connections: u32) -> PgPool {
-    let options: PgConnectOptions = database_url.parse().unwrap();
+/// How the Postgres connection pool is sized and how long its connections live.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PoolConfig {
+    pub max_connections: u32,
+    // Kept open even when idle, so the first queries after a quiet period don't wait on new
+    // connections. At most `max_connections`.
+    pub min_connections: u32,
+    // How long a query waits for a free connection before failing.
+    pub acquire_timeout: Duration,
+    // Idle connections above `min_connections` are closed after this long, never when unset.
+    pub idle_timeout: Option<Duration>,
+    pub statement_timeout: Option<Duration>,
+}
+
+async fn setup_pg_pool(database_url: &str, pool_config: &PoolConfig) -> PgPool {
+    let mut options: PgConnectOptions = database_url.parse().unwrap();
+    if let Some(timeout) = pool_config.statement_timeout {
+        // Postgres cancels any statement of these connections that runs longer.
+        options = options.options([("statement_timeout", timeout.as_millis().to_string())]);
+    }
     PgPoolOptions::new()
-        .max_connections(max_connections)
+        .max_connections(pool_config.max_connections)
+        .min_connections(pool_config.min_connections.min(pool_config.max_connections))
+        .acquire_timeout(pool_config.acquire_timeout)
+        .idle_timeout(pool_config.idle_timeout)
         .connect_with(options)
         .await
         .unwrap()
 }
 
-pub async fn setup_database_connection(db_url: String, max_connections: u32) -> DatabaseConnection {
-    SqlxPostgresConnector::from_sqlx_postgres_pool(setup_pg_pool(&db_url, max_connections).await)
+pub async fn setup_database_connection(
+    db_url: String,
+    pool_config: &PoolConfig,
+) -> DatabaseConnection {
+    SqlxPostgresConnector::from_sqlx_postgres_pool(setup_pg_pool(&db_url, pool_config).await)
 }
@@ -1,8 +1,24 @@
 use cadence_macros::is_global_default_set;
 use {
-    cadence::{BufferedUdpMetricSink, QueuingMetricSink, StatsdClient},
+    cadence::{BufferedUdpMetricSink, MetricSink, QueuingMetricSink, StatsdClient},
     cadence_macros::set_global_default,
-    std::net::UdpSocket,
+    hyper::{
+        header::CONTENT_TYPE,
+        service::{make_service_fn, service_fn},
+        Body, Request, Response, Server, StatusCode,
+    },
+    log::{error, info, warn},
+    once_cell::sync::Lazy,
+    prometheus::{
+        CounterVec, Encoder, GaugeVec, HistogramOpts, HistogramVec, Opts, Registry, TextEncoder,
+    },
+    std::{
+        collections::HashMap,
+        convert::Infallible,
+        io,
+        net::{SocketAddr, UdpSocket},
+        sync::Mutex,
+    },
 };
 
 pub fn safe_metric<F: Fn()>(f: F) {
@@ -23,15 +39,208 @@ macro_rules! metric {
     };
 }
 
-pub fn setup_metrics(prefix: &str, uri: Option<String>, port: Option<u16>, env: Option<String>) {
+static PROMETHEUS_REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+// The Prometheus metrics created so far from statsd metrics, by name.
+static PROMETHEUS_METRICS: Lazy<Mutex<HashMap<String, PrometheusMetric>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+enum PrometheusMetric {
+    Counter(CounterVec),
+    Gauge(GaugeVec),
+    Histogram(HistogramVec),
+}
+
+/// The registry served on `/metrics`. Collectors registered here are exported along with the
+/// statsd metrics.
+pub fn prometheus_registry() -> &'static Registry {
+    &PROMETHEUS_REGISTRY
+}
+
+fn sanitize_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// A sink recording the statsd metrics in the Prometheus registry. Counters stay counters,
+/// gauges stay gauges, and timers become histograms in seconds, with the statsd tags as labels.
+pub struct PrometheusSink;
+
+impl PrometheusSink {
+    fn record(metric: &str) -> Option<()> {
+        let mut parts = metric.split('|');
+        let (name, value) = parts.next()?.rsplit_once(':')?;
+        let value = value.parse::<f64>().ok()?;
+        let kind = parts.next()?;
+        let mut labels = parts
+            .filter_map(|part| part.strip_prefix('#'))
+            .flat_map(|tags| tags.split(','))
+            .map(|tag| {
+                let (key, value) = tag.split_once(':').unwrap_or((tag, ""));
+                (sanitize_name(key), value)
+            })
+            .collect::<Vec<_>>();
+        labels.sort();
+        let label_names = labels
+            .iter()
+            .map(|(key, _)| key.as_str())
+            .collect::<Vec<_>>();
+        let label_values = labels.iter().map(|(_, value)| *value).collect::<Vec<_>>();
+
+        let (name, value) = match kind {
+            "ms" => (format!("{}_seconds", sanitize_name(name)), value / 1000.0),
+            "c" | "g" | "h" | "d" => (sanitize_name(name), value),
+            _ => return None,
+        };
+        let mut metrics = PROMETHEUS_METRICS.lock().unwrap();
+        if !metrics.contains_key(&name) {
+            let help = format!("statsd {}", name);
+            let metric = match kind {
+                "c" => CounterVec::new(Opts::new(name.clone(), help), &label_names)
+                    .map(PrometheusMetric::Counter),
+                "g" => GaugeVec::new(Opts::new(name.clone(), help), &label_names)
+                    .map(PrometheusMetric::Gauge),
+                _ => HistogramVec::new(HistogramOpts::new(name.clone(), help), &label_names)
+                    .map(PrometheusMetric::Histogram),
+            }
+            .ok()?;
+            let registered = match &metric {
+                PrometheusMetric::Counter(counter) => {
+                    PROMETHEUS_REGISTRY.register(Box::new(counter.clone()))
+                }
+                PrometheusMetric::Gauge(gauge) => {
+                    PROMETHEUS_REGISTRY.register(Box::new(gauge.clone()))
+                }
+                PrometheusMetric::Histogram(histogram) => {
+                    PROMETHEUS_REGISTRY.register(Box::new(histogram.clone()))
+                }
+            };
+            if let Err(e) = registered {
+                warn!("Failed to register Prometheus metric {}: {}", name, e);
+                return None;
+            }
+            metrics.insert(name.clone(), metric);
+        }
+
+        // Fails when the metric was first emitted with other tags, Prometheus needs the same
+        // labels for every series of a metric.
+        match metrics.get(&name)? {
+            PrometheusMetric::Counter(counter) if value >= 0.0 => counter
+                .get_metric_with_label_values(&label_values)
+                .ok()?
+                .inc_by(value),
+            PrometheusMetric::Gauge(gauge) => gauge
+                .get_metric_with_label_values(&label_values)
+                .ok()?
+                .set(value),
+            PrometheusMetric::Histogram(histogram) => histogram
+                .get_metric_with_label_values(&label_values)
+                .ok()?
+                .observe(value),
+            _ => {}
+        }
+        Some(())
+    }
+}
+
+impl MetricSink for PrometheusSink {
+    fn emit(&self, metric: &str) -> io::Result<usize> {
+        Self::record(metric);
+        Ok(metric.len())
+    }
+}
+
+// Sends every metric to statsd and to Prometheus, whichever are enabled.
+struct FanoutSink {
+    statsd: Option<QueuingMetricSink>,
+    prometheus: Option<PrometheusSink>,
+}
+
+impl MetricSink for FanoutSink {
+    fn emit(&self, metric: &str) -> io::Result<usize> {
+        if let Some(prometheus) = &self.prometheus {
+            prometheus.emit(metric)?;
+        }
+        match &self.statsd {
+            Some(statsd) => statsd.emit(metric),
+            None => Ok(metric.len()),
+        }
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        match &self.statsd {
+            Some(statsd) => statsd.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The registry in the Prometheus text format, as served on `/metrics`.
+pub fn encode_prometheus_metrics() -> String {
+    let mut buffer = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&PROMETHEUS_REGISTRY.gather(), &mut buffer) {
+        error!("Failed to encode Prometheus metrics: {}", e);
+    }
+    String::from_utf8(buffer).unwrap_or_default()
+}
+
+async fn serve_metrics(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if request.uri().path() != "/metrics" {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return Ok(response);
+    }
+    let mut response = Response::new(Body::from(encode_prometheus_metrics()));
+    if let Ok(content_type) = TextEncoder::new().format_type().parse() {
+        response.headers_mut().insert(CONTENT_TYPE, content_type);
+    }
+    Ok(response)
+}
+
+// Serves the registry on `/metrics`, on the current tokio runtime.
+fn start_prometheus_server(port: u16) {
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let server = match Server::try_bind(&addr) {
+        Ok(server) => server,
+        Err(e) => {
+            error!("Failed to serve Prometheus metrics on {}: {}", addr, e);
+            return;
+        }
+    };
+    info!("Serving Prometheus metrics on {}/metrics", addr);
+    tokio::spawn(async move {
+        let make_service =
+            make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(serve_metrics)) });
+        if let Err(e) = server.serve(make_service).await {
+            error!("Prometheus metrics server failed: {}", e);
+        }
+    });
+}
+
+/// Sends metrics to statsd when `uri` or `port` are set, and serves them for Prometheus on
+/// `prometheus_port` when it is.
+pub fn setup_metrics(
+    prefix: &str,
+    uri: Option<String>,
+    port: Option<u16>,
+    env: Option<String>,
+    prometheus_port: Option<u16>,
+) {
     let env = env.clone().unwrap_or_else(|| "dev".to_string());
-    if uri.is_some() || port.is_some() {
+    let statsd = (uri.is_some() || port.is_some()).then(|| {
         let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
         socket.set_nonblocking(true).unwrap();
         let host = (uri.unwrap(), port.unwrap());
         let udp_sink = BufferedUdpMetricSink::from(host, socket).unwrap();
-        let queuing_sink = QueuingMetricSink::from(udp_sink);
-        let builder = StatsdClient::builder(prefix, queuing_sink);
+        QueuingMetricSink::from(udp_sink)
+    });
+    let prometheus = prometheus_port.map(|port| {
+        start_prometheus_server(port);
+        PrometheusSink
+    });
+    if statsd.is_some() || prometheus.is_some() {
+        let builder = StatsdClient::builder(prefix, FanoutSink { statsd, prometheus });
         let client = builder.with_tag("env", env).build();
         set_global_default(client);
     }
@@ -18,6 +18,8 @@ pub struct Model {
     pub parent_slot: i64,
     pub block_height: i64,
     pub block_time: i64,
+    pub blockhash: Option<Vec<u8>>,
+    pub parent_blockhash: Option<Vec<u8>>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveColumn)]
@@ -26,6 +28,8 @@ pub enum Column {
     ParentSlot,
     BlockHeight,
     BlockTime,
+    Blockhash,
+    ParentBlockhash,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DerivePrimaryKey)]
@@ -52,6 +56,8 @@ impl ColumnTrait for Column {
             Self::ParentSlot => ColumnType::BigInteger.def(),
             Self::BlockHeight => ColumnType::BigInteger.def(),
             Self::BlockTime => ColumnType::BigInteger.def(),
+            Self::Blockhash => ColumnType::Binary.def().null(),
+            Self::ParentBlockhash => ColumnType::Binary.def().null(),
         }
     }
 }
@@ -2,5 +2,13 @@
 
 pub mod prelude;
 
+pub mod balance_changes;
 pub mod blocks;
+pub mod checkpoints;
+pub mod failed_transactions;
+pub mod mints;
+pub mod sea_orm_active_enums;
+pub mod skipped_slots;
+pub mod token_accounts;
 pub mod token_transfers;
+pub mod transactions;
@@ -1,4 +1,11 @@
 //! SeaORM Entity. Generated by sea-orm-codegen 0.9.3
 
+pub use super::balance_changes::Entity as BalanceChanges;
 pub use super::blocks::Entity as Blocks;
+pub use super::checkpoints::Entity as Checkpoints;
+pub use super::failed_transactions::Entity as FailedTransactions;
+pub use super::mints::Entity as Mints;
+pub use super::skipped_slots::Entity as SkippedSlots;
+pub use super::token_accounts::Entity as TokenAccounts;
 pub use super::token_transfers::Entity as TokenTransfers;
+pub use super::transactions::Entity as Transactions;
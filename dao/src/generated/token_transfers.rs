@@ -1,5 +1,6 @@
 //! SeaORM Entity. Generated by sea-orm-codegen 0.9.3
 
+use super::sea_orm_active_enums::TokenType;
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
 
@@ -22,10 +23,19 @@ pub struct Model {
     pub destination_ata: Option<Vec<u8>>,
     pub mint_address: Option<Vec<u8>>,
     pub slot: i64,
-    pub amount: i64,
-    pub error: Option<String>,
+    pub amount: Decimal,
     pub block_time: DateTimeWithTimeZone,
     pub created_at: DateTime,
+    pub token_type: TokenType,
+    pub source_owner: Option<Vec<u8>>,
+    pub destination_owner: Option<Vec<u8>>,
+    pub is_self_transfer: bool,
+    pub instruction_index: i32,
+    pub inner_instruction_index: i32,
+    pub ui_amount: Option<f64>,
+    pub decimals: Option<i16>,
+    pub fee: Option<Decimal>,
+    pub data: Option<Vec<u8>>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveColumn)]
@@ -39,9 +49,18 @@ pub enum Column {
     MintAddress,
     Slot,
     Amount,
-    Error,
     BlockTime,
     CreatedAt,
+    TokenType,
+    SourceOwner,
+    DestinationOwner,
+    IsSelfTransfer,
+    InstructionIndex,
+    InnerInstructionIndex,
+    UiAmount,
+    Decimals,
+    Fee,
+    Data,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DerivePrimaryKey)]
@@ -49,18 +68,22 @@ pub enum PrimaryKey {
     Signature,
     SourceAddress,
     DestinationAddress,
+    InstructionIndex,
+    InnerInstructionIndex,
     BlockTime,
 }
 
 impl PrimaryKeyTrait for PrimaryKey {
-    type ValueType = (Vec<u8>, Vec<u8>, Vec<u8>, DateTimeWithTimeZone);
+    type ValueType = (Vec<u8>, Vec<u8>, Vec<u8>, i32, i32, DateTimeWithTimeZone);
     fn auto_increment() -> bool {
         false
     }
 }
 
 #[derive(Copy, Clone, Debug, EnumIter)]
-pub enum Relation {}
+pub enum Relation {
+    Transactions,
+}
 
 impl ColumnTrait for Column {
     type EntityName = Entity;
@@ -74,17 +97,40 @@ impl ColumnTrait for Column {
             Self::DestinationAta => ColumnType::Binary.def().null(),
             Self::MintAddress => ColumnType::Binary.def().null(),
             Self::Slot => ColumnType::BigInteger.def(),
-            Self::Amount => ColumnType::BigInteger.def(),
-            Self::Error => ColumnType::Text.def().null(),
+            Self::Amount => ColumnType::Decimal(Some((20, 0))).def(),
             Self::BlockTime => ColumnType::TimestampWithTimeZone.def(),
             Self::CreatedAt => ColumnType::DateTime.def(),
+            Self::TokenType => TokenType::db_type(),
+            Self::SourceOwner => ColumnType::Binary.def().null(),
+            Self::DestinationOwner => ColumnType::Binary.def().null(),
+            Self::IsSelfTransfer => ColumnType::Boolean.def(),
+            Self::InstructionIndex => ColumnType::Integer.def(),
+            Self::InnerInstructionIndex => ColumnType::Integer.def(),
+            Self::UiAmount => ColumnType::Double.def().null(),
+            Self::Decimals => ColumnType::SmallInteger.def().null(),
+            Self::Fee => ColumnType::Decimal(Some((20, 0))).def().null(),
+            Self::Data => ColumnType::Binary.def().null(),
         }
     }
 }
 
 impl RelationTrait for Relation {
     fn def(&self) -> RelationDef {
-        panic!("No RelationDef")
+        match self {
+            Self::Transactions => Entity::belongs_to(super::transactions::Entity)
+                .from((Column::Signature, Column::BlockTime))
+                .to((
+                    super::transactions::Column::Signature,
+                    super::transactions::Column::BlockTime,
+                ))
+                .into(),
+        }
+    }
+}
+
+impl Related<super::transactions::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Transactions.def()
     }
 }
 
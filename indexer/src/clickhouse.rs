@@ -0,0 +1,210 @@
+use async_trait::async_trait;
+use clickhouse::{Client, Row};
+use sea_orm::ActiveEnum;
+use serde::Serialize;
+use solana_sdk::{bs58, clock::Slot};
+
+use crate::{
+    config::ClickHouseConfig,
+    error::IndexerError,
+    parser::token_type,
+    sink::TransferSink,
+    types::{BlockMetadata, InstructionGroup, Transaction},
+};
+
+// ReplacingMergeTree collapses rows with the same sorting key, so re-indexed blocks are
+// deduplicated the way the Postgres primary keys do it. Addresses are stored base58 encoded.
+const CREATE_TOKEN_TRANSFERS: &str = "
+    CREATE TABLE IF NOT EXISTS token_transfers (
+        signature String,
+        slot UInt64,
+        block_time DateTime('UTC'),
+        program_id String,
+        token_type LowCardinality(String),
+        mint_address Nullable(String),
+        source_address String,
+        destination_address String,
+        source_ata Nullable(String),
+        destination_ata Nullable(String),
+        source_owner Nullable(String),
+        destination_owner Nullable(String),
+        is_self_transfer Bool,
+        amount UInt64,
+        instruction_index UInt8,
+        inner_instruction_index Int16,
+        error Nullable(String)
+    )
+    ENGINE = ReplacingMergeTree
+    PARTITION BY toYYYYMM(block_time)
+    ORDER BY (
+        signature,
+        source_address,
+        destination_address,
+        instruction_index,
+        inner_instruction_index,
+        block_time
+    )
+";
+
+const CREATE_BLOCKS: &str = "
+    CREATE TABLE IF NOT EXISTS blocks (
+        slot UInt64,
+        parent_slot UInt64,
+        block_height UInt64,
+        block_time Int64
+    )
+    ENGINE = ReplacingMergeTree
+    ORDER BY slot
+";
+
+#[derive(Row, Serialize)]
+struct TokenTransferRow {
+    signature: String,
+    slot: u64,
+    block_time: u32,
+    program_id: String,
+    token_type: String,
+    mint_address: Option<String>,
+    source_address: String,
+    destination_address: String,
+    source_ata: Option<String>,
+    destination_ata: Option<String>,
+    source_owner: Option<String>,
+    destination_owner: Option<String>,
+    is_self_transfer: bool,
+    amount: u64,
+    instruction_index: u8,
+    inner_instruction_index: i16,
+    error: Option<String>,
+}
+
+#[derive(Row, Serialize)]
+struct BlockRow {
+    slot: u64,
+    parent_slot: u64,
+    block_height: u64,
+    block_time: i64,
+}
+
+fn encode(bytes: &[u8]) -> String {
+    bs58::encode(bytes).into_string()
+}
+
+fn clickhouse_error(err: clickhouse::error::Error) -> IndexerError {
+    IndexerError::DatabaseError(format!("ClickHouseError: {}", err))
+}
+
+/// Batch-inserts blocks and transfers into ClickHouse. There is no `token_accounts` table to
+/// fall back on, so owners are only known when the transaction carries token balances, and no
+/// UI amounts are stored.
+#[derive(Clone)]
+pub struct ClickHouseSink {
+    client: Client,
+}
+
+impl ClickHouseSink {
+    pub fn new(config: &ClickHouseConfig) -> Self {
+        let mut client = Client::default().with_url(&config.url);
+        if let Some(database) = &config.database {
+            client = client.with_database(database);
+        }
+        if let Some(user) = &config.user {
+            client = client.with_user(user);
+        }
+        if let Some(password) = &config.password {
+            client = client.with_password(password);
+        }
+        ClickHouseSink { client }
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub async fn create_tables(&self) -> Result<(), IndexerError> {
+        for statement in [CREATE_TOKEN_TRANSFERS, CREATE_BLOCKS] {
+            self.client
+                .query(statement)
+                .execute()
+                .await
+                .map_err(clickhouse_error)?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TransferSink for ClickHouseSink {
+    // Reorgs aren't detected in ClickHouse, blocks from abandoned forks are kept.
+    async fn index_block_metadatas(
+        &self,
+        blocks: Vec<&BlockMetadata>,
+    ) -> Result<Vec<Slot>, IndexerError> {
+        // Blocks without a time would be stored as of 1970.
+        let blocks = blocks
+            .into_iter()
+            .filter(|block| block.block_time > 0)
+            .collect::<Vec<_>>();
+        if blocks.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut insert = self
+            .client
+            .insert::<BlockRow>("blocks")
+            .map_err(clickhouse_error)?;
+        for block in blocks {
+            insert
+                .write(&BlockRow {
+                    slot: block.slot,
+                    parent_slot: block.parent_slot,
+                    block_height: block.block_height,
+                    block_time: block.block_time,
+                })
+                .await
+                .map_err(clickhouse_error)?;
+        }
+        insert.end().await.map_err(clickhouse_error)?;
+        Ok(vec![])
+    }
+
+    async fn index_transaction(&self, transactions: &[Transaction]) -> Result<(), IndexerError> {
+        let mut insert = self
+            .client
+            .insert::<TokenTransferRow>("token_transfers")
+            .map_err(clickhouse_error)?;
+        for transaction in transactions {
+            for instruction in transaction
+                .instruction_groups
+                .iter()
+                .flat_map(InstructionGroup::transfers)
+            {
+                insert
+                    .write(&TokenTransferRow {
+                        signature: transaction.signature.to_string(),
+                        slot: transaction.slot,
+                        block_time: transaction.block_time as u32,
+                        program_id: instruction.program_id.to_string(),
+                        token_type: token_type(&instruction.program_id).to_value(),
+                        mint_address: instruction.mint.as_deref().map(encode),
+                        source_address: encode(&instruction.source_address),
+                        destination_address: encode(&instruction.destination_address),
+                        source_ata: instruction.source_ata.as_deref().map(encode),
+                        destination_ata: instruction.destination_ata.as_deref().map(encode),
+                        source_owner: instruction.source_owner.as_deref().map(encode),
+                        destination_owner: instruction.destination_owner.as_deref().map(encode),
+                        is_self_transfer: instruction.is_self_transfer(),
+                        amount: instruction.amount,
+                        instruction_index: instruction.instruction_index,
+                        // -1 marks a top level transfer, as in Postgres.
+                        inner_instruction_index: instruction
+                            .inner_instruction_index
+                            .map_or(-1, i16::from),
+                        error: transaction.error.as_ref().map(|e| e.replace('\0', "")),
+                    })
+                    .await
+                    .map_err(clickhouse_error)?;
+            }
+        }
+        insert.end().await.map_err(clickhouse_error)
+    }
+}
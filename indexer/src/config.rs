@@ -1,29 +1,138 @@
-use common::config::load_config_using_env_prefix;
+use clap::{Parser, Subcommand};
+use common::{
+    config::{load_config_with_overrides, redact_url, REDACTED},
+    db::PoolConfig,
+};
+use figment::value::Value;
 use serde::Deserialize;
+use solana_sdk::pubkey::Pubkey;
+use std::{str::FromStr, time::Duration};
 
-use crate::error::IndexerError;
+use crate::{
+    error::IndexerError,
+    grpc::RpcFallbackPolicy,
+    types::{IndexFilter, MAX_NOTIFY_PAYLOAD_BYTES, MAX_SQL_INSERTS},
+};
 
 #[derive(Deserialize, PartialEq, Debug, Clone, Default)]
 pub struct IndexerConfig {
     pub database_config: DatabaseConfig,
     pub env: Option<String>,
+    // Where statsd metrics are sent, and the port serving them for Prometheus. Metrics are
+    // only collected when either is set.
+    pub metrics_host: Option<String>,
+    pub metrics_port: Option<u16>,
+    pub prometheus_port: Option<u16>,
+    // Serves `/health` and `/readiness` for the live indexer when set.
+    pub health_port: Option<u16>,
     pub rpc_config: RpcConfig,
+    // Size of the Postgres connection pool shared by all workers, defaults to 10.
     pub max_connections: Option<u32>,
+    // How the pool keeps and hands out connections, see `get_pool_config` for the defaults.
+    pub min_connections: Option<u32>,
+    pub acquire_timeout_ms: Option<u64>,
+    pub idle_timeout_seconds: Option<u64>,
     pub account_stream_worker_count: Option<u32>,
     pub max_concurrent_block_fetches: Option<usize>,
+    #[serde(default = "default_max_block_fetch_retries")]
+    pub max_block_fetch_retries: u32,
+    // The live indexer only fetches slots at least this many slots behind the tip, so fewer of
+    // the blocks it indexes are dropped later. Defaults to 0, blocks streamed over gRPC aren't
+    // held back.
+    pub confirmation_depth: Option<u64>,
     pub grpc_url: Option<String>,
     #[serde(default = "default_start_slot")]
     pub start_slot: u64,
+    // Turns the indexer into a one-off backfill of `start_slot..=end_slot` that exits when done.
+    pub end_slot: Option<u64>,
+    // The messenger runs this many block workers and as many transfer workers, and as many
+    // balance change workers when those are indexed. See `get_connections_per_worker` for the
+    // connections they hold and `get_workers` for the upper bound.
     #[serde(default = "default_workers")]
     pub workers: u16,
+    #[serde(default = "default_insert_concurrency")]
+    pub insert_concurrency: usize,
+    // The most rows written by a single INSERT statement, defaults to 5000.
+    pub max_sql_inserts: Option<usize>,
+    // How many blocks are accumulated before they are handed to the workers, defaults to 1.
+    pub block_batch_size: Option<usize>,
+    // How long the live indexer waits for a batch to fill before sending it partially,
+    // defaults to 500.
+    pub block_batch_window_ms: Option<u64>,
     pub index_recent: Option<bool>,
+    // How far the gRPC stream may fall out of order before its gaps are fetched over RPC, see
+    // `get_rpc_fallback_policy`.
+    pub grpc_fallback_gap_slots: Option<u64>,
+    pub grpc_fallback_gap_blocks: Option<usize>,
+    pub grpc_recovery_blocks: Option<usize>,
     pub grpc_x_token: String,
+    // Reads mint accounts over RPC so Token-2022 interest-bearing and scaled UI amount mints
+    // get their real UI amount instead of amount / 10^decimals.
+    #[serde(default)]
+    pub index_scaled_ui_amounts: bool,
+    // Only transfers of these mints and token programs are indexed, when set.
+    #[serde(default)]
+    pub mint_allowlist: Vec<String>,
+    #[serde(default)]
+    pub program_allowlist: Vec<String>,
+    // The transfers of failed transactions didn't happen, so they are left out unless set.
+    #[serde(default)]
+    pub index_failed_transactions: bool,
+    // Records how every transaction changed the balances of token accounts, including moves by
+    // programs whose instructions aren't decoded. The balances come with the transactions, so
+    // block subscriptions still leave out account updates.
+    #[serde(default)]
+    pub index_balance_changes: bool,
+    // Where transfers and block metadata are written, Postgres unless set to "clickhouse".
+    #[serde(default)]
+    pub backend: IndexerBackend,
+    // Whether transfers indexed again keep or replace the stored ones, "ignore" unless set.
+    // `reprocess` always replaces them.
+    #[serde(default)]
+    pub conflict_strategy: ConflictStrategy,
+    pub clickhouse_config: Option<ClickHouseConfig>,
+}
+
+#[derive(Deserialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexerBackend {
+    #[default]
+    Postgres,
+    ClickHouse,
+}
+
+// What indexing a transfer or transaction that is already stored does.
+#[derive(Deserialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictStrategy {
+    // Keeps the stored row, so indexing a block again is harmless.
+    #[default]
+    Ignore,
+    // Overwrites the columns of the stored row outside its key, e.g. so a parser fix applies to
+    // it. `created_at` is kept.
+    Update,
+}
+
+#[derive(Deserialize, PartialEq, Eq, Debug, Clone, Default)]
+pub struct ClickHouseConfig {
+    pub url: String,
+    pub database: Option<String>,
+    pub user: Option<String>,
+    pub password: Option<String>,
 }
 
 fn default_workers() -> u16 {
     100
 }
 
+fn default_insert_concurrency() -> usize {
+    1
+}
+
+fn default_max_block_fetch_retries() -> u32 {
+    10
+}
+
 fn default_start_slot() -> u64 {
     0
 }
@@ -39,6 +148,112 @@ impl IndexerConfig {
             .unwrap()
     }
 
+    // The Postgres channel indexed transfers are announced on, if any.
+    pub fn get_listener_channel(&self) -> Option<String> {
+        self.database_config
+            .get(DATABASE_LISTENER_CHANNEL_KEY)
+            .and_then(|channel| channel.clone().into_string())
+    }
+
+    // The largest payload of a single notification on the listener channel, which Postgres caps
+    // below 8000 bytes.
+    pub fn get_notify_max_payload_bytes(&self) -> usize {
+        self.database_config
+            .get(DATABASE_NOTIFY_MAX_PAYLOAD_BYTES_KEY)
+            .and_then(|bytes| bytes.to_u128())
+            .map_or(MAX_NOTIFY_PAYLOAD_BYTES, |bytes| {
+                (bytes as usize).min(MAX_NOTIFY_PAYLOAD_BYTES)
+            })
+    }
+
+    pub fn get_max_connections(&self) -> u32 {
+        self.max_connections.unwrap_or(10)
+    }
+
+    // Defaults to keeping one connection warm, waiting up to 30 seconds for a free one, and
+    // closing the others after 10 idle minutes. `idle_timeout_seconds = 0` never closes them.
+    pub fn get_pool_config(&self) -> PoolConfig {
+        PoolConfig {
+            max_connections: self.get_max_connections(),
+            min_connections: self.min_connections.unwrap_or(1),
+            acquire_timeout: Duration::from_millis(self.acquire_timeout_ms.unwrap_or(30_000)),
+            idle_timeout: match self.idle_timeout_seconds.unwrap_or(600) {
+                0 => None,
+                seconds => Some(Duration::from_secs(seconds)),
+            },
+            statement_timeout: None,
+        }
+    }
+
+    // The pooled connections one of each kind of worker holds at most: one for the block
+    // worker, `insert_concurrency` for the transfer worker, and one for the balance change
+    // worker when balance changes are indexed.
+    pub fn get_connections_per_worker(&self) -> u32 {
+        1 + self.insert_concurrency.max(1) as u32 + self.index_balance_changes as u32
+    }
+
+    // Workers beyond what the pool can serve only wait on it, so there are at most as many as
+    // give each worker all the connections it can hold. ClickHouse doesn't write through the
+    // pool.
+    pub fn get_workers(&self) -> u16 {
+        let workers = self.workers.max(1);
+        if self.backend != IndexerBackend::Postgres {
+            return workers;
+        }
+        let max_workers = (self.get_max_connections() / self.get_connections_per_worker())
+            .clamp(1, u16::MAX as u32) as u16;
+        workers.min(max_workers)
+    }
+
+    pub fn get_max_sql_inserts(&self) -> usize {
+        self.max_sql_inserts.unwrap_or(MAX_SQL_INSERTS).max(1)
+    }
+
+    pub fn get_block_batch_size(&self) -> usize {
+        self.block_batch_size.unwrap_or(1).max(1)
+    }
+
+    pub fn get_confirmation_depth(&self) -> u64 {
+        self.confirmation_depth.unwrap_or(0)
+    }
+
+    pub fn get_block_batch_window(&self) -> Duration {
+        Duration::from_millis(self.block_batch_window_ms.unwrap_or(500))
+    }
+
+    // Gaps are fetched over RPC as soon as they appear unless a tolerance is set. Setting only
+    // one of the gap bounds leaves the other unbounded.
+    pub fn get_rpc_fallback_policy(&self) -> RpcFallbackPolicy {
+        let (max_gap_slots, max_gap_blocks) =
+            match (self.grpc_fallback_gap_slots, self.grpc_fallback_gap_blocks) {
+                (None, None) => (0, 0),
+                (slots, blocks) => (slots.unwrap_or(u64::MAX), blocks.unwrap_or(usize::MAX)),
+            };
+        RpcFallbackPolicy {
+            max_gap_slots,
+            max_gap_blocks,
+            recovery_blocks: self.grpc_recovery_blocks.unwrap_or(0),
+        }
+    }
+
+    // Every RPC endpoint to fail over between, `rpc_config.urls` when set and otherwise just
+    // `rpc_config.url`.
+    pub fn get_rpc_urls(&self) -> Vec<String> {
+        let urls = self.rpc_config.get(RPC_URLS_KEY);
+        match urls.and_then(|u| u.clone().into_array()) {
+            Some(urls) => urls
+                .into_iter()
+                .map(|url| {
+                    url.into_string().ok_or(IndexerError::ConfigurationError {
+                        msg: format!("RPC endpoints must be strings: {}", RPC_URLS_KEY),
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+            None => vec![self.get_rpc_url()],
+        }
+    }
+
     pub fn get_rpc_url(&self) -> String {
         self.rpc_config
             .get(RPC_URL_KEY)
@@ -49,9 +264,86 @@ impl IndexerConfig {
             .unwrap()
     }
 
+    pub fn get_index_filter(&self) -> Result<IndexFilter, IndexerError> {
+        let parse = |address: &String| {
+            Pubkey::from_str(address).map_err(|_| IndexerError::ConfigurationError {
+                msg: format!("Invalid address in allowlist: {}", address),
+            })
+        };
+        Ok(IndexFilter {
+            mints: self
+                .mint_allowlist
+                .iter()
+                .map(|address| parse(address).map(|mint| mint.to_bytes().to_vec()))
+                .collect::<Result<_, _>>()?,
+            programs: self
+                .program_allowlist
+                .iter()
+                .map(parse)
+                .collect::<Result<_, _>>()?,
+            failed_transactions: self.index_failed_transactions,
+            balance_changes: self.index_balance_changes,
+        })
+    }
+
+    pub fn get_clickhouse_config(&self) -> Result<ClickHouseConfig, IndexerError> {
+        self.clickhouse_config
+            .clone()
+            .ok_or(IndexerError::ConfigurationError {
+                msg: "clickhouse_config is required by the clickhouse backend".to_string(),
+            })
+    }
+
     pub fn get_account_stream_worker_count(&self) -> u32 {
         self.account_stream_worker_count.unwrap_or(2)
     }
+
+    // A copy of the config that is safe to log, with connection strings and tokens redacted.
+    pub fn sanitized(&self) -> IndexerConfig {
+        IndexerConfig {
+            database_config: redact_dict_url(&self.database_config, DATABASE_URL_KEY),
+            rpc_config: redact_dict_urls(
+                &redact_dict_url(&self.rpc_config, RPC_URL_KEY),
+                RPC_URLS_KEY,
+            ),
+            grpc_url: self.grpc_url.as_deref().map(redact_url),
+            grpc_x_token: REDACTED.to_string(),
+            clickhouse_config: self.clickhouse_config.as_ref().map(|clickhouse_config| {
+                ClickHouseConfig {
+                    url: redact_url(&clickhouse_config.url),
+                    password: clickhouse_config
+                        .password
+                        .as_ref()
+                        .map(|_| REDACTED.to_string()),
+                    ..clickhouse_config.clone()
+                }
+            }),
+            ..self.clone()
+        }
+    }
+}
+
+fn redact_dict_url(dict: &figment::value::Dict, key: &str) -> figment::value::Dict {
+    let mut dict = dict.clone();
+    if let Some(url) = dict.get(key).and_then(|u| u.clone().into_string()) {
+        dict.insert(key.to_string(), redact_url(&url).into());
+    }
+    dict
+}
+
+fn redact_dict_urls(dict: &figment::value::Dict, key: &str) -> figment::value::Dict {
+    let mut dict = dict.clone();
+    if let Some(urls) = dict.get(key).and_then(|u| u.clone().into_array()) {
+        let urls = urls
+            .into_iter()
+            .map(|url| match url.into_string() {
+                Some(url) => redact_url(&url),
+                None => REDACTED.to_string(),
+            })
+            .collect::<Vec<_>>();
+        dict.insert(key.to_string(), urls.into());
+    }
+    dict
 }
 
 // Types and constants used for Figment configuration items.
@@ -59,10 +351,83 @@ pub type DatabaseConfig = figment::value::Dict;
 
 pub const DATABASE_URL_KEY: &str = "url";
 pub const DATABASE_LISTENER_CHANNEL_KEY: &str = "listener_channel";
+pub const DATABASE_NOTIFY_MAX_PAYLOAD_BYTES_KEY: &str = "notify_max_payload_bytes";
 pub const RPC_URL_KEY: &str = "url";
+pub const RPC_URLS_KEY: &str = "urls";
 
 pub type RpcConfig = figment::value::Dict;
 
+/// Command-line flags for one-off runs, which take precedence over the environment and the
+/// local config.
+#[derive(Parser, Debug, Clone, Default)]
+#[clap(about = "Indexes SPL token transfers")]
+pub struct IndexerArgs {
+    #[clap(long)]
+    pub start_slot: Option<u64>,
+    #[clap(long)]
+    pub end_slot: Option<u64>,
+    // Replaces every RPC endpoint, including the failover ones.
+    #[clap(long)]
+    pub rpc_url: Option<String>,
+    #[clap(long)]
+    pub grpc_url: Option<String>,
+    #[clap(long)]
+    pub database_url: Option<String>,
+    // Prints the transfers parsed from the block of this slot and exits, without indexing.
+    #[clap(long, value_name = "SLOT")]
+    pub dry_run: Option<u64>,
+    #[clap(subcommand)]
+    pub command: Option<IndexerCommand>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum IndexerCommand {
+    /// Fetches the slots in the range that have transactions in the failed_transactions table
+    /// again, indexes them and exits. Only the transactions that still fail stay recorded.
+    Reprocess {
+        #[clap(long, value_name = "SLOT")]
+        from: u64,
+        #[clap(long, value_name = "SLOT")]
+        to: u64,
+    },
+}
+
+impl IndexerArgs {
+    fn overrides(&self) -> Vec<(String, Value)> {
+        let mut overrides = vec![];
+        if let Some(start_slot) = self.start_slot {
+            overrides.push(("start_slot".to_string(), start_slot.into()));
+        }
+        if let Some(end_slot) = self.end_slot {
+            overrides.push(("end_slot".to_string(), end_slot.into()));
+        }
+        if let Some(rpc_url) = &self.rpc_url {
+            overrides.push((
+                format!("rpc_config.{}", RPC_URL_KEY),
+                rpc_url.clone().into(),
+            ));
+            overrides.push((
+                format!("rpc_config.{}", RPC_URLS_KEY),
+                vec![rpc_url.clone()].into(),
+            ));
+        }
+        if let Some(grpc_url) = &self.grpc_url {
+            overrides.push(("grpc_url".to_string(), grpc_url.clone().into()));
+        }
+        if let Some(database_url) = &self.database_url {
+            overrides.push((
+                format!("database_config.{}", DATABASE_URL_KEY),
+                database_url.clone().into(),
+            ));
+        }
+        overrides
+    }
+}
+
 pub fn setup_config() -> IndexerConfig {
-    load_config_using_env_prefix("INDEXER_")
+    setup_config_with_args(&IndexerArgs::default())
+}
+
+pub fn setup_config_with_args(args: &IndexerArgs) -> IndexerConfig {
+    load_config_with_overrides("INDEXER_", args.overrides())
 }
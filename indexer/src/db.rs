@@ -1,57 +1,273 @@
-use std::{sync::Arc, thread::sleep, time::Duration};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use cadence_macros::statsd_count;
+use cadence_macros::{statsd_count, statsd_time};
 use common::metric;
-use sea_orm::{sea_query::Expr, DatabaseConnection, FromQueryResult, TransactionTrait};
+use futures::{stream::FuturesUnordered, TryStreamExt};
+use sea_orm::{
+    sea_query::Expr, ColumnTrait, DatabaseConnection, FromQueryResult, Iterable, QueryFilter,
+    QueryOrder, TransactionTrait,
+};
 
-use chrono::{DateTime, NaiveDateTime, Utc};
-use dao::generated::{blocks, token_transfers};
-use log::{debug, error};
+use chrono::{DateTime, Utc};
+use dao::generated::{
+    balance_changes, blocks, checkpoints, failed_transactions, skipped_slots, token_accounts,
+    token_transfers, transactions,
+};
+use log::{debug, error, warn};
 use sea_orm::{
-    sea_query::OnConflict, ConnectionTrait, DatabaseTransaction, EntityTrait, QuerySelect,
-    QueryTrait, Set,
+    prelude::Decimal,
+    sea_query::{IntoIden, OnConflict},
+    ConnectionTrait, DatabaseTransaction, EntityTrait, QuerySelect, QueryTrait, Set, Statement,
+};
+use serde_json::json;
+use solana_sdk::{
+    bs58,
+    clock::{Slot, UnixTimestamp},
+    pubkey::Pubkey,
 };
+use tokio::time::sleep;
 
 use crate::{
+    config::ConflictStrategy,
     error::IndexerError,
-    parser::parse_block_state_update,
-    types::{BlockInfo, BlockMetadata, StateUpdate, Transaction, MAX_SQL_INSERTS},
+    mints::{MintCache, MintInfo},
+    parser::{parse_block_state_update, token_type},
+    types::{
+        BalanceChange, BlockInfo, BlockMetadata, InstructionGroup, ParseFailure, StateUpdate,
+        Transaction, MAX_NOTIFY_PAYLOAD_BYTES, MAX_SQL_INSERTS,
+    },
 };
 
+// The row of the `checkpoints` table holding the slot the live indexer resumes after.
+const INDEXED_SLOT_CHECKPOINT: &str = "indexed_slot";
+
+// Block times are unix seconds. Blocks without one report 0, which isn't stored as 1970 where it
+// would show up in date queries.
+fn block_datetime(block_time: UnixTimestamp) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp(block_time, 0).filter(|_| block_time > 0)
+}
+
+// Leaves out the transactions `block_datetime` has no time for, borrowing `transactions` when
+// they all have one.
+fn with_block_times(transactions: &[Transaction]) -> Cow<'_, [Transaction]> {
+    if transactions
+        .iter()
+        .all(|transaction| block_datetime(transaction.block_time).is_some())
+    {
+        return Cow::Borrowed(transactions);
+    }
+    Cow::Owned(
+        transactions
+            .iter()
+            .filter(|transaction| {
+                if block_datetime(transaction.block_time).is_some() {
+                    return true;
+                }
+                warn!(
+                    "Skipping transaction {} in slot {} with block time {}",
+                    transaction.signature, transaction.slot, transaction.block_time
+                );
+                metric! {
+                    statsd_count!("invalid_block_time", 1);
+                }
+                false
+            })
+            .cloned()
+            .collect(),
+    )
+}
+
+// Joins the JSON `entries` into arrays of at most `max_bytes`. An entry that doesn't fit on its
+// own is left out.
+fn pack_notifications(entries: Vec<String>, max_bytes: usize) -> Vec<String> {
+    let mut payloads = Vec::new();
+    let mut payload = String::new();
+    for entry in entries {
+        // The brackets and the separating comma.
+        if entry.len() + 2 > max_bytes {
+            warn!(
+                "Dropping a notification of {} bytes: {}",
+                entry.len(),
+                entry
+            );
+            metric! {
+                statsd_count!("notification_dropped", 1);
+            }
+            continue;
+        }
+        if !payload.is_empty() && payload.len() + entry.len() + 2 > max_bytes {
+            payload.push(']');
+            payloads.push(std::mem::take(&mut payload));
+        }
+        payload.push(if payload.is_empty() { '[' } else { ',' });
+        payload.push_str(&entry);
+    }
+    if !payload.is_empty() {
+        payload.push(']');
+        payloads.push(payload);
+    }
+    payloads
+}
+
 #[derive(FromQueryResult)]
 pub struct SlotModel {
     // Postgres do not support u64 as return type. We need to use i64 and cast it to u64.
     pub slot: Option<i64>,
 }
 
+// Blocks from sinks and tests that don't carry their hash are stored without it.
+fn decode_blockhash(blockhash: &str) -> Option<Vec<u8>> {
+    bs58::decode(blockhash)
+        .into_vec()
+        .ok()
+        .filter(|hash| !hash.is_empty())
+}
+
+// Reports what a committed database transaction wrote and how long it took, from `begin` to
+// `commit`.
+fn report_indexed(operation: &str, blocks: usize, transactions: usize, started_at: Instant) {
+    metric! {
+        statsd_count!("blocks_indexed", blocks as i64);
+        statsd_count!("transactions_indexed", transactions as i64);
+        statsd_time!("db_transaction", started_at.elapsed(), "operation" => operation);
+    }
+}
+
 #[derive(Clone)]
 pub struct Dao {
     pub db: Arc<DatabaseConnection>,
+    // Set when scaled UI amounts are indexed.
+    pub mint_cache: Option<Arc<MintCache>>,
+    // How many connections a single `TransferSink::index_transaction` call inserts on at once.
+    pub insert_concurrency: usize,
+    // The most rows written by a single INSERT statement.
+    pub max_sql_inserts: usize,
+    // Indexed transfers are announced on this Postgres channel with NOTIFY, when set.
+    pub listener_channel: Option<String>,
+    // The largest payload of a single notification.
+    pub notify_max_payload_bytes: usize,
+    pub conflict_strategy: ConflictStrategy,
 }
 
 impl Dao {
     pub fn new(db: DatabaseConnection) -> Self {
-        Dao { db: Arc::new(db) }
+        Dao {
+            db: Arc::new(db),
+            mint_cache: None,
+            insert_concurrency: 1,
+            max_sql_inserts: MAX_SQL_INSERTS,
+            listener_channel: None,
+            notify_max_payload_bytes: MAX_NOTIFY_PAYLOAD_BYTES,
+            conflict_strategy: ConflictStrategy::Ignore,
+        }
+    }
+
+    pub fn with_conflict_strategy(mut self, conflict_strategy: ConflictStrategy) -> Self {
+        self.conflict_strategy = conflict_strategy;
+        self
+    }
+
+    // Completes the upsert on `key` according to the conflict strategy, `columns` being the ones
+    // it updates.
+    fn on_conflict<C: IntoIden>(
+        &self,
+        key: impl IntoIterator<Item = C>,
+        columns: impl IntoIterator<Item = C>,
+    ) -> OnConflict {
+        let mut on_conflict = OnConflict::columns(key);
+        match self.conflict_strategy {
+            ConflictStrategy::Ignore => on_conflict.do_nothing(),
+            ConflictStrategy::Update => on_conflict.update_columns(columns),
+        };
+        on_conflict
+    }
+
+    pub fn with_listener_channel(mut self, listener_channel: Option<String>) -> Self {
+        self.listener_channel = listener_channel;
+        self
+    }
+
+    pub fn with_notify_max_payload_bytes(mut self, notify_max_payload_bytes: usize) -> Self {
+        self.notify_max_payload_bytes = notify_max_payload_bytes.min(MAX_NOTIFY_PAYLOAD_BYTES);
+        self
+    }
+
+    pub fn with_max_sql_inserts(mut self, max_sql_inserts: usize) -> Self {
+        self.max_sql_inserts = max_sql_inserts.max(1);
+        self
+    }
+
+    pub fn with_insert_concurrency(mut self, insert_concurrency: usize) -> Self {
+        self.insert_concurrency = insert_concurrency.max(1);
+        self
+    }
+
+    pub fn with_mint_cache(mut self, mint_cache: Arc<MintCache>) -> Self {
+        self.mint_cache = Some(mint_cache);
+        self
     }
 
     fn get_db(&self) -> &DatabaseConnection {
         &self.db
     }
 
-    pub async fn index_block(&self, block: &BlockInfo) -> Result<(), IndexerError> {
+    /// Returns the slots that need to be fetched again, as `index_block_metadatas` does.
+    pub async fn index_block(&self, block: &BlockInfo) -> Result<Vec<Slot>, IndexerError> {
+        let started_at = Instant::now();
+        let mints = self.fetch_mints(&block.transactions).await;
         let txn = self.db.begin().await?;
-        self.index_block_metadatas_without_commit(&txn, vec![&block.metadata])
-            .await?;
-        self.index_transaction_update(&txn, parse_block_state_update(block)?)
+        let slots_to_refetch = self.index_block_without_commit(&txn, block, &mints).await?;
+        txn.commit().await?;
+        report_indexed("index_block", 1, block.transactions.len(), started_at);
+        Ok(slots_to_refetch)
+    }
+
+    /// Indexes a block that was fetched again, replacing the parse failures recorded for its
+    /// slot with the ones left after parsing it again. Returns the slots that need to be fetched
+    /// again, as `index_block_metadatas` does.
+    pub async fn reprocess_block(&self, block: &BlockInfo) -> Result<Vec<Slot>, IndexerError> {
+        let started_at = Instant::now();
+        let mints = self.fetch_mints(&block.transactions).await;
+        let txn = self.db.begin().await?;
+        failed_transactions::Entity::delete_many()
+            .filter(failed_transactions::Column::Slot.eq(block.metadata.slot as i64))
+            .exec(&txn)
             .await?;
+        let slots_to_refetch = self.index_block_without_commit(&txn, block, &mints).await?;
         txn.commit().await?;
-        Ok(())
+        report_indexed("reprocess_block", 1, block.transactions.len(), started_at);
+        Ok(slots_to_refetch)
     }
 
-    pub async fn index_block_batches(&self, block_batch: Vec<BlockInfo>) {
+    async fn index_block_without_commit(
+        &self,
+        txn: &DatabaseTransaction,
+        block: &BlockInfo,
+        mints: &HashMap<Vec<u8>, MintInfo>,
+    ) -> Result<Vec<Slot>, IndexerError> {
+        let slots_to_refetch = self
+            .index_block_metadatas_without_commit(txn, vec![&block.metadata])
+            .await?;
+        self.index_transaction_update(txn, parse_block_state_update(block)?, mints)
+            .await?;
+        self.record_parse_failures_without_commit(txn, &block.parse_failures)
+            .await?;
+        self.index_balance_changes_without_commit(txn, &block.balance_changes)
+            .await?;
+        Ok(slots_to_refetch)
+    }
+
+    /// Retries `index_block_batch` until it succeeds, and returns the slots it found need to be
+    /// fetched again.
+    pub async fn index_block_batches(&self, block_batch: Vec<BlockInfo>) -> Vec<Slot> {
         loop {
             match self.index_block_batch(&block_batch).await {
-                Ok(()) => return,
+                Ok(slots_to_refetch) => return slots_to_refetch,
                 Err(e) => {
                     let start_block = block_batch.first().unwrap().metadata.slot;
                     let end_block = block_batch.last().unwrap().metadata.slot;
@@ -60,45 +276,172 @@ impl Dao {
                         start_block, end_block, e
                     );
                     statsd_count!("block_index_error", 1);
-                    sleep(Duration::from_secs(1));
+                    sleep(Duration::from_secs(1)).await;
                 }
             }
         }
     }
 
-    pub async fn index_block_batch(&self, block_batch: &[BlockInfo]) -> Result<(), IndexerError> {
+    /// Writes the blocks and their rows in one transaction, so a reorg found on the way is
+    /// removed along with them. Returns the slots that need to be fetched again, as
+    /// `index_block_metadatas` does.
+    pub async fn index_block_batch(
+        &self,
+        block_batch: &[BlockInfo],
+    ) -> Result<Vec<Slot>, IndexerError> {
+        let started_at = Instant::now();
+        let mints = self
+            .fetch_mints(block_batch.iter().flat_map(|block| &block.transactions))
+            .await;
         let tx = self.db.begin().await?;
         let block_metadatas: Vec<&BlockMetadata> =
             block_batch.iter().map(|b| &b.metadata).collect();
-        self.index_block_metadatas_without_commit(&tx, block_metadatas)
+        let slots_to_refetch = self
+            .index_block_metadatas_without_commit(&tx, block_metadatas)
             .await?;
         let mut state_updates = Vec::new();
         for block in block_batch {
             state_updates.push(parse_block_state_update(block)?);
         }
-        self.index_transaction_update(&tx, StateUpdate::merge_updates(state_updates))
+        self.index_transaction_update(&tx, StateUpdate::merge_updates(state_updates), &mints)
+            .await?;
+        let parse_failures = block_batch
+            .iter()
+            .flat_map(|block| block.parse_failures.iter().cloned())
+            .collect::<Vec<_>>();
+        self.record_parse_failures_without_commit(&tx, &parse_failures)
+            .await?;
+        let balance_changes = block_batch
+            .iter()
+            .flat_map(|block| block.balance_changes.iter().cloned())
+            .collect::<Vec<_>>();
+        self.index_balance_changes_without_commit(&tx, &balance_changes)
             .await?;
         tx.commit().await?;
-        Ok(())
+        let transaction_count = block_batch.iter().map(|b| b.transactions.len()).sum();
+        report_indexed(
+            "index_block_batch",
+            block_batch.len(),
+            transaction_count,
+            started_at,
+        );
+        Ok(slots_to_refetch)
     }
 
+    /// Returns the slots whose indexed block was replaced by a different block on the canonical
+    /// chain, which need to be fetched again.
     pub async fn index_block_metadatas(
         &self,
         blocks: Vec<&BlockMetadata>,
-    ) -> Result<(), IndexerError> {
+    ) -> Result<Vec<Slot>, IndexerError> {
+        let started_at = Instant::now();
+        let block_count = blocks.len();
         let tx = self.db.begin().await?;
-        self.index_block_metadatas_without_commit(&tx, blocks)
+        let slots_to_refetch = self
+            .index_block_metadatas_without_commit(&tx, blocks)
             .await?;
         tx.commit().await?;
-        Ok(())
+        report_indexed("index_block_metadatas", block_count, 0, started_at);
+        Ok(slots_to_refetch)
+    }
+
+    // Removes the indexed blocks, and their transfers, that `blocks` show to be on an abandoned
+    // fork. Blocks stored between a block and its parent are not on its chain, and neither is a
+    // stored block with a different hash than the one now at its slot. Returns the parents that
+    // were removed, since their canonical blocks are still missing.
+    async fn remove_orphaned_blocks_without_commit(
+        &self,
+        txn: &DatabaseTransaction,
+        blocks: &[&BlockMetadata],
+    ) -> Result<Vec<Slot>, IndexerError> {
+        let (Some(first_slot), Some(last_slot)) = (
+            blocks.iter().map(|block| block.parent_slot).min(),
+            blocks.iter().map(|block| block.slot).max(),
+        ) else {
+            return Ok(vec![]);
+        };
+        let stored_blocks = blocks::Entity::find()
+            .filter(blocks::Column::Slot.between(first_slot as i64, last_slot as i64))
+            .all(txn)
+            .await?
+            .into_iter()
+            .map(|block| (block.slot as Slot, block.blockhash))
+            .collect::<BTreeMap<_, _>>();
+        // Blocks indexed without their hash can't be compared.
+        let hash_differs = |slot: Slot, hash: Option<Vec<u8>>| {
+            matches!(
+                (stored_blocks.get(&slot), hash),
+                (Some(Some(stored_hash)), Some(hash)) if *stored_hash != hash
+            )
+        };
+
+        let mut orphaned_slots = BTreeSet::new();
+        let mut slots_to_refetch = BTreeSet::new();
+        for block in blocks.iter().filter(|block| block.parent_slot < block.slot) {
+            orphaned_slots.extend(
+                stored_blocks
+                    .range(block.parent_slot + 1..block.slot)
+                    .map(|(slot, _)| *slot),
+            );
+            if hash_differs(block.slot, decode_blockhash(&block.blockhash)) {
+                orphaned_slots.insert(block.slot);
+            }
+            if hash_differs(block.parent_slot, decode_blockhash(&block.parent_blockhash)) {
+                orphaned_slots.insert(block.parent_slot);
+                slots_to_refetch.insert(block.parent_slot);
+            }
+        }
+        if orphaned_slots.is_empty() {
+            return Ok(vec![]);
+        }
+
+        warn!(
+            "Chain reorg, removing the blocks at slots {:?} from an abandoned fork",
+            orphaned_slots
+        );
+        metric! {
+            statsd_count!("chain_reorg", 1);
+            statsd_count!("orphaned_blocks", orphaned_slots.len() as i64);
+        }
+        let orphaned_slots = orphaned_slots
+            .into_iter()
+            .map(|slot| slot as i64)
+            .collect::<Vec<_>>();
+        token_transfers::Entity::delete_many()
+            .filter(token_transfers::Column::Slot.is_in(orphaned_slots.clone()))
+            .exec(txn)
+            .await?;
+        transactions::Entity::delete_many()
+            .filter(transactions::Column::Slot.is_in(orphaned_slots.clone()))
+            .exec(txn)
+            .await?;
+        balance_changes::Entity::delete_many()
+            .filter(balance_changes::Column::Slot.is_in(orphaned_slots.clone()))
+            .exec(txn)
+            .await?;
+        blocks::Entity::delete_many()
+            .filter(blocks::Column::Slot.is_in(orphaned_slots))
+            .exec(txn)
+            .await?;
+        Ok(slots_to_refetch.into_iter().collect())
     }
 
     pub async fn index_block_metadatas_without_commit(
         &self,
         txn: &DatabaseTransaction,
         blocks: Vec<&BlockMetadata>,
-    ) -> Result<(), IndexerError> {
-        for block_chunk in blocks.chunks(MAX_SQL_INSERTS) {
+    ) -> Result<Vec<Slot>, IndexerError> {
+        let slots_to_refetch = self
+            .remove_orphaned_blocks_without_commit(txn, &blocks)
+            .await?;
+
+        // Blocks without a time still tell which slots were skipped and which blocks they
+        // replace, but aren't stored themselves.
+        let timed_blocks = blocks
+            .iter()
+            .filter(|block| block_datetime(block.block_time).is_some())
+            .collect::<Vec<_>>();
+        for block_chunk in timed_blocks.chunks(self.max_sql_inserts) {
             let block_models: Vec<blocks::ActiveModel> = block_chunk
                 .iter()
                 .map(|block| {
@@ -107,14 +450,22 @@ impl Dao {
                         parent_slot: Set(block.parent_slot as i64),
                         block_time: Set(block.block_time),
                         block_height: Set(block.block_height as i64),
+                        blockhash: Set(decode_blockhash(&block.blockhash)),
+                        parent_blockhash: Set(decode_blockhash(&block.parent_blockhash)),
                     })
                 })
                 .collect::<Result<Vec<blocks::ActiveModel>, IndexerError>>()?;
 
+            // The primary key includes `block_time` because Timescale partitions by it, so a
+            // different block at the same slot is removed as orphaned first rather than conflicting.
+            // Blocks indexed again fill in the hashes of rows from before they were stored.
             let query = blocks::Entity::insert_many(block_models)
                 .on_conflict(
                     OnConflict::columns([blocks::Column::Slot, blocks::Column::BlockTime])
-                        .do_nothing()
+                        .update_columns([
+                            blocks::Column::Blockhash,
+                            blocks::Column::ParentBlockhash,
+                        ])
                         .to_owned(),
                 )
                 .build(txn.get_database_backend());
@@ -124,6 +475,167 @@ impl Dao {
             }
         }
 
+        // The slots between a block and its parent were skipped by their leaders. Recording them
+        // tells them apart from slots that are missing because they weren't indexed.
+        let skipped_slots = blocks
+            .iter()
+            .flat_map(|block| block.parent_slot + 1..block.slot)
+            .map(|slot| skipped_slots::ActiveModel {
+                slot: Set(slot as i64),
+            })
+            .collect::<Vec<_>>();
+        for slot_chunk in skipped_slots.chunks(self.max_sql_inserts) {
+            let query = skipped_slots::Entity::insert_many(slot_chunk.to_vec())
+                .on_conflict(
+                    OnConflict::column(skipped_slots::Column::Slot)
+                        .do_nothing()
+                        .to_owned(),
+                )
+                .build(txn.get_database_backend());
+            if let Err(e) = txn.execute(query).await {
+                error!("Failed to execute skipped slot insert: {:?}", e);
+                return Err(IndexerError::from(e));
+            }
+        }
+        // A slot recorded as skipped by a block from an abandoned fork can have a block after all.
+        let slots = blocks
+            .iter()
+            .map(|block| block.slot as i64)
+            .collect::<Vec<_>>();
+        skipped_slots::Entity::delete_many()
+            .filter(skipped_slots::Column::Slot.is_in(slots))
+            .exec(txn)
+            .await?;
+
+        Ok(slots_to_refetch)
+    }
+
+    /// Adds a transaction that could not be parsed to the `failed_transactions` table.
+    pub async fn record_parse_failure(&self, failure: &ParseFailure) -> Result<(), IndexerError> {
+        let txn = self.db.begin().await?;
+        self.record_parse_failures_without_commit(&txn, std::slice::from_ref(failure))
+            .await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    /// The slots in `[from_slot, to_slot]` with transactions in the `failed_transactions` table,
+    /// in ascending order.
+    pub async fn fetch_failed_slots(
+        &self,
+        from_slot: Slot,
+        to_slot: Slot,
+    ) -> Result<Vec<Slot>, IndexerError> {
+        let slots = failed_transactions::Entity::find()
+            .select_only()
+            .column(failed_transactions::Column::Slot)
+            .distinct()
+            .filter(failed_transactions::Column::Slot.between(from_slot as i64, to_slot as i64))
+            .order_by_asc(failed_transactions::Column::Slot)
+            .into_model::<SlotModel>()
+            .all(self.get_db())
+            .await?;
+        Ok(slots
+            .into_iter()
+            .filter_map(|model| model.slot.map(|slot| slot as Slot))
+            .collect())
+    }
+
+    pub async fn record_parse_failures_without_commit(
+        &self,
+        txn: &DatabaseTransaction,
+        failures: &[ParseFailure],
+    ) -> Result<(), IndexerError> {
+        for failure_chunk in failures.chunks(self.max_sql_inserts) {
+            let failure_models = failure_chunk
+                .iter()
+                .map(|failure| failed_transactions::ActiveModel {
+                    signature: Set(failure
+                        .signature
+                        .map(|signature| signature.as_ref().to_vec())),
+                    slot: Set(failure.slot as i64),
+                    error: Set(failure.error.clone()),
+                    ..Default::default()
+                })
+                .collect::<Vec<_>>();
+            let query = failed_transactions::Entity::insert_many(failure_models)
+                .on_conflict(
+                    OnConflict::column(failed_transactions::Column::Signature)
+                        .do_nothing()
+                        .to_owned(),
+                )
+                .build(txn.get_database_backend());
+            if let Err(e) = txn.execute(query).await {
+                error!("Failed to execute failed transaction insert: {:?}", e);
+                return Err(IndexerError::from(e));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn index_balance_changes(
+        &self,
+        balance_changes: &[BalanceChange],
+    ) -> Result<(), IndexerError> {
+        let txn = self.db.begin().await?;
+        self.index_balance_changes_without_commit(&txn, balance_changes)
+            .await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    // A block indexed again writes the same changes, which are kept as they are.
+    pub async fn index_balance_changes_without_commit(
+        &self,
+        txn: &DatabaseTransaction,
+        balance_changes: &[BalanceChange],
+    ) -> Result<(), IndexerError> {
+        let balance_change_models = balance_changes
+            .iter()
+            .filter_map(|change| {
+                let Some(datetime_utc) = block_datetime(change.block_time) else {
+                    warn!(
+                        "Skipping balance change of {} in slot {} with block time {}",
+                        change.signature, change.slot, change.block_time
+                    );
+                    metric! {
+                        statsd_count!("invalid_block_time", 1);
+                    }
+                    return None;
+                };
+                Some(balance_changes::ActiveModel {
+                    signature: Set(Into::<[u8; 64]>::into(change.signature).to_vec()),
+                    account: Set(change.account.clone()),
+                    slot: Set(change.slot as i64),
+                    block_time: Set(datetime_utc.into()),
+                    mint: Set(change.mint.clone()),
+                    owner: Set(change.owner.clone()),
+                    decimals: Set(change.decimals as i16),
+                    pre_amount: Set(Decimal::from(change.pre_amount)),
+                    post_amount: Set(Decimal::from(change.post_amount)),
+                    delta: Set(Decimal::from_i128_with_scale(change.delta(), 0)),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for balance_change_chunk in balance_change_models.chunks(self.max_sql_inserts) {
+            let query = balance_changes::Entity::insert_many(balance_change_chunk.to_vec())
+                .on_conflict(
+                    OnConflict::columns([
+                        balance_changes::Column::Signature,
+                        balance_changes::Column::Account,
+                        balance_changes::Column::BlockTime,
+                    ])
+                    .do_nothing()
+                    .to_owned(),
+                )
+                .build(txn.get_database_backend());
+            if let Err(e) = txn.execute(query).await {
+                error!("Failed to execute balance change insert: {:?}", e);
+                return Err(IndexerError::from(e));
+            }
+        }
         Ok(())
     }
 
@@ -131,10 +643,197 @@ impl Dao {
         &self,
         transactions: &[Transaction],
     ) -> Result<(), IndexerError> {
+        let started_at = Instant::now();
+        let mints = self.fetch_mints(transactions).await;
         let txn = self.db.begin().await?;
-        self.index_transactions_without_commit(&txn, transactions)
+        self.index_transactions_without_commit(&txn, transactions, &mints)
             .await?;
         txn.commit().await?;
+        report_indexed("index_transaction", 0, transactions.len(), started_at);
+        Ok(())
+    }
+
+    // Splits `transactions` into up to `concurrency` chunks and indexes each in its own database
+    // transaction, concurrently. Every chunk holds a pooled connection until it commits, and
+    // chunks that succeeded stay committed when another one fails.
+    pub async fn index_transactions_concurrently(
+        &self,
+        transactions: &[Transaction],
+        concurrency: usize,
+    ) -> Result<(), IndexerError> {
+        if transactions.is_empty() {
+            return Ok(());
+        }
+        let concurrency = concurrency.max(1);
+        let chunk_size = transactions.len().div_ceil(concurrency);
+        let mut chunks = transactions
+            .chunks(chunk_size)
+            .map(|chunk| Box::pin(self.index_transaction(chunk)))
+            .collect::<FuturesUnordered<_>>();
+        while chunks.try_next().await?.is_some() {}
+        Ok(())
+    }
+
+    // Records the token account owners seen in `transactions` and looks up the owners the
+    // transactions themselves could not identify. Accounts that are still unknown are left out.
+    async fn resolve_token_account_owners(
+        &self,
+        txn: &DatabaseTransaction,
+        transactions: &[Transaction],
+    ) -> Result<HashMap<Vec<u8>, Vec<u8>>, IndexerError> {
+        let mut owners = HashMap::new();
+        let mut unresolved = HashSet::new();
+        for instruction in transactions
+            .iter()
+            .flat_map(|transaction| transaction.instruction_groups.iter())
+            .flat_map(InstructionGroup::transfers)
+        {
+            for (address, owner) in [
+                (&instruction.source_address, &instruction.source_owner),
+                (
+                    &instruction.destination_address,
+                    &instruction.destination_owner,
+                ),
+            ] {
+                match owner {
+                    Some(owner) => {
+                        owners.insert(address.clone(), owner.clone());
+                    }
+                    None => {
+                        unresolved.insert(address.clone());
+                    }
+                }
+            }
+        }
+
+        // Concurrent inserts upsert the accounts in the same order, so they can't deadlock on
+        // each other's row locks.
+        let mut accounts = owners.iter().collect::<Vec<_>>();
+        accounts.sort();
+        let account_models = accounts
+            .into_iter()
+            .map(|(address, owner)| token_accounts::ActiveModel {
+                address: Set(address.clone()),
+                owner: Set(owner.clone()),
+            })
+            .collect::<Vec<_>>();
+        for account_chunk in account_models.chunks(self.max_sql_inserts) {
+            let query = token_accounts::Entity::insert_many(account_chunk.to_vec())
+                .on_conflict(
+                    OnConflict::column(token_accounts::Column::Address)
+                        .update_column(token_accounts::Column::Owner)
+                        .to_owned(),
+                )
+                .build(txn.get_database_backend());
+            if let Err(e) = txn.execute(query).await {
+                error!("Failed to execute token account insert: {:?}", e);
+                return Err(IndexerError::from(e));
+            }
+        }
+
+        let unresolved = unresolved
+            .into_iter()
+            .filter(|address| !owners.contains_key(address))
+            .collect::<Vec<_>>();
+        for address_chunk in unresolved.chunks(self.max_sql_inserts) {
+            let known_accounts = token_accounts::Entity::find()
+                .filter(token_accounts::Column::Address.is_in(address_chunk.to_vec()))
+                .all(txn)
+                .await?;
+            for account in known_accounts {
+                owners.insert(account.address, account.owner);
+            }
+        }
+
+        Ok(owners)
+    }
+
+    // Reads the mints of `transactions` through the mint cache. Mints that can't be read are
+    // left out, so their transfers are stored without a UI amount. Reading a mint can take RPC
+    // calls, so this runs before the database transaction that writes the transfers is opened.
+    async fn fetch_mints<'a>(
+        &self,
+        transactions: impl IntoIterator<Item = &'a Transaction>,
+    ) -> HashMap<Vec<u8>, MintInfo> {
+        let mut mints = HashMap::new();
+        let mint_cache = match &self.mint_cache {
+            Some(mint_cache) => mint_cache,
+            None => return mints,
+        };
+        let addresses = transactions
+            .into_iter()
+            .flat_map(|transaction| transaction.instruction_groups.iter())
+            .flat_map(InstructionGroup::transfers)
+            .filter_map(|instruction| instruction.mint.clone())
+            .collect::<HashSet<_>>();
+        for address in addresses {
+            let mint = match Pubkey::try_from(address.as_slice()) {
+                Ok(mint) => mint,
+                Err(_) => continue,
+            };
+            match mint_cache.get_mint(self.get_db(), mint).await {
+                Ok(mint_info) => {
+                    mints.insert(address, mint_info);
+                }
+                Err(e) => {
+                    warn!("Failed to fetch mint {}: {:?}", mint, e);
+                    metric! {
+                        statsd_count!("mint_fetch_error", 1);
+                    }
+                }
+            }
+        }
+        mints
+    }
+
+    // Writes a `transactions` row for every transaction with a transfer. Transfers refer to it
+    // by signature and block time, so it goes in first.
+    async fn index_transaction_metadata_without_commit(
+        &self,
+        txn: &DatabaseTransaction,
+        transactions: &[Transaction],
+    ) -> Result<(), IndexerError> {
+        let transaction_models = transactions
+            .iter()
+            .filter(|transaction| {
+                transaction
+                    .instruction_groups
+                    .iter()
+                    .any(|group| group.transfers().next().is_some())
+            })
+            .filter_map(|transaction| {
+                let datetime_utc = block_datetime(transaction.block_time)?;
+                Some(transactions::ActiveModel {
+                    signature: Set(Into::<[u8; 64]>::into(transaction.signature).to_vec()),
+                    slot: Set(transaction.slot as i64),
+                    block_time: Set(datetime_utc.into()),
+                    error: Set(transaction.error.as_ref().map(|e| e.replace('\0', ""))),
+                    fee: Set(Some(transaction.fee as i64)),
+                    fee_payer: Set(Some(transaction.fee_payer.to_bytes().to_vec())),
+                    // Postgres text can't hold NUL characters.
+                    memo: Set(transaction.memo.as_ref().map(|memo| memo.replace('\0', ""))),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for transaction_chunk in transaction_models.chunks(self.max_sql_inserts) {
+            let query = transactions::Entity::insert_many(transaction_chunk.to_vec())
+                .on_conflict(self.on_conflict(
+                    [
+                        transactions::Column::Signature,
+                        transactions::Column::BlockTime,
+                    ],
+                    [
+                        transactions::Column::Slot,
+                        transactions::Column::Error,
+                        transactions::Column::Fee,
+                        transactions::Column::FeePayer,
+                        transactions::Column::Memo,
+                    ],
+                ))
+                .build(txn.get_database_backend());
+            txn.execute(query).await?;
+        }
         Ok(())
     }
 
@@ -142,63 +841,106 @@ impl Dao {
         &self,
         txn: &DatabaseTransaction,
         transactions: &[Transaction],
+        mints: &HashMap<Vec<u8>, MintInfo>,
     ) -> Result<(), IndexerError> {
+        let transactions = with_block_times(transactions);
+        let token_account_owners = self
+            .resolve_token_account_owners(txn, &transactions)
+            .await?;
+        self.index_transaction_metadata_without_commit(txn, &transactions)
+            .await?;
         let transaction_models = transactions
             .iter()
             .flat_map(|transaction| {
                 transaction
                     .instruction_groups
                     .iter()
-                    .map(move |instruction_group| {
-                        let naive_datetime =
-                            NaiveDateTime::from_timestamp(transaction.block_time, 0);
-                        let datetime_utc: DateTime<Utc> =
-                            DateTime::from_naive_utc_and_offset(naive_datetime, Utc);
+                    .flat_map(InstructionGroup::transfers)
+                    .filter_map(|instruction| {
+                        let datetime_utc = block_datetime(transaction.block_time)?;
+                        let source_owner = token_account_owners
+                            .get(&instruction.source_address)
+                            .cloned();
+                        let destination_owner = token_account_owners
+                            .get(&instruction.destination_address)
+                            .cloned();
+                        let is_self_transfer =
+                            source_owner.is_some() && source_owner == destination_owner;
+                        let ui_amount = instruction
+                            .mint
+                            .as_ref()
+                            .and_then(|mint| mints.get(mint))
+                            .map(|mint| mint.ui_amount(instruction.amount, transaction.block_time))
+                            // Without the mint account, the decimals from the token balances
+                            // still give the unscaled amount.
+                            .or_else(|| {
+                                instruction.decimals.map(|decimals| {
+                                    instruction.amount as f64 / 10f64.powi(decimals as i32)
+                                })
+                            });
 
-                        token_transfers::ActiveModel {
+                        Some(token_transfers::ActiveModel {
                             signature: Set(Into::<[u8; 64]>::into(transaction.signature).to_vec()),
                             slot: Set(transaction.slot as i64),
-                            error: Set(transaction.error.as_ref().map(|e| e.replace('\0', ""))),
                             block_time: Set(datetime_utc.into()),
                             created_at: Set(chrono::Utc::now().naive_utc()),
-                            source_address: Set(instruction_group
-                                .outer_instruction
-                                .source_address
-                                .clone()),
-                            destination_address: Set(instruction_group
-                                .outer_instruction
-                                .destination_address
-                                .clone()),
-                            mint_address: Set(instruction_group.outer_instruction.mint.clone()),
-                            source_ata: Set(instruction_group.outer_instruction.source_ata.clone()),
-                            destination_ata: Set(instruction_group
-                                .outer_instruction
-                                .destination_ata
-                                .clone()),
-                            amount: Set(instruction_group.outer_instruction.amount as i64),
-                            program_id: Set(instruction_group
-                                .outer_instruction
-                                .program_id
-                                .to_bytes()
-                                .to_vec()
-                                .clone()),
-                        }
+                            source_address: Set(instruction.source_address.clone()),
+                            destination_address: Set(instruction.destination_address.clone()),
+                            mint_address: Set(instruction.mint.clone()),
+                            source_ata: Set(instruction.source_ata.clone()),
+                            destination_ata: Set(instruction.destination_ata.clone()),
+                            amount: Set(Decimal::from(instruction.amount)),
+                            token_type: Set(token_type(&instruction.program_id)),
+                            source_owner: Set(source_owner),
+                            destination_owner: Set(destination_owner),
+                            is_self_transfer: Set(is_self_transfer),
+                            instruction_index: Set(instruction.instruction_index as i32),
+                            // -1 marks a top level transfer.
+                            inner_instruction_index: Set(instruction
+                                .inner_instruction_index
+                                .map_or(-1, i32::from)),
+                            ui_amount: Set(ui_amount),
+                            decimals: Set(instruction.decimals.map(i16::from)),
+                            fee: Set(instruction.fee.map(Decimal::from)),
+                            program_id: Set(instruction.program_id.to_bytes().to_vec()),
+                            data: Set(Some(instruction.data.clone())),
+                        })
                     })
             })
             .collect::<Vec<_>>();
 
-        if !transaction_models.is_empty() {
-            let query = token_transfers::Entity::insert_many(transaction_models)
-                .on_conflict(
-                    OnConflict::columns([
+        // Inner transfers can add many rows per transaction, so the insert is chunked to stay
+        // under the parameter limit.
+        let rows_per_insert =
+            (u16::MAX as usize / token_transfers::Column::iter().count()).min(self.max_sql_inserts);
+        for transaction_chunk in transaction_models.chunks(rows_per_insert) {
+            let query = token_transfers::Entity::insert_many(transaction_chunk.to_vec())
+                .on_conflict(self.on_conflict(
+                    [
                         token_transfers::Column::Signature,
                         token_transfers::Column::BlockTime,
                         token_transfers::Column::SourceAddress,
                         token_transfers::Column::DestinationAddress,
-                    ])
-                    .do_nothing()
-                    .to_owned(),
-                )
+                        token_transfers::Column::InstructionIndex,
+                        token_transfers::Column::InnerInstructionIndex,
+                    ],
+                    [
+                        token_transfers::Column::ProgramId,
+                        token_transfers::Column::SourceAta,
+                        token_transfers::Column::DestinationAta,
+                        token_transfers::Column::MintAddress,
+                        token_transfers::Column::Slot,
+                        token_transfers::Column::Amount,
+                        token_transfers::Column::TokenType,
+                        token_transfers::Column::SourceOwner,
+                        token_transfers::Column::DestinationOwner,
+                        token_transfers::Column::IsSelfTransfer,
+                        token_transfers::Column::UiAmount,
+                        token_transfers::Column::Decimals,
+                        token_transfers::Column::Fee,
+                        token_transfers::Column::Data,
+                    ],
+                ))
                 .build(txn.get_database_backend());
 
             if let Err(e) = txn.execute(query).await {
@@ -206,9 +948,93 @@ impl Dao {
                 return Err(IndexerError::from(e));
             }
         }
+        self.notify_transfers(txn, &transactions).await
+    }
+
+    // Announces the signature, mint and slot of every transaction and mint in `transactions` on
+    // the listener channel. They are sent as JSON arrays, each notification holding as many as
+    // fit in `notify_max_payload_bytes`. Postgres delivers them once `txn` commits and drops them
+    // if it is rolled back.
+    async fn notify_transfers(
+        &self,
+        txn: &DatabaseTransaction,
+        transactions: &[Transaction],
+    ) -> Result<(), IndexerError> {
+        let channel = match &self.listener_channel {
+            Some(channel) => channel,
+            None => return Ok(()),
+        };
+        let entries = transactions
+            .iter()
+            .flat_map(|transaction| {
+                transaction
+                    .instruction_groups
+                    .iter()
+                    .flat_map(InstructionGroup::transfers)
+                    .map(|instruction| instruction.mint.clone())
+                    .collect::<BTreeSet<_>>()
+                    .into_iter()
+                    .map(move |mint| {
+                        json!({
+                            "signature": transaction.signature.to_string(),
+                            "mint": mint.map(|mint| bs58::encode(mint).into_string()),
+                            "slot": transaction.slot,
+                        })
+                        .to_string()
+                    })
+            })
+            .collect::<Vec<_>>();
+        let payloads = pack_notifications(entries, self.notify_max_payload_bytes);
+
+        // The channel takes the first parameter.
+        let notifications_per_query = self.max_sql_inserts.min(u16::MAX as usize - 1);
+        for payload_chunk in payloads.chunks(notifications_per_query) {
+            let placeholders = (0..payload_chunk.len())
+                .map(|i| format!("(${})", i + 2))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let mut values = vec![sea_orm::Value::from(channel.clone())];
+            values.extend(payload_chunk.iter().cloned().map(sea_orm::Value::from));
+            let query = Statement::from_sql_and_values(
+                txn.get_database_backend(),
+                &format!(
+                    "SELECT pg_notify($1, payload) FROM (VALUES {}) AS notifications(payload)",
+                    placeholders
+                ),
+                values,
+            );
+            if let Err(e) = txn.execute(query).await {
+                error!("Failed to notify indexed transfers: {:?}", e);
+                return Err(IndexerError::from(e));
+            }
+        }
+        Ok(())
+    }
+
+    /// Records that every block up to `slot` is written, for the indexer to resume after it.
+    pub async fn record_indexed_slot(&self, slot: Slot) -> Result<(), IndexerError> {
+        let query = checkpoints::Entity::insert(checkpoints::ActiveModel {
+            name: Set(INDEXED_SLOT_CHECKPOINT.to_string()),
+            slot: Set(slot as i64),
+        })
+        .on_conflict(
+            OnConflict::column(checkpoints::Column::Name)
+                .update_column(checkpoints::Column::Slot)
+                .to_owned(),
+        )
+        .build(self.get_db().get_database_backend());
+        self.get_db().execute(query).await?;
         Ok(())
     }
 
+    /// The slot last recorded by `record_indexed_slot`.
+    pub async fn fetch_indexed_slot(&self) -> Result<Option<Slot>, IndexerError> {
+        Ok(checkpoints::Entity::find_by_id(INDEXED_SLOT_CHECKPOINT.to_string())
+            .one(self.get_db())
+            .await?
+            .map(|checkpoint| checkpoint.slot as Slot))
+    }
+
     pub async fn fetch_last_indexed_slot(&self) -> Option<i64> {
         loop {
             let context = blocks::Entity::find()
@@ -229,7 +1055,7 @@ impl Dao {
                     metric! {
                         statsd_count!("db_get_slot_error", 1);
                     }
-                    sleep(Duration::from_secs(5));
+                    sleep(Duration::from_secs(5)).await;
                 }
             }
         }
@@ -239,17 +1065,19 @@ impl Dao {
         &self,
         txn: &DatabaseTransaction,
         state_update: StateUpdate,
+        mints: &HashMap<Vec<u8>, MintInfo>,
     ) -> Result<(), IndexerError> {
         if state_update == StateUpdate::default() {
             return Ok(());
         }
         let StateUpdate { transactions } = state_update;
 
-        let transactions_vec = transactions.into_iter().collect::<Vec<_>>();
+        let transactions_vec = transactions.into_values().collect::<Vec<_>>();
 
         debug!("indexing transaction metadatas...");
-        for chunk in transactions_vec.chunks(MAX_SQL_INSERTS) {
-            self.index_transactions_without_commit(txn, chunk).await?;
+        for chunk in transactions_vec.chunks(self.max_sql_inserts) {
+            self.index_transactions_without_commit(txn, chunk, mints)
+                .await?;
         }
 
         Ok(())
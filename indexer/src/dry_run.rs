@@ -0,0 +1,76 @@
+use std::fmt::Write;
+
+use crate::{
+    error::IndexerError,
+    parser::parse_block_state_update,
+    poller::{BlockFetchError, PollerStreamer},
+    rpc::FailoverRpcClient,
+    types::BlockInfo,
+};
+
+/// What indexing `block` would write: its transfers by transaction, and the transactions that
+/// could not be parsed.
+pub fn describe_block(block: &BlockInfo) -> Result<String, IndexerError> {
+    let state_update = parse_block_state_update(block)?;
+    let mut transactions = state_update.transactions.into_values().collect::<Vec<_>>();
+    transactions.sort_by_key(|transaction| transaction.signature.to_string());
+    let transfer_count: usize = transactions
+        .iter()
+        .flat_map(|transaction| &transaction.instruction_groups)
+        .map(|instruction_group| instruction_group.transfers().count())
+        .sum();
+
+    let mut description = String::new();
+    writeln!(
+        description,
+        "Slot {} (parent {}, block time {}): {} transfers in {} transactions, {} unparseable",
+        block.metadata.slot,
+        block.metadata.parent_slot,
+        block.metadata.block_time,
+        transfer_count,
+        transactions.len(),
+        block.parse_failures.len()
+    )
+    .unwrap();
+    for transaction in &transactions {
+        let status = match &transaction.error {
+            Some(error) => format!(" (failed: {})", error),
+            None => String::new(),
+        };
+        writeln!(description, "{}{}", transaction.signature, status).unwrap();
+        for transfer in transaction
+            .instruction_groups
+            .iter()
+            .flat_map(|instruction_group| instruction_group.transfers())
+        {
+            writeln!(description, "  {}", transfer).unwrap();
+        }
+    }
+    for failure in &block.parse_failures {
+        let signature = failure
+            .signature
+            .map(|signature| signature.to_string())
+            .unwrap_or_else(|| "undecodable transaction".to_string());
+        writeln!(description, "Unparseable {}: {}", signature, failure.error).unwrap();
+    }
+    Ok(description)
+}
+
+/// Fetches the block of `slot` over RPC and prints what indexing it would write, without
+/// touching the database.
+pub async fn dry_run(
+    rpc_client: &FailoverRpcClient,
+    slot: u64,
+    max_retries: u32,
+) -> Result<(), IndexerError> {
+    let block = match PollerStreamer::get_block(rpc_client, slot, max_retries).await {
+        Ok(block) => block,
+        Err(BlockFetchError::SlotSkipped) => {
+            println!("Slot {} was skipped and has no block", slot);
+            return Ok(());
+        }
+        Err(BlockFetchError::FetchFailed(e)) => return Err(e),
+    };
+    print!("{}", describe_block(&block)?);
+    Ok(())
+}
@@ -1,14 +1,22 @@
-use std::{collections::HashMap, pin::Pin, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use async_std::stream::StreamExt;
 use async_stream::stream;
-use cadence_macros::statsd_count;
+use cadence_macros::{statsd_count, statsd_gauge};
 use common::metric;
 use futures::{
     future::{select, Either},
     pin_mut, SinkExt, Stream,
 };
-use log::{error, info};
+use log::{error, info, warn};
 use rand::distributions::Alphanumeric;
 use rand::Rng;
 use tokio::time::sleep;
@@ -25,8 +33,207 @@ use crate::{
     types::{BlockInfo, BlockStreamConfig},
 };
 
+// Reconnects back off exponentially up to the maximum, resetting once a subscription succeeds.
+const INITIAL_RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
+/// A random delay between half of `backoff` and `backoff`, so that indexers that lost the same
+/// provider don't all reconnect at once.
+pub fn with_jitter(backoff: Duration) -> Duration {
+    let half = backoff / 2;
+    half + half.mul_f64(rand::thread_rng().gen::<f64>())
+}
+
+/// Hands out blocks in slot order, each one the child of the block before it. Blocks that
+/// arrive ahead of the chain are buffered until the blocks between them have arrived.
+pub struct BlockOrderer {
+    last_slot: u64,
+    // Keyed by parent slot, so the next block is the one whose parent was handed out last.
+    buffered: BTreeMap<u64, BlockInfo>,
+    // Hands out blocks ahead of the chain right away, skipping the slots in between.
+    allow_gaps: bool,
+}
+
+impl BlockOrderer {
+    pub fn new(last_slot: u64, allow_gaps: bool) -> Self {
+        BlockOrderer {
+            last_slot,
+            buffered: BTreeMap::new(),
+            allow_gaps,
+        }
+    }
+
+    /// The slot of the last block handed out.
+    pub fn last_slot(&self) -> u64 {
+        self.last_slot
+    }
+
+    /// The earliest block waiting for the blocks before it, which RPC can fetch.
+    pub fn earliest_buffered_slot(&self) -> Option<u64> {
+        self.buffered
+            .values()
+            .map(|block| block.metadata.slot)
+            .min()
+    }
+
+    /// Adds a block, and returns the blocks that are now ready in slot order.
+    pub fn push(&mut self, block: BlockInfo) -> Vec<BlockInfo> {
+        // A block at or below the last slot was already handed out, by the other stream.
+        if block.metadata.slot <= self.last_slot {
+            return vec![];
+        }
+        // The first block is only used to find the chain when nothing has been indexed yet.
+        if self.allow_gaps || self.last_slot == 0 {
+            self.last_slot = block.metadata.parent_slot;
+        }
+        self.buffered.insert(block.metadata.parent_slot, block);
+
+        let mut ready = vec![];
+        while let Some(block) = self.buffered.remove(&self.last_slot) {
+            self.last_slot = block.metadata.slot;
+            ready.push(block);
+        }
+        // Blocks whose parent is behind the chain can no longer link up with it.
+        self.buffered = self.buffered.split_off(&self.last_slot);
+        ready
+    }
+
+    /// Hands out every buffered block in slot order, leaving gaps where the chain didn't link.
+    pub fn flush(&mut self) -> Vec<BlockInfo> {
+        let mut blocks: Vec<_> = std::mem::take(&mut self.buffered).into_values().collect();
+        blocks.sort_by_key(|block| block.metadata.slot);
+        if let Some(block) = blocks.last() {
+            warn!(
+                "Handing out blocks after slot {} without the blocks before them",
+                self.last_slot
+            );
+            metric! {
+                statsd_count!("block_gap", 1);
+            }
+            self.last_slot = block.metadata.slot;
+        }
+        blocks
+    }
+}
+
+/// When a gap in the gRPC stream is filled over RPC. The default fills every gap right away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RpcFallbackPolicy {
+    /// Gaps of up to this many slots are left for gRPC to fill with late blocks, while no more
+    /// than `max_gap_blocks` gRPC blocks arrived since the gap opened.
+    pub max_gap_slots: u64,
+    pub max_gap_blocks: usize,
+    /// Once falling back, gaps are filled right away until this many gRPC blocks in a row
+    /// linked up with the chain.
+    pub recovery_blocks: usize,
+}
+
+/// Orders the blocks of `grpc_stream`, filling the gaps it leaves with the blocks of
+/// `fetch_rpc_blocks(last_slot, end_slot)`, the slots after `last_slot` up to `end_slot`, as
+/// `policy` allows. Blocks with slot 0 mark a reconnect of the gRPC stream and are skipped.
+pub fn order_with_rpc_fallback<G, F, R>(
+    grpc_stream: G,
+    mut orderer: BlockOrderer,
+    policy: RpcFallbackPolicy,
+    mut fetch_rpc_blocks: F,
+) -> impl Stream<Item = BlockInfo>
+where
+    G: Stream<Item = BlockInfo>,
+    F: FnMut(u64, u64) -> R,
+    R: Stream<Item = BlockInfo>,
+{
+    stream! {
+        pin_mut!(grpc_stream);
+        let mut rpc_poll_stream: Option<Pin<Box<R>>> = None;
+        // Whether gaps are filled over RPC right away, until gRPC has recovered.
+        let mut falling_back = false;
+        // gRPC blocks received since the open gap appeared, and since the chain last had one.
+        let mut gap_blocks = 0;
+        let mut linked_blocks = 0;
+        // Await either the gRPC stream or the RPC block fetching
+        loop {
+            match rpc_poll_stream.as_mut() {
+                Some(rpc_poll_stream_value) => {
+                    match select(grpc_stream.next(), rpc_poll_stream_value.next()).await {
+                        Either::Left((Some(grpc_block), _)) => {
+                            if grpc_block.metadata.slot == 0 {
+                                continue;
+                            }
+                            for block in orderer.push(grpc_block) {
+                                yield block;
+                            }
+                        }
+                        Either::Left((None, _)) => {
+                            panic!("gRPC stream ended unexpectedly");
+                        }
+                        Either::Right((Some(rpc_block), _)) => {
+                            for block in orderer.push(rpc_block) {
+                                yield block;
+                            }
+                        }
+                        Either::Right((None, _)) => {
+                            // The polled slots didn't link up with the buffered blocks, so
+                            // there is nothing left to wait for.
+                            for block in orderer.flush() {
+                                yield block;
+                            }
+                        }
+                    }
+                    if orderer.earliest_buffered_slot().is_none() {
+                        rpc_poll_stream = None;
+                        linked_blocks = 0;
+                    }
+                }
+                None => {
+                    let block = grpc_stream.next().await.expect("gRPC stream ended unexpectedly");
+                    if block.metadata.slot == 0 {
+                        continue;
+                    }
+                    for block in orderer.push(block) {
+                        yield block;
+                    }
+                    match orderer.earliest_buffered_slot() {
+                        Some(buffered_slot) => {
+                            gap_blocks += 1;
+                            linked_blocks = 0;
+                            let gap_slots = buffered_slot - 1 - orderer.last_slot();
+                            if falling_back
+                                || gap_slots > policy.max_gap_slots
+                                || gap_blocks > policy.max_gap_blocks
+                            {
+                                if !falling_back {
+                                    info!("Switching to RPC block fetching");
+                                    metric! {
+                                        statsd_count!("grpc_rpc_fallback", 1);
+                                    }
+                                    falling_back = true;
+                                }
+                                gap_blocks = 0;
+                                rpc_poll_stream = Some(Box::pin(fetch_rpc_blocks(
+                                    orderer.last_slot(),
+                                    buffered_slot - 1,
+                                )));
+                            }
+                        }
+                        None => {
+                            gap_blocks = 0;
+                            linked_blocks += 1;
+                        }
+                    }
+                }
+            }
+            if falling_back && rpc_poll_stream.is_none() && linked_blocks >= policy.recovery_blocks {
+                falling_back = false;
+                info!("Switching back to gRPC block fetching");
+            }
+        }
+    }
+}
+
 pub struct GrpcStreamer {
     config: BlockStreamConfig,
+    // Whether the block subscription is currently open.
+    connected: Arc<AtomicBool>,
 }
 impl Streamer for GrpcStreamer {
     fn load_block_stream(&self, slot: u64) -> Pin<Box<dyn Stream<Item = BlockInfo> + Send + '_>> {
@@ -36,75 +243,49 @@ impl Streamer for GrpcStreamer {
 
 impl GrpcStreamer {
     pub fn new(config: BlockStreamConfig) -> Self {
-        Self { config }
+        Self {
+            config,
+            connected: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Set while the block subscription is open, and cleared as soon as connecting,
+    /// subscribing or the stream fails.
+    pub fn connection_status(&self) -> Arc<AtomicBool> {
+        self.connected.clone()
+    }
+
+    fn set_connected(&self, connected: bool) {
+        self.connected.store(connected, Ordering::Relaxed);
+        metric! {
+            statsd_gauge!("grpc_connected", connected as u64);
+        }
     }
 
     pub fn get_grpc_stream_with_rpc_fallback(
         &self,
-        latest_slot: u64,
+        _latest_slot: u64,
     ) -> impl Stream<Item = BlockInfo> + '_ {
         let rpc_client = self.config.rpc_client.clone();
-        let mut last_indexed_slot = self.config.last_indexed_slot;
         let max_concurrent_block_fetches = self.config.max_concurrent_block_fetches;
+        let max_block_fetch_retries = self.config.max_block_fetch_retries;
         let endpoint = self.config.grpc_url.clone().unwrap();
         let auth_header = self.config.grpc_x_token.clone();
-        stream! {
-            let grpc_stream = self.get_grpc_block_stream(endpoint, auth_header);
-            pin_mut!(grpc_stream);
-            let mut rpc_poll_stream:  Option<Pin<Box<dyn Stream<Item = BlockInfo> + Send>>> = None;
-            // Await either the gRPC stream or the RPC block fetching
-            loop {
-                match rpc_poll_stream.as_mut() {
-                    Some(rpc_poll_stream_value) => {
-                        match select(grpc_stream.next(), rpc_poll_stream_value.next()).await {
-                            Either::Left((Some(grpc_block), _)) => {
-
-                                if grpc_block.metadata.parent_slot == last_indexed_slot || self.config.index_recent {
-                                    last_indexed_slot = grpc_block.metadata.slot;
-                                    yield grpc_block;
-                                    rpc_poll_stream = None;
-                                }
-
-                            }
-                            Either::Left((None, _)) => {
-                                panic!("gRPC stream ended unexpectedly");
-                            }
-                            Either::Right((Some(rpc_block), _)) => {
-                                if rpc_block.metadata.parent_slot == last_indexed_slot {
-                                    last_indexed_slot = rpc_block.metadata.slot;
-                                    yield rpc_block;
-                                }
-                            }
-                            Either::Right((None, _)) => {
-                                rpc_poll_stream = None;
-                                info!("Switching back to gRPC block fetching");
-                            }
-                        }
-                    }
-                    None => {
-                        let block = grpc_stream.next().await.unwrap();
-                        if block.metadata.slot == 0 {
-                            continue;
-                        }
-                        if block.metadata.parent_slot == last_indexed_slot || self.config.index_recent {
-                            last_indexed_slot = block.metadata.slot;
-                            yield block;
-                        } else {
-                            info!("Switching to RPC block fetching");
-                            rpc_poll_stream = Some(Box::pin(PollerStreamer::get_poller_block_stream(
-                                rpc_client.clone(),
-                                last_indexed_slot,
-                                max_concurrent_block_fetches,
-                                Some(block.metadata.slot),
-                            )));
-                        }
-
-                    }
-                }
-
-
-            }
-        }
+        order_with_rpc_fallback(
+            self.get_grpc_block_stream(endpoint, auth_header),
+            BlockOrderer::new(self.config.last_indexed_slot, self.config.index_recent),
+            self.config.rpc_fallback,
+            move |last_slot, end_slot| {
+                PollerStreamer::get_poller_block_stream(
+                    rpc_client.clone(),
+                    last_slot,
+                    max_concurrent_block_fetches,
+                    max_block_fetch_retries,
+                    0,
+                    Some(end_slot),
+                )
+            },
+        )
     }
 
     fn get_grpc_block_stream(
@@ -113,6 +294,7 @@ impl GrpcStreamer {
         auth_header: String,
     ) -> impl Stream<Item = BlockInfo> + '_ {
         stream! {
+            let mut backoff = INITIAL_RECONNECT_BACKOFF;
             loop {
                 let mut grpc_tx;
                 let mut grpc_rx;
@@ -121,12 +303,15 @@ impl GrpcStreamer {
                     let grpc_client =
                         self.build_geyser_client(endpoint.clone(), auth_header.clone()).await;
                     if let Err(e) = grpc_client {
-                        error!("Error connecting to gRPC, waiting one second then retrying connect: {}", e);
+                        self.set_connected(false);
+                        let delay = with_jitter(backoff);
+                        error!("Error connecting to gRPC, retrying connect in {:?}: {}", delay, e);
                         metric! {
                             statsd_count!("grpc_connect_error", 1);
                         }
 
-                        sleep(Duration::from_secs(1)).await;
+                        sleep(delay).await;
+                        backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
                         continue;
                     }
                     let subscription = grpc_client
@@ -134,14 +319,19 @@ impl GrpcStreamer {
                         .subscribe_with_request(Some(self.get_block_subscribe_request()))
                         .await;
                     if let Err(e) = subscription {
-                        error!("Error subscribing to gRPC stream, waiting one second then retrying connect: {}", e);
+                        self.set_connected(false);
+                        let delay = with_jitter(backoff);
+                        error!("Error subscribing to gRPC stream, retrying connect in {:?}: {}", delay, e);
                         metric! {
                             statsd_count!("grpc_subscribe_error", 1);
                         }
-                        sleep(Duration::from_secs(1)).await;
+                        sleep(delay).await;
+                        backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
                         continue;
                     }
                     (grpc_tx, grpc_rx) = subscription.unwrap();
+                    self.set_connected(true);
+                    backoff = INITIAL_RECONNECT_BACKOFF;
                 }
                 while let Some(message) = grpc_rx.next().await {
                     match message {
@@ -178,9 +368,7 @@ impl GrpcStreamer {
                             }
                         },
                         Err(error) => {
-                            error!(
-                                "error in block subscribe, resubscribing in 1 second: {error:?}"
-                            );
+                            error!("error in block subscribe, resubscribing: {error:?}");
                             metric! {
                                 statsd_count!("grpc_resubscribe", 1);
                             }
@@ -188,7 +376,9 @@ impl GrpcStreamer {
                         }
                     }
                 }
-            sleep(Duration::from_secs(1)).await;
+            self.set_connected(false);
+            sleep(with_jitter(backoff)).await;
+            backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
             }
         }
     }
@@ -219,7 +409,7 @@ impl GrpcStreamer {
             blocks: HashMap::from_iter(vec![(
                 self.generate_random_string(20),
                 SubscribeRequestFilterBlocks {
-                    account_include: vec![],
+                    account_include: self.config.account_include.clone(),
                     include_transactions: Some(true),
                     include_accounts: Some(false),
                     include_entries: Some(false),
@@ -0,0 +1,71 @@
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use log::{error, info};
+
+/// What the health endpoint reports on. `grpc_connected` is only set when blocks are streamed
+/// over gRPC, see `GrpcStreamer::connection_status`.
+#[derive(Clone, Default)]
+pub struct IndexerHealth {
+    pub grpc_connected: Option<Arc<AtomicBool>>,
+}
+
+fn response(status: StatusCode, body: &'static str) -> Response<Body> {
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response
+}
+
+/// `/health` answers as long as the indexer runs. `/readiness` fails while the gRPC
+/// subscription is down, blocks then only arrive through the slower RPC fallback.
+pub async fn handle_health_request(
+    health: IndexerHealth,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    Ok(match request.uri().path() {
+        "/health" => response(StatusCode::OK, "ok"),
+        "/readiness" => match &health.grpc_connected {
+            Some(connected) if !connected.load(Ordering::Relaxed) => {
+                response(StatusCode::SERVICE_UNAVAILABLE, "gRPC subscription is down")
+            }
+            _ => response(StatusCode::OK, "ok"),
+        },
+        _ => response(StatusCode::NOT_FOUND, ""),
+    })
+}
+
+// Serves the health endpoints on `port`, on the current tokio runtime.
+pub fn serve_health(port: u16, health: IndexerHealth) {
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let server = match Server::try_bind(&addr) {
+        Ok(server) => server,
+        Err(e) => {
+            error!("Failed to serve health checks on {}: {}", addr, e);
+            return;
+        }
+    };
+    info!("Serving health checks on {}", addr);
+    tokio::spawn(async move {
+        let make_service = make_service_fn(move |_| {
+            let health = health.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    handle_health_request(health.clone(), request)
+                }))
+            }
+        });
+        if let Err(e) = server.serve(make_service).await {
+            error!("Health check server failed: {}", e);
+        }
+    });
+}
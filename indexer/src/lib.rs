@@ -1,9 +1,17 @@
+#[cfg(feature = "clickhouse")]
+pub mod clickhouse;
 pub mod config;
 pub mod db;
+pub mod dry_run;
 pub mod error;
 pub mod grpc;
+pub mod health;
 pub mod messenger;
+pub mod mints;
 pub mod parser;
 pub mod poller;
+pub mod reprocess;
+pub mod rpc;
+pub mod sink;
 pub mod streamer;
 pub mod types;
@@ -1,17 +1,24 @@
-use common::{db::setup_database_connection, init_logger};
-use log::{error, info};
+use clap::Parser;
+use common::{init_logger, metrics::setup_metrics};
+use log::{error, info, warn};
 use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::commitment_config::CommitmentConfig;
 use std::{sync::Arc, time::Duration};
 
 use indexer::{
-    config::setup_config,
-    db::Dao,
+    config::{
+        setup_config_with_args, ConflictStrategy, IndexerArgs, IndexerBackend, IndexerCommand,
+    },
+    dry_run::dry_run,
     error::IndexerError,
     grpc::GrpcStreamer,
+    health::{serve_health, IndexerHealth},
     messenger,
     poller::PollerStreamer,
-    streamer::{continously_index_new_blocks, fetch_block_parent_slot, Streamer},
+    reprocess::reprocess,
+    rpc::FailoverRpcClient,
+    sink::{setup_dao, setup_sink},
+    streamer::{backfill_blocks, continously_index_new_blocks, fetch_block_parent_slot, Streamer},
     types::BlockStreamConfig,
 };
 
@@ -21,16 +28,76 @@ pub mod error;
 pub async fn main() -> Result<(), IndexerError> {
     init_logger();
 
-    let config = setup_config();
-    let dao = Dao::new(setup_database_connection(config.get_database_url(), 10).await);
-
-    let rpc_client = Arc::new(RpcClient::new_with_timeout_and_commitment(
-        config.get_rpc_url(),
-        Duration::from_secs(10),
-        CommitmentConfig::confirmed(),
+    let args = IndexerArgs::parse();
+    let config = setup_config_with_args(&args);
+    info!("Effective config: {:?}", config.sanitized());
+    if config.get_workers() < config.workers {
+        warn!(
+            "Reducing workers from {} to {}, as {} pooled connections can't serve more with {} connections per worker",
+            config.workers,
+            config.get_workers(),
+            config.get_max_connections(),
+            config.get_connections_per_worker()
+        );
+    }
+    if let Some(end_slot) = config.end_slot {
+        if config.start_slot == 0 || config.start_slot > end_slot {
+            return Err(IndexerError::ConfigurationError {
+                msg: format!(
+                    "end_slot {} requires a start_slot between 1 and end_slot, got {}",
+                    end_slot, config.start_slot
+                ),
+            });
+        }
+    }
+    setup_metrics(
+        "indexer",
+        config.metrics_host.clone(),
+        config.metrics_port,
+        config.env.clone(),
+        config.prometheus_port,
+    );
+    let rpc_urls = config.get_rpc_urls();
+    let rpc_client = Arc::new(FailoverRpcClient::new(
+        rpc_urls
+            .iter()
+            .map(|url| {
+                Arc::new(RpcClient::new_with_timeout_and_commitment(
+                    url.clone(),
+                    Duration::from_secs(10),
+                    CommitmentConfig::confirmed(),
+                ))
+            })
+            .collect(),
     ));
+    if let Some(slot) = args.dry_run {
+        return dry_run(&rpc_client, slot, config.max_block_fetch_retries).await;
+    }
+    if let Some(IndexerCommand::Reprocess { from, to }) = args.command {
+        // Parse failures are only recorded in Postgres.
+        if config.backend != IndexerBackend::Postgres {
+            return Err(IndexerError::ConfigurationError {
+                msg: "reprocess requires the postgres backend".to_string(),
+            });
+        }
+        // Reprocessing is how parser fixes reach the indexed blocks, so it replaces their rows.
+        let dao = setup_dao(&config, rpc_client.client())
+            .await
+            .with_conflict_strategy(ConflictStrategy::Update);
+        return reprocess(
+            &rpc_client,
+            &dao,
+            &config.get_index_filter()?,
+            from,
+            to,
+            config.max_block_fetch_retries,
+        )
+        .await;
+    }
+
+    let sink = setup_sink(&config, rpc_client.client()).await?;
 
-    let is_rpc_node_local = config.get_rpc_url().contains("127.0.0.1");
+    let is_rpc_node_local = rpc_urls.iter().all(|url| url.contains("127.0.0.1"));
 
     info!("Starting indexer...");
     // For localnet we can safely use a large batch size to speed up indexing.
@@ -45,36 +112,76 @@ pub async fn main() -> Result<(), IndexerError> {
         }
     };
 
-    let messenger = Arc::new(messenger::Messenger::new(config.clone()));
-    messenger.clone().run(dao.clone());
+    let messenger = Arc::new(
+        messenger::Messenger::new(config.clone()).with_index_filter(config.get_index_filter()?),
+    );
+    messenger.clone().run(sink.clone());
 
+    // Without a start slot, the live indexer resumes after the last slot it confirmed written.
     let mut last_indexed_slot = 0;
     if config.start_slot != 0 {
-        last_indexed_slot = fetch_block_parent_slot(rpc_client.clone(), config.start_slot).await;
+        last_indexed_slot = fetch_block_parent_slot(rpc_client.client(), config.start_slot).await;
+    } else if let Some(indexed_slot) = sink.fetch_indexed_slot().await? {
+        info!("Resuming after indexed slot {}", indexed_slot);
+        last_indexed_slot = indexed_slot;
     }
 
     let block_stream_config = BlockStreamConfig {
         rpc_client: rpc_client.clone(),
         max_concurrent_block_fetches,
+        max_block_fetch_retries: config.max_block_fetch_retries,
+        confirmation_depth: config.get_confirmation_depth(),
+        // Plain transfers don't reference their mint, so only programs can be filtered upstream.
+        account_include: config.program_allowlist.clone(),
         last_indexed_slot,
         grpc_url: config.grpc_url.clone(),
         index_recent: config.index_recent.unwrap_or(true),
+        rpc_fallback: config.get_rpc_fallback_policy(),
         grpc_x_token: config.grpc_x_token.clone(),
     };
 
+    if let Some(end_slot) = config.end_slot {
+        tokio::select! {
+            _ = backfill_blocks(block_stream_config, messenger, end_slot) => {
+                info!("Backfill complete, shutting down indexer...");
+            }
+            _ = tokio::signal::ctrl_c() => {
+                info!("Shutting down indexer before the backfill completed...");
+            }
+        }
+        return Ok(());
+    }
+
+    // The gRPC stream hands out blocks without their parents when `index_recent` is set, so it
+    // only ever tails the chain.
+    let tail_only = config.grpc_url.is_some() && block_stream_config.index_recent;
+    if tail_only && config.start_slot != 0 {
+        warn!(
+            "Ignoring start slot {} since index_recent tails the gRPC stream from the current slot",
+            config.start_slot
+        );
+    }
+    let mut health = IndexerHealth::default();
     let streamer: Box<dyn Streamer + Send + Sync + 'static> = if config.grpc_url.is_some() {
-        Box::new(GrpcStreamer::new(block_stream_config))
-            as Box<dyn Streamer + Send + Sync + 'static>
+        let grpc_streamer = GrpcStreamer::new(block_stream_config);
+        health.grpc_connected = Some(grpc_streamer.connection_status());
+        Box::new(grpc_streamer) as Box<dyn Streamer + Send + Sync + 'static>
     } else {
         Box::new(PollerStreamer::new(block_stream_config))
             as Box<dyn Streamer + Send + Sync + 'static>
     };
+    if let Some(health_port) = config.health_port {
+        serve_health(health_port, health);
+    }
 
     let indexer_handle = tokio::task::spawn(continously_index_new_blocks(
         streamer,
         messenger,
+        sink,
         rpc_client.clone(),
         last_indexed_slot,
+        tail_only,
+        config.get_confirmation_depth(),
     ));
 
     match tokio::signal::ctrl_c().await {
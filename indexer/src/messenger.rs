@@ -1,82 +1,318 @@
-use std::{sync::Arc, thread::sleep, time::Duration};
+use std::{
+    collections::BTreeSet,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use cadence_macros::statsd_count;
 use common::metric;
 use futures::future::join_all;
 use tokio::sync::{
     mpsc::{self},
-    Mutex, Notify,
+    oneshot, Mutex, Notify,
 };
+use tokio::time::sleep;
 
 use crate::{
     config::IndexerConfig,
-    db::Dao,
     error::IndexerError,
-    parser::parse_block_state_update,
-    types::{BlockInfo, BlockMetadata, StateUpdate, Transaction, MAX_SQL_INSERTS},
+    parser::{filter_block, parse_block_state_update},
+    sink::TransferSink,
+    types::{
+        BalanceChange, BlockInfo, BlockMetadata, IndexFilter, ParseFailure, StateUpdate,
+        Transaction,
+    },
 };
 use log::{debug, error, warn};
+use solana_sdk::clock::Slot;
 
 impl Messenger {}
 
+/// Resolves once everything in a batch handed to `send_block_batches` is written, to false when
+/// one of the writes failed.
+pub type BatchCommit = oneshot::Receiver<bool>;
+
+// Shared by the chunks a block batch is split into, the last one written reports the commit.
+#[derive(Debug)]
+struct BatchAck {
+    remaining: AtomicUsize,
+    failed: AtomicBool,
+    commit: std::sync::Mutex<Option<oneshot::Sender<bool>>>,
+    finished: Notify,
+}
+
+impl BatchAck {
+    // Starts with one chunk outstanding, which the sender finishes once every chunk is queued so
+    // the batch can't commit before then.
+    fn new() -> (Arc<Self>, BatchCommit) {
+        let (commit, receiver) = oneshot::channel();
+        let ack = BatchAck {
+            remaining: AtomicUsize::new(1),
+            failed: AtomicBool::new(false),
+            commit: std::sync::Mutex::new(Some(commit)),
+            finished: Notify::new(),
+        };
+        (Arc::new(ack), receiver)
+    }
+
+    fn add_chunk(&self) {
+        self.remaining.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn chunk_done(&self, written: bool) {
+        if !written {
+            self.failed.store(true, Ordering::SeqCst);
+        }
+        if self.remaining.fetch_sub(1, Ordering::SeqCst) == 1 {
+            if let Some(commit) = self.commit.lock().unwrap().take() {
+                let _ = commit.send(!self.failed.load(Ordering::SeqCst));
+            }
+            self.finished.notify_one();
+        }
+    }
+
+    // Returns once every chunk is done, whether or not it was written.
+    async fn finished(&self) {
+        while self.remaining.load(Ordering::SeqCst) > 0 {
+            self.finished.notified().await;
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Batch<T> {
+    rows: Vec<T>,
+    ack: Arc<BatchAck>,
+}
+
+// Holds the batch a worker is writing. When the worker panics before the write finishes, the
+// batch is sent back on its channel for the restarted worker instead of being lost.
+struct InFlightBatch<'a, T> {
+    batch: Batch<T>,
+    requeue: &'a mpsc::UnboundedSender<Batch<T>>,
+}
+
+impl<T> InFlightBatch<'_, T> {
+    fn done(&self, written: bool) {
+        self.batch.ack.chunk_done(written);
+    }
+}
+
+impl<T> Drop for InFlightBatch<'_, T> {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            let _ = self.requeue.send(Batch {
+                rows: std::mem::take(&mut self.batch.rows),
+                ack: self.batch.ack.clone(),
+            });
+        }
+    }
+}
+
+// Runs the worker started by `start_worker` until it returns, starting it again whenever it
+// panics.
+async fn supervise<F, Fut>(name: &'static str, start_worker: F)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    loop {
+        match tokio::spawn(start_worker()).await {
+            Err(e) if e.is_panic() => {
+                error!("The {} worker panicked, restarting it", name);
+                metric! {
+                    statsd_count!("messenger_worker_restart", 1);
+                }
+                sleep(Duration::from_secs(1)).await;
+            }
+            _ => return,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Messenger {
     config: IndexerConfig,
-    transaction_sender: mpsc::UnboundedSender<Vec<Transaction>>,
-    transaction_receiver: Arc<Mutex<mpsc::UnboundedReceiver<Vec<Transaction>>>>,
-    block_sender: mpsc::UnboundedSender<Vec<BlockMetadata>>,
-    block_receiver: Arc<Mutex<mpsc::UnboundedReceiver<Vec<BlockMetadata>>>>,
+    // Block batches waiting for the batches before them to be written.
+    block_batch_sender: mpsc::UnboundedSender<Batch<BlockInfo>>,
+    block_batch_receiver: Arc<Mutex<mpsc::UnboundedReceiver<Batch<BlockInfo>>>>,
+    // The last batch sent to the workers, kept here so that a restarted block batch worker waits
+    // for it too.
+    previous_block_batch: std::sync::Mutex<Option<Arc<BatchAck>>>,
+    transaction_sender: mpsc::UnboundedSender<Batch<Transaction>>,
+    transaction_receiver: Arc<Mutex<mpsc::UnboundedReceiver<Batch<Transaction>>>>,
+    block_sender: mpsc::UnboundedSender<Batch<BlockMetadata>>,
+    block_receiver: Arc<Mutex<mpsc::UnboundedReceiver<Batch<BlockMetadata>>>>,
+    failure_sender: mpsc::UnboundedSender<Batch<ParseFailure>>,
+    failure_receiver: Arc<Mutex<mpsc::UnboundedReceiver<Batch<ParseFailure>>>>,
+    balance_change_sender: mpsc::UnboundedSender<Batch<BalanceChange>>,
+    balance_change_receiver: Arc<Mutex<mpsc::UnboundedReceiver<Batch<BalanceChange>>>>,
     shutdown_notify: Arc<Notify>,
+    // Batches sent to the workers that haven't been written yet.
+    pending_batches: AtomicUsize,
+    index_filter: IndexFilter,
+    // Slots whose indexed block was removed in a reorg, for the streamer to fetch again.
+    slots_to_refetch: std::sync::Mutex<BTreeSet<Slot>>,
 }
 
 impl Messenger {
     pub fn new(config: IndexerConfig) -> Self {
+        let (block_batch_sender, block_batch_receiver) = mpsc::unbounded_channel();
         let (transaction_sender, transaction_receiver) = mpsc::unbounded_channel();
         let (block_sender, block_receiver) = mpsc::unbounded_channel();
+        let (failure_sender, failure_receiver) = mpsc::unbounded_channel();
+        let (balance_change_sender, balance_change_receiver) = mpsc::unbounded_channel();
         let shutdown_notify = Arc::new(Notify::new());
 
         Messenger {
             config,
+            block_batch_sender,
+            block_batch_receiver: Arc::new(Mutex::new(block_batch_receiver)),
+            previous_block_batch: std::sync::Mutex::new(None),
             transaction_sender,
             transaction_receiver: Arc::new(Mutex::new(transaction_receiver)),
             block_sender,
             block_receiver: Arc::new(Mutex::new(block_receiver)),
+            failure_sender,
+            failure_receiver: Arc::new(Mutex::new(failure_receiver)),
+            balance_change_sender,
+            balance_change_receiver: Arc::new(Mutex::new(balance_change_receiver)),
             shutdown_notify,
+            pending_batches: AtomicUsize::new(0),
+            index_filter: IndexFilter::default(),
+            slots_to_refetch: std::sync::Mutex::new(BTreeSet::new()),
         }
     }
 
-    pub fn run(self: Arc<Self>, dao: Dao) {
+    pub fn with_index_filter(mut self, index_filter: IndexFilter) -> Self {
+        self.index_filter = index_filter;
+        self
+    }
+
+    // How many blocks the streamers accumulate before sending them as one batch.
+    pub fn block_batch_size(&self) -> usize {
+        self.config.get_block_batch_size()
+    }
+
+    // How long the live indexer holds a partial batch before sending it anyway.
+    pub fn block_batch_window(&self) -> Duration {
+        self.config.get_block_batch_window()
+    }
+
+    pub fn take_slots_to_refetch(&self) -> Vec<Slot> {
+        std::mem::take(&mut *self.slots_to_refetch.lock().unwrap())
+            .into_iter()
+            .collect()
+    }
+
+    // Waits until the workers have written every batch sent so far, whether or not the writes
+    // succeeded.
+    pub async fn wait_until_idle(&self) {
+        while self.pending_batches.load(Ordering::SeqCst) > 0 {
+            sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    // Every worker is supervised, so a panic while writing a batch restarts the worker and the
+    // batch is written again.
+    pub fn run(self: Arc<Self>, sink: Arc<dyn TransferSink>) {
+        let block_batch_rx = Arc::clone(&self.block_batch_receiver);
         let txn_rx = Arc::clone(&self.transaction_receiver);
         let block_rx = Arc::clone(&self.block_receiver);
+        let failure_rx = Arc::clone(&self.failure_receiver);
+        let balance_change_rx = Arc::clone(&self.balance_change_receiver);
 
         tokio::spawn(async move {
-            let txn_worker_handles = (0..self.config.workers)
+            // Block batches are handed to the other workers one after the other.
+            let block_batch_worker_handle = {
+                let messenger = self.clone();
+                tokio::spawn(supervise("block batch", move || {
+                    messenger
+                        .clone()
+                        .block_batch_worker(Arc::clone(&block_batch_rx))
+                }))
+            };
+
+            let txn_worker_handles = (0..self.config.get_workers())
                 .map(|_| {
-                    tokio::spawn(
-                        self.clone()
-                            .transaction_worker(Arc::clone(&txn_rx), dao.clone()),
-                    )
+                    let (messenger, txn_rx, sink) = (self.clone(), txn_rx.clone(), sink.clone());
+                    tokio::spawn(supervise("transaction", move || {
+                        messenger
+                            .clone()
+                            .transaction_worker(Arc::clone(&txn_rx), sink.clone())
+                    }))
                 })
                 .collect::<Vec<_>>();
 
-            let block_worker_handles = (0..self.config.workers)
+            let block_worker_handles = (0..self.config.get_workers())
                 .map(|_| {
-                    tokio::spawn(
-                        self.clone()
-                            .block_worker(Arc::clone(&block_rx), dao.clone()),
-                    )
+                    let (messenger, block_rx, sink) =
+                        (self.clone(), block_rx.clone(), sink.clone());
+                    tokio::spawn(supervise("block", move || {
+                        messenger
+                            .clone()
+                            .block_worker(Arc::clone(&block_rx), sink.clone())
+                    }))
+                })
+                .collect::<Vec<_>>();
+
+            // Parse failures are rare, so a single worker records them.
+            let failure_worker_handle = {
+                let (messenger, sink) = (self.clone(), sink.clone());
+                tokio::spawn(supervise("parse failure", move || {
+                    messenger
+                        .clone()
+                        .failure_worker(Arc::clone(&failure_rx), sink.clone())
+                }))
+            };
+
+            // Nothing is sent to them unless balance changes are indexed.
+            let balance_change_worker_count = if self.index_filter.balance_changes {
+                self.config.get_workers()
+            } else {
+                0
+            };
+            let balance_change_worker_handles = (0..balance_change_worker_count)
+                .map(|_| {
+                    let (messenger, balance_change_rx, sink) =
+                        (self.clone(), balance_change_rx.clone(), sink.clone());
+                    tokio::spawn(supervise("balance change", move || {
+                        messenger
+                            .clone()
+                            .balance_change_worker(Arc::clone(&balance_change_rx), sink.clone())
+                    }))
                 })
                 .collect::<Vec<_>>();
 
             join_all(txn_worker_handles).await;
             join_all(block_worker_handles).await;
+            join_all(balance_change_worker_handles).await;
+            let _ = failure_worker_handle.await;
+            let _ = block_batch_worker_handle.await;
         });
     }
-    pub async fn send_block_batches(&self, block_batch: Vec<BlockInfo>) {
+
+    // The returned commit lets the caller hold back whatever depends on the blocks being
+    // written, e.g. the indexed slot.
+    pub async fn send_block_batches(&self, mut block_batch: Vec<BlockInfo>) -> BatchCommit {
+        for block in &mut block_batch {
+            filter_block(block, &self.index_filter);
+        }
         loop {
-            match self.send_block_batch(&block_batch).await {
-                Ok(()) => return,
+            let (ack, commit) = BatchAck::new();
+            self.pending_batches.fetch_add(1, Ordering::SeqCst);
+            let sent = self.block_batch_sender.send(Batch {
+                rows: block_batch.clone(),
+                ack,
+            });
+            match sent {
+                Ok(()) => return commit,
                 Err(e) => {
+                    self.pending_batches.fetch_sub(1, Ordering::SeqCst);
                     let start_block = block_batch.first().unwrap().metadata.slot;
                     let end_block = block_batch.last().unwrap().metadata.slot;
                     error!(
@@ -86,65 +322,181 @@ impl Messenger {
                     metric! {
                         statsd_count!("messenger_send_error", 1);
                     }
-                    sleep(Duration::from_secs(1));
+                    sleep(Duration::from_secs(1)).await;
                 }
             }
         }
     }
 
-    pub async fn send_block_batch(&self, block_batch: &[BlockInfo]) -> Result<(), IndexerError> {
+    async fn send_block_batch(
+        &self,
+        block_batch: &[BlockInfo],
+        ack: &Arc<BatchAck>,
+    ) -> Result<(), IndexerError> {
         let block_metadatas: Vec<BlockMetadata> =
             block_batch.iter().map(|b| b.metadata.clone()).collect();
-        self.send_block_metadatas(block_metadatas).await?;
+        // Writing the blocks removes those of an abandoned fork along with their rows, so the
+        // rows of the batch are only sent once that is done.
+        let (blocks_ack, blocks_written) = BatchAck::new();
+        self.send_block_metadatas(block_metadatas, &blocks_ack)
+            .await?;
+        blocks_ack.chunk_done(true);
+        if blocks_written.await != Ok(true) {
+            return Err(IndexerError::MessengerError(
+                "failed to write the blocks of the batch".to_string(),
+            ));
+        }
+        let parse_failures: Vec<ParseFailure> = block_batch
+            .iter()
+            .flat_map(|b| b.parse_failures.iter().cloned())
+            .collect();
+        self.send_parse_failures(parse_failures, ack).await?;
+        let balance_changes: Vec<BalanceChange> = block_batch
+            .iter()
+            .flat_map(|b| b.balance_changes.iter().cloned())
+            .collect();
+        self.send_balance_changes(balance_changes, ack).await?;
         let mut state_updates = Vec::new();
         for block in block_batch {
             state_updates.push(parse_block_state_update(block)?);
         }
-        self.send_transaction_update(StateUpdate::merge_updates(state_updates))
+        self.send_transaction_update(StateUpdate::merge_updates(state_updates), ack)
             .await?;
         Ok(())
     }
 
-    pub async fn send_block_metadatas(
+    fn queue<T>(
+        &self,
+        sender: &mpsc::UnboundedSender<Batch<T>>,
+        rows: Vec<T>,
+        ack: &Arc<BatchAck>,
+    ) -> Result<(), IndexerError> {
+        self.pending_batches.fetch_add(1, Ordering::SeqCst);
+        ack.add_chunk();
+        sender
+            .send(Batch {
+                rows,
+                ack: ack.clone(),
+            })
+            .map_err(|e| {
+                self.pending_batches.fetch_sub(1, Ordering::SeqCst);
+                ack.chunk_done(false);
+                IndexerError::MessengerError(e.to_string())
+            })
+    }
+
+    async fn send_block_metadatas(
         &self,
         blocks: Vec<BlockMetadata>,
+        ack: &Arc<BatchAck>,
+    ) -> Result<(), IndexerError> {
+        for block_chunk in blocks.chunks(self.config.get_max_sql_inserts()) {
+            self.queue(&self.block_sender, block_chunk.to_vec(), ack)?;
+        }
+
+        Ok(())
+    }
+
+    async fn send_parse_failures(
+        &self,
+        parse_failures: Vec<ParseFailure>,
+        ack: &Arc<BatchAck>,
+    ) -> Result<(), IndexerError> {
+        if parse_failures.is_empty() {
+            return Ok(());
+        }
+        self.queue(&self.failure_sender, parse_failures, ack)
+    }
+
+    async fn send_balance_changes(
+        &self,
+        balance_changes: Vec<BalanceChange>,
+        ack: &Arc<BatchAck>,
     ) -> Result<(), IndexerError> {
-        for block_chunk in blocks.chunks(MAX_SQL_INSERTS) {
-            let chunk = block_chunk.to_vec();
-            self.block_sender
-                .send(chunk)
-                .map_err(|e| IndexerError::MessengerError(e.to_string()))?;
+        for chunk in balance_changes.chunks(self.config.get_max_sql_inserts()) {
+            self.queue(&self.balance_change_sender, chunk.to_vec(), ack)?;
         }
 
         Ok(())
     }
 
-    pub async fn send_transaction_update(
+    async fn send_transaction_update(
         &self,
         state_update: StateUpdate,
+        ack: &Arc<BatchAck>,
     ) -> Result<(), IndexerError> {
         if state_update == StateUpdate::default() {
             return Ok(());
         }
         let StateUpdate { transactions } = state_update;
 
-        let transactions_vec = transactions.into_iter().collect::<Vec<_>>();
+        let transactions_vec = transactions.into_values().collect::<Vec<_>>();
 
         debug!("sending transaction metadatas...");
-        for chunk in transactions_vec.chunks(MAX_SQL_INSERTS) {
-            let chunk = chunk.to_vec();
-            self.transaction_sender
-                .send(chunk)
-                .map_err(|e| IndexerError::MessengerError(e.to_string()))?;
+        for chunk in transactions_vec.chunks(self.config.get_max_sql_inserts()) {
+            self.queue(&self.transaction_sender, chunk.to_vec(), ack)?;
         }
 
         Ok(())
     }
 
-    pub async fn block_worker(
+    // Sends each block batch to the workers once the batch before it is written, so that the
+    // removal of an abandoned fork can't race with the writes of its slots in another batch.
+    async fn block_batch_worker(
+        self: Arc<Self>,
+        block_batch_receiver: Arc<Mutex<mpsc::UnboundedReceiver<Batch<BlockInfo>>>>,
+    ) {
+        loop {
+            tokio::select! {
+                block_batch = async {
+                    let mut rx_lock = block_batch_receiver.lock().await;
+                    rx_lock.recv().await
+                } => {
+                    match block_batch {
+                        Some(block_batch) => {
+                            let block_batch = InFlightBatch {
+                                batch: block_batch,
+                                requeue: &self.block_batch_sender,
+                            };
+                            let previous_ack = self.previous_block_batch.lock().unwrap().clone();
+                            if let Some(previous_ack) = previous_ack {
+                                previous_ack.finished().await;
+                            }
+                            let sent = self
+                                .send_block_batch(&block_batch.batch.rows, &block_batch.batch.ack)
+                                .await;
+                            if let Err(e) = &sent {
+                                error!("Failed to send block batch: {:?}", e);
+                                metric! {
+                                    statsd_count!("messenger_send_error", 1);
+                                }
+                            }
+                            *self.previous_block_batch.lock().unwrap() =
+                                Some(block_batch.batch.ack.clone());
+                            block_batch.done(sent.is_ok());
+                            self.pending_batches.fetch_sub(1, Ordering::SeqCst);
+                        },
+                        None => {
+                            error!("Block batch receiver closed");
+                            metric! {
+                                statsd_count!("block_batch_receiver_closed", 1);
+                            }
+                            break;
+                        }
+                    }
+                }
+                _ = self.shutdown_notify.notified() => {
+                    warn!("Shutdown signal received");
+                    break;
+                }
+            }
+        }
+    }
+
+    async fn block_worker(
         self: Arc<Self>,
-        block_receiver: Arc<Mutex<mpsc::UnboundedReceiver<Vec<BlockMetadata>>>>,
-        dao: Dao,
+        block_receiver: Arc<Mutex<mpsc::UnboundedReceiver<Batch<BlockMetadata>>>>,
+        sink: Arc<dyn TransferSink>,
     ) {
         loop {
             tokio::select! {
@@ -154,13 +506,26 @@ impl Messenger {
                 } => {
                     match blocks {
                         Some(blocks) => {
-                            let block_refs: Vec<&BlockMetadata> = blocks.iter().collect();
-                            if let Err(e) = dao.index_block_metadatas(block_refs).await {
-                                error!("Failed to index block metadata: {:?}", e);
-                                metric! {
-                                    statsd_count!("index_block_error", 1);
+                            let blocks = InFlightBatch {
+                                batch: blocks,
+                                requeue: &self.block_sender,
+                            };
+                            let block_refs: Vec<&BlockMetadata> =
+                                blocks.batch.rows.iter().collect();
+                            match sink.index_block_metadatas(block_refs).await {
+                                Ok(slots_to_refetch) => {
+                                    self.slots_to_refetch.lock().unwrap().extend(slots_to_refetch);
+                                    blocks.done(true);
+                                }
+                                Err(e) => {
+                                    error!("Failed to index block metadata: {:?}", e);
+                                    metric! {
+                                        statsd_count!("index_block_error", 1);
+                                    }
+                                    blocks.done(false);
                                 }
                             }
+                            self.pending_batches.fetch_sub(1, Ordering::SeqCst);
                         },
                         None => {
                             error!("Block receiver closed");
@@ -179,10 +544,10 @@ impl Messenger {
         }
     }
 
-    pub async fn transaction_worker(
+    async fn transaction_worker(
         self: Arc<Self>,
-        transaction_receiver: Arc<Mutex<mpsc::UnboundedReceiver<Vec<Transaction>>>>,
-        dao: Dao,
+        transaction_receiver: Arc<Mutex<mpsc::UnboundedReceiver<Batch<Transaction>>>>,
+        sink: Arc<dyn TransferSink>,
     ) {
         loop {
             tokio::select! {
@@ -192,12 +557,19 @@ impl Messenger {
                     } => {
                     match transactions {
                         Some(transactions) => {
-                            if let Err(e) = dao.index_transaction(&transactions).await {
+                            let transactions = InFlightBatch {
+                                batch: transactions,
+                                requeue: &self.transaction_sender,
+                            };
+                            let written = sink.index_transaction(&transactions.batch.rows).await;
+                            if let Err(e) = &written {
                                 error!("Failed to index transaction: {:?}", e);
                                 metric! {
                                     statsd_count!("index_transaction_error", 1);
                                 }
                             }
+                            transactions.done(written.is_ok());
+                            self.pending_batches.fetch_sub(1, Ordering::SeqCst);
                         },
                         None => {
                             error!("Transaction receiver closed");
@@ -215,4 +587,88 @@ impl Messenger {
             }
         }
     }
+
+    async fn balance_change_worker(
+        self: Arc<Self>,
+        balance_change_receiver: Arc<Mutex<mpsc::UnboundedReceiver<Batch<BalanceChange>>>>,
+        sink: Arc<dyn TransferSink>,
+    ) {
+        loop {
+            tokio::select! {
+                balance_changes = async {
+                    let mut rx_lock = balance_change_receiver.lock().await;
+                    rx_lock.recv().await
+                } => {
+                    match balance_changes {
+                        Some(balance_changes) => {
+                            let balance_changes = InFlightBatch {
+                                batch: balance_changes,
+                                requeue: &self.balance_change_sender,
+                            };
+                            let written = sink
+                                .index_balance_changes(&balance_changes.batch.rows)
+                                .await;
+                            if let Err(e) = &written {
+                                error!("Failed to index balance changes: {:?}", e);
+                                metric! {
+                                    statsd_count!("index_balance_change_error", 1);
+                                }
+                            }
+                            balance_changes.done(written.is_ok());
+                            self.pending_batches.fetch_sub(1, Ordering::SeqCst);
+                        },
+                        None => {
+                            error!("Balance change receiver closed");
+                            break;
+                        }
+                    }
+                }
+                _ = self.shutdown_notify.notified() => {
+                    warn!("Shutdown signal received");
+                    break;
+                }
+            }
+        }
+    }
+
+    async fn failure_worker(
+        self: Arc<Self>,
+        failure_receiver: Arc<Mutex<mpsc::UnboundedReceiver<Batch<ParseFailure>>>>,
+        sink: Arc<dyn TransferSink>,
+    ) {
+        loop {
+            tokio::select! {
+                failures = async {
+                    let mut rx_lock = failure_receiver.lock().await;
+                    rx_lock.recv().await
+                } => {
+                    match failures {
+                        Some(failures) => {
+                            let failures = InFlightBatch {
+                                batch: failures,
+                                requeue: &self.failure_sender,
+                            };
+                            let written = sink.record_parse_failures(&failures.batch.rows).await;
+                            if let Err(e) = &written {
+                                error!("Failed to record parse failures: {:?}", e);
+                                metric! {
+                                    statsd_count!("record_parse_failure_error", 1);
+                                }
+                            }
+                            failures.done(written.is_ok());
+                            self.pending_batches.fetch_sub(1, Ordering::SeqCst);
+                        },
+                        None => {
+                            error!("Parse failure receiver closed");
+                            break;
+                        }
+                    }
+                }
+                _ = self.shutdown_notify.notified() => {
+                    warn!("Shutdown signal received");
+                    break;
+                }
+            }
+        }
+    }
 }
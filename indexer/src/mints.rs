@@ -0,0 +1,220 @@
+use std::{collections::HashMap, sync::Arc};
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use dao::generated::mints;
+use sea_orm::{sea_query::OnConflict, DatabaseConnection, EntityTrait, Set};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{clock::UnixTimestamp, pubkey::Pubkey};
+use tokio::sync::RwLock;
+
+use crate::error::IndexerError;
+
+// Token-2022 pads mints to the size of a token account and follows them with the account type
+// and the extension TLV entries.
+const MINT_LEN: usize = 82;
+const MINT_DECIMALS_OFFSET: usize = 44;
+const EXTENSIONS_OFFSET: usize = 166;
+const INTEREST_BEARING_CONFIG_EXTENSION: u16 = 10;
+const SCALED_UI_AMOUNT_EXTENSION: u16 = 25;
+
+// Interest accrues per year of 365.24 days, matching the Token-2022 program.
+const SECONDS_PER_YEAR: f64 = 60.0 * 60.0 * 24.0 * 365.24;
+const ONE_IN_BASIS_POINTS: f64 = 10_000.0;
+
+// Rates and multipliers can be updated by their authority, so mints using them are re-read
+// periodically. Plain mints never change decimals and are cached for good.
+const SCALED_MINT_REFRESH_MINUTES: i64 = 60;
+
+/// The interest-bearing extension of a Token-2022 mint.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InterestBearingConfig {
+    pub initialization_timestamp: UnixTimestamp,
+    pub pre_update_average_rate: i16,
+    pub last_update_timestamp: UnixTimestamp,
+    pub current_rate: i16,
+}
+
+/// The scaled UI amount extension of a Token-2022 mint.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScaledUiAmountConfig {
+    pub multiplier: f64,
+    pub new_multiplier_effective_timestamp: UnixTimestamp,
+    pub new_multiplier: f64,
+}
+
+/// The parts of a mint account needed to turn raw amounts into UI amounts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MintInfo {
+    pub decimals: u8,
+    pub interest_bearing: Option<InterestBearingConfig>,
+    pub scaled_ui_amount: Option<ScaledUiAmountConfig>,
+}
+
+fn read_i16(data: &[u8], offset: usize) -> i16 {
+    i16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_i64(data: &[u8], offset: usize) -> i64 {
+    i64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_f64(data: &[u8], offset: usize) -> f64 {
+    f64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+impl MintInfo {
+    /// Reads a mint account owned by either token program.
+    pub fn parse(data: &[u8]) -> Result<MintInfo, IndexerError> {
+        if data.len() < MINT_LEN {
+            return Err(IndexerError::ParserError(format!(
+                "Mint account is too short: {} bytes",
+                data.len()
+            )));
+        }
+        let mut mint = MintInfo {
+            decimals: data[MINT_DECIMALS_OFFSET],
+            interest_bearing: None,
+            scaled_ui_amount: None,
+        };
+
+        let mut offset = EXTENSIONS_OFFSET;
+        while offset + 4 <= data.len() {
+            let extension_type = u16::from_le_bytes([data[offset], data[offset + 1]]);
+            let length = u16::from_le_bytes([data[offset + 2], data[offset + 3]]) as usize;
+            let value_offset = offset + 4;
+            if extension_type == 0 || value_offset + length > data.len() {
+                break;
+            }
+            let value = &data[value_offset..value_offset + length];
+            // Both configs start with their 32 byte authority.
+            match extension_type {
+                INTEREST_BEARING_CONFIG_EXTENSION if length >= 52 => {
+                    mint.interest_bearing = Some(InterestBearingConfig {
+                        initialization_timestamp: read_i64(value, 32),
+                        pre_update_average_rate: read_i16(value, 40),
+                        last_update_timestamp: read_i64(value, 42),
+                        current_rate: read_i16(value, 50),
+                    });
+                }
+                SCALED_UI_AMOUNT_EXTENSION if length >= 56 => {
+                    mint.scaled_ui_amount = Some(ScaledUiAmountConfig {
+                        multiplier: read_f64(value, 32),
+                        new_multiplier_effective_timestamp: read_i64(value, 40),
+                        new_multiplier: read_f64(value, 48),
+                    });
+                }
+                _ => {}
+            }
+            offset = value_offset + length;
+        }
+        Ok(mint)
+    }
+
+    pub fn is_scaled(&self) -> bool {
+        self.interest_bearing.is_some() || self.scaled_ui_amount.is_some()
+    }
+
+    /// The amount a wallet would display for `amount` base units at `unix_timestamp`.
+    pub fn ui_amount(&self, amount: u64, unix_timestamp: UnixTimestamp) -> f64 {
+        let mut scale = 1.0;
+        if let Some(config) = &self.interest_bearing {
+            let pre_update_timespan =
+                config.last_update_timestamp - config.initialization_timestamp;
+            let post_update_timespan = unix_timestamp - config.last_update_timestamp;
+            let pre_update_exp = (config.pre_update_average_rate as f64
+                * pre_update_timespan as f64
+                / SECONDS_PER_YEAR
+                / ONE_IN_BASIS_POINTS)
+                .exp();
+            let post_update_exp = (config.current_rate as f64 * post_update_timespan as f64
+                / SECONDS_PER_YEAR
+                / ONE_IN_BASIS_POINTS)
+                .exp();
+            scale *= pre_update_exp * post_update_exp;
+        }
+        if let Some(config) = &self.scaled_ui_amount {
+            scale *= if unix_timestamp >= config.new_multiplier_effective_timestamp {
+                config.new_multiplier
+            } else {
+                config.multiplier
+            };
+        }
+        amount as f64 * scale / 10f64.powi(self.decimals as i32)
+    }
+}
+
+/// Mint accounts fetched over RPC, cached in memory and in the `mints` table.
+pub struct MintCache {
+    rpc_client: Arc<RpcClient>,
+    mints: RwLock<HashMap<Pubkey, (MintInfo, NaiveDateTime)>>,
+}
+
+impl MintCache {
+    pub fn new(rpc_client: Arc<RpcClient>) -> Self {
+        MintCache {
+            rpc_client,
+            mints: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn is_fresh(mint: &MintInfo, updated_at: NaiveDateTime) -> bool {
+        !mint.is_scaled()
+            || Utc::now().naive_utc() - updated_at < Duration::minutes(SCALED_MINT_REFRESH_MINUTES)
+    }
+
+    pub async fn get_mint(
+        &self,
+        db: &DatabaseConnection,
+        address: Pubkey,
+    ) -> Result<MintInfo, IndexerError> {
+        if let Some((mint, updated_at)) = self.mints.read().await.get(&address) {
+            if Self::is_fresh(mint, *updated_at) {
+                return Ok(mint.clone());
+            }
+        }
+
+        if let Some(model) = mints::Entity::find_by_id(address.to_bytes().to_vec())
+            .one(db)
+            .await?
+        {
+            let mint = MintInfo::parse(&model.data)?;
+            if Self::is_fresh(&mint, model.updated_at) {
+                self.mints
+                    .write()
+                    .await
+                    .insert(address, (mint.clone(), model.updated_at));
+                return Ok(mint);
+            }
+        }
+
+        let data = self
+            .rpc_client
+            .get_account_data(&address)
+            .await
+            .map_err(|e| IndexerError::BatchInitNetworkingError(e.to_string()))?;
+        let mint = MintInfo::parse(&data)?;
+        let updated_at = Utc::now().naive_utc();
+        mints::Entity::insert(mints::ActiveModel {
+            address: Set(address.to_bytes().to_vec()),
+            decimals: Set(mint.decimals as i16),
+            data: Set(data),
+            updated_at: Set(updated_at),
+        })
+        .on_conflict(
+            OnConflict::column(mints::Column::Address)
+                .update_columns([
+                    mints::Column::Decimals,
+                    mints::Column::Data,
+                    mints::Column::UpdatedAt,
+                ])
+                .to_owned(),
+        )
+        .exec(db)
+        .await?;
+        self.mints
+            .write()
+            .await
+            .insert(address, (mint.clone(), updated_at));
+        Ok(mint)
+    }
+}
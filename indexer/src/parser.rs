@@ -1,38 +1,55 @@
+use cadence_macros::statsd_count;
+use common::metric;
 use solana_sdk::signature::Signature;
 use solana_sdk::{
     bs58,
-    clock::Slot,
+    clock::{Slot, UnixTimestamp},
     pubkey::Pubkey,
     transaction::VersionedTransaction,
 };
 use solana_transaction_status::{
     option_serializer::OptionSerializer, EncodedConfirmedTransactionWithStatusMeta,
     EncodedTransactionWithStatusMeta, UiConfirmedBlock, UiInstruction, UiTransactionStatusMeta,
+    UiTransactionTokenBalance,
 };
 use yellowstone_grpc_proto::geyser::{SubscribeUpdateBlock, SubscribeUpdateTransactionInfo};
-use yellowstone_grpc_proto::prelude::{InnerInstructions, TransactionError};
-use std::{fmt, str::FromStr};
-use log::error;
+use yellowstone_grpc_proto::prelude::{InnerInstructions, TokenBalance, TransactionError};
+use std::{collections::{BTreeMap, HashMap}, fmt, str::FromStr};
+use log::{error, warn};
+use spl_token_2022::extension::transfer_fee::instruction::TransferFeeInstruction;
 
 use std::convert::TryFrom;
 
 use crate::{
     error::IndexerError,
     types::{
-        BlockInfo, BlockMetadata, Instruction, InstructionGroup, StateUpdate, Transaction,
+        BalanceChange, BlockInfo, BlockMetadata, IndexFilter, Instruction, InstructionGroup,
+        ParseFailure, StateUpdate, TokenType, Transaction,
     },
 };
 
 const SPL_ASSOCIATED_TOKEN_ACCOUNT_PROGRAM_ID: &str =
     "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL";
+// The current SPL Memo program and its legacy first version, which is still in use.
+const MEMO_PROGRAM_IDS: [&str; 2] = [
+    "MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr",
+    "Memo1UhkJRfHyvDKhZ6xSjnh1Wwb3bP3Nd9tM6s3BGY1",
+];
 
 pub struct PollerParser {}
 
+// A transaction fetched on its own with `getTransaction`, which reports its slot and block time.
 impl TryFrom<EncodedConfirmedTransactionWithStatusMeta> for Transaction {
     type Error = IndexerError;
 
     fn try_from(tx: EncodedConfirmedTransactionWithStatusMeta) -> Result<Self, Self::Error> {
-        let EncodedConfirmedTransactionWithStatusMeta { transaction, .. } = tx;
+        let EncodedConfirmedTransactionWithStatusMeta {
+            slot,
+            transaction,
+            block_time,
+        } = tx;
+        let block_time =
+            block_time.ok_or(IndexerError::ParserError("Missing block_time".to_string()))?;
 
         let EncodedTransactionWithStatusMeta {
             transaction, meta, ..
@@ -41,22 +58,48 @@ impl TryFrom<EncodedConfirmedTransactionWithStatusMeta> for Transaction {
         let versioned_transaction: VersionedTransaction = transaction.decode().ok_or(
             IndexerError::ParserError("Transaction cannot be decoded".to_string()),
         )?;
-        let signature = versioned_transaction.signatures[0];
+        let signature = PollerParser::signature(&versioned_transaction)?;
+        let fee_payer = PollerParser::fee_payer(&versioned_transaction)?;
+        let memo = PollerParser::memo(&versioned_transaction);
         let meta = meta.ok_or(IndexerError::ParserError("Missing metadata".to_string()))?;
         let error = meta.clone().err.map(|e| e.to_string());
+        let fee = meta.fee;
         Ok(Transaction {
             instruction_groups: PollerParser::parse_instruction_groups(versioned_transaction, meta.clone())?,
             signature,
             error,
-            slot: 0,
-            block_time: 0,
+            slot,
+            block_time,
+            fee_payer,
+            fee,
+            memo,
         })
     }
 }
 
 impl fmt::Display for Instruction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Instruction {{ program_id: {}}}", self.program_id,)
+        let mint = match &self.mint {
+            Some(mint) => bs58::encode(mint).into_string(),
+            None => "unknown mint".to_string(),
+        };
+        write!(
+            f,
+            "{} of {} from {} to {} by {}, instruction {}",
+            self.amount,
+            mint,
+            bs58::encode(&self.source_address).into_string(),
+            bs58::encode(&self.destination_address).into_string(),
+            self.program_id,
+            self.instruction_index
+        )?;
+        if let Some(inner_instruction_index) = self.inner_instruction_index {
+            write!(f, ".{}", inner_instruction_index)?;
+        }
+        if let Some(fee) = self.fee {
+            write!(f, ", fee {}", fee)?;
+        }
+        Ok(())
     }
 }
 
@@ -65,7 +108,9 @@ impl fmt::Display for InstructionGroup {
         write!(
             f,
             "InstructionGroup {{ outer_instruction: {}, inner_instructions: [{}] }}",
-            self.outer_instruction,
+            self.outer_instruction
+                .as_ref()
+                .map_or("None".to_string(), Instruction::to_string),
             self.inner_instructions
                 .iter()
                 .map(Instruction::to_string)
@@ -89,6 +134,91 @@ impl fmt::Display for Transaction {
     }
 }
 
+// The text of the first memo among a transaction's top level instructions, given as their
+// program and data. Memos are meant to be UTF-8, one that isn't is passed over.
+fn find_memo<'a>(instructions: impl IntoIterator<Item = (Pubkey, &'a [u8])>) -> Option<String> {
+    instructions
+        .into_iter()
+        .filter(|(program_id, _)| MEMO_PROGRAM_IDS.contains(&program_id.to_string().as_str()))
+        .find_map(|(_, data)| String::from_utf8(data.to_vec()).ok())
+}
+
+pub fn token_type(program_id: &Pubkey) -> TokenType {
+    if *program_id == spl_token::id() {
+        TokenType::SplToken
+    } else if *program_id == spl_token_2022::id() {
+        TokenType::Token2022
+    } else {
+        TokenType::Native
+    }
+}
+
+// The parts of a transfer instruction's data, and where it keeps the accounts involved.
+struct DecodedTransfer {
+    amount: u64,
+    decimals: Option<u8>,
+    fee: Option<u64>,
+    source: usize,
+    mint: Option<usize>,
+    destination: usize,
+}
+
+// Tokens move through `Transfer` and `TransferChecked`, or for Token-2022 mints with a transfer
+// fee, through `TransferCheckedWithFee`, which also states the fee withheld from the amount.
+fn decode_transfer(program_id: &Pubkey, data: &[u8]) -> Option<DecodedTransfer> {
+    match spl_token::instruction::TokenInstruction::unpack(data) {
+        Ok(spl_token::instruction::TokenInstruction::Transfer { amount }) => {
+            return Some(DecodedTransfer { amount, decimals: None, fee: None, source: 0, mint: None, destination: 1 });
+        }
+        Ok(spl_token::instruction::TokenInstruction::TransferChecked { amount, decimals }) => {
+            return Some(DecodedTransfer { amount, decimals: Some(decimals), fee: None, source: 0, mint: Some(1), destination: 2 });
+        }
+        _ => {}
+    }
+    if *program_id != spl_token_2022::id() {
+        return None;
+    }
+    match spl_token_2022::instruction::TokenInstruction::unpack(data) {
+        Ok(spl_token_2022::instruction::TokenInstruction::TransferFeeExtension(
+            TransferFeeInstruction::TransferCheckedWithFee { amount, decimals, fee },
+        )) => Some(DecodedTransfer { amount, decimals: Some(decimals), fee: Some(fee), source: 0, mint: Some(1), destination: 2 }),
+        _ => None,
+    }
+}
+
+// Token-2022 withholds transfer fees in the destination account, so a `TransferChecked` of a
+// fee-bearing mint credits less than its amount and the difference is the fee. The credit is
+// read from the token balances, which is only unambiguous for destinations no other transfer
+// of the transaction touches. Mints without the transfer fee extension credit the full amount,
+// so a zero difference records no fee rather than a fee of 0.
+fn derive_transfer_fees(instruction_groups: &mut [InstructionGroup], balance_changes: &HashMap<Vec<u8>, i128>) {
+    let mut transfers_per_account: HashMap<Vec<u8>, usize> = HashMap::new();
+    for transfer in instruction_groups.iter().flat_map(InstructionGroup::transfers) {
+        *transfers_per_account.entry(transfer.source_address.clone()).or_default() += 1;
+        *transfers_per_account.entry(transfer.destination_address.clone()).or_default() += 1;
+    }
+    for instruction_group in instruction_groups.iter_mut() {
+        for transfer in instruction_group
+            .outer_instruction
+            .iter_mut()
+            .chain(instruction_group.inner_instructions.iter_mut())
+        {
+            if transfer.fee.is_some()
+                || transfer.program_id != spl_token_2022::id()
+                || transfers_per_account.get(&transfer.destination_address) != Some(&1)
+            {
+                continue;
+            }
+            if let Some(credited) = balance_changes.get(&transfer.destination_address) {
+                let fee = transfer.amount as i128 - credited;
+                if (1..=transfer.amount as i128).contains(&fee) {
+                    transfer.fee = Some(fee as u64);
+                }
+            }
+        }
+    }
+}
+
 pub fn find_associated_token_address(
     owner: Pubkey,
     mint: Pubkey,
@@ -106,6 +236,173 @@ pub fn find_associated_token_address(
     .0)
 }
 
+/// Parses a block fetched over RPC (`getBlock` with base64 encoding and full transaction
+/// details) into a [`BlockInfo`], keeping only the token transfers the indexer understands.
+///
+/// This is the same parsing the poller applies, exposed so other crates can reuse it with
+/// their own RPC data without running the streaming machinery.
+///
+/// ```
+/// use indexer::parser::parse_block_info;
+/// use solana_transaction_status::UiConfirmedBlock;
+///
+/// let block: UiConfirmedBlock = serde_json::from_value(serde_json::json!({
+///     "previousBlockhash": "11111111111111111111111111111111",
+///     "blockhash": "11111111111111111111111111111111",
+///     "parentSlot": 99,
+///     "transactions": [],
+///     "blockTime": 1_700_000_000,
+///     "blockHeight": 90,
+/// }))
+/// .unwrap();
+///
+/// let block_info = parse_block_info(block, 100).unwrap();
+/// assert_eq!(block_info.metadata.slot, 100);
+/// assert_eq!(block_info.metadata.parent_slot, 99);
+/// assert!(block_info.transactions.is_empty());
+/// ```
+pub fn parse_block_info(block: UiConfirmedBlock, slot: Slot) -> Result<BlockInfo, IndexerError> {
+    PollerParser::parse_ui_confirmed_block(block, slot)
+}
+
+/// Parses a single RPC-encoded transaction that was included in `slot` at `block_time`.
+///
+/// Returns `Ok(None)` when the transaction contains no token transfers.
+pub fn parse_transaction_info(
+    transaction: EncodedTransactionWithStatusMeta,
+    slot: Slot,
+    block_time: UnixTimestamp,
+) -> Result<Option<Transaction>, IndexerError> {
+    PollerParser::parse_encoded_transaction(transaction, slot, block_time)
+}
+
+/// Drops the transfers `filter` doesn't allow, and the transactions left without any.
+pub fn filter_block(block: &mut BlockInfo, filter: &IndexFilter) {
+    // The parsers collect balance changes for every block, they are only dropped here.
+    if !filter.balance_changes {
+        block.balance_changes.clear();
+    } else if !filter.mints.is_empty() {
+        block
+            .balance_changes
+            .retain(|change| filter.mints.contains(&change.mint));
+    }
+    if !filter.failed_transactions {
+        block
+            .transactions
+            .retain(|transaction| transaction.error.is_none());
+    }
+    if filter.mints.is_empty() && filter.programs.is_empty() {
+        return;
+    }
+    for transaction in &mut block.transactions {
+        for instruction_group in &mut transaction.instruction_groups {
+            if !instruction_group
+                .outer_instruction
+                .as_ref()
+                .is_some_and(|instruction| filter.allows(instruction))
+            {
+                instruction_group.outer_instruction = None;
+            }
+            instruction_group
+                .inner_instructions
+                .retain(|instruction| filter.allows(instruction));
+        }
+        transaction
+            .instruction_groups
+            .retain(|instruction_group| instruction_group.transfers().next().is_some());
+    }
+    block
+        .transactions
+        .retain(|transaction| !transaction.instruction_groups.is_empty());
+}
+
+// A transaction that fails to parse is logged, counted and left out, so that retrying its block
+// can't get stuck on it. The failure is kept in `parse_failures` so that it can be recorded.
+fn skip_unparseable(
+    result: Result<Option<Transaction>, IndexerError>,
+    signature: Option<Signature>,
+    slot: Slot,
+    parse_failures: &mut Vec<ParseFailure>,
+) -> Option<Transaction> {
+    match result {
+        Ok(transaction) => transaction,
+        Err(e) => {
+            warn!("Skipping unparseable transaction in slot {}: {}", slot, e);
+            metric! {
+                statsd_count!("parse_skip", 1);
+            }
+            parse_failures.push(ParseFailure {
+                signature,
+                slot,
+                error: e.to_string(),
+            });
+            None
+        }
+    }
+}
+
+// A token balance from either encoding of the transaction meta.
+struct TokenAccountBalance {
+    account_index: u32,
+    mint: String,
+    owner: Option<String>,
+    decimals: u8,
+    amount: u64,
+}
+
+// Pairs up the token balances of each account before and after the transaction, leaving out
+// the accounts whose balance didn't change. `accounts` resolves the balances' account indexes.
+fn net_balance_changes(
+    pre_balances: Vec<TokenAccountBalance>,
+    post_balances: Vec<TokenAccountBalance>,
+    accounts: &[Vec<u8>],
+    signature: Signature,
+    slot: Slot,
+    block_time: UnixTimestamp,
+) -> Vec<BalanceChange> {
+    let mut changes: BTreeMap<u32, BalanceChange> = BTreeMap::new();
+    let balances = pre_balances
+        .into_iter()
+        .map(|balance| (balance, false))
+        .chain(post_balances.into_iter().map(|balance| (balance, true)));
+    for (balance, is_post) in balances {
+        let (Some(account), Ok(mint)) = (
+            accounts.get(balance.account_index as usize),
+            Pubkey::from_str(&balance.mint),
+        ) else {
+            continue;
+        };
+        let change = changes
+            .entry(balance.account_index)
+            .or_insert_with(|| BalanceChange {
+                signature,
+                slot,
+                block_time,
+                account: account.clone(),
+                mint: mint.to_bytes().to_vec(),
+                owner: None,
+                decimals: balance.decimals,
+                pre_amount: 0,
+                post_amount: 0,
+            });
+        if let Some(owner) = balance
+            .owner
+            .and_then(|owner| Pubkey::from_str(&owner).ok())
+        {
+            change.owner = Some(owner.to_bytes().to_vec());
+        }
+        if is_post {
+            change.post_amount = balance.amount;
+        } else {
+            change.pre_amount = balance.amount;
+        }
+    }
+    changes
+        .into_values()
+        .filter(|change| change.pre_amount != change.post_amount)
+        .collect()
+}
+
 pub fn parse_block_state_update(block: &BlockInfo) -> Result<StateUpdate, IndexerError> {
     let mut state_updates: Vec<StateUpdate> = Vec::new();
     for transaction in &block.transactions {
@@ -120,7 +417,7 @@ pub fn parse_transaction(
     let state_updates = Vec::new();
 
     let mut state_update = StateUpdate::merge_updates(state_updates);
-    state_update.transactions.insert(tx.clone());
+    state_update.insert(tx.clone());
     Ok(state_update)
 }
 
@@ -142,19 +439,37 @@ impl PollerParser {
         let block_time = block_time
         .ok_or(IndexerError::ParserError("Missing block_time".to_string()))?;
 
-        let transactions: Result<Vec<_>, _> = transactions
+        let mut parse_failures = Vec::new();
+        let mut balance_changes = Vec::new();
+        let transactions = transactions
             .unwrap_or(Vec::new())
             .into_iter()
-            .map(|tx| Self::parse_encoded_transaction(tx, slot, block_time))
-            .collect();
-
-        let transactions = transactions?
-            .into_iter()
-            .flatten()
+            .filter_map(|EncodedTransactionWithStatusMeta { transaction, meta, .. }| {
+                let versioned_transaction = transaction.decode();
+                let signature = versioned_transaction
+                    .as_ref()
+                    .and_then(|transaction| transaction.signatures.first().copied());
+                if let (Some(versioned_transaction), Some(meta)) = (&versioned_transaction, &meta) {
+                    balance_changes.extend(Self::balance_changes(
+                        versioned_transaction,
+                        meta,
+                        slot,
+                        block_time,
+                    ));
+                }
+                let result = versioned_transaction
+                    .ok_or(IndexerError::ParserError("Transaction cannot be decoded".to_string()))
+                    .and_then(|versioned_transaction| {
+                        Self::parse_versioned_transaction(versioned_transaction, meta, slot, block_time)
+                    });
+                skip_unparseable(result, signature, slot, &mut parse_failures)
+            })
             .collect::<Vec<_>>();
 
         Ok(BlockInfo {
             transactions,
+            parse_failures,
+            balance_changes,
             metadata: BlockMetadata {
                 parent_slot,
                 block_time,
@@ -170,8 +485,8 @@ impl PollerParser {
 
     fn parse_encoded_transaction(
         transaction: EncodedTransactionWithStatusMeta,
-        slot: u64,
-        block_time: i64,
+        slot: Slot,
+        block_time: UnixTimestamp,
     ) -> Result<Option<Transaction>, IndexerError> {
         let EncodedTransactionWithStatusMeta {
             transaction, meta, ..
@@ -180,10 +495,22 @@ impl PollerParser {
         let versioned_transaction: VersionedTransaction = transaction.decode().ok_or(
             IndexerError::ParserError("Transaction cannot be decoded".to_string()),
         )?;
+        Self::parse_versioned_transaction(versioned_transaction, meta, slot, block_time)
+    }
+
+    fn parse_versioned_transaction(
+        versioned_transaction: VersionedTransaction,
+        meta: Option<UiTransactionStatusMeta>,
+        slot: Slot,
+        block_time: UnixTimestamp,
+    ) -> Result<Option<Transaction>, IndexerError> {
         let meta = meta.ok_or(IndexerError::ParserError("Missing metadata".to_string()))?;
 
-        let signature = versioned_transaction.signatures[0];
+        let signature = Self::signature(&versioned_transaction)?;
+        let fee_payer = Self::fee_payer(&versioned_transaction)?;
+        let memo = Self::memo(&versioned_transaction);
         let error = meta.clone().err.map(|e| e.to_string());
+        let fee = meta.fee;
         let instruction_groups = Self::parse_instruction_groups(versioned_transaction, meta)?;
 
         if instruction_groups.is_empty() {
@@ -196,15 +523,205 @@ impl PollerParser {
             error,
             slot,
             block_time,
+            fee_payer,
+            fee,
+            memo,
         }))
     }
 
+    // Programs are always static keys, so top level instructions never need the lookups.
+    fn memo(versioned_transaction: &VersionedTransaction) -> Option<String> {
+        let accounts = versioned_transaction.message.static_account_keys();
+        find_memo(versioned_transaction.message.instructions().iter().filter_map(|ix| {
+            accounts
+                .get(ix.program_id_index as usize)
+                .map(|program_id| (*program_id, ix.data.as_slice()))
+        }))
+    }
+
+    fn balance_changes(
+        versioned_transaction: &VersionedTransaction,
+        meta: &UiTransactionStatusMeta,
+        slot: Slot,
+        block_time: UnixTimestamp,
+    ) -> Vec<BalanceChange> {
+        let Ok(signature) = Self::signature(versioned_transaction) else {
+            return Vec::new();
+        };
+        // Account indexes past the static keys would resolve to the wrong accounts.
+        if !Self::has_expected_account_count(versioned_transaction, meta) {
+            return Vec::new();
+        }
+        let mut accounts = versioned_transaction
+            .message
+            .static_account_keys()
+            .iter()
+            .map(|account| account.to_bytes().to_vec())
+            .collect::<Vec<_>>();
+        if let OptionSerializer::Some(loaded_addresses) = &meta.loaded_addresses {
+            for address in loaded_addresses
+                .writable
+                .iter()
+                .chain(loaded_addresses.readonly.iter())
+            {
+                match Pubkey::from_str(address) {
+                    Ok(address) => accounts.push(address.to_bytes().to_vec()),
+                    Err(_) => return Vec::new(),
+                }
+            }
+        }
+        let token_balances = |balances: &OptionSerializer<Vec<UiTransactionTokenBalance>>|
+         -> Vec<TokenAccountBalance> {
+            match balances {
+                OptionSerializer::Some(balances) => balances
+                    .iter()
+                    .filter_map(|balance| {
+                        Some(TokenAccountBalance {
+                            account_index: balance.account_index as u32,
+                            mint: balance.mint.clone(),
+                            owner: match &balance.owner {
+                                OptionSerializer::Some(owner) => Some(owner.clone()),
+                                _ => None,
+                            },
+                            decimals: balance.ui_token_amount.decimals,
+                            amount: balance.ui_token_amount.amount.parse().ok()?,
+                        })
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            }
+        };
+        net_balance_changes(
+            token_balances(&meta.pre_token_balances),
+            token_balances(&meta.post_token_balances),
+            &accounts,
+            signature,
+            slot,
+            block_time,
+        )
+    }
+
+    // The first signature identifies the transaction, a malformed one can come without any.
+    fn signature(versioned_transaction: &VersionedTransaction) -> Result<Signature, IndexerError> {
+        versioned_transaction
+            .signatures
+            .first()
+            .copied()
+            .ok_or(IndexerError::ParserError("Transaction has no signature".to_string()))
+    }
+
+    // The first account of a transaction signs it and pays its fee.
+    fn fee_payer(versioned_transaction: &VersionedTransaction) -> Result<Pubkey, IndexerError> {
+        versioned_transaction
+            .message
+            .static_account_keys()
+            .first()
+            .copied()
+            .ok_or(IndexerError::ParserError("Missing fee payer".to_string()))
+    }
+
+
+    // Every account index in the transaction resolves against the static keys, followed by the
+    // writable addresses of all lookups and then their readonly addresses. The loaded addresses
+    // must agree with the lookups on both counts, or indexes past the static keys would resolve
+    // to the wrong accounts.
+    fn has_expected_account_count(
+        versioned_transaction: &VersionedTransaction,
+        meta: &UiTransactionStatusMeta,
+    ) -> bool {
+        let expected_loaded = versioned_transaction
+            .message
+            .address_table_lookups()
+            .map(|lookups| {
+                lookups.iter().fold((0, 0), |(writable, readonly), lookup| {
+                    (
+                        writable + lookup.writable_indexes.len(),
+                        readonly + lookup.readonly_indexes.len(),
+                    )
+                })
+            })
+            .unwrap_or((0, 0));
+        let loaded = match &meta.loaded_addresses {
+            OptionSerializer::Some(loaded_addresses) => {
+                (loaded_addresses.writable.len(), loaded_addresses.readonly.len())
+            }
+            _ => (0, 0),
+        };
+        expected_loaded == loaded
+    }
+
+    // Maps account indexes of token accounts to their owners, as reported in the token balances.
+    fn token_account_owners(meta: &UiTransactionStatusMeta) -> HashMap<u8, Pubkey> {
+        let mut owners = HashMap::new();
+        for balances in [&meta.pre_token_balances, &meta.post_token_balances] {
+            if let OptionSerializer::Some(balances) = balances {
+                for balance in balances {
+                    if let OptionSerializer::Some(owner) = &balance.owner {
+                        if let Ok(owner) = Pubkey::from_str(owner) {
+                            owners.insert(balance.account_index, owner);
+                        }
+                    }
+                }
+            }
+        }
+        owners
+    }
+
+    // How much each token account's balance changed over the transaction, in base units.
+    fn token_balance_changes(meta: &UiTransactionStatusMeta, accounts: &[Pubkey]) -> HashMap<Vec<u8>, i128> {
+        let mut changes = HashMap::new();
+        for (balances, sign) in [(&meta.pre_token_balances, -1), (&meta.post_token_balances, 1)] {
+            if let OptionSerializer::Some(balances) = balances {
+                for balance in balances {
+                    let account = accounts.get(balance.account_index as usize);
+                    let amount = balance.ui_token_amount.amount.parse::<u64>();
+                    if let (Some(account), Ok(amount)) = (account, amount) {
+                        *changes.entry(account.to_bytes().to_vec()).or_default() += sign * amount as i128;
+                    }
+                }
+            }
+        }
+        changes
+    }
+
+    // Points a parser error at the transaction and instruction it came from, so the transaction
+    // can be fetched and parsed again to reproduce it.
+    fn error_at(
+        error: IndexerError,
+        signature: &Signature,
+        instruction_index: usize,
+        inner_instruction_index: Option<usize>,
+    ) -> IndexerError {
+        match error {
+            IndexerError::ParserError(msg) => IndexerError::ParserError(match inner_instruction_index {
+                Some(inner_instruction_index) => format!(
+                    "{} (transaction {}, instruction {}, inner instruction {})",
+                    msg, signature, instruction_index, inner_instruction_index
+                ),
+                None => format!("{} (transaction {}, instruction {})", msg, signature, instruction_index),
+            }),
+            error => error,
+        }
+    }
 
     #[allow(clippy::collapsible_match)]
     pub fn parse_instruction_groups(
         versioned_transaction: VersionedTransaction,
         meta: UiTransactionStatusMeta,
     ) -> Result<Vec<InstructionGroup>, IndexerError> {
+        let signature = Self::signature(&versioned_transaction)?;
+        if !Self::has_expected_account_count(&versioned_transaction, &meta) {
+            warn!(
+                "Skipping transaction {}: loaded addresses do not match its address table lookups",
+                signature
+            );
+            metric! {
+                statsd_count!("account_count_mismatch", 1);
+            }
+            return Ok(Vec::new());
+        }
+
+        // The order the runtime resolves account indexes in, see `has_expected_account_count`.
         let mut accounts = Vec::from(versioned_transaction.message.static_account_keys());
         if versioned_transaction
             .message
@@ -217,8 +734,12 @@ impl PollerParser {
                     .iter()
                     .chain(loaded_addresses.readonly.iter())
                 {
-                    let pubkey = Pubkey::from_str(address)
-                        .map_err(|e| IndexerError::ParserError(e.to_string()))?;
+                    let pubkey = Pubkey::from_str(address).map_err(|e| {
+                        IndexerError::ParserError(format!(
+                            "Invalid loaded address {}: {} (transaction {})",
+                            address, e, signature
+                        ))
+                    })?;
                     accounts.push(pubkey);
                 }
             }
@@ -227,133 +748,199 @@ impl PollerParser {
         let token_program_id = Pubkey::from_str("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA")?;
         let token_extensions_program_id =
             Pubkey::from_str("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb")?;
+        let token_account_owners = Self::token_account_owners(&meta);
+        let owner_of = |account_index: Option<&u8>| {
+            account_index
+                .and_then(|index| token_account_owners.get(index))
+                .map(|owner| owner.to_bytes().to_vec())
+        };
 
-        let mut instruction_groups: Vec<InstructionGroup> = Vec::new();
-
-        for ix in versioned_transaction.message.instructions().iter() {
-            let program_id_index = ix.program_id_index as usize;
-            if program_id_index >= accounts.len(){
-                return Err(IndexerError::ParserError("Program ID index out of bounds".to_string()));
-            }
-            let program_id = accounts[program_id_index];
-            let data = ix.data.clone();
-            let instruction_accounts: Vec<Pubkey> = ix
-                .accounts
+        // The token balance of the source or destination account of a transfer, given as
+        // indexes into the transaction's accounts.
+        let token_balance = |transfer_accounts: [Option<&u8>; 2]| match &meta.post_token_balances {
+            OptionSerializer::Some(balances) => Ok(balances
+                .iter()
+                .find(|balance_info| transfer_accounts.contains(&Some(&balance_info.account_index)))),
+            OptionSerializer::None => {
+                Err(IndexerError::ParserError("Post token balances are missing".to_string()))
+            },
+            OptionSerializer::Skip => {
+                Err(IndexerError::ParserError("Post token balances were skipped".to_string()))
+            },
+        };
+        let mint = |transfer_accounts| {
+            let balance_info = token_balance(transfer_accounts)?
+                .ok_or(IndexerError::ParserError("Token balance not found".to_string()))?;
+            Pubkey::from_str(&balance_info.mint)
+                .map_err(|e| IndexerError::ParserError(e.to_string()))
+        };
+        // Transfer instructions don't carry the mint's decimals, the token balances do.
+        let decimals = |transfer_accounts| {
+            token_balance(transfer_accounts)
+                .ok()
+                .flatten()
+                .map(|balance_info| balance_info.ui_token_amount.decimals)
+        };
+        let resolve_accounts = |account_indexes: &[u8]| {
+            account_indexes
                 .iter()
                 .map(|account_index| {
                     let account_index = *account_index as usize;
                     if account_index >= accounts.len() {
-                        return Err(IndexerError::ParserError("Account index out of bounds".to_string()));
+                        return Err(IndexerError::ParserError(format!(
+                            "Account index {} out of bounds of {} accounts",
+                            account_index,
+                            accounts.len()
+                        )));
                     }
                     Ok(accounts[account_index])
                 })
-                .collect::<Result<Vec<_>, IndexerError>>()?;
+                .collect::<Result<Vec<Pubkey>, IndexerError>>()
+        };
 
-            if (program_id == token_program_id || program_id == token_extensions_program_id)
-                && instruction_accounts.len() >= 2
-            {
-                if let Ok(transfer_instruction) = spl_token::instruction::TokenInstruction::unpack(&data) {
-                    if let spl_token::instruction::TokenInstruction::Transfer { amount } = transfer_instruction {
-                        let source_address = instruction_accounts[0];
-                        let destination_address = instruction_accounts[1];
-
-                        let mint= match &meta.post_token_balances {
-                            OptionSerializer::Some(balances) => {
-                                let balance_info = balances.first().ok_or(IndexerError::ParserError("Token balance not found".to_string()))?;
-                                Pubkey::from_str(&balance_info.mint)
-                                    .map_err(|e| IndexerError::ParserError(e.to_string()))?
-                            },
-                            OptionSerializer::None => {
-                                return Err(IndexerError::ParserError("Post token balances are missing".to_string()));
-                            },
-                            OptionSerializer::Skip => {
-                                return Err(IndexerError::ParserError("Post token balances were skipped".to_string()));
-                            },
+        let mut instruction_groups: Vec<InstructionGroup> = Vec::new();
+
+        for (instruction_index, ix) in versioned_transaction.message.instructions().iter().enumerate() {
+            let outer_error = |e| Self::error_at(e, &signature, instruction_index, None);
+            let program_id_index = ix.program_id_index as usize;
+            if program_id_index >= accounts.len(){
+                return Err(outer_error(IndexerError::ParserError(format!(
+                    "Program ID index {} out of bounds of {} accounts",
+                    program_id_index,
+                    accounts.len()
+                ))));
+            }
+            let program_id = accounts[program_id_index];
+            let data = ix.data.clone();
+            let instruction_accounts = resolve_accounts(&ix.accounts).map_err(outer_error)?;
+
+            let mut outer_instruction = None;
+            if program_id == token_program_id || program_id == token_extensions_program_id {
+                if let Some(transfer) = decode_transfer(&program_id, &data)
+                    .filter(|transfer| transfer.destination < instruction_accounts.len())
+                {
+                    let transfer_accounts = [ix.accounts.get(transfer.source), ix.accounts.get(transfer.destination)];
+                    let source_address = instruction_accounts[transfer.source];
+                    let destination_address = instruction_accounts[transfer.destination];
+                    let mint = match transfer.mint {
+                        Some(mint_index) => instruction_accounts[mint_index],
+                        None => mint(transfer_accounts).map_err(outer_error)?,
+                    };
+                    // Token-2022 accounts derive their ATAs from the Token-2022 program id.
+                    let source_ata = find_associated_token_address(source_address, mint, Some(program_id))
+                        .map_err(outer_error)?;
+                    let destination_ata =
+                        find_associated_token_address(destination_address, mint, Some(program_id))
+                            .map_err(outer_error)?;
+
+                    outer_instruction = Some(Instruction {
+                        program_id,
+                        data,
+                        accounts: instruction_accounts,
+                        source_address: source_address.to_bytes().to_vec(),
+                        destination_address: destination_address.to_bytes().to_vec(),
+                        source_ata: Some(source_ata.to_bytes().to_vec()),
+                        destination_ata: Some(destination_ata.to_bytes().to_vec()),
+                        source_owner: owner_of(ix.accounts.get(transfer.source)),
+                        destination_owner: owner_of(ix.accounts.get(transfer.destination)),
+                        mint: Some(mint.to_bytes().to_vec()),
+                        decimals: transfer.decimals.or_else(|| decimals(transfer_accounts)),
+                        amount: transfer.amount,
+                        fee: transfer.fee,
+                        instruction_index: instruction_index as u8,
+                        inner_instruction_index: None,
+                    });
+                }
+            }
+
+            // Transfers made through CPI by this instruction, e.g. when routed through a swap program.
+            let mut inner_instructions = Vec::new();
+            if let OptionSerializer::Some(inner_instructions_vec) = meta.inner_instructions.as_ref() {
+                for inner_instructions_item in inner_instructions_vec
+                    .iter()
+                    .filter(|item| item.index as usize == instruction_index)
+                {
+                    for (inner_instruction_index, ui_instruction) in
+                        inner_instructions_item.instructions.iter().enumerate()
+                    {
+                        let inner_error = |e| {
+                            Self::error_at(e, &signature, instruction_index, Some(inner_instruction_index))
                         };
-                        let source_ata = find_associated_token_address(source_address, mint, Some(token_program_id))?;
-                        let destination_ata = find_associated_token_address(destination_address, mint, Some(token_program_id))?;
-
-                        let mut inner_instructions = Vec::new();
-
-                        if let OptionSerializer::Some(inner_instructions_vec) = meta.inner_instructions.as_ref() {
-                            for inner_instructions_item in inner_instructions_vec.iter() {
-                                let _index = inner_instructions_item.index;
-                                for ui_instruction in inner_instructions_item.instructions.iter() {
-                                    match ui_instruction {
-                                        UiInstruction::Compiled(ui_compiled_instruction) => {
-                                            let inner_program_id_index = ui_compiled_instruction.program_id_index as usize;
-                                            if inner_program_id_index >= accounts.len() {
-                                                return Err(IndexerError::ParserError("Inner program ID index out of bounds".to_string()));
-                                            }
-                                            let inner_program_id = accounts[inner_program_id_index];
-                                            let inner_data = bs58::decode(&ui_compiled_instruction.data)
-                                                .into_vec()
-                                                .map_err(|e| IndexerError::ParserError(e.to_string()))?;
-                                            let inner_accounts: Vec<Pubkey> = ui_compiled_instruction
-                                                .accounts
-                                                .iter()
-                                                .map(|account_index| {
-                                                    let account_index = *account_index as usize;
-                                                    if account_index >= accounts.len() {
-                                                        return Err(IndexerError::ParserError("Inner account index out of bounds".to_string()));
-                                                    }
-                                                    Ok(accounts[account_index])
-                                                })
-                                                .collect::<Result<Vec<_>, IndexerError>>()?;
-
-                                            if inner_program_id == token_program_id
-                                                || inner_program_id == token_extensions_program_id
-                                            {
-                                                if let Ok(inner_transfer_instruction) = 
-                                                    spl_token::instruction::TokenInstruction::unpack(&inner_data) 
-                                                {
-                                                    if let spl_token::instruction::TokenInstruction::Transfer { amount } = inner_transfer_instruction {
-                                                        let inner_source_address = inner_accounts[0].to_bytes().to_vec();
-                                                        let inner_destination_address = inner_accounts[1].to_bytes().to_vec();
-                                                        
-                                                        inner_instructions.push(Instruction {
-                                                            program_id: inner_program_id,
-                                                            data: inner_data,
-                                                            accounts: inner_accounts,
-                                                            source_address: inner_source_address,
-                                                            destination_address: inner_destination_address,
-                                                            source_ata: None,
-                                                            destination_ata: None,
-                                                            mint: None,
-                                                            amount,
-                                                        });
-                                                    }
-                                                }
-                                            }
-                                        }
-                                        UiInstruction::Parsed(_) => {
-                                            return Err(IndexerError::ParserError(
-                                                "Parsed instructions are not implemented yet".to_string(),
-                                            ));
-                                        }
+                        match ui_instruction {
+                            UiInstruction::Compiled(ui_compiled_instruction) => {
+                                let inner_program_id_index = ui_compiled_instruction.program_id_index as usize;
+                                if inner_program_id_index >= accounts.len() {
+                                    return Err(inner_error(IndexerError::ParserError(format!(
+                                        "Inner program ID index {} out of bounds of {} accounts",
+                                        inner_program_id_index,
+                                        accounts.len()
+                                    ))));
+                                }
+                                let inner_program_id = accounts[inner_program_id_index];
+                                if inner_program_id != token_program_id
+                                    && inner_program_id != token_extensions_program_id
+                                {
+                                    continue;
+                                }
+                                let inner_data = bs58::decode(&ui_compiled_instruction.data)
+                                    .into_vec()
+                                    .map_err(|e| inner_error(IndexerError::ParserError(e.to_string())))?;
+                                let inner_accounts =
+                                    resolve_accounts(&ui_compiled_instruction.accounts).map_err(inner_error)?;
+
+                                if let Some(transfer) = decode_transfer(&inner_program_id, &inner_data) {
+                                    if inner_accounts.len() <= transfer.destination {
+                                        continue;
                                     }
+                                    let transfer_accounts = [
+                                        ui_compiled_instruction.accounts.get(transfer.source),
+                                        ui_compiled_instruction.accounts.get(transfer.destination),
+                                    ];
+                                    let mint = match transfer.mint {
+                                        Some(mint_index) => inner_accounts[mint_index],
+                                        None => mint(transfer_accounts).map_err(inner_error)?,
+                                    };
+                                    inner_instructions.push(Instruction {
+                                        program_id: inner_program_id,
+                                        data: inner_data,
+                                        source_address: inner_accounts[transfer.source].to_bytes().to_vec(),
+                                        destination_address: inner_accounts[transfer.destination].to_bytes().to_vec(),
+                                        accounts: inner_accounts,
+                                        source_ata: None,
+                                        destination_ata: None,
+                                        source_owner: owner_of(ui_compiled_instruction.accounts.get(transfer.source)),
+                                        destination_owner: owner_of(ui_compiled_instruction.accounts.get(transfer.destination)),
+                                        mint: Some(mint.to_bytes().to_vec()),
+                                        decimals: transfer.decimals.or_else(|| decimals(transfer_accounts)),
+                                        amount: transfer.amount,
+                                        fee: transfer.fee,
+                                        instruction_index: inner_instructions_item.index,
+                                        inner_instruction_index: Some(inner_instruction_index as u8),
+                                    });
                                 }
                             }
+                            UiInstruction::Parsed(_) => {
+                                return Err(inner_error(IndexerError::ParserError(
+                                    "Parsed instructions are not implemented yet".to_string(),
+                                )));
+                            }
                         }
-
-                        instruction_groups.push(InstructionGroup {
-                            outer_instruction: Instruction {
-                                program_id,
-                                data,
-                                accounts: accounts.clone(),
-                                source_address: source_address.to_bytes().to_vec(),
-                                destination_address: destination_address.to_bytes().to_vec(),
-                                source_ata: Some(source_ata.to_bytes().to_vec()),
-                                destination_ata: Some(destination_ata.to_bytes().to_vec()),
-                                mint: Some(mint.to_bytes().to_vec()),
-                                amount,
-                            },
-                            inner_instructions,
-                        });
                     }
                 }
             }
+
+            let transfer_program_id = match (&outer_instruction, inner_instructions.first()) {
+                (Some(outer_instruction), _) => outer_instruction.program_id,
+                (None, Some(inner_instruction)) => inner_instruction.program_id,
+                (None, None) => continue,
+            };
+            instruction_groups.push(InstructionGroup {
+                token_type: token_type(&transfer_program_id),
+                outer_instruction,
+                inner_instructions,
+            });
         }
+        derive_transfer_fees(&mut instruction_groups, &Self::token_balance_changes(&meta, &accounts));
 
         Ok(instruction_groups)
     }
@@ -380,6 +967,9 @@ impl GrpcParser {
 
         let error = meta.clone().err.map(|e| Self::transaction_error_to_string(&e));
 
+        if transaction.signature.is_empty() {
+            return Err(IndexerError::ParserError("Transaction has no signature".to_string()));
+        }
         let signature = Signature::try_from(transaction.signature)
             .map_err(|_| IndexerError::ParserError("error parsing signature".to_string()))?;
         let message = transaction
@@ -388,6 +978,25 @@ impl GrpcParser {
             .message
             .ok_or(IndexerError::ParserError("Missing message".to_string()))?;
 
+        // Static keys, then the writable addresses of all lookups, then their readonly ones.
+        let expected_writable = message
+            .address_table_lookups
+            .iter()
+            .map(|lookup| lookup.writable_indexes.len())
+            .sum::<usize>();
+        let expected_readonly = message
+            .address_table_lookups
+            .iter()
+            .map(|lookup| lookup.readonly_indexes.len())
+            .sum::<usize>();
+        let expected_account_count = message.account_keys.len() + expected_writable + expected_readonly;
+        let loaded_writable = meta.loaded_writable_addresses.len();
+
+        let fee_payer = message
+            .account_keys
+            .first()
+            .and_then(|account| Pubkey::try_from(account.as_slice()).ok())
+            .ok_or(IndexerError::ParserError("Missing fee payer".to_string()))?;
         let mut accounts = message.account_keys;
         for account in meta.loaded_writable_addresses {
             accounts.push(account);
@@ -395,18 +1004,94 @@ impl GrpcParser {
         for account in meta.loaded_readonly_addresses {
             accounts.push(account);
         }
+        if accounts.len() != expected_account_count || loaded_writable != expected_writable {
+            warn!(
+                "Skipping transaction {}: resolved {} accounts ({} writable loaded), expected {} ({} writable loaded)",
+                signature,
+                accounts.len(),
+                loaded_writable,
+                expected_account_count,
+                expected_writable
+            );
+            metric! {
+                statsd_count!("account_count_mismatch", 1);
+            }
+            return Ok(None);
+        }
+
+        let mut token_account_owners = HashMap::new();
+        for balance in meta
+            .pre_token_balances
+            .iter()
+            .chain(meta.post_token_balances.iter())
+        {
+            if let Ok(owner) = Pubkey::from_str(&balance.owner) {
+                token_account_owners.insert(balance.account_index, owner);
+            }
+        }
+        let owner_of = |account_index: Option<&u8>| {
+            account_index
+                .and_then(|index| token_account_owners.get(&(*index as u32)))
+                .map(|owner| owner.to_bytes().to_vec())
+        };
+
+        // The token balance of the source or destination account of a transfer, given as
+        // indexes into the transaction's accounts.
+        let token_balance = |transfer_accounts: [Option<&u8>; 2]| {
+            meta.post_token_balances.iter().find(|balance| {
+                transfer_accounts
+                    .iter()
+                    .flatten()
+                    .any(|account_index| u32::from(**account_index) == balance.account_index)
+            })
+        };
+        // Transfers that don't name their mint take it from the token balances. Without one, only
+        // that transfer is skipped, so the rest of the transaction is still indexed.
+        let mint = |transfer_accounts| {
+            let mint = token_balance(transfer_accounts)
+                .map(|balance| Pubkey::from_str(&balance.mint))
+                .transpose()?;
+            if mint.is_none() {
+                warn!(
+                    "Skipping a transfer of transaction {} without token balances to take its mint from",
+                    signature
+                );
+                metric! {
+                    statsd_count!("transfer_without_token_balance", 1);
+                }
+            }
+            Ok::<_, IndexerError>(mint)
+        };
+        let decimals = |transfer_accounts| {
+            token_balance(transfer_accounts)
+                .and_then(|balance| balance.ui_token_amount.as_ref())
+                .and_then(|ui_token_amount| u8::try_from(ui_token_amount.decimals).ok())
+        };
+        let token_program_id = Pubkey::from_str("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA")?;
+        let token_extensions_program_id =
+            Pubkey::from_str("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb")?;
+        let memo = find_memo(message.instructions.iter().filter_map(|ix| {
+            accounts
+                .get(ix.program_id_index as usize)
+                .and_then(|program_id| Pubkey::try_from(program_id.as_slice()).ok())
+                .map(|program_id| (program_id, ix.data.as_slice()))
+        }));
 
         let mut instruction_groups: Vec<InstructionGroup> = Vec::new();
 
-        for ix in message.instructions.iter() {
+        for (instruction_index, ix) in message.instructions.iter().enumerate() {
+            let outer_error = |e| PollerParser::error_at(e, &signature, instruction_index, None);
             let program_id_index = ix.program_id_index as usize;
             if program_id_index >= accounts.len() {
-                return Err(IndexerError::ParserError(
-                    "Program ID index out of bounds".to_string(),
-                ));
+                return Err(outer_error(IndexerError::ParserError(format!(
+                    "Program ID index {} out of bounds of {} accounts",
+                    program_id_index,
+                    accounts.len()
+                ))));
             }
-            let program_id = Pubkey::try_from(accounts[program_id_index].clone())
-                .map_err(|_| IndexerError::ParserError("error parsing program id".to_string()))?;
+            let program_id = Pubkey::try_from(accounts[program_id_index].clone()).map_err(|_| {
+                outer_error(IndexerError::ParserError("error parsing program id".to_string()))
+            })?;
             let data = ix.data.clone();
             let instruction_accounts: Vec<Pubkey> = ix
                 .accounts
@@ -414,145 +1099,318 @@ impl GrpcParser {
                 .map(|account_index| {
                     let account_index = *account_index as usize;
                     if account_index >= accounts.len() {
-                        return Err(IndexerError::ParserError(
-                            "Account index out of bounds".to_string(),
-                        ));
+                        return Err(IndexerError::ParserError(format!(
+                            "Account index {} out of bounds of {} accounts",
+                            account_index,
+                            accounts.len()
+                        )));
                     }
                     Pubkey::try_from(accounts[account_index].clone()).map_err(|_| {
                         IndexerError::ParserError("error getting accounts from grpc".to_string())
                     })
                 })
-                .collect::<Result<Vec<_>, IndexerError>>()?;
+                .collect::<Result<Vec<_>, IndexerError>>()
+                .map_err(outer_error)?;
 
-            let token_program_id = Pubkey::from_str("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA")?;
-            let token_extensions_program_id =
-                Pubkey::from_str("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb")?;
-            let mut inner_instructions = Vec::new();
-
-            if (program_id == token_program_id || program_id == token_extensions_program_id)
-                && instruction_accounts.len() >= 2
-            {
-                let source_address = instruction_accounts[0];
-                let destination_address = instruction_accounts[1];
-
-                if let Ok(spl_token::instruction::TokenInstruction::Transfer { amount }) =
-                    spl_token::instruction::TokenInstruction::unpack(&data)
+            let mut outer_instruction = None;
+            if program_id == token_program_id || program_id == token_extensions_program_id {
+                let transfer = match decode_transfer(&program_id, &data)
+                    .filter(|transfer| transfer.destination < instruction_accounts.len())
                 {
-                    let mint = meta
-                        .post_token_balances
-                        .first()
-                        .map(|balance| Pubkey::from_str(&balance.mint))
-                        .transpose()?
-                        .ok_or(IndexerError::ParserError(
-                            "Token balance not found".to_string(),
-                        ))?;
-
+                    Some(transfer) => match transfer.mint {
+                        Some(mint_index) => Some((transfer, instruction_accounts[mint_index])),
+                        None => mint([ix.accounts.get(transfer.source), ix.accounts.get(transfer.destination)])
+                            .map_err(outer_error)?
+                            .map(|mint| (transfer, mint)),
+                    },
+                    None => None,
+                };
+                if let Some((transfer, mint)) = transfer {
+                    let transfer_accounts = [ix.accounts.get(transfer.source), ix.accounts.get(transfer.destination)];
+                    let source_address = instruction_accounts[transfer.source];
+                    let destination_address = instruction_accounts[transfer.destination];
                     let source_ata = Some(
-                        find_associated_token_address(source_address, mint, Some(program_id))?
+                        find_associated_token_address(source_address, mint, Some(program_id))
+                            .map_err(outer_error)?
                             .to_bytes()
                             .to_vec(),
                     );
                     let destination_ata = Some(
-                        find_associated_token_address(destination_address, mint, Some(program_id))?
+                        find_associated_token_address(destination_address, mint, Some(program_id))
+                            .map_err(outer_error)?
                             .to_bytes()
                             .to_vec(),
                     );
-                    for inner_instruction_group in meta.inner_instructions.iter() {
-                        let InnerInstructions {
-                            index: _,
-                            instructions,
-                        } = inner_instruction_group;
-                        for instruction in instructions {
-                            let inner_data = instruction.data.clone();
-                            let inner_accounts: Vec<Pubkey> = instruction
-                                .accounts
-                                .iter()
-                                .filter_map(|account_index| {
-                                    let account_index = *account_index as usize;
-                                    if account_index >= accounts.len() {
-                                        error!(
-                                            "Error: Account index out of bounds: {} (len: {}). Skipping this account.",
-                                            account_index, accounts.len()
-                                        );
-                                        return None;
-                                    }
-                                    let pubkey =
-                                        Pubkey::try_from(accounts[account_index].clone()).ok()?;
-                                    Some(pubkey)
-                                })
-                                .collect();
-
-                            inner_instructions.push(Instruction {
-                                program_id,
-                                data: inner_data,
-                                accounts: inner_accounts,
-                                source_address: source_address.to_bytes().to_vec(),
-                                destination_address: destination_address.to_bytes().to_vec(),
-                                source_ata: None,
-                                destination_ata: None,
-                                mint: None,
-                                amount,
-                            });
+
+                    outer_instruction = Some(Instruction {
+                        program_id,
+                        data,
+                        accounts: instruction_accounts,
+                        source_address: source_address.to_bytes().to_vec(),
+                        destination_address: destination_address.to_bytes().to_vec(),
+                        source_ata,
+                        destination_ata,
+                        source_owner: owner_of(ix.accounts.get(transfer.source)),
+                        destination_owner: owner_of(ix.accounts.get(transfer.destination)),
+                        mint: Some(mint.to_bytes().to_vec()),
+                        decimals: transfer.decimals.or_else(|| decimals(transfer_accounts)),
+                        amount: transfer.amount,
+                        fee: transfer.fee,
+                        instruction_index: instruction_index as u8,
+                        inner_instruction_index: None,
+                    });
+                }
+            }
+
+            // Transfers made through CPI by this instruction, e.g. when routed through a swap program.
+            let mut inner_instructions = Vec::new();
+            for inner_instruction_group in meta
+                .inner_instructions
+                .iter()
+                .filter(|group| group.index as usize == instruction_index)
+            {
+                let InnerInstructions {
+                    index,
+                    instructions,
+                } = inner_instruction_group;
+                for (inner_instruction_index, instruction) in instructions.iter().enumerate() {
+                    let inner_program_id = match accounts
+                        .get(instruction.program_id_index as usize)
+                        .and_then(|program_id| Pubkey::try_from(program_id.clone()).ok())
+                    {
+                        Some(inner_program_id) => inner_program_id,
+                        None => {
+                            error!(
+                                "Error: Inner program ID index out of bounds: {}. Skipping this instruction.",
+                                instruction.program_id_index
+                            );
+                            continue;
                         }
+                    };
+                    if inner_program_id != token_program_id
+                        && inner_program_id != token_extensions_program_id
+                    {
+                        continue;
                     }
-
-                    instruction_groups.push(InstructionGroup {
-                        outer_instruction: Instruction {
-                            program_id,
-                            data,
-                            accounts: instruction_accounts,
-                            source_address: source_address.to_bytes().to_vec(),
-                            destination_address: destination_address.to_bytes().to_vec(),
-                            source_ata,
-                            destination_ata,
-                            mint: Some(mint.to_bytes().to_vec()),
-                            amount,
+                    let transfer = match decode_transfer(&inner_program_id, &instruction.data) {
+                        Some(transfer) => transfer,
+                        None => continue,
+                    };
+                    let inner_data = instruction.data.clone();
+                    let inner_accounts: Vec<Pubkey> = instruction
+                        .accounts
+                        .iter()
+                        .filter_map(|account_index| {
+                            let account_index = *account_index as usize;
+                            if account_index >= accounts.len() {
+                                error!(
+                                    "Error: Account index out of bounds: {} (len: {}). Skipping this account.",
+                                    account_index, accounts.len()
+                                );
+                                return None;
+                            }
+                            let pubkey =
+                                Pubkey::try_from(accounts[account_index].clone()).ok()?;
+                            Some(pubkey)
+                        })
+                        .collect();
+                    if inner_accounts.len() <= transfer.destination {
+                        continue;
+                    }
+                    let transfer_accounts = [
+                        instruction.accounts.get(transfer.source),
+                        instruction.accounts.get(transfer.destination),
+                    ];
+                    let mint = match transfer.mint {
+                        Some(mint_index) => inner_accounts[mint_index],
+                        None => match mint(transfer_accounts)? {
+                            Some(mint) => mint,
+                            None => continue,
                         },
-                        inner_instructions,
+                    };
+
+                    inner_instructions.push(Instruction {
+                        program_id: inner_program_id,
+                        data: inner_data,
+                        source_address: inner_accounts[transfer.source].to_bytes().to_vec(),
+                        destination_address: inner_accounts[transfer.destination].to_bytes().to_vec(),
+                        accounts: inner_accounts,
+                        source_ata: None,
+                        destination_ata: None,
+                        source_owner: owner_of(instruction.accounts.get(transfer.source)),
+                        destination_owner: owner_of(instruction.accounts.get(transfer.destination)),
+                        mint: Some(mint.to_bytes().to_vec()),
+                        decimals: transfer.decimals.or_else(|| decimals(transfer_accounts)),
+                        amount: transfer.amount,
+                        fee: transfer.fee,
+                        instruction_index: *index as u8,
+                        inner_instruction_index: Some(inner_instruction_index as u8),
                     });
                 }
             }
+
+            let transfer_program_id = match (&outer_instruction, inner_instructions.first()) {
+                (Some(outer_instruction), _) => outer_instruction.program_id,
+                (None, Some(inner_instruction)) => inner_instruction.program_id,
+                (None, None) => continue,
+            };
+            instruction_groups.push(InstructionGroup {
+                token_type: token_type(&transfer_program_id),
+                outer_instruction,
+                inner_instructions,
+            });
         }
         if instruction_groups.is_empty() {
             return Ok(None);
         }
 
+        let mut balance_changes: HashMap<Vec<u8>, i128> = HashMap::new();
+        for (balances, sign) in [(&meta.pre_token_balances, -1), (&meta.post_token_balances, 1)] {
+            for balance in balances {
+                let account = accounts.get(balance.account_index as usize);
+                let amount = balance
+                    .ui_token_amount
+                    .as_ref()
+                    .and_then(|ui_token_amount| ui_token_amount.amount.parse::<u64>().ok());
+                if let (Some(account), Some(amount)) = (account, amount) {
+                    *balance_changes.entry(account.clone()).or_default() += sign * amount as i128;
+                }
+            }
+        }
+        derive_transfer_fees(&mut instruction_groups, &balance_changes);
+
         Ok(Some(Transaction {
             instruction_groups,
             signature,
             error,
             slot,
             block_time,
+            fee_payer,
+            fee: meta.fee,
+            memo,
         }))
     }
 
+    fn balance_changes(
+        transaction: &SubscribeUpdateTransactionInfo,
+        slot: u64,
+        block_time: i64,
+    ) -> Vec<BalanceChange> {
+        let (Some(meta), Some(message), Ok(signature)) = (
+            transaction.meta.as_ref(),
+            transaction
+                .transaction
+                .as_ref()
+                .and_then(|transaction| transaction.message.as_ref()),
+            Signature::try_from(transaction.signature.as_slice()),
+        ) else {
+            return Vec::new();
+        };
+        // Account indexes past the static keys would resolve to the wrong accounts.
+        let (expected_writable, expected_readonly) = message
+            .address_table_lookups
+            .iter()
+            .fold((0, 0), |(writable, readonly), lookup| {
+                (
+                    writable + lookup.writable_indexes.len(),
+                    readonly + lookup.readonly_indexes.len(),
+                )
+            });
+        if meta.loaded_writable_addresses.len() != expected_writable
+            || meta.loaded_readonly_addresses.len() != expected_readonly
+        {
+            return Vec::new();
+        }
+        let accounts = message
+            .account_keys
+            .iter()
+            .chain(meta.loaded_writable_addresses.iter())
+            .chain(meta.loaded_readonly_addresses.iter())
+            .cloned()
+            .collect::<Vec<_>>();
+        let token_balances = |balances: &[TokenBalance]| -> Vec<TokenAccountBalance> {
+            balances
+                .iter()
+                .filter_map(|balance| {
+                    let ui_token_amount = balance.ui_token_amount.as_ref()?;
+                    Some(TokenAccountBalance {
+                        account_index: balance.account_index,
+                        mint: balance.mint.clone(),
+                        owner: Some(balance.owner.clone()).filter(|owner| !owner.is_empty()),
+                        decimals: u8::try_from(ui_token_amount.decimals).ok()?,
+                        amount: ui_token_amount.amount.parse().ok()?,
+                    })
+                })
+                .collect()
+        };
+        net_balance_changes(
+            token_balances(&meta.pre_token_balances),
+            token_balances(&meta.post_token_balances),
+            &accounts,
+            signature,
+            slot,
+            block_time,
+        )
+    }
+
     pub fn parse_block(block: SubscribeUpdateBlock) -> Result<BlockInfo, IndexerError> {
+        let (Some(block_time), Some(block_height)) = (block.block_time, block.block_height) else {
+            // The metadata row and the transfers of such a block would be stored as of 1970, so it
+            // is only recorded as a parse failure of its slot, which `reprocess` fetches over RPC.
+            warn!("Skipping block {} without a block time or height", block.slot);
+            metric! {
+                statsd_count!("invalid_block_time", 1);
+            }
+            return Ok(BlockInfo {
+                metadata: BlockMetadata {
+                    slot: block.slot,
+                    parent_slot: block.parent_slot,
+                    blockhash: block.blockhash,
+                    parent_blockhash: block.parent_blockhash,
+                    ..Default::default()
+                },
+                parse_failures: vec![ParseFailure {
+                    signature: None,
+                    slot: block.slot,
+                    error: "Block has no block time or height".to_string(),
+                }],
+                ..Default::default()
+            });
+        };
         let metadata = BlockMetadata {
             slot: block.slot,
             parent_slot: block.parent_slot,
-            block_time: block.block_time.unwrap().timestamp,
+            block_time: block_time.timestamp,
             blockhash: block.blockhash,
             parent_blockhash: block.parent_blockhash,
-            block_height: block.block_height.unwrap().block_height,
+            block_height: block_height.block_height,
         };
 
-        let transactions: Result<Vec<Transaction>, IndexerError> = block
+        let mut parse_failures = Vec::new();
+        let mut balance_changes = Vec::new();
+        let transactions = block
             .transactions
             .into_iter()
-            .map(|transaction| {
-                Self::parse_transaction(transaction, metadata.slot, metadata.block_time)
-            })
-            .filter_map(|result| match result {
-                Ok(Some(transaction)) => Some(Ok(transaction)),
-                Ok(None) => None,
-                Err(e) => Some(Err(e)),
+            .filter_map(|transaction| {
+                let signature = Signature::try_from(transaction.signature.as_slice()).ok();
+                balance_changes.extend(Self::balance_changes(
+                    &transaction,
+                    metadata.slot,
+                    metadata.block_time,
+                ));
+                skip_unparseable(
+                    Self::parse_transaction(transaction, metadata.slot, metadata.block_time),
+                    signature,
+                    metadata.slot,
+                    &mut parse_failures,
+                )
             })
-            .collect();
-
-        let transactions = transactions?;
+            .collect::<Vec<_>>();
         Ok(BlockInfo {
             metadata,
             transactions,
+            parse_failures,
+            balance_changes,
         })
     }
 
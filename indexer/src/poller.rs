@@ -1,22 +1,77 @@
-use std::{pin::Pin, sync::Arc, thread::sleep, time::Duration};
+use std::{
+    collections::BTreeMap,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use cadence_macros::statsd_count;
+use common::metric;
 
 use async_stream::stream;
 use futures::{stream::FuturesUnordered, Stream, StreamExt};
-use solana_client::{
-    nonblocking::rpc_client::RpcClient, rpc_config::RpcBlockConfig, rpc_request::RpcError,
-};
+use solana_client::{rpc_config::RpcBlockConfig, rpc_request::RpcError};
 use solana_sdk::commitment_config::CommitmentConfig;
 use solana_transaction_status::{TransactionDetails, UiTransactionEncoding};
+use tokio::time::sleep;
 
 use crate::{
     error::IndexerError,
     parser::PollerParser,
-    streamer::{fetch_current_slot, Streamer},
+    rpc::{report_rpc_call, FailoverRpcClient},
+    streamer::{fetch_confirmed_slot, Streamer},
     types::{BlockInfo, BlockStreamConfig},
 };
 
 const SKIPPED_BLOCK_ERRORS: [i64; 2] = [-32007, -32009];
-const FAILED_BLOCK_LOGGING_FREQUENCY: u64 = 100;
+const INITIAL_BLOCK_FETCH_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BLOCK_FETCH_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Why the block of a slot is missing from the poller stream.
+#[derive(Debug, PartialEq, Eq)]
+pub enum BlockFetchError {
+    /// The slot has no block, e.g. because its leader skipped it. Not worth retrying.
+    SlotSkipped,
+    /// The block could not be fetched or parsed, even after retrying transient errors.
+    FetchFailed(IndexerError),
+}
+
+/// Holds fetched blocks back until every slot before them is resolved, so that they come out in
+/// slot order however the fetches complete. A skipped slot, or one whose block couldn't be
+/// fetched, is resolved without a block.
+pub struct SlotReorderBuffer {
+    next_slot: u64,
+    resolved: BTreeMap<u64, Option<BlockInfo>>,
+}
+
+impl SlotReorderBuffer {
+    pub fn new(first_slot: u64) -> Self {
+        SlotReorderBuffer {
+            next_slot: first_slot,
+            resolved: BTreeMap::new(),
+        }
+    }
+
+    /// The lowest slot that isn't resolved yet.
+    pub fn next_slot(&self) -> u64 {
+        self.next_slot
+    }
+
+    /// Resolves `slot`, and returns the blocks that are now ready in slot order.
+    pub fn push(&mut self, slot: u64, block: Option<BlockInfo>) -> Vec<BlockInfo> {
+        if slot < self.next_slot {
+            return vec![];
+        }
+        self.resolved.insert(slot, block);
+
+        let mut ready = vec![];
+        while let Some(block) = self.resolved.remove(&self.next_slot) {
+            self.next_slot += 1;
+            ready.extend(block);
+        }
+        ready
+    }
+}
 
 #[derive(Clone)]
 pub struct PollerStreamer {
@@ -24,12 +79,15 @@ pub struct PollerStreamer {
 }
 
 impl Streamer for PollerStreamer {
+    // Follows the tip from `slot`, `confirmation_depth` slots behind it.
     fn load_block_stream(&self, slot: u64) -> Pin<Box<dyn Stream<Item = BlockInfo> + Send + '_>> {
         Box::pin(PollerStreamer::get_poller_block_stream(
             self.config.rpc_client.clone(),
-            self.config.last_indexed_slot,
+            slot,
             self.config.max_concurrent_block_fetches,
-            Some(slot),
+            self.config.max_block_fetch_retries,
+            self.config.confirmation_depth,
+            None,
         ))
     }
 }
@@ -39,10 +97,20 @@ impl PollerStreamer {
         Self { config }
     }
 
-    async fn get_block(client: &RpcClient, slot: u64) -> Result<BlockInfo, IndexerError> {
-        let mut attempt_counter = 0;
+    // Retries with exponential backoff. Once `max_retries` retries have failed the slot is
+    // reported and left out of the stream, so a slot the RPC node can't serve doesn't stall the
+    // poller. Skipped slots are returned right away.
+    pub async fn get_block(
+        client: &FailoverRpcClient,
+        slot: u64,
+        max_retries: u32,
+    ) -> Result<BlockInfo, BlockFetchError> {
+        let mut retries = 0;
+        let mut backoff = INITIAL_BLOCK_FETCH_BACKOFF;
         loop {
+            let started_at = Instant::now();
             match client
+                .client()
                 .get_block_with_config(
                     slot,
                     RpcBlockConfig {
@@ -55,33 +123,64 @@ impl PollerStreamer {
                 )
                 .await
             {
-                Ok(block) => match PollerParser::parse_ui_confirmed_block(block, slot) {
-                    Ok(block_info) => return Ok(block_info),
-                    Err(e) => return Err(e),
-                },
+                Ok(block) => {
+                    client.record_success();
+                    report_rpc_call("getBlock", started_at, None);
+                    return PollerParser::parse_ui_confirmed_block(block, slot)
+                        .map_err(BlockFetchError::FetchFailed);
+                }
                 Err(e) => {
                     if let solana_client::client_error::ClientErrorKind::RpcError(
                         RpcError::RpcResponseError { code, .. },
                     ) = e.kind
                     {
+                        // The node answered, the slot just has no block.
                         if SKIPPED_BLOCK_ERRORS.contains(&code) {
-                            log::warn!("Skipped block: {}", slot);
-                            return Err(IndexerError::ParserError(e.to_string()));
+                            client.record_success();
+                            report_rpc_call("getBlock", started_at, None);
+                            log::debug!("Skipped slot: {}", slot);
+                            metric! {
+                                statsd_count!("slot_skipped", 1);
+                            }
+                            return Err(BlockFetchError::SlotSkipped);
                         }
                     }
-                    if attempt_counter % FAILED_BLOCK_LOGGING_FREQUENCY == 1 {
-                        log::warn!("Failed to fetch block: {}. {}", slot, e.to_string());
+                    client.record_failure();
+                    report_rpc_call("getBlock", started_at, Some(&e));
+                    if retries >= max_retries {
+                        log::error!(
+                            "Giving up on block {} after {} retries. {}",
+                            slot,
+                            retries,
+                            e.to_string()
+                        );
+                        metric! {
+                            statsd_count!("block_fetch_failed", 1);
+                        }
+                        return Err(BlockFetchError::FetchFailed(
+                            IndexerError::BatchInitNetworkingError(e.to_string()),
+                        ));
                     }
-                    attempt_counter += 1;
+                    log::warn!(
+                        "Failed to fetch block: {}. Retrying in {:?}. {}",
+                        slot,
+                        backoff,
+                        e.to_string()
+                    );
+                    sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BLOCK_FETCH_BACKOFF);
+                    retries += 1;
                 }
             }
         }
     }
 
     pub fn get_poller_block_stream(
-        client: Arc<RpcClient>,
+        client: Arc<FailoverRpcClient>,
         last_indexed_slot: u64,
         max_concurrent_block_fetches: usize,
+        max_block_fetch_retries: u32,
+        confirmation_depth: u64,
         end_block_slot: Option<u64>,
     ) -> impl futures::Stream<Item = BlockInfo> {
         stream! {
@@ -91,47 +190,61 @@ impl PollerStreamer {
             };
 
             let polls_forever = end_block_slot.is_none();
-            let mut end_block_slot = end_block_slot.unwrap_or(fetch_current_slot(client.as_ref()).await);
+            let mut end_block_slot = match end_block_slot {
+                Some(end_block_slot) => end_block_slot,
+                None => fetch_confirmed_slot(client.as_ref(), confirmation_depth).await,
+            };
+            let mut reorder_buffer = SlotReorderBuffer::new(current_slot_to_fetch);
+            let mut block_fetching_futures = FuturesUnordered::new();
             loop {
-                if current_slot_to_fetch > end_block_slot  && !polls_forever {
-                    break;
-                }
-
-                while current_slot_to_fetch > end_block_slot {
-                    end_block_slot = fetch_current_slot(client.as_ref()).await;
-                    if end_block_slot <= current_slot_to_fetch {
-                        sleep(Duration::from_millis(10));
-                    }
-                }
-
-                let mut block_fetching_futures_batch = vec![];
-                while block_fetching_futures_batch.len() < max_concurrent_block_fetches && current_slot_to_fetch <= end_block_slot  {
+                // The window only moves past a slot once it is resolved, so the blocks held
+                // back behind a slow slot stay within `max_concurrent_block_fetches`.
+                while current_slot_to_fetch < reorder_buffer.next_slot() + max_concurrent_block_fetches as u64
+                    && current_slot_to_fetch <= end_block_slot
+                {
+                    let slot = current_slot_to_fetch;
                     let client = client.clone();
-                    block_fetching_futures_batch.push(PollerStreamer::fetch_block_with_using_arc(
-                        client.clone(),
-                        current_slot_to_fetch,
-                    ));
+                    block_fetching_futures.push(async move {
+                        let block = PollerStreamer::fetch_block_with_using_arc(
+                            client,
+                            slot,
+                            max_block_fetch_retries,
+                        )
+                        .await;
+                        (slot, block)
+                    });
                     current_slot_to_fetch += 1;
                 }
-                let blocks_to_yield = block_fetching_futures_batch
-                    .into_iter()
-                    .collect::<FuturesUnordered<_>>()
-                    .collect::<Vec<_>>()
-                    .await;
-                let mut blocks_to_yield: Vec<_>  = blocks_to_yield.into_iter().flatten().collect();
-                blocks_to_yield.sort_by_key(|block| block.metadata.slot);
-                for block in blocks_to_yield.drain(..) {
-                    yield block;
-                }
 
+                match block_fetching_futures.next().await {
+                    // Skipped and failed slots were already reported. Skipped slots are recorded
+                    // from the parent slot of the next block.
+                    Some((slot, block)) => {
+                        for block in reorder_buffer.push(slot, block.ok()) {
+                            yield block;
+                        }
+                    }
+                    None => {
+                        if !polls_forever {
+                            break;
+                        }
+                        while current_slot_to_fetch > end_block_slot {
+                            end_block_slot = fetch_confirmed_slot(client.as_ref(), confirmation_depth).await;
+                            if end_block_slot <= current_slot_to_fetch {
+                                sleep(Duration::from_millis(10)).await;
+                            }
+                        }
+                    }
+                }
             }
         }
     }
 
     async fn fetch_block_with_using_arc(
-        client: Arc<RpcClient>,
+        client: Arc<FailoverRpcClient>,
         slot: u64,
-    ) -> Result<BlockInfo, IndexerError> {
-        Self::get_block(client.as_ref(), slot).await
+        max_retries: u32,
+    ) -> Result<BlockInfo, BlockFetchError> {
+        Self::get_block(client.as_ref(), slot, max_retries).await
     }
 }
@@ -0,0 +1,90 @@
+use std::collections::VecDeque;
+
+use cadence_macros::statsd_count;
+use common::metric;
+use log::{error, info, warn};
+use solana_sdk::clock::Slot;
+
+use crate::{
+    db::Dao,
+    error::IndexerError,
+    parser::filter_block,
+    poller::{BlockFetchError, PollerStreamer},
+    rpc::FailoverRpcClient,
+    types::IndexFilter,
+};
+
+/// Fetches the blocks of the slots in `[from_slot, to_slot]` that have transactions in the
+/// `failed_transactions` table, parses them again and indexes them. Only the transactions that
+/// still can't be parsed stay recorded, so a parser fix applies to the blocks indexed before it.
+/// The canonical blocks of slots that turn out to be on an abandoned fork are indexed as well.
+pub async fn reprocess(
+    rpc_client: &FailoverRpcClient,
+    dao: &Dao,
+    index_filter: &IndexFilter,
+    from_slot: Slot,
+    to_slot: Slot,
+    max_retries: u32,
+) -> Result<(), IndexerError> {
+    if from_slot > to_slot {
+        return Err(IndexerError::ConfigurationError {
+            msg: format!(
+                "reprocess needs --from {} to be at most --to {}",
+                from_slot, to_slot
+            ),
+        });
+    }
+    let slots = dao.fetch_failed_slots(from_slot, to_slot).await?;
+    info!(
+        "Reprocessing {} slots with parse failures between {} and {}",
+        slots.len(),
+        from_slot,
+        to_slot
+    );
+
+    let mut slots = VecDeque::from(slots);
+    let mut unprocessed_slots = vec![];
+    while let Some(slot) = slots.pop_front() {
+        let mut block = match PollerStreamer::get_block(rpc_client, slot, max_retries).await {
+            Ok(block) => block,
+            Err(BlockFetchError::SlotSkipped) => {
+                warn!(
+                    "Slot {} has no block anymore, keeping its parse failures",
+                    slot
+                );
+                unprocessed_slots.push(slot);
+                continue;
+            }
+            Err(BlockFetchError::FetchFailed(e)) => {
+                error!("Failed to fetch block {}: {}", slot, e);
+                unprocessed_slots.push(slot);
+                continue;
+            }
+        };
+        filter_block(&mut block, index_filter);
+        match dao.reprocess_block(&block).await {
+            Ok(slots_to_refetch) => slots.extend(slots_to_refetch),
+            Err(e) => {
+                error!("Failed to reprocess block {}: {}", slot, e);
+                unprocessed_slots.push(slot);
+                continue;
+            }
+        }
+        info!(
+            "Reprocessed slot {}, {} transactions are still unparseable",
+            slot,
+            block.parse_failures.len()
+        );
+        metric! {
+            statsd_count!("slot_reprocessed", 1);
+        }
+    }
+
+    if !unprocessed_slots.is_empty() {
+        return Err(IndexerError::BatchInitNetworkingError(format!(
+            "Failed to reprocess slots {:?}",
+            unprocessed_slots
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use std::{
+    sync::{
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use cadence_macros::{statsd_count, statsd_time};
+use common::{config::redact_url, metric};
+use log::warn;
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind},
+    nonblocking::rpc_client::RpcClient,
+    rpc_request::RpcError,
+};
+
+// Consecutive failures on the current endpoint before requests move on to the next one.
+const RPC_FAILOVER_THRESHOLD: u32 = 3;
+
+/// A list of RPC endpoints that are used one at a time. Callers report the outcome of their
+/// requests, and after repeated failures the next endpoint takes over, wrapping around.
+pub struct FailoverRpcClient {
+    clients: Vec<Arc<RpcClient>>,
+    current: AtomicUsize,
+    consecutive_failures: AtomicU32,
+}
+
+impl FailoverRpcClient {
+    pub fn new(clients: Vec<Arc<RpcClient>>) -> Self {
+        assert!(!clients.is_empty(), "at least one RPC endpoint is required");
+        FailoverRpcClient {
+            clients,
+            current: AtomicUsize::new(0),
+            consecutive_failures: AtomicU32::new(0),
+        }
+    }
+
+    /// The endpoint requests should currently be sent to.
+    pub fn client(&self) -> Arc<RpcClient> {
+        self.clients[self.current.load(Ordering::SeqCst) % self.clients.len()].clone()
+    }
+
+    pub fn record_success(&self) {
+        self.consecutive_failures.store(0, Ordering::SeqCst);
+    }
+
+    pub fn record_failure(&self) {
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::SeqCst) + 1;
+        // Concurrent requests fail together, only the one that reaches the threshold switches.
+        if failures != RPC_FAILOVER_THRESHOLD || self.clients.len() == 1 {
+            return;
+        }
+        let next = (self.current.fetch_add(1, Ordering::SeqCst) + 1) % self.clients.len();
+        self.consecutive_failures.store(0, Ordering::SeqCst);
+        warn!(
+            "Switching to RPC endpoint {} after {} consecutive failures",
+            redact_url(&self.clients[next].url()),
+            failures
+        );
+        metric! {
+            statsd_count!("rpc_failover", 1);
+        }
+    }
+}
+
+impl From<Arc<RpcClient>> for FailoverRpcClient {
+    fn from(client: Arc<RpcClient>) -> Self {
+        FailoverRpcClient::new(vec![client])
+    }
+}
+
+fn rpc_error_kind(error: &ClientError) -> &'static str {
+    match &error.kind {
+        ClientErrorKind::Io(_) => "io",
+        ClientErrorKind::Reqwest(e) if e.is_timeout() => "timeout",
+        ClientErrorKind::Reqwest(_) => "http",
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { .. }) => "rpc_response",
+        ClientErrorKind::RpcError(_) => "rpc",
+        ClientErrorKind::SerdeJson(_) => "serde",
+        _ => "other",
+    }
+}
+
+/// Reports how long an RPC call to `method` took and whether it failed. Failures are also
+/// counted by kind, to tell an unreachable endpoint from one returning errors.
+pub fn report_rpc_call(method: &str, started_at: Instant, error: Option<&ClientError>) {
+    metric! {
+        let success = error.is_none().to_string();
+        statsd_time!("rpc_call", started_at.elapsed(), "method" => method, "success" => &success);
+        if let Some(error) = error {
+            statsd_count!("rpc_error", 1, "method" => method, "kind" => rpc_error_kind(error));
+        };
+    }
+}
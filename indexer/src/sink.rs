@@ -0,0 +1,135 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use common::db::setup_database_connection;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::clock::Slot;
+
+use crate::{
+    config::{IndexerBackend, IndexerConfig},
+    db::Dao,
+    error::IndexerError,
+    mints::MintCache,
+    types::{BalanceChange, BlockMetadata, ParseFailure, Transaction},
+};
+
+/// The write side of the indexer. The messenger workers hand every batch of parsed blocks and
+/// transfers to a sink.
+#[async_trait]
+pub trait TransferSink: Send + Sync {
+    /// Returns the slots that need to be fetched again, because the indexed block at the slot
+    /// turned out to be on an abandoned fork.
+    async fn index_block_metadatas(
+        &self,
+        blocks: Vec<&BlockMetadata>,
+    ) -> Result<Vec<Slot>, IndexerError>;
+
+    async fn index_transaction(&self, transactions: &[Transaction]) -> Result<(), IndexerError>;
+
+    /// Keeps a record of transactions that were skipped because they could not be parsed. Sinks
+    /// without anywhere to put them rely on the warning logged by the parser.
+    async fn record_parse_failures(&self, _failures: &[ParseFailure]) -> Result<(), IndexerError> {
+        Ok(())
+    }
+
+    /// Records that every block up to `slot` is written, so that a restarted indexer resumes
+    /// after it. Sinks without anywhere to put it start from the configured slot again.
+    async fn record_indexed_slot(&self, _slot: Slot) -> Result<(), IndexerError> {
+        Ok(())
+    }
+
+    /// The slot last recorded by `record_indexed_slot`.
+    async fn fetch_indexed_slot(&self) -> Result<Option<Slot>, IndexerError> {
+        Ok(None)
+    }
+
+    /// Writes the token balance changes of indexed blocks, which only Postgres stores.
+    async fn index_balance_changes(
+        &self,
+        _balance_changes: &[BalanceChange],
+    ) -> Result<(), IndexerError> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TransferSink for Dao {
+    async fn index_block_metadatas(
+        &self,
+        blocks: Vec<&BlockMetadata>,
+    ) -> Result<Vec<Slot>, IndexerError> {
+        Dao::index_block_metadatas(self, blocks).await
+    }
+
+    async fn index_transaction(&self, transactions: &[Transaction]) -> Result<(), IndexerError> {
+        self.index_transactions_concurrently(transactions, self.insert_concurrency)
+            .await
+    }
+
+    async fn record_parse_failures(&self, failures: &[ParseFailure]) -> Result<(), IndexerError> {
+        for failure in failures {
+            self.record_parse_failure(failure).await?;
+        }
+        Ok(())
+    }
+
+    async fn index_balance_changes(
+        &self,
+        balance_changes: &[BalanceChange],
+    ) -> Result<(), IndexerError> {
+        Dao::index_balance_changes(self, balance_changes).await
+    }
+
+    async fn record_indexed_slot(&self, slot: Slot) -> Result<(), IndexerError> {
+        Dao::record_indexed_slot(self, slot).await
+    }
+
+    async fn fetch_indexed_slot(&self) -> Result<Option<Slot>, IndexerError> {
+        Dao::fetch_indexed_slot(self).await
+    }
+}
+
+/// Connects to the backend selected by `backend`.
+pub async fn setup_sink(
+    config: &IndexerConfig,
+    rpc_client: Arc<RpcClient>,
+) -> Result<Arc<dyn TransferSink>, IndexerError> {
+    match config.backend {
+        IndexerBackend::Postgres => Ok(Arc::new(setup_dao(config, rpc_client).await)),
+        IndexerBackend::ClickHouse => setup_clickhouse_sink(config).await,
+    }
+}
+
+/// Connects to Postgres, whatever the configured backend.
+pub async fn setup_dao(config: &IndexerConfig, rpc_client: Arc<RpcClient>) -> Dao {
+    let mut dao = Dao::new(
+        setup_database_connection(config.get_database_url(), &config.get_pool_config()).await,
+    )
+    .with_insert_concurrency(config.insert_concurrency)
+    .with_max_sql_inserts(config.get_max_sql_inserts())
+    .with_listener_channel(config.get_listener_channel())
+    .with_notify_max_payload_bytes(config.get_notify_max_payload_bytes())
+    .with_conflict_strategy(config.conflict_strategy);
+    if config.index_scaled_ui_amounts {
+        dao = dao.with_mint_cache(Arc::new(MintCache::new(rpc_client)));
+    }
+    dao
+}
+
+#[cfg(feature = "clickhouse")]
+async fn setup_clickhouse_sink(
+    config: &IndexerConfig,
+) -> Result<Arc<dyn TransferSink>, IndexerError> {
+    let sink = crate::clickhouse::ClickHouseSink::new(&config.get_clickhouse_config()?);
+    sink.create_tables().await?;
+    Ok(Arc::new(sink))
+}
+
+#[cfg(not(feature = "clickhouse"))]
+async fn setup_clickhouse_sink(
+    _config: &IndexerConfig,
+) -> Result<Arc<dyn TransferSink>, IndexerError> {
+    Err(IndexerError::ConfigurationError {
+        msg: "the indexer was built without the clickhouse feature".to_string(),
+    })
+}
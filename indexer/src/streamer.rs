@@ -1,18 +1,30 @@
-use std::{pin::Pin, sync::Arc, thread::sleep, time::Duration};
+use std::{collections::VecDeque, pin::Pin, sync::Arc, time::Duration};
 
-use cadence_macros::statsd_count;
+use cadence_macros::{statsd_count, statsd_gauge};
+use chrono::Utc;
 use common::metric;
 use futures::{pin_mut, Stream};
 use log::{error, info, warn};
 use solana_client::{nonblocking::rpc_client::RpcClient, rpc_config::RpcBlockConfig};
 use solana_sdk::commitment_config::CommitmentConfig;
 use solana_transaction_status::{TransactionDetails, UiTransactionEncoding};
+use tokio::{
+    sync::oneshot::error::TryRecvError,
+    time::{sleep, timeout_at, Instant},
+};
 use tokio_stream::StreamExt;
 
-use crate::{messenger::Messenger, types::BlockInfo};
+use crate::{
+    messenger::{BatchCommit, Messenger},
+    poller::PollerStreamer,
+    rpc::{report_rpc_call, FailoverRpcClient},
+    sink::TransferSink,
+    types::{BlockInfo, BlockStreamConfig},
+};
 
 const POST_BACKFILL_FREQUENCY: u64 = 100;
 const PRE_BACKFILL_FREQUENCY: u64 = 10;
+const REFETCH_BLOCK_RETRIES: u32 = 10;
 
 pub trait Streamer: Send {
     fn load_block_stream(&self, slot: u64) -> Pin<Box<dyn Stream<Item = BlockInfo> + Send + '_>>;
@@ -27,7 +39,7 @@ pub async fn get_genesis_hash(rpc_client: &RpcClient) -> String {
                 metric! {
                     statsd_count!("get_genesis_hash_error", 1);
                 }
-                sleep(Duration::from_secs(5));
+                sleep(Duration::from_secs(5)).await;
             }
         }
     }
@@ -50,39 +62,144 @@ pub async fn fetch_block_parent_slot(rpc_client: Arc<RpcClient>, slot: u64) -> u
         .parent_slot
 }
 
-pub async fn fetch_current_slot(client: &RpcClient) -> u64 {
+pub async fn fetch_current_slot(client: &FailoverRpcClient) -> u64 {
     loop {
-        match client.get_slot().await {
-            Ok(slot) => return slot,
+        let started_at = std::time::Instant::now();
+        match client.client().get_slot().await {
+            Ok(slot) => {
+                client.record_success();
+                report_rpc_call("getSlot", started_at, None);
+                return slot;
+            }
             Err(e) => {
                 error!("Failed to fetch current slot: {}", e);
-                sleep(Duration::from_secs(5));
+                client.record_failure();
+                report_rpc_call("getSlot", started_at, Some(&e));
+                sleep(Duration::from_secs(5)).await;
+            }
+        }
+    }
+}
+
+// The slot `confirmation_depth` slots behind the tip, the last one the live indexer fetches.
+pub async fn fetch_confirmed_slot(client: &FailoverRpcClient, confirmation_depth: u64) -> u64 {
+    fetch_current_slot(client)
+        .await
+        .saturating_sub(confirmation_depth)
+}
+
+// Fetches the canonical blocks of the slots whose indexed block was removed in a reorg, which
+// in turn lets their parents be checked.
+async fn refetch_reorged_blocks(messenger: &Messenger, rpc_client: Arc<FailoverRpcClient>) {
+    for slot in messenger.take_slots_to_refetch() {
+        info!(
+            "Fetching the canonical block at slot {} after a reorg",
+            slot
+        );
+        let blocks = PollerStreamer::get_poller_block_stream(
+            rpc_client.clone(),
+            slot.saturating_sub(1),
+            1,
+            REFETCH_BLOCK_RETRIES,
+            0,
+            Some(slot),
+        )
+        .collect::<Vec<_>>()
+        .await;
+        if !blocks.is_empty() {
+            messenger.send_block_batches(blocks).await;
+        }
+    }
+}
+
+// The batches sent to the messenger that aren't confirmed written yet, oldest first. The indexed
+// slot only moves past a block once it and every block before it are written, and a batch whose
+// write failed is sent again.
+#[derive(Default)]
+struct UncommittedBatches {
+    batches: VecDeque<(Vec<BlockInfo>, BatchCommit)>,
+}
+
+impl UncommittedBatches {
+    async fn send(&mut self, messenger: &Messenger, block_batch: Vec<BlockInfo>) {
+        let commit = messenger.send_block_batches(block_batch.clone()).await;
+        self.batches.push_back((block_batch, commit));
+    }
+
+    // Returns the last slot of the batches written since the previous call, if any.
+    async fn take_committed_slot(&mut self, messenger: &Messenger) -> Option<u64> {
+        let mut committed_slot = None;
+        while let Some((block_batch, commit)) = self.batches.front_mut() {
+            match commit.try_recv() {
+                Ok(true) => {
+                    committed_slot = block_batch.last().map(|block| block.metadata.slot);
+                    self.batches.pop_front();
+                }
+                Ok(false) | Err(TryRecvError::Closed) => {
+                    warn!(
+                        "Blocks {}-{} failed to be written, sending them again",
+                        block_batch.first().unwrap().metadata.slot,
+                        block_batch.last().unwrap().metadata.slot
+                    );
+                    metric! {
+                        statsd_count!("block_batch_resent", 1);
+                    }
+                    *commit = messenger.send_block_batches(block_batch.clone()).await;
+                    break;
+                }
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+        committed_slot
+    }
+
+    // Returns once every batch sent is written, sending the failed ones again meanwhile.
+    async fn wait_until_committed(&mut self, messenger: &Messenger) {
+        loop {
+            self.take_committed_slot(messenger).await;
+            if self.batches.is_empty() {
+                return;
             }
+            sleep(Duration::from_millis(100)).await;
         }
     }
 }
 
+// With `tail_only` the blocks before the current slot aren't fetched, so there is no backfill
+// to track and indexing starts at the tip. The tip is taken `confirmation_depth` slots back.
+// Every slot confirmed written is recorded in `sink` for a restart to resume after.
 pub async fn continously_index_new_blocks(
     streamer: Box<dyn Streamer + Send + Sync>,
     messenger: Arc<Messenger>,
-    rpc_client: Arc<RpcClient>,
+    sink: Arc<dyn TransferSink>,
+    rpc_client: Arc<FailoverRpcClient>,
     mut last_indexed_slot_at_start: u64,
+    tail_only: bool,
+    confirmation_depth: u64,
 ) -> tokio::task::JoinHandle<()> {
     tokio::spawn(async move {
-        let current_slot = fetch_current_slot(rpc_client.as_ref()).await;
-        if last_indexed_slot_at_start == 0 {
+        let current_slot = fetch_confirmed_slot(rpc_client.as_ref(), confirmation_depth).await;
+        if last_indexed_slot_at_start == 0 || tail_only {
             last_indexed_slot_at_start = current_slot;
         }
         let block_stream = streamer.load_block_stream(last_indexed_slot_at_start);
         pin_mut!(block_stream);
 
-        let number_of_blocks_to_backfill = current_slot - last_indexed_slot_at_start;
+        let number_of_blocks_to_backfill = current_slot.saturating_sub(last_indexed_slot_at_start);
 
         let mut last_indexed_slot = last_indexed_slot_at_start;
+        // Backfilled blocks arrive faster than the batches fill, so the size bounds them. At the
+        // tip of the chain a partial batch is sent once it has waited for the window.
+        let block_batch_size = messenger.block_batch_size();
+        let block_batch_window = messenger.block_batch_window();
+        let mut block_batch = Vec::with_capacity(block_batch_size);
+        let mut batch_deadline = None;
+        let mut uncommitted_batches = UncommittedBatches::default();
 
-        // Temp hack to not backfill or backfill blocks when we restart the indexer
-        let mut finished_backfill = false;
-        if !finished_backfill {
+        let mut finished_backfill = tail_only;
+        if tail_only {
+            info!("Tailing new blocks from slot {}", current_slot);
+        } else {
             warn!(
                 "Backfilling historical blocks. Current number of blocks to backfill: {}, Current slot: {}",
                 number_of_blocks_to_backfill, current_slot
@@ -90,14 +207,42 @@ pub async fn continously_index_new_blocks(
         }
 
         loop {
-            let block = block_stream.next().await.unwrap();
+            let block = match batch_deadline {
+                Some(deadline) => match timeout_at(deadline, block_stream.next()).await {
+                    Ok(block) => block,
+                    Err(_) => {
+                        batch_deadline = None;
+                        uncommitted_batches
+                            .send(&messenger, std::mem::take(&mut block_batch))
+                            .await;
+                        continue;
+                    }
+                },
+                None => block_stream.next().await,
+            }
+            .unwrap();
             let slot_indexed = block.metadata.slot;
-            messenger.send_block_batches(vec![block]).await;
+            // Seconds between the block being produced and it reaching the workers.
+            let lag = (Utc::now().timestamp() - block.metadata.block_time).max(0) as u64;
+            metric! {
+                statsd_gauge!("indexing_lag", lag);
+            }
+            if block_batch.is_empty() {
+                batch_deadline = Some(Instant::now() + block_batch_window);
+            }
+            block_batch.push(block);
+            if block_batch.len() >= block_batch_size {
+                batch_deadline = None;
+                uncommitted_batches
+                    .send(&messenger, std::mem::take(&mut block_batch))
+                    .await;
+            }
+            refetch_reorged_blocks(&messenger, rpc_client.clone()).await;
 
             if !finished_backfill {
                 let blocks_indexed = slot_indexed - last_indexed_slot_at_start;
                 if blocks_indexed <= number_of_blocks_to_backfill {
-                    if blocks_indexed % PRE_BACKFILL_FREQUENCY == 0 {
+                    if blocks_indexed.is_multiple_of(PRE_BACKFILL_FREQUENCY) {
                         info!(
                             "Backfilled {} / {} blocks",
                             blocks_indexed, number_of_blocks_to_backfill
@@ -107,15 +252,69 @@ pub async fn continously_index_new_blocks(
                     finished_backfill = true;
                     warn!("Finished backfilling historical blocks!");
                 }
-            } else {
-                for slot in last_indexed_slot..slot_indexed {
-                    if slot % POST_BACKFILL_FREQUENCY == 0 {
-                        info!("Indexed slot {}", slot);
+            }
+
+            if let Some(committed_slot) = uncommitted_batches.take_committed_slot(&messenger).await
+            {
+                if finished_backfill {
+                    for slot in last_indexed_slot..committed_slot {
+                        if slot % POST_BACKFILL_FREQUENCY == 0 {
+                            info!("Indexed slot {}", slot);
+                        }
+                    }
+                }
+                metric! {
+                    statsd_gauge!("last_indexed_slot", committed_slot);
+                }
+                // A checkpoint that fails to be recorded is replaced by the next one.
+                if let Err(e) = sink.record_indexed_slot(committed_slot).await {
+                    error!("Failed to record indexed slot {}: {}", committed_slot, e);
+                    metric! {
+                        statsd_count!("record_indexed_slot_error", 1);
                     }
                 }
+                last_indexed_slot = committed_slot;
             }
-
-            last_indexed_slot = slot_indexed;
         }
     })
 }
+
+// Indexes the blocks after `config.last_indexed_slot` up to and including `end_slot`, and returns
+// once all of them are written. Batches that fail to be written are sent again.
+pub async fn backfill_blocks(config: BlockStreamConfig, messenger: Arc<Messenger>, end_slot: u64) {
+    warn!(
+        "Backfilling blocks {} to {}",
+        config.last_indexed_slot + 1,
+        end_slot
+    );
+    let block_stream = PollerStreamer::get_poller_block_stream(
+        config.rpc_client.clone(),
+        config.last_indexed_slot,
+        config.max_concurrent_block_fetches,
+        config.max_block_fetch_retries,
+        0,
+        Some(end_slot),
+    );
+    pin_mut!(block_stream);
+    let block_batch_size = messenger.block_batch_size();
+    let mut block_batch = Vec::with_capacity(block_batch_size);
+    let mut uncommitted_batches = UncommittedBatches::default();
+    while let Some(block) = block_stream.next().await {
+        let slot_indexed = block.metadata.slot;
+        block_batch.push(block);
+        if block_batch.len() >= block_batch_size {
+            uncommitted_batches
+                .send(&messenger, std::mem::take(&mut block_batch))
+                .await;
+        }
+        uncommitted_batches.take_committed_slot(&messenger).await;
+        if slot_indexed % POST_BACKFILL_FREQUENCY == 0 {
+            info!("Backfilled slot {}", slot_indexed);
+        }
+    }
+    if !block_batch.is_empty() {
+        uncommitted_batches.send(&messenger, block_batch).await;
+    }
+    uncommitted_batches.wait_until_committed(&messenger).await;
+    warn!("Finished backfilling blocks up to {}", end_slot);
+}
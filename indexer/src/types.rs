@@ -1,15 +1,25 @@
 use std::{collections::HashSet, sync::Arc};
 
-use solana_client::nonblocking::rpc_client::RpcClient;
+// The token program that moved the tokens, stored as `token_type`. Only transfers are decoded,
+// so every indexed row is a transfer and there is no event kind to tell apart.
+pub use dao::generated::sea_orm_active_enums::TokenType;
+use indexmap::IndexMap;
+
+use crate::{grpc::RpcFallbackPolicy, rpc::FailoverRpcClient};
+
 use solana_sdk::{
     clock::{Slot, UnixTimestamp},
     pubkey::Pubkey,
     signature::Signature,
 };
 
-// To avoid exceeding the 64k total parameter limit
+// The default number of rows per INSERT, to avoid exceeding the 64k total parameter limit
 pub const MAX_SQL_INSERTS: usize = 5000;
 
+// Postgres rejects NOTIFY payloads of 8000 bytes or more.
+pub const MAX_NOTIFY_PAYLOAD_BYTES: usize = 7999;
+
+/// A single decoded token transfer instruction.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Instruction {
     pub program_id: Pubkey,
@@ -18,30 +28,137 @@ pub struct Instruction {
     pub source_address: Vec<u8>,
     pub destination_address: Vec<u8>,
     pub mint: Option<Vec<u8>>,
+    /// The mint's decimals, taken from the transaction's token balances.
+    pub decimals: Option<u8>,
     pub source_ata: Option<Vec<u8>>,
     pub destination_ata: Option<Vec<u8>>,
+    /// Owners of the source and destination token accounts, when the transaction's token
+    /// balances identify them.
+    pub source_owner: Option<Vec<u8>>,
+    pub destination_owner: Option<Vec<u8>>,
     pub amount: u64,
+    /// The Token-2022 transfer fee withheld from `amount`, so the destination received
+    /// `amount - fee`. Unknown for other programs and for transfers whose credit can't be told
+    /// apart from the transaction's other transfers.
+    pub fee: Option<u64>,
+    /// Position of the top level instruction in the transaction, and for transfers invoked
+    /// through CPI, their position among that instruction's inner instructions.
+    pub instruction_index: u8,
+    pub inner_instruction_index: Option<u8>,
+}
+
+impl Instruction {
+    /// Whether both token accounts are known to belong to the same owner.
+    pub fn is_self_transfer(&self) -> bool {
+        matches!(
+            (&self.source_owner, &self.destination_owner),
+            (Some(source_owner), Some(destination_owner)) if source_owner == destination_owner
+        )
+    }
+}
+
+/// Restricts indexing to transfers of the listed mints and token programs. An empty list
+/// allows everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IndexFilter {
+    pub mints: HashSet<Vec<u8>>,
+    pub programs: HashSet<Pubkey>,
+    /// Also indexes the transfers of failed transactions, which were rolled back on chain.
+    pub failed_transactions: bool,
+    /// Also indexes the balance changes of token accounts, see `BalanceChange`.
+    pub balance_changes: bool,
+}
+
+impl IndexFilter {
+    pub fn allows(&self, instruction: &Instruction) -> bool {
+        let mint_allowed = self.mints.is_empty()
+            || instruction
+                .mint
+                .as_ref()
+                .is_some_and(|mint| self.mints.contains(mint));
+        mint_allowed
+            && (self.programs.is_empty() || self.programs.contains(&instruction.program_id))
+    }
 }
 
+/// The transfers made by one top level instruction: the instruction itself, when it is a
+/// transfer, and the transfers it invoked through CPI.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct InstructionGroup {
-    pub outer_instruction: Instruction,
+    pub outer_instruction: Option<Instruction>,
     pub inner_instructions: Vec<Instruction>,
+    /// The token program that executed the transfer.
+    pub token_type: TokenType,
+}
+
+impl InstructionGroup {
+    /// Every transfer in the group, the outer one first.
+    pub fn transfers(&self) -> impl Iterator<Item = &Instruction> {
+        self.outer_instruction
+            .iter()
+            .chain(self.inner_instructions.iter())
+    }
 }
 
+/// A transaction reduced to the token transfers it contains.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Transaction {
     pub instruction_groups: Vec<InstructionGroup>,
     pub signature: Signature,
     pub block_time: UnixTimestamp,
+    /// The transaction error, if the transaction failed on chain.
     pub error: Option<String>,
     pub slot: u64,
+    /// The first signer, who paid `fee` lamports for the transaction.
+    pub fee_payer: Pubkey,
+    pub fee: u64,
+    /// The text of the transaction's SPL Memo instruction, if it has one.
+    pub memo: Option<String>,
 }
 
+/// How much a transaction changed the balance of a token account, from the token balances
+/// reported before and after it. These also cover tokens moved by programs whose instructions
+/// aren't decoded as transfers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BalanceChange {
+    pub signature: Signature,
+    pub slot: Slot,
+    pub block_time: UnixTimestamp,
+    pub account: Vec<u8>,
+    pub mint: Vec<u8>,
+    pub owner: Option<Vec<u8>>,
+    pub decimals: u8,
+    /// Zero for the accounts the transaction opened, as `post_amount` is for those it closed.
+    pub pre_amount: u64,
+    pub post_amount: u64,
+}
+
+impl BalanceChange {
+    /// The net change in base units, negative when the balance went down.
+    pub fn delta(&self) -> i128 {
+        self.post_amount as i128 - self.pre_amount as i128
+    }
+}
+
+/// A parsed block: its metadata and the transactions that contain token transfers.
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct BlockInfo {
     pub metadata: BlockMetadata,
     pub transactions: Vec<Transaction>,
+    /// Transactions of the block that could not be parsed and were left out.
+    pub parse_failures: Vec<ParseFailure>,
+    /// The balance changes of every transaction of the block, transfers or not. Only kept when
+    /// they are indexed.
+    pub balance_changes: Vec<BalanceChange>,
+}
+
+/// A transaction that was skipped because it could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseFailure {
+    /// Unknown when the transaction could not be decoded.
+    pub signature: Option<Signature>,
+    pub slot: Slot,
+    pub error: String,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
@@ -56,17 +173,26 @@ pub struct BlockMetadata {
 
 #[derive(Clone)]
 pub struct BlockStreamConfig {
-    pub rpc_client: Arc<RpcClient>,
+    pub rpc_client: Arc<FailoverRpcClient>,
     pub grpc_url: Option<String>,
     pub grpc_x_token: String,
     pub max_concurrent_block_fetches: usize,
+    // Failed fetches of a slot are retried this many times before the slot is given up on.
+    pub max_block_fetch_retries: u32,
+    // The live poller only fetches the slots at least this many slots behind the tip.
+    pub confirmation_depth: u64,
+    // Passed to the gRPC provider, which then only streams the transactions of a block that
+    // touch these accounts.
+    pub account_include: Vec<String>,
     pub last_indexed_slot: u64,
     pub index_recent: bool,
+    pub rpc_fallback: RpcFallbackPolicy,
 }
 
+/// The transactions to index, by signature and slot, in the order they were added.
 #[derive(Default, Debug, Clone, PartialEq, Eq)]
 pub struct StateUpdate {
-    pub transactions: HashSet<Transaction>,
+    pub transactions: IndexMap<(Signature, Slot), Transaction>,
 }
 
 impl StateUpdate {
@@ -74,10 +200,20 @@ impl StateUpdate {
         StateUpdate::default()
     }
 
+    /// Adds `transaction`, replacing an earlier copy of it but keeping that one's position.
+    pub fn insert(&mut self, transaction: Transaction) {
+        self.transactions
+            .insert((transaction.signature, transaction.slot), transaction);
+    }
+
+    /// Merges `updates` in order, so the transactions of blocks given in slot order stay in slot
+    /// order.
     pub fn merge_updates(updates: Vec<StateUpdate>) -> StateUpdate {
         let mut merged = StateUpdate::default();
         for update in updates {
-            merged.transactions.extend(update.transactions);
+            for transaction in update.transactions.into_values() {
+                merged.insert(transaction);
+            }
         }
         merged
     }
@@ -0,0 +1,50 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::{ConnectionTrait, DatabaseBackend, Statement};
+
+use crate::interval::{interval_from_env, COMPRESSION_INTERVAL_ENV, DEFAULT_COMPRESSION_INTERVAL};
+
+pub async fn execute_sql(manager: &SchemaManager<'_>, sql: String) -> Result<(), DbErr> {
+    manager
+        .get_connection()
+        .execute(Statement::from_string(DatabaseBackend::Postgres, sql))
+        .await?;
+    Ok(())
+}
+
+// Keys and constraints of a compressed hypertable can't be changed, so compression is switched
+// off around such changes and restored with the same settings afterwards.
+pub async fn disable_compression(manager: &SchemaManager<'_>) -> Result<(), DbErr> {
+    execute_sql(
+        manager,
+        "SELECT remove_compression_policy('token_transfers', if_exists => true);".to_string(),
+    )
+    .await?;
+    execute_sql(
+        manager,
+        "SELECT decompress_chunk(chunk, if_compressed => true) FROM show_chunks('token_transfers') AS chunk;".to_string(),
+    )
+    .await?;
+    execute_sql(
+        manager,
+        "ALTER TABLE token_transfers SET (timescaledb.compress = false);".to_string(),
+    )
+    .await
+}
+
+pub async fn enable_compression(manager: &SchemaManager<'_>) -> Result<(), DbErr> {
+    let compression_interval =
+        interval_from_env(COMPRESSION_INTERVAL_ENV, DEFAULT_COMPRESSION_INTERVAL)?;
+    execute_sql(
+        manager,
+        "ALTER TABLE token_transfers SET (timescaledb.compress, timescaledb.compress_segmentby = 'mint_address');".to_string(),
+    )
+    .await?;
+    execute_sql(
+        manager,
+        format!(
+            "SELECT add_compression_policy('token_transfers', INTERVAL '{}');",
+            compression_interval
+        ),
+    )
+    .await
+}
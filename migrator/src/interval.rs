@@ -0,0 +1,24 @@
+use sea_orm_migration::prelude::DbErr;
+
+pub const RETENTION_INTERVAL_ENV: &str = "ITIHAS_RETENTION_INTERVAL";
+pub const DEFAULT_RETENTION_INTERVAL: &str = "3 months";
+pub const COMPRESSION_INTERVAL_ENV: &str = "ITIHAS_COMPRESSION_INTERVAL";
+pub const DEFAULT_COMPRESSION_INTERVAL: &str = "7 days";
+
+// Reads a Postgres interval literal (e.g. "3 months") from the environment. The value ends up
+// inside the migration SQL, so anything other than alphanumerics and spaces is rejected.
+pub fn interval_from_env(env_var: &str, default: &str) -> Result<String, DbErr> {
+    let interval = std::env::var(env_var).unwrap_or_else(|_| default.to_string());
+    let interval = interval.trim();
+    if interval.is_empty()
+        || !interval
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == ' ')
+    {
+        return Err(DbErr::Migration(format!(
+            "Invalid interval in {}: '{}'",
+            env_var, interval
+        )));
+    }
+    Ok(interval.to_string())
+}
@@ -1,8 +1,33 @@
 pub use sea_orm_migration::prelude::*;
 
+mod compression;
+mod interval;
 mod m20240802_114508_init;
 mod m20240805_174804_hypertable;
 mod m20240822_181224_create_indices;
+mod m20240905_101512_compression;
+mod m20240910_143020_add_token_type;
+mod m20240916_091245_token_account_owners;
+mod m20240918_103000_instruction_index;
+mod m20240920_142210_mints;
+mod m20240923_101500_token_type_check;
+mod m20240925_083000_address_indices;
+mod m20240927_094500_failed_transactions;
+mod m20240930_111500_skipped_slots;
+mod m20241002_090000_transfer_decimals;
+mod m20241004_101500_block_hashes;
+mod m20241006_093000_transfer_fees;
+mod m20241008_091500_transaction_fees;
+mod m20241010_100000_transactions;
+mod m20241012_090000_transaction_memos;
+mod m20241014_100000_balance_changes;
+mod m20241016_090000_block_height_index;
+mod m20241018_090000_program_id_index;
+mod m20241020_090000_numeric_amounts;
+mod m20241022_090000_instruction_data;
+mod m20241024_090000_retention_policies;
+mod m20241026_090000_destination_address_index;
+mod m20241028_090000_checkpoints;
 mod model;
 pub struct Migrator;
 
@@ -13,6 +38,29 @@ impl MigratorTrait for Migrator {
             Box::new(m20240802_114508_init::Migration),
             Box::new(m20240805_174804_hypertable::Migration),
             Box::new(m20240822_181224_create_indices::Migration),
+            Box::new(m20240905_101512_compression::Migration),
+            Box::new(m20240910_143020_add_token_type::Migration),
+            Box::new(m20240916_091245_token_account_owners::Migration),
+            Box::new(m20240918_103000_instruction_index::Migration),
+            Box::new(m20240920_142210_mints::Migration),
+            Box::new(m20240923_101500_token_type_check::Migration),
+            Box::new(m20240925_083000_address_indices::Migration),
+            Box::new(m20240927_094500_failed_transactions::Migration),
+            Box::new(m20240930_111500_skipped_slots::Migration),
+            Box::new(m20241002_090000_transfer_decimals::Migration),
+            Box::new(m20241004_101500_block_hashes::Migration),
+            Box::new(m20241006_093000_transfer_fees::Migration),
+            Box::new(m20241008_091500_transaction_fees::Migration),
+            Box::new(m20241010_100000_transactions::Migration),
+            Box::new(m20241012_090000_transaction_memos::Migration),
+            Box::new(m20241014_100000_balance_changes::Migration),
+            Box::new(m20241016_090000_block_height_index::Migration),
+            Box::new(m20241018_090000_program_id_index::Migration),
+            Box::new(m20241020_090000_numeric_amounts::Migration),
+            Box::new(m20241022_090000_instruction_data::Migration),
+            Box::new(m20241024_090000_retention_policies::Migration),
+            Box::new(m20241026_090000_destination_address_index::Migration),
+            Box::new(m20241028_090000_checkpoints::Migration),
         ]
     }
 }
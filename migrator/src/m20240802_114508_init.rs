@@ -8,7 +8,7 @@ use super::model::table::{Blocks, TokenTransfers};
 #[derive(DeriveMigrationName)]
 pub struct Migration;
 
-async fn execute_sql<'a>(manager: &SchemaManager<'_>, sql: &str) -> Result<(), DbErr> {
+async fn execute_sql(manager: &SchemaManager<'_>, sql: &str) -> Result<(), DbErr> {
     manager
         .get_connection()
         .execute(Statement::from_string(
@@ -35,6 +35,7 @@ impl MigrationTrait for Migration {
         )
         .await?;
 
+        // Holds any u64, the raw token amounts are converted to it by a later migration.
         execute_sql(
             manager,
             "
@@ -0,0 +1,19 @@
+use sea_orm_migration::prelude::*;
+
+use crate::compression::{disable_compression, enable_compression};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        enable_compression(manager).await
+    }
+
+    // Compression can only be disabled once every chunk is decompressed again, which
+    // `disable_compression` does first.
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        disable_compression(manager).await
+    }
+}
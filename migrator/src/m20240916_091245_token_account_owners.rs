@@ -0,0 +1,82 @@
+use sea_orm_migration::prelude::*;
+
+use super::model::table::{TokenAccounts, TokenTransfers};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(TokenAccounts::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(TokenAccounts::Address)
+                            .binary()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(TokenAccounts::Owner).binary().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TokenTransfers::Table)
+                    .add_column(ColumnDef::new(TokenTransfers::SourceOwner).binary())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TokenTransfers::Table)
+                    .add_column(ColumnDef::new(TokenTransfers::DestinationOwner).binary())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TokenTransfers::Table)
+                    .add_column(
+                        ColumnDef::new(TokenTransfers::IsSelfTransfer)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in [
+            TokenTransfers::SourceOwner,
+            TokenTransfers::DestinationOwner,
+            TokenTransfers::IsSelfTransfer,
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(TokenTransfers::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        manager
+            .drop_table(Table::drop().table(TokenAccounts::Table).to_owned())
+            .await?;
+
+        Ok(())
+    }
+}
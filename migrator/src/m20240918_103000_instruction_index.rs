@@ -0,0 +1,114 @@
+use sea_orm_migration::prelude::*;
+
+use super::model::table::TokenTransfers;
+use crate::compression::{disable_compression, enable_compression, execute_sql};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        disable_compression(manager).await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TokenTransfers::Table)
+                    .add_column(
+                        ColumnDef::new(TokenTransfers::InstructionIndex)
+                            .integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        // Primary key columns can't be null, so top level transfers use -1.
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TokenTransfers::Table)
+                    .add_column(
+                        ColumnDef::new(TokenTransfers::InnerInstructionIndex)
+                            .integer()
+                            .not_null()
+                            .default(-1),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        execute_sql(
+            manager,
+            "
+            ALTER TABLE token_transfers
+                DROP CONSTRAINT pk_token_transfers,
+                ADD CONSTRAINT pk_token_transfers PRIMARY KEY (
+                    signature,
+                    source_address,
+                    destination_address,
+                    instruction_index,
+                    inner_instruction_index,
+                    block_time
+                );
+            "
+            .to_string(),
+        )
+        .await?;
+
+        enable_compression(manager).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        disable_compression(manager).await?;
+
+        // Only one transfer per signature and account pair fits the old key.
+        execute_sql(
+            manager,
+            "
+            DELETE FROM token_transfers a
+            USING token_transfers b
+            WHERE a.signature = b.signature
+                AND a.source_address = b.source_address
+                AND a.destination_address = b.destination_address
+                AND a.block_time = b.block_time
+                AND (a.instruction_index, a.inner_instruction_index)
+                    > (b.instruction_index, b.inner_instruction_index);
+            "
+            .to_string(),
+        )
+        .await?;
+        execute_sql(
+            manager,
+            "
+            ALTER TABLE token_transfers
+                DROP CONSTRAINT pk_token_transfers,
+                ADD CONSTRAINT pk_token_transfers PRIMARY KEY (
+                    signature,
+                    source_address,
+                    destination_address,
+                    block_time
+                );
+            "
+            .to_string(),
+        )
+        .await?;
+
+        for column in [
+            TokenTransfers::InstructionIndex,
+            TokenTransfers::InnerInstructionIndex,
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(TokenTransfers::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        enable_compression(manager).await
+    }
+}
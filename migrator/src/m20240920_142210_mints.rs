@@ -0,0 +1,62 @@
+use sea_orm_migration::prelude::*;
+
+use super::model::table::{Mints, TokenTransfers};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Mints::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Mints::Address)
+                            .binary()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Mints::Decimals).small_integer().not_null())
+                    .col(ColumnDef::new(Mints::Data).binary().not_null())
+                    .col(
+                        ColumnDef::new(Mints::UpdatedAt)
+                            .timestamp()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TokenTransfers::Table)
+                    .add_column(ColumnDef::new(TokenTransfers::UiAmount).double())
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TokenTransfers::Table)
+                    .drop_column(TokenTransfers::UiAmount)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .drop_table(Table::drop().table(Mints::Table).to_owned())
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,35 @@
+use sea_orm_migration::prelude::*;
+
+use crate::compression::{disable_compression, enable_compression, execute_sql};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        disable_compression(manager).await?;
+        execute_sql(
+            manager,
+            "
+            ALTER TABLE token_transfers
+                ADD CONSTRAINT token_transfers_token_type_check
+                CHECK (token_type IN ('spl-token', 'token-2022', 'native'));
+            "
+            .to_string(),
+        )
+        .await?;
+        enable_compression(manager).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        disable_compression(manager).await?;
+        execute_sql(
+            manager,
+            "ALTER TABLE token_transfers DROP CONSTRAINT token_transfers_token_type_check;"
+                .to_string(),
+        )
+        .await?;
+        enable_compression(manager).await
+    }
+}
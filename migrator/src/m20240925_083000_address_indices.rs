@@ -0,0 +1,78 @@
+use sea_orm_migration::prelude::*;
+
+use super::model::table::TokenTransfers;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Compressed chunks are segmented by mint, so these only speed up the uncompressed chunks,
+        // which hold the recent transfers most queries ask for.
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_token_transfers_source_address")
+                    .table(TokenTransfers::Table)
+                    .col(TokenTransfers::SourceAddress)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_token_transfers_destination_address")
+                    .table(TokenTransfers::Table)
+                    .col(TokenTransfers::DestinationAddress)
+                    .to_owned(),
+            )
+            .await?;
+
+        // Serves the keyset pagination, which filters and orders on block_time.
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_token_transfers_source_address_block_time")
+                    .table(TokenTransfers::Table)
+                    .col(TokenTransfers::SourceAddress)
+                    .col(TokenTransfers::BlockTime)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_token_transfers_source_address")
+                    .table(TokenTransfers::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_token_transfers_destination_address")
+                    .table(TokenTransfers::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_token_transfers_source_address_block_time")
+                    .table(TokenTransfers::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,73 @@
+use sea_orm_migration::prelude::*;
+
+use super::model::table::FailedTransactions;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(FailedTransactions::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(FailedTransactions::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    // Missing when the transaction could not even be decoded.
+                    .col(ColumnDef::new(FailedTransactions::Signature).binary())
+                    .col(
+                        ColumnDef::new(FailedTransactions::Slot)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(FailedTransactions::Error).text().not_null())
+                    .col(
+                        ColumnDef::new(FailedTransactions::CreatedAt)
+                            .timestamp()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Block batches are retried as a whole, so the same failure can be recorded twice.
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_failed_transactions_signature")
+                    .table(FailedTransactions::Table)
+                    .col(FailedTransactions::Signature)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_failed_transactions_slot")
+                    .table(FailedTransactions::Table)
+                    .col(FailedTransactions::Slot)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(FailedTransactions::Table).to_owned())
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,36 @@
+use sea_orm_migration::prelude::*;
+
+use super::model::table::SkippedSlots;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(SkippedSlots::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(SkippedSlots::Slot)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(SkippedSlots::Table).to_owned())
+            .await?;
+
+        Ok(())
+    }
+}
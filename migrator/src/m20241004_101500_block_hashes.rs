@@ -0,0 +1,38 @@
+use sea_orm_migration::prelude::*;
+
+use super::model::table::Blocks;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Blocks indexed before this migration have no hashes, and are only checked by slot.
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Blocks::Table)
+                    .add_column(ColumnDef::new(Blocks::Blockhash).binary())
+                    .add_column(ColumnDef::new(Blocks::ParentBlockhash).binary())
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Blocks::Table)
+                    .drop_column(Blocks::Blockhash)
+                    .drop_column(Blocks::ParentBlockhash)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
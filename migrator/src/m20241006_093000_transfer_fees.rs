@@ -0,0 +1,35 @@
+use sea_orm_migration::prelude::*;
+
+use super::model::table::TokenTransfers;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TokenTransfers::Table)
+                    .add_column(ColumnDef::new(TokenTransfers::Fee).big_integer())
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TokenTransfers::Table)
+                    .drop_column(TokenTransfers::Fee)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+use sea_orm_migration::prelude::*;
+
+use super::model::table::{TokenTransfers, Transactions};
+use crate::compression::{disable_compression, enable_compression, execute_sql};
+use crate::interval::{interval_from_env, DEFAULT_RETENTION_INTERVAL, RETENTION_INTERVAL_ENV};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let retention_interval =
+            interval_from_env(RETENTION_INTERVAL_ENV, DEFAULT_RETENTION_INTERVAL)?;
+
+        // Hypertable keys have to include the partitioning column, so block_time is part of the
+        // key even though a signature identifies a transaction on its own.
+        manager
+            .create_table(
+                Table::create()
+                    .table(Transactions::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(Transactions::Signature).binary().not_null())
+                    .col(ColumnDef::new(Transactions::Slot).big_integer().not_null())
+                    .col(
+                        ColumnDef::new(Transactions::BlockTime)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(Transactions::Error).text())
+                    .col(ColumnDef::new(Transactions::Fee).big_integer())
+                    .col(ColumnDef::new(Transactions::FeePayer).binary())
+                    .primary_key(
+                        Index::create()
+                            .name("pk_transactions")
+                            .col(Transactions::Signature)
+                            .col(Transactions::BlockTime),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        execute_sql(
+            manager,
+            "SELECT create_hypertable('transactions', 'block_time');".to_string(),
+        )
+        .await?;
+        execute_sql(
+            manager,
+            format!(
+                "SELECT add_retention_policy('transactions', INTERVAL '{}');",
+                retention_interval
+            ),
+        )
+        .await?;
+        // Reorgs delete transactions by slot.
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_transactions_slot")
+                    .table(Transactions::Table)
+                    .col(Transactions::Slot)
+                    .to_owned(),
+            )
+            .await?;
+
+        execute_sql(
+            manager,
+            "
+            INSERT INTO transactions (signature, slot, block_time, error, fee, fee_payer)
+            SELECT DISTINCT ON (signature, block_time)
+                signature, slot, block_time, error, transaction_fee, fee_payer
+            FROM token_transfers
+            ORDER BY signature, block_time
+            ON CONFLICT DO NOTHING;
+            "
+            .to_string(),
+        )
+        .await?;
+
+        disable_compression(manager).await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TokenTransfers::Table)
+                    .drop_column(TokenTransfers::Error)
+                    .drop_column(TokenTransfers::FeePayer)
+                    .drop_column(TokenTransfers::TransactionFee)
+                    .to_owned(),
+            )
+            .await?;
+        enable_compression(manager).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        disable_compression(manager).await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TokenTransfers::Table)
+                    .add_column(ColumnDef::new(TokenTransfers::Error).text())
+                    .add_column(ColumnDef::new(TokenTransfers::FeePayer).binary())
+                    .add_column(ColumnDef::new(TokenTransfers::TransactionFee).big_integer())
+                    .to_owned(),
+            )
+            .await?;
+        execute_sql(
+            manager,
+            "
+            UPDATE token_transfers
+            SET error = t.error, fee_payer = t.fee_payer, transaction_fee = t.fee
+            FROM transactions t
+            WHERE token_transfers.signature = t.signature
+                AND token_transfers.block_time = t.block_time;
+            "
+            .to_string(),
+        )
+        .await?;
+        enable_compression(manager).await?;
+
+        manager
+            .drop_table(Table::drop().table(Transactions::Table).to_owned())
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+use sea_orm_migration::prelude::*;
+
+use super::model::table::BalanceChanges;
+use crate::compression::execute_sql;
+use crate::interval::{interval_from_env, DEFAULT_RETENTION_INTERVAL, RETENTION_INTERVAL_ENV};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let retention_interval =
+            interval_from_env(RETENTION_INTERVAL_ENV, DEFAULT_RETENTION_INTERVAL)?;
+
+        // One row per token account whose balance a transaction changed. `delta` is
+        // `post_amount - pre_amount`, in base units of the mint.
+        manager
+            .create_table(
+                Table::create()
+                    .table(BalanceChanges::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(BalanceChanges::Signature)
+                            .binary()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(BalanceChanges::Account).binary().not_null())
+                    .col(
+                        ColumnDef::new(BalanceChanges::Slot)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(BalanceChanges::BlockTime)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(BalanceChanges::Mint).binary().not_null())
+                    .col(ColumnDef::new(BalanceChanges::Owner).binary())
+                    .col(
+                        ColumnDef::new(BalanceChanges::Decimals)
+                            .small_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(BalanceChanges::PreAmount)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(BalanceChanges::PostAmount)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(BalanceChanges::Delta)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .primary_key(
+                        Index::create()
+                            .name("pk_balance_changes")
+                            .col(BalanceChanges::Signature)
+                            .col(BalanceChanges::Account)
+                            .col(BalanceChanges::BlockTime),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        execute_sql(
+            manager,
+            "SELECT create_hypertable('balance_changes', 'block_time');".to_string(),
+        )
+        .await?;
+        execute_sql(
+            manager,
+            format!(
+                "SELECT add_retention_policy('balance_changes', INTERVAL '{}');",
+                retention_interval
+            ),
+        )
+        .await?;
+        // Reorgs delete balance changes by slot.
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_balance_changes_slot")
+                    .table(BalanceChanges::Table)
+                    .col(BalanceChanges::Slot)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_balance_changes_account")
+                    .table(BalanceChanges::Table)
+                    .col(BalanceChanges::Account)
+                    .col(BalanceChanges::BlockTime)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_balance_changes_owner")
+                    .table(BalanceChanges::Table)
+                    .col(BalanceChanges::Owner)
+                    .col(BalanceChanges::BlockTime)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(BalanceChanges::Table).to_owned())
+            .await?;
+
+        Ok(())
+    }
+}
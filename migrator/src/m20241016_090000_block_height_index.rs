@@ -0,0 +1,37 @@
+use sea_orm_migration::prelude::*;
+
+use super::model::table::Blocks;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Resolves the block height bounds of transfer queries to the slots of those blocks.
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_blocks_block_height")
+                    .table(Blocks::Table)
+                    .col(Blocks::BlockHeight)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_blocks_block_height")
+                    .table(Blocks::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,38 @@
+use sea_orm_migration::prelude::*;
+
+use super::model::table::TokenTransfers;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Serves the transfers of a program, paginated on block_time like those of an address.
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_token_transfers_program_id_block_time")
+                    .table(TokenTransfers::Table)
+                    .col(TokenTransfers::ProgramId)
+                    .col(TokenTransfers::BlockTime)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_token_transfers_program_id_block_time")
+                    .table(TokenTransfers::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
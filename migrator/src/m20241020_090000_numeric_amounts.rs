@@ -0,0 +1,73 @@
+use sea_orm_migration::prelude::*;
+
+use crate::compression::{disable_compression, enable_compression, execute_sql};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Raw amounts are u64s, which were written to BIGINT columns bit for bit, so the ones above
+// i64::MAX read back negative. They are converted to the `bigint2` domain from the init
+// migration, moving those back to their real value.
+const AMOUNT_COLUMNS: [(&str, &str); 4] = [
+    ("token_transfers", "amount"),
+    ("token_transfers", "fee"),
+    ("balance_changes", "pre_amount"),
+    ("balance_changes", "post_amount"),
+];
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        disable_compression(manager).await?;
+
+        for (table, column) in AMOUNT_COLUMNS {
+            execute_sql(
+                manager,
+                format!(
+                    "ALTER TABLE {0} ALTER COLUMN {1} TYPE bigint2
+                        USING CASE WHEN {1} < 0 THEN {1} + 18446744073709551616::numeric
+                            ELSE {1} END;",
+                    table, column
+                ),
+            )
+            .await?;
+        }
+        // Deltas were truncated to 64 bits, so they are computed again from the amounts.
+        execute_sql(
+            manager,
+            "ALTER TABLE balance_changes ALTER COLUMN delta TYPE bigint2
+                USING post_amount - pre_amount;"
+                .to_string(),
+        )
+        .await?;
+
+        enable_compression(manager).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        disable_compression(manager).await?;
+
+        for (table, column) in AMOUNT_COLUMNS {
+            execute_sql(
+                manager,
+                format!(
+                    "ALTER TABLE {0} ALTER COLUMN {1} TYPE BIGINT
+                        USING CASE WHEN {1} > 9223372036854775807
+                            THEN {1} - 18446744073709551616::numeric
+                            ELSE {1} END;",
+                    table, column
+                ),
+            )
+            .await?;
+        }
+        execute_sql(
+            manager,
+            "ALTER TABLE balance_changes ALTER COLUMN delta TYPE BIGINT
+                USING GREATEST(LEAST(delta, 9223372036854775807), -9223372036854775808);"
+                .to_string(),
+        )
+        .await?;
+
+        enable_compression(manager).await
+    }
+}
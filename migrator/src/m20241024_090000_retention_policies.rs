@@ -0,0 +1,72 @@
+use sea_orm_migration::prelude::*;
+
+use crate::compression::execute_sql;
+use crate::interval::{interval_from_env, DEFAULT_RETENTION_INTERVAL, RETENTION_INTERVAL_ENV};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let retention_interval =
+            interval_from_env(RETENTION_INTERVAL_ENV, DEFAULT_RETENTION_INTERVAL)?;
+
+        // The hypertable migration kept token_transfers for a fixed 3 months.
+        execute_sql(
+            manager,
+            "SELECT remove_retention_policy('token_transfers', if_exists => true);".to_string(),
+        )
+        .await?;
+        execute_sql(
+            manager,
+            format!(
+                "SELECT add_retention_policy('token_transfers', INTERVAL '{}');",
+                retention_interval
+            ),
+        )
+        .await?;
+
+        // blocks.block_time is a unix timestamp stored as a bigint, so TimescaleDB needs an
+        // integer "now" function and an integer drop_after to apply a retention policy.
+        execute_sql(
+            manager,
+            "CREATE OR REPLACE FUNCTION unix_now() RETURNS BIGINT LANGUAGE SQL STABLE AS $$ SELECT EXTRACT(EPOCH FROM NOW())::BIGINT $$;".to_string(),
+        )
+        .await?;
+        execute_sql(
+            manager,
+            "SELECT set_integer_now_func('blocks', 'unix_now', replace_if_exists => true);"
+                .to_string(),
+        )
+        .await?;
+        execute_sql(
+            manager,
+            format!(
+                "SELECT add_retention_policy('blocks', EXTRACT(EPOCH FROM INTERVAL '{}')::BIGINT);",
+                retention_interval
+            ),
+        )
+        .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        execute_sql(
+            manager,
+            "SELECT remove_retention_policy('blocks', if_exists => true);".to_string(),
+        )
+        .await?;
+        execute_sql(manager, "DROP FUNCTION IF EXISTS unix_now();".to_string()).await?;
+
+        execute_sql(
+            manager,
+            "SELECT remove_retention_policy('token_transfers', if_exists => true);".to_string(),
+        )
+        .await?;
+        execute_sql(
+            manager,
+            "SELECT add_retention_policy('token_transfers', INTERVAL '3 months');".to_string(),
+        )
+        .await
+    }
+}
@@ -0,0 +1,39 @@
+use sea_orm_migration::prelude::*;
+
+use super::model::table::TokenTransfers;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Serves the keyset pagination of the transfers received by an address, as
+        // idx_token_transfers_source_address_block_time does for those it sent.
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_token_transfers_destination_address_block_time")
+                    .table(TokenTransfers::Table)
+                    .col(TokenTransfers::DestinationAddress)
+                    .col(TokenTransfers::BlockTime)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_token_transfers_destination_address_block_time")
+                    .table(TokenTransfers::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
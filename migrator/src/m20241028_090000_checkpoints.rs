@@ -0,0 +1,39 @@
+use sea_orm_migration::prelude::*;
+
+use super::model::table::Checkpoints;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // The last slot up to which every block is known to be written, which the indexer
+        // resumes after when restarted.
+        manager
+            .create_table(
+                Table::create()
+                    .table(Checkpoints::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Checkpoints::Name)
+                            .text()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Checkpoints::Slot).big_integer().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Checkpoints::Table).to_owned())
+            .await?;
+
+        Ok(())
+    }
+}
@@ -7,6 +7,8 @@ pub enum Blocks {
     ParentSlot,
     BlockHeight,
     BlockTime,
+    Blockhash,
+    ParentBlockhash,
 }
 
 #[derive(Copy, Clone, Iden)]
@@ -24,4 +26,82 @@ pub enum TokenTransfers {
     Error,
     BlockTime,
     CreatedAt,
+    TokenType,
+    SourceOwner,
+    DestinationOwner,
+    IsSelfTransfer,
+    InstructionIndex,
+    InnerInstructionIndex,
+    UiAmount,
+    Decimals,
+    Fee,
+    FeePayer,
+    TransactionFee,
+    Data,
+}
+
+#[derive(Copy, Clone, Iden)]
+pub enum Transactions {
+    Table,
+    Signature,
+    Slot,
+    BlockTime,
+    Error,
+    Fee,
+    FeePayer,
+    Memo,
+}
+
+#[derive(Copy, Clone, Iden)]
+pub enum TokenAccounts {
+    Table,
+    Address,
+    Owner,
+}
+
+#[derive(Copy, Clone, Iden)]
+pub enum Mints {
+    Table,
+    Address,
+    Decimals,
+    Data,
+    UpdatedAt,
+}
+
+#[derive(Copy, Clone, Iden)]
+pub enum FailedTransactions {
+    Table,
+    Id,
+    Signature,
+    Slot,
+    Error,
+    CreatedAt,
+}
+
+#[derive(Copy, Clone, Iden)]
+pub enum SkippedSlots {
+    Table,
+    Slot,
+}
+
+#[derive(Copy, Clone, Iden)]
+pub enum BalanceChanges {
+    Table,
+    Signature,
+    Account,
+    Slot,
+    BlockTime,
+    Mint,
+    Owner,
+    Decimals,
+    PreAmount,
+    PostAmount,
+    Delta,
+}
+
+#[derive(Copy, Clone, Iden)]
+pub enum Checkpoints {
+    Table,
+    Name,
+    Slot,
 }
@@ -0,0 +1,384 @@
+use std::sync::Arc;
+
+use api::spec::{ApiContract, GetBlock};
+use dao::generated::{blocks, failed_transactions, skipped_slots, token_transfers, transactions};
+use function_name::named;
+use indexer::{
+    config::IndexerConfig,
+    messenger::Messenger,
+    parser::parse_transaction_info,
+    rpc::FailoverRpcClient,
+    streamer::backfill_blocks,
+    types::{BlockInfo, BlockMetadata, BlockStreamConfig, ParseFailure},
+};
+use rstest::rstest;
+use sea_orm::EntityTrait;
+use solana_sdk::{hash::Hash, pubkey::Pubkey, signature::Signature};
+
+use insta::assert_json_snapshot;
+use serial_test::serial;
+
+use crate::parser_tests::{encode, lookup_table_transfers};
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_get_block() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let latest_block = setup.api.get_latest_indexed_block().await.unwrap();
+    assert_eq!(latest_block, None);
+
+    let blocks = [
+        BlockMetadata {
+            slot: 100,
+            parent_slot: 99,
+            block_time: 1_700_000_000,
+            block_height: 90,
+            ..Default::default()
+        },
+        BlockMetadata {
+            slot: 102,
+            parent_slot: 100,
+            block_time: 1_700_000_001,
+            blockhash: "EkSnNWid2cvwEVnVx9aBqawnmiCNiDgp3gUdkDPTKN1N".to_string(),
+            parent_blockhash: "11111111111111111111111111111111".to_string(),
+            block_height: 91,
+        },
+    ];
+    setup
+        .dao
+        .index_block_metadatas(blocks.iter().collect())
+        .await
+        .unwrap();
+
+    let block = setup.api.get_block(GetBlock { slot: 100 }).await.unwrap();
+    assert_json_snapshot!(format!("{}-block", name.clone()), block);
+
+    let latest_block = setup.api.get_latest_indexed_block().await.unwrap();
+    assert_json_snapshot!(format!("{}-latest-block", name.clone()), latest_block);
+
+    // Slot 101 was skipped.
+    assert!(setup.api.get_block(GetBlock { slot: 101 }).await.is_err());
+    let skipped_slots = skipped_slots::Entity::find()
+        .all(setup.dao.db.as_ref())
+        .await
+        .unwrap();
+    assert_eq!(skipped_slots, vec![skipped_slots::Model { slot: 101 }]);
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_backfill_stops_at_end_slot() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let messenger = Arc::new(Messenger::new(IndexerConfig {
+        workers: 1,
+        // Larger than the backfill, so the blocks are only sent once the stream ends.
+        block_batch_size: Some(3),
+        ..Default::default()
+    }));
+    messenger.clone().run(Arc::new(setup.dao.clone()));
+    let config = BlockStreamConfig {
+        rpc_client: Arc::new(FailoverRpcClient::from(setup.client.clone())),
+        grpc_url: None,
+        grpc_x_token: String::new(),
+        max_concurrent_block_fetches: 2,
+        max_block_fetch_retries: 3,
+        confirmation_depth: 0,
+        account_include: vec![],
+        last_indexed_slot: 285941930,
+        index_recent: false,
+        rpc_fallback: Default::default(),
+    };
+    backfill_blocks(config, messenger, 285941932).await;
+
+    // Returning means every block up to the end slot is already written.
+    let latest_block = setup.api.get_latest_indexed_block().await.unwrap().unwrap();
+    assert_eq!(latest_block.slot, 285941932);
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_parse_failures_are_recorded() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let block = BlockInfo {
+        metadata: BlockMetadata {
+            slot: 100,
+            parent_slot: 99,
+            block_time: 1_700_000_000,
+            block_height: 90,
+            ..Default::default()
+        },
+        parse_failures: vec![ParseFailure {
+            signature: Some(Signature::new_unique()),
+            slot: 100,
+            error: "Missing metadata".to_string(),
+        }],
+        ..Default::default()
+    };
+    setup.dao.index_block(&block).await.unwrap();
+    // Retrying the block must not record the failure twice.
+    setup.dao.index_block(&block).await.unwrap();
+
+    let failures = failed_transactions::Entity::find()
+        .all(setup.dao.db.as_ref())
+        .await
+        .unwrap();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(
+        failures[0].signature,
+        block.parse_failures[0]
+            .signature
+            .map(|signature| signature.as_ref().to_vec())
+    );
+    assert_eq!(failures[0].slot, 100);
+    assert_eq!(failures[0].error, "Missing metadata");
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_reprocessed_block_clears_fixed_parse_failures() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let failure = |slot: u64| ParseFailure {
+        signature: Some(Signature::new_unique()),
+        slot,
+        error: "Missing metadata".to_string(),
+    };
+    let block = |slot: u64, parse_failures: Vec<ParseFailure>| BlockInfo {
+        metadata: BlockMetadata {
+            slot,
+            parent_slot: slot - 1,
+            block_time: 1_700_000_000 + slot as i64,
+            block_height: slot - 10,
+            ..Default::default()
+        },
+        parse_failures,
+        ..Default::default()
+    };
+    let still_failing = failure(100);
+    for indexed in [
+        block(100, vec![failure(100), still_failing.clone()]),
+        block(101, vec![failure(101)]),
+        block(102, vec![]),
+        block(200, vec![failure(200)]),
+    ] {
+        setup.dao.index_block(&indexed).await.unwrap();
+    }
+    assert_eq!(
+        setup.dao.fetch_failed_slots(100, 199).await.unwrap(),
+        vec![100, 101]
+    );
+
+    // After a parser fix only one of the transactions of slot 100 still fails.
+    setup
+        .dao
+        .reprocess_block(&block(100, vec![still_failing.clone()]))
+        .await
+        .unwrap();
+    setup
+        .dao
+        .reprocess_block(&block(101, vec![]))
+        .await
+        .unwrap();
+
+    let failures = failed_transactions::Entity::find()
+        .all(setup.dao.db.as_ref())
+        .await
+        .unwrap();
+    let mut slots = failures
+        .iter()
+        .map(|failure| failure.slot)
+        .collect::<Vec<_>>();
+    slots.sort();
+    // Slots outside the reprocessed ones keep their failures.
+    assert_eq!(slots, vec![100, 200]);
+    let remaining = failures.iter().find(|failure| failure.slot == 100).unwrap();
+    assert_eq!(
+        remaining.signature,
+        still_failing
+            .signature
+            .map(|signature| signature.as_ref().to_vec())
+    );
+    assert_eq!(
+        setup.dao.fetch_failed_slots(100, 199).await.unwrap(),
+        vec![100]
+    );
+}
+
+fn chained_block(slot: u64, parent: &BlockMetadata) -> BlockMetadata {
+    BlockMetadata {
+        slot,
+        parent_slot: parent.slot,
+        block_time: 1_700_000_000 + slot as i64,
+        blockhash: Hash::new_unique().to_string(),
+        parent_blockhash: parent.blockhash.clone(),
+        block_height: slot - 10,
+    }
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_reorged_blocks_are_removed() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let root = BlockMetadata {
+        slot: 100,
+        parent_slot: 99,
+        block_time: 1_700_000_100,
+        blockhash: Hash::new_unique().to_string(),
+        parent_blockhash: Hash::new_unique().to_string(),
+        block_height: 90,
+    };
+    let orphan = chained_block(101, &root);
+    let transfer = parse_transaction_info(
+        encode(
+            &lookup_table_transfers(42, 1),
+            vec![Pubkey::new_unique(), Pubkey::new_unique()],
+            [None, None],
+        ),
+        101,
+        orphan.block_time,
+    )
+    .unwrap()
+    .unwrap();
+    for metadata in [root.clone(), orphan.clone()] {
+        let transactions = match metadata.slot {
+            101 => vec![transfer.clone()],
+            _ => vec![],
+        };
+        setup
+            .dao
+            .index_block(&BlockInfo {
+                metadata,
+                transactions,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    }
+
+    // The canonical chain went from 100 straight to 102, abandoning 101.
+    let canonical = chained_block(102, &root);
+    let slots_to_refetch = setup
+        .dao
+        .index_block_metadatas(vec![&canonical])
+        .await
+        .unwrap();
+    assert!(slots_to_refetch.is_empty());
+    let mut slots = blocks::Entity::find()
+        .all(setup.dao.db.as_ref())
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|block| block.slot)
+        .collect::<Vec<_>>();
+    slots.sort();
+    assert_eq!(slots, vec![100, 102]);
+    assert!(token_transfers::Entity::find()
+        .all(setup.dao.db.as_ref())
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(transactions::Entity::find()
+        .all(setup.dao.db.as_ref())
+        .await
+        .unwrap()
+        .is_empty());
+    let skipped_slots = skipped_slots::Entity::find()
+        .all(setup.dao.db.as_ref())
+        .await
+        .unwrap();
+    assert_eq!(skipped_slots, vec![skipped_slots::Model { slot: 101 }]);
+
+    // A child of a different block at slot 102 abandons the indexed one.
+    let child = BlockMetadata {
+        parent_blockhash: Hash::new_unique().to_string(),
+        ..chained_block(103, &canonical)
+    };
+    let slots_to_refetch = setup.dao.index_block_metadatas(vec![&child]).await.unwrap();
+    assert_eq!(slots_to_refetch, vec![102]);
+    assert!(blocks::Entity::find_by_id((102, canonical.block_time))
+        .one(setup.dao.db.as_ref())
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_indexed_slot_is_recorded_for_a_restart() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    assert_eq!(setup.dao.fetch_indexed_slot().await.unwrap(), None);
+    setup.dao.record_indexed_slot(100).await.unwrap();
+    setup.dao.record_indexed_slot(102).await.unwrap();
+    assert_eq!(setup.dao.fetch_indexed_slot().await.unwrap(), Some(102));
+}
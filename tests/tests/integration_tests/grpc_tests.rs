@@ -0,0 +1,466 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use futures::{stream, StreamExt};
+use hyper::{Body, Request, StatusCode};
+use indexer::{
+    grpc::{order_with_rpc_fallback, with_jitter, BlockOrderer, RpcFallbackPolicy},
+    health::{handle_health_request, IndexerHealth},
+    parser::GrpcParser,
+    types::BlockInfo,
+};
+use prost::Message;
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use spl_token::instruction::TokenInstruction;
+use yellowstone_grpc_proto::{
+    geyser::{
+        subscribe_update::UpdateOneof, SubscribeUpdate, SubscribeUpdateBlock,
+        SubscribeUpdateTransactionInfo,
+    },
+    prelude::{
+        CompiledInstruction, Message as TransactionMessage, TokenBalance, Transaction,
+        TransactionStatusMeta, UiTokenAmount,
+    },
+};
+
+use crate::setup::{block, relative_project_path, slots};
+
+// Fixtures in `tests/data/grpc` are the updates of a Geyser block subscription as they were
+// received, each encoded with `Message::encode_length_delimited`.
+fn load_grpc_fixture(name: &str) -> Vec<SubscribeUpdate> {
+    let data = std::fs::read(relative_project_path(&format!("tests/data/grpc/{}", name))).unwrap();
+    let mut buffer = data.as_slice();
+    let mut updates = vec![];
+    while !buffer.is_empty() {
+        updates.push(SubscribeUpdate::decode_length_delimited(&mut buffer).unwrap());
+    }
+    updates
+}
+
+// The blocks of the recorded updates, which the gRPC stream hands out in the same order.
+fn recorded_blocks(name: &str) -> Vec<SubscribeUpdateBlock> {
+    load_grpc_fixture(name)
+        .into_iter()
+        .filter_map(|update| match update.update_oneof {
+            Some(UpdateOneof::Block(block)) => Some(block),
+            _ => None,
+        })
+        .collect()
+}
+
+fn parse_recorded_blocks(name: &str) -> Vec<BlockInfo> {
+    recorded_blocks(name)
+        .into_iter()
+        .map(|block| GrpcParser::parse_block(block).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_early_blocks_wait_for_the_gap_to_fill() {
+    let mut orderer = BlockOrderer::new(100, false);
+
+    // gRPC runs ahead while RPC is still fetching 101 and 102.
+    assert!(orderer.push(block(103, 102)).is_empty());
+    assert!(orderer.push(block(104, 103)).is_empty());
+    assert_eq!(orderer.earliest_buffered_slot(), Some(103));
+
+    assert_eq!(slots(orderer.push(block(101, 100))), vec![101]);
+    assert_eq!(slots(orderer.push(block(102, 101))), vec![102, 103, 104]);
+    assert_eq!(orderer.earliest_buffered_slot(), None);
+
+    // Both streams can deliver the same block.
+    assert!(orderer.push(block(104, 103)).is_empty());
+    assert_eq!(slots(orderer.push(block(106, 104))), vec![106]);
+    assert_eq!(orderer.last_slot(), 106);
+}
+
+#[test]
+fn test_unlinked_blocks_are_flushed_in_order() {
+    let mut orderer = BlockOrderer::new(100, false);
+    assert!(orderer.push(block(105, 104)).is_empty());
+    assert!(orderer.push(block(103, 102)).is_empty());
+
+    assert_eq!(slots(orderer.flush()), vec![103, 105]);
+    assert_eq!(orderer.last_slot(), 105);
+    assert!(orderer.push(block(104, 103)).is_empty());
+}
+
+#[test]
+fn test_recent_blocks_skip_the_gap() {
+    let mut orderer = BlockOrderer::new(100, true);
+    assert_eq!(slots(orderer.push(block(200, 199))), vec![200]);
+    assert!(orderer.push(block(150, 149)).is_empty());
+    assert_eq!(orderer.earliest_buffered_slot(), None);
+}
+
+#[test]
+fn test_reconnect_jitter_stays_within_the_backoff() {
+    let backoff = Duration::from_secs(8);
+    for _ in 0..100 {
+        let delay = with_jitter(backoff);
+        assert!(delay >= backoff / 2 && delay <= backoff, "{:?}", delay);
+    }
+}
+
+#[test]
+fn test_recorded_grpc_blocks_are_parsed() {
+    let blocks = parse_recorded_blocks("out_of_order_blocks");
+    assert_eq!(slots(blocks.clone()), vec![101, 103, 102, 105, 106]);
+
+    let metadata = &blocks[0].metadata;
+    assert_eq!(metadata.parent_slot, 100);
+    assert_eq!(metadata.block_time, 1_700_000_101);
+    assert_eq!(metadata.block_height, 91);
+    assert_eq!(
+        metadata.blockhash,
+        "2YEjAFv9Vx1TuEj23T9ixw2sHzQjrZTxKN8oSJ6n72GT"
+    );
+    assert_eq!(
+        metadata.parent_blockhash,
+        "CfefT41Wnce1pdVAF7rvos8F2RwQ7ukFvuG3v4c78NRB"
+    );
+    assert!(blocks[0].transactions.is_empty());
+}
+
+#[tokio::test]
+async fn test_out_of_order_grpc_blocks_are_yielded_in_slot_order() {
+    let mut grpc_blocks = parse_recorded_blocks("out_of_order_blocks");
+    // The stream reconnected after 102, losing 104. Reconnects show up as blocks of slot 0.
+    grpc_blocks.insert(0, BlockInfo::default());
+    grpc_blocks.insert(4, BlockInfo::default());
+    // A live stream never ends, it waits for the next block.
+    let grpc_stream = stream::iter(grpc_blocks).chain(stream::pending());
+
+    let rpc_fetches = Arc::new(Mutex::new(vec![]));
+    let blocks = order_with_rpc_fallback(
+        grpc_stream,
+        BlockOrderer::new(100, false),
+        RpcFallbackPolicy::default(),
+        {
+            let rpc_fetches = rpc_fetches.clone();
+            move |last_slot, end_slot| {
+                rpc_fetches.lock().unwrap().push((last_slot, end_slot));
+                stream::iter((last_slot + 1..=end_slot).map(|slot| block(slot, slot - 1)))
+            }
+        },
+    )
+    .take(6)
+    .collect::<Vec<_>>()
+    .await;
+
+    assert_eq!(slots(blocks), vec![101, 102, 103, 104, 105, 106]);
+    // 103 arrived before 102 and 105 without 104, the gaps RPC was asked to fill.
+    assert_eq!(*rpc_fetches.lock().unwrap(), vec![(101, 102), (103, 104)]);
+}
+
+#[tokio::test]
+async fn test_recent_grpc_blocks_need_no_rpc_fallback() {
+    let grpc_stream =
+        stream::iter(parse_recorded_blocks("out_of_order_blocks")).chain(stream::pending());
+
+    let blocks = order_with_rpc_fallback(
+        grpc_stream,
+        BlockOrderer::new(0, true),
+        RpcFallbackPolicy::default(),
+        |last_slot, end_slot| -> stream::Empty<BlockInfo> {
+            panic!("fetched {}..={} over RPC", last_slot + 1, end_slot)
+        },
+    )
+    .take(4)
+    .collect::<Vec<_>>()
+    .await;
+
+    // Following the tip skips the gaps, and 102 arrived after the block it would precede.
+    assert_eq!(slots(blocks), vec![101, 103, 105, 106]);
+}
+
+#[tokio::test]
+async fn test_small_grpc_gaps_are_tolerated_before_falling_back() {
+    let grpc_stream =
+        stream::iter(parse_recorded_blocks("out_of_order_blocks")).chain(stream::pending());
+
+    let rpc_fetches = Arc::new(Mutex::new(vec![]));
+    let blocks = order_with_rpc_fallback(
+        grpc_stream,
+        BlockOrderer::new(100, false),
+        RpcFallbackPolicy {
+            max_gap_slots: 1,
+            max_gap_blocks: 1,
+            recovery_blocks: 0,
+        },
+        {
+            let rpc_fetches = rpc_fetches.clone();
+            move |last_slot, end_slot| {
+                rpc_fetches.lock().unwrap().push((last_slot, end_slot));
+                stream::iter((last_slot + 1..=end_slot).map(|slot| block(slot, slot - 1)))
+            }
+        },
+    )
+    .take(6)
+    .collect::<Vec<_>>()
+    .await;
+
+    assert_eq!(slots(blocks), vec![101, 102, 103, 104, 105, 106]);
+    // 102 arrived right after 103, only 104 stayed missing past the next block.
+    assert_eq!(*rpc_fetches.lock().unwrap(), vec![(103, 104)]);
+}
+
+#[tokio::test]
+async fn test_rpc_fallback_lasts_until_grpc_recovers() {
+    let grpc_blocks = vec![
+        block(101, 100),
+        block(110, 109),
+        block(112, 111),
+        block(113, 112),
+        block(114, 113),
+        block(116, 115),
+        block(115, 114),
+    ];
+    // Each block takes a moment to arrive, so RPC fills a gap before the next one does.
+    let grpc_stream = stream::iter(grpc_blocks)
+        .then(|block| async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            block
+        })
+        .chain(stream::pending());
+
+    let rpc_fetches = Arc::new(Mutex::new(vec![]));
+    let blocks = order_with_rpc_fallback(
+        grpc_stream,
+        BlockOrderer::new(100, false),
+        RpcFallbackPolicy {
+            max_gap_slots: 5,
+            max_gap_blocks: 5,
+            recovery_blocks: 2,
+        },
+        {
+            let rpc_fetches = rpc_fetches.clone();
+            move |last_slot, end_slot| {
+                rpc_fetches.lock().unwrap().push((last_slot, end_slot));
+                stream::iter((last_slot + 1..=end_slot).map(|slot| block(slot, slot - 1)))
+            }
+        },
+    )
+    .take(16)
+    .collect::<Vec<_>>()
+    .await;
+
+    assert_eq!(slots(blocks), (101..=116).collect::<Vec<_>>());
+    // The gap before 110 is too large to wait for. 111 is fetched right away since gRPC had
+    // to recover first, and 115 is waited for once two blocks in a row linked up.
+    assert_eq!(*rpc_fetches.lock().unwrap(), vec![(101, 109), (110, 111)]);
+}
+
+#[tokio::test]
+async fn test_readiness_follows_the_grpc_connection() {
+    let connected = Arc::new(AtomicBool::new(false));
+    let health = IndexerHealth {
+        grpc_connected: Some(connected.clone()),
+    };
+    let status = |path: &'static str| {
+        let health = health.clone();
+        async move {
+            let request = Request::get(path).body(Body::empty()).unwrap();
+            handle_health_request(health, request)
+                .await
+                .unwrap()
+                .status()
+        }
+    };
+
+    assert_eq!(status("/health").await, StatusCode::OK);
+    assert_eq!(status("/readiness").await, StatusCode::SERVICE_UNAVAILABLE);
+    connected.store(true, Ordering::Relaxed);
+    assert_eq!(status("/readiness").await, StatusCode::OK);
+
+    // Without gRPC there is no subscription to wait for.
+    let request = Request::get("/readiness").body(Body::empty()).unwrap();
+    let response = handle_health_request(IndexerHealth::default(), request)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[test]
+fn test_transaction_without_signature_is_an_error() {
+    let transaction = SubscribeUpdateTransactionInfo {
+        meta: Some(Default::default()),
+        ..Default::default()
+    };
+    let error = GrpcParser::parse_transaction(transaction, 1, 1).unwrap_err();
+    assert!(error.to_string().contains("no signature"));
+}
+
+#[test]
+fn test_transfer_without_token_balances_is_skipped() {
+    let mint = Pubkey::new_unique();
+    // The fee payer, the source and destination token accounts, the mint and the token program.
+    let account_keys = [
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        mint,
+        spl_token::id(),
+    ];
+    let transaction = SubscribeUpdateTransactionInfo {
+        signature: vec![1; 64],
+        transaction: Some(Transaction {
+            message: Some(TransactionMessage {
+                account_keys: account_keys
+                    .iter()
+                    .map(|account| account.to_bytes().to_vec())
+                    .collect(),
+                instructions: vec![
+                    // A plain transfer only has the token balances to take its mint from.
+                    CompiledInstruction {
+                        program_id_index: 4,
+                        accounts: vec![1, 2, 0],
+                        data: TokenInstruction::Transfer { amount: 1 }.pack(),
+                    },
+                    CompiledInstruction {
+                        program_id_index: 4,
+                        accounts: vec![1, 3, 2, 0],
+                        data: TokenInstruction::TransferChecked {
+                            amount: 2,
+                            decimals: 6,
+                        }
+                        .pack(),
+                    },
+                ],
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        meta: Some(Default::default()),
+        ..Default::default()
+    };
+
+    let transaction = GrpcParser::parse_transaction(transaction, 1, 1)
+        .unwrap()
+        .expect("the transfer naming its mint should be kept");
+    let transfers = transaction
+        .instruction_groups
+        .iter()
+        .flat_map(|group| group.transfers())
+        .collect::<Vec<_>>();
+    assert_eq!(transfers.len(), 1);
+    assert_eq!(transfers[0].amount, 2);
+    assert_eq!(transfers[0].mint, Some(mint.to_bytes().to_vec()));
+}
+
+#[test]
+fn test_transfer_takes_its_mint_from_the_balance_of_its_accounts() {
+    let mint = Pubkey::new_unique();
+    // The fee payer, the source and destination token accounts and the token program.
+    let account_keys = [
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        spl_token::id(),
+    ];
+    let token_balance = |account_index: u32, mint: Pubkey, decimals: u32| TokenBalance {
+        account_index,
+        mint: mint.to_string(),
+        ui_token_amount: Some(UiTokenAmount {
+            decimals,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let transaction = SubscribeUpdateTransactionInfo {
+        signature: vec![1; 64],
+        transaction: Some(Transaction {
+            message: Some(TransactionMessage {
+                account_keys: account_keys
+                    .iter()
+                    .map(|account| account.to_bytes().to_vec())
+                    .collect(),
+                instructions: vec![CompiledInstruction {
+                    program_id_index: 3,
+                    accounts: vec![1, 2, 0],
+                    data: TokenInstruction::Transfer { amount: 1 }.pack(),
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        meta: Some(TransactionStatusMeta {
+            // The balance of an account the transfer doesn't touch comes first.
+            post_token_balances: vec![
+                token_balance(0, Pubkey::new_unique(), 9),
+                token_balance(2, mint, 6),
+            ],
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    let transaction = GrpcParser::parse_transaction(transaction, 1, 1)
+        .unwrap()
+        .expect("the transfer should be parsed");
+    let transfer = transaction.instruction_groups[0]
+        .outer_instruction
+        .as_ref()
+        .unwrap();
+    assert_eq!(transfer.mint, Some(mint.to_bytes().to_vec()));
+    assert_eq!(transfer.decimals, Some(6));
+}
+
+#[test]
+fn test_parser_errors_point_at_the_instruction() {
+    let account_keys = [Pubkey::new_unique(), spl_token::id()];
+    let transfer = |accounts: Vec<u8>| CompiledInstruction {
+        program_id_index: 1,
+        accounts,
+        data: TokenInstruction::Transfer { amount: 1 }.pack(),
+    };
+    let transaction = SubscribeUpdateTransactionInfo {
+        signature: vec![1; 64],
+        transaction: Some(Transaction {
+            message: Some(TransactionMessage {
+                account_keys: account_keys
+                    .iter()
+                    .map(|account| account.to_bytes().to_vec())
+                    .collect(),
+                instructions: vec![transfer(vec![0, 0, 0]), transfer(vec![0, 9, 0])],
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        meta: Some(Default::default()),
+        ..Default::default()
+    };
+
+    let error = GrpcParser::parse_transaction(transaction, 1, 1)
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("Account index 9 out of bounds of 2 accounts"));
+    assert!(error.contains(&format!(
+        "transaction {}, instruction 1",
+        Signature::from([1u8; 64])
+    )));
+}
+
+#[test]
+fn test_block_without_a_time_is_recorded_as_a_parse_failure() {
+    let block = SubscribeUpdateBlock {
+        slot: 100,
+        parent_slot: 99,
+        transactions: vec![SubscribeUpdateTransactionInfo::default()],
+        ..Default::default()
+    };
+    let block = GrpcParser::parse_block(block).unwrap();
+    assert_eq!(block.metadata.slot, 100);
+    assert_eq!(block.metadata.parent_slot, 99);
+    assert_eq!(block.metadata.block_time, 0);
+    assert!(block.transactions.is_empty());
+    assert!(block.balance_changes.is_empty());
+    assert_eq!(block.parse_failures.len(), 1);
+    assert_eq!(block.parse_failures[0].slot, 100);
+    assert_eq!(block.parse_failures[0].signature, None);
+}
@@ -1,2 +1,10 @@
+mod block_tests;
+mod config_tests;
+mod grpc_tests;
+mod metrics_tests;
+mod mint_tests;
+mod parser_tests;
+mod poller_tests;
 mod setup;
+mod sink_tests;
 mod transaction_tests;
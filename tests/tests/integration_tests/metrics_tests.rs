@@ -0,0 +1,24 @@
+use cadence::{Counted, Gauged, StatsdClient, Timed};
+use common::metrics::{encode_prometheus_metrics, PrometheusSink};
+
+#[test]
+fn test_statsd_metrics_are_exported_to_prometheus() {
+    let client = StatsdClient::builder("metrics_test", PrometheusSink)
+        .with_tag("env", "test")
+        .build();
+    client
+        .time_with_tags("api_call", 250u64)
+        .with_tag("method", "getBlock")
+        .with_tag("success", "true")
+        .send();
+    client.count("blocks_indexed", 2i64).unwrap();
+    client.count("blocks_indexed", 3i64).unwrap();
+    client.gauge("indexing_lag", 7u64).unwrap();
+
+    let metrics = encode_prometheus_metrics();
+    assert!(metrics.contains(
+        "metrics_test_api_call_seconds_sum{env=\"test\",method=\"getBlock\",success=\"true\"} 0.25"
+    ));
+    assert!(metrics.contains("metrics_test_blocks_indexed{env=\"test\"} 5"));
+    assert!(metrics.contains("metrics_test_indexing_lag{env=\"test\"} 7"));
+}
@@ -0,0 +1,70 @@
+use indexer::mints::MintInfo;
+
+const DECIMALS: u8 = 6;
+
+// A Token-2022 mint account with the given extension TLV entries after the base mint.
+fn mint_account(extensions: &[(u16, Vec<u8>)]) -> Vec<u8> {
+    let mut data = vec![0u8; 166];
+    data[44] = DECIMALS;
+    data[45] = 1; // is_initialized
+    data[165] = 1; // AccountType::Mint
+    for (extension_type, value) in extensions {
+        data.extend_from_slice(&extension_type.to_le_bytes());
+        data.extend_from_slice(&(value.len() as u16).to_le_bytes());
+        data.extend_from_slice(value);
+    }
+    data
+}
+
+fn scaled_ui_amount(
+    multiplier: f64,
+    effective_timestamp: i64,
+    new_multiplier: f64,
+) -> (u16, Vec<u8>) {
+    let mut value = vec![0u8; 32];
+    value.extend_from_slice(&multiplier.to_le_bytes());
+    value.extend_from_slice(&effective_timestamp.to_le_bytes());
+    value.extend_from_slice(&new_multiplier.to_le_bytes());
+    (25, value)
+}
+
+fn interest_bearing(initialization_timestamp: i64, rate: i16) -> (u16, Vec<u8>) {
+    let mut value = vec![0u8; 32];
+    value.extend_from_slice(&initialization_timestamp.to_le_bytes());
+    value.extend_from_slice(&rate.to_le_bytes());
+    value.extend_from_slice(&initialization_timestamp.to_le_bytes());
+    value.extend_from_slice(&rate.to_le_bytes());
+    (10, value)
+}
+
+#[test]
+fn test_plain_mint_ui_amount() {
+    let mint = MintInfo::parse(&mint_account(&[])).unwrap();
+    assert_eq!(mint.decimals, DECIMALS);
+    assert!(!mint.is_scaled());
+    assert_eq!(mint.ui_amount(2_500_000, 0), 2.5);
+
+    // spl-token mints have no extensions at all.
+    let mint = MintInfo::parse(&mint_account(&[])[..82]).unwrap();
+    assert_eq!(mint.ui_amount(2_500_000, 0), 2.5);
+}
+
+#[test]
+fn test_scaled_ui_amount_mint() {
+    let mint = MintInfo::parse(&mint_account(&[scaled_ui_amount(1.5, 1_000, 2.0)])).unwrap();
+    assert!(mint.is_scaled());
+    assert_eq!(mint.ui_amount(2_000_000, 999), 3.0);
+    // The new multiplier applies from its effective timestamp on.
+    assert_eq!(mint.ui_amount(2_000_000, 1_000), 4.0);
+}
+
+#[test]
+fn test_interest_bearing_mint() {
+    // 5% a year, compounded continuously.
+    let mint = MintInfo::parse(&mint_account(&[interest_bearing(0, 500)])).unwrap();
+    assert!(mint.is_scaled());
+    assert_eq!(mint.ui_amount(1_000_000, 0), 1.0);
+    let one_year = 31_556_736;
+    let ui_amount = mint.ui_amount(1_000_000, one_year);
+    assert!((ui_amount - 0.05f64.exp()).abs() < 1e-9);
+}
@@ -0,0 +1,1107 @@
+use std::{collections::HashSet, str::FromStr};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use indexer::{
+    dry_run::describe_block,
+    parser::{
+        filter_block, parse_block_info, parse_block_state_update, parse_transaction_info,
+        token_type, PollerParser,
+    },
+    types::{
+        BlockInfo, BlockMetadata, IndexFilter, ParseFailure, StateUpdate, TokenType, Transaction,
+    },
+};
+use sea_orm::{ActiveEnum, Iterable};
+use solana_sdk::{
+    bs58,
+    hash::Hash,
+    instruction::{CompiledInstruction, InstructionError},
+    message::{
+        v0::{self, MessageAddressTableLookup},
+        MessageHeader, VersionedMessage,
+    },
+    pubkey::Pubkey,
+    signature::Signature,
+    transaction::{TransactionError, VersionedTransaction},
+};
+use solana_transaction_status::{
+    EncodedConfirmedTransactionWithStatusMeta, EncodedTransactionWithStatusMeta, UiConfirmedBlock,
+};
+
+use crate::setup::load_block_fixture;
+
+pub const MINT: &str = "AmeroCaeKg55p6J8d1y2R4t9taqgn3TH4BARgzQJyHvd";
+
+// A v0 transaction whose transfer references both token accounts (indexes 2 and 3) through an
+// address lookup table.
+pub fn lookup_table_transfer(amount: u64) -> VersionedTransaction {
+    lookup_table_transfers(amount, 1)
+}
+
+// Like `lookup_table_transfer`, but repeats the same transfer `count` times.
+pub fn lookup_table_transfers(amount: u64, count: usize) -> VersionedTransaction {
+    let fee_payer = Pubkey::new_unique();
+    let message = v0::Message {
+        header: MessageHeader {
+            num_required_signatures: 1,
+            num_readonly_signed_accounts: 0,
+            num_readonly_unsigned_accounts: 1,
+        },
+        account_keys: vec![fee_payer, spl_token::id()],
+        recent_blockhash: Hash::new_unique(),
+        instructions: vec![
+            CompiledInstruction {
+                program_id_index: 1,
+                accounts: vec![2, 3, 0],
+                data: spl_token::instruction::TokenInstruction::Transfer { amount }.pack(),
+            };
+            count
+        ],
+        address_table_lookups: vec![MessageAddressTableLookup {
+            account_key: Pubkey::new_unique(),
+            writable_indexes: vec![0, 1],
+            readonly_indexes: vec![],
+        }],
+    };
+    VersionedTransaction {
+        signatures: vec![Signature::from([7u8; 64])],
+        message: VersionedMessage::V0(message),
+    }
+}
+
+// A v0 transaction where a router program (index 2) moves tokens between the two looked up
+// token accounts (indexes 3 and 4) through a CPI transfer. The CPI itself is in `cpi_transfer`.
+pub fn router_transfer() -> VersionedTransaction {
+    let fee_payer = Pubkey::new_unique();
+    let message = v0::Message {
+        header: MessageHeader {
+            num_required_signatures: 1,
+            num_readonly_signed_accounts: 0,
+            num_readonly_unsigned_accounts: 2,
+        },
+        account_keys: vec![fee_payer, spl_token::id(), Pubkey::new_unique()],
+        recent_blockhash: Hash::new_unique(),
+        instructions: vec![CompiledInstruction {
+            program_id_index: 2,
+            accounts: vec![1, 3, 4, 0],
+            data: vec![1, 2, 3],
+        }],
+        address_table_lookups: vec![MessageAddressTableLookup {
+            account_key: Pubkey::new_unique(),
+            writable_indexes: vec![0, 1],
+            readonly_indexes: vec![],
+        }],
+    };
+    VersionedTransaction {
+        signatures: vec![Signature::from([8u8; 64])],
+        message: VersionedMessage::V0(message),
+    }
+}
+
+pub fn cpi_transfer(amount: u64) -> serde_json::Value {
+    let data = spl_token::instruction::TokenInstruction::Transfer { amount }.pack();
+    serde_json::json!([{
+        "index": 0,
+        "instructions": [{
+            "programIdIndex": 1,
+            "accounts": [3, 4, 0],
+            "data": bs58::encode(data).into_string(),
+            "stackHeight": 2
+        }]
+    }])
+}
+
+fn token_balance(account_index: u8, owner: Option<Pubkey>) -> serde_json::Value {
+    let mut balance = serde_json::json!({
+        "accountIndex": account_index,
+        "mint": MINT,
+        "uiTokenAmount": {
+            "uiAmount": 1.0,
+            "decimals": 6,
+            "amount": "1000000",
+            "uiAmountString": "1"
+        }
+    });
+    if let Some(owner) = owner {
+        balance["owner"] = serde_json::json!(owner.to_string());
+    }
+    balance
+}
+
+pub fn encode(
+    transaction: &VersionedTransaction,
+    loaded_writable: Vec<Pubkey>,
+    owners: [Option<Pubkey>; 2],
+) -> EncodedTransactionWithStatusMeta {
+    encode_with_inner_instructions(transaction, loaded_writable, owners, serde_json::json!([]))
+}
+
+// The looked up token accounts follow the static keys, so their token balances are reported
+// at those indexes.
+pub fn encode_with_inner_instructions(
+    transaction: &VersionedTransaction,
+    loaded_writable: Vec<Pubkey>,
+    owners: [Option<Pubkey>; 2],
+    inner_instructions: serde_json::Value,
+) -> EncodedTransactionWithStatusMeta {
+    let first_token_account = transaction.message.static_account_keys().len() as u8;
+    let transaction = STANDARD.encode(bincode::serialize(transaction).unwrap());
+    serde_json::from_value(serde_json::json!({
+        "transaction": [transaction, "base64"],
+        "meta": {
+            "err": null,
+            "status": { "Ok": null },
+            "fee": 5000,
+            "preBalances": [],
+            "postBalances": [],
+            "innerInstructions": inner_instructions,
+            "logMessages": [],
+            "preTokenBalances": [],
+            "postTokenBalances": [
+                token_balance(first_token_account, owners[0]),
+                token_balance(first_token_account + 1, owners[1])
+            ],
+            "rewards": [],
+            "loadedAddresses": {
+                "writable": loaded_writable.iter().map(Pubkey::to_string).collect::<Vec<_>>(),
+                "readonly": []
+            }
+        },
+        "version": 0
+    }))
+    .unwrap()
+}
+
+#[test]
+fn test_lookup_table_accounts_resolve() {
+    let source = Pubkey::new_unique();
+    let destination = Pubkey::new_unique();
+    let transaction = lookup_table_transfer(42);
+
+    let parsed = parse_transaction_info(
+        encode(&transaction, vec![source, destination], [None, None]),
+        1,
+        1,
+    )
+    .unwrap()
+    .expect("transfer should be parsed");
+    let transfer = parsed.instruction_groups[0]
+        .outer_instruction
+        .as_ref()
+        .unwrap();
+    assert_eq!(transfer.source_address, source.to_bytes().to_vec());
+    assert_eq!(
+        transfer.destination_address,
+        destination.to_bytes().to_vec()
+    );
+    assert_eq!(transfer.amount, 42);
+    // Taken from the token balances, the transfer instruction doesn't carry it.
+    assert_eq!(transfer.decimals, Some(6));
+}
+
+#[test]
+fn test_fee_payer_and_fee_are_parsed() {
+    let transaction = lookup_table_transfer(42);
+    let fee_payer = transaction.message.static_account_keys()[0];
+
+    let parsed = parse_transaction_info(
+        encode(
+            &transaction,
+            vec![Pubkey::new_unique(), Pubkey::new_unique()],
+            [None, None],
+        ),
+        1,
+        1,
+    )
+    .unwrap()
+    .unwrap();
+    assert_eq!(parsed.fee_payer, fee_payer);
+    assert_eq!(parsed.fee, 5000);
+}
+
+#[test]
+fn test_memo_is_parsed() {
+    let memo_program_id = Pubkey::from_str("MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr").unwrap();
+    let message = v0::Message {
+        header: MessageHeader {
+            num_required_signatures: 1,
+            num_readonly_signed_accounts: 0,
+            num_readonly_unsigned_accounts: 2,
+        },
+        account_keys: vec![Pubkey::new_unique(), spl_token::id(), memo_program_id],
+        recent_blockhash: Hash::new_unique(),
+        instructions: vec![
+            CompiledInstruction {
+                program_id_index: 2,
+                accounts: vec![0],
+                data: b"withdrawal 4711".to_vec(),
+            },
+            CompiledInstruction {
+                program_id_index: 1,
+                accounts: vec![3, 4, 0],
+                data: spl_token::instruction::TokenInstruction::Transfer { amount: 42 }.pack(),
+            },
+        ],
+        address_table_lookups: vec![MessageAddressTableLookup {
+            account_key: Pubkey::new_unique(),
+            writable_indexes: vec![0, 1],
+            readonly_indexes: vec![],
+        }],
+    };
+    let transaction = VersionedTransaction {
+        signatures: vec![Signature::from([7u8; 64])],
+        message: VersionedMessage::V0(message),
+    };
+
+    let parsed = parse_transaction_info(
+        encode(
+            &transaction,
+            vec![Pubkey::new_unique(), Pubkey::new_unique()],
+            [None, None],
+        ),
+        1,
+        1,
+    )
+    .unwrap()
+    .unwrap();
+    assert_eq!(parsed.memo, Some("withdrawal 4711".to_string()));
+    assert_eq!(parsed.instruction_groups.len(), 1);
+
+    let parsed = parse_transaction_info(
+        encode(
+            &lookup_table_transfer(42),
+            vec![Pubkey::new_unique(), Pubkey::new_unique()],
+            [None, None],
+        ),
+        1,
+        1,
+    )
+    .unwrap()
+    .unwrap();
+    assert_eq!(parsed.memo, None);
+}
+
+#[test]
+fn test_decimals_are_taken_from_the_balance_of_the_transfer_accounts() {
+    let mut transaction = serde_json::to_value(encode(
+        &lookup_table_transfer(42),
+        vec![Pubkey::new_unique(), Pubkey::new_unique()],
+        [None, None],
+    ))
+    .unwrap();
+    // The balance of an account the transfer doesn't touch comes first.
+    let mut unrelated = token_balance(0, None);
+    unrelated["mint"] = serde_json::json!(Pubkey::new_unique().to_string());
+    unrelated["uiTokenAmount"]["decimals"] = serde_json::json!(9);
+    transaction["meta"]["postTokenBalances"]
+        .as_array_mut()
+        .unwrap()
+        .insert(0, unrelated);
+
+    let parsed = parse_transaction_info(serde_json::from_value(transaction).unwrap(), 1, 1)
+        .unwrap()
+        .expect("transfer should be parsed");
+    let transfer = parsed.instruction_groups[0]
+        .outer_instruction
+        .as_ref()
+        .unwrap();
+    assert_eq!(
+        transfer.mint,
+        Some(Pubkey::from_str(MINT).unwrap().to_bytes().to_vec())
+    );
+    assert_eq!(transfer.decimals, Some(6));
+}
+
+#[test]
+fn test_mismatched_account_count_is_skipped() {
+    let source = Pubkey::new_unique();
+    let transaction = lookup_table_transfer(42);
+
+    // The lookup declares two writable addresses but the meta only resolved one.
+    let parsed =
+        parse_transaction_info(encode(&transaction, vec![source], [None, None]), 1, 1).unwrap();
+    assert!(parsed.is_none());
+}
+
+#[test]
+fn test_same_owner_transfer_is_self_transfer() {
+    let source = Pubkey::new_unique();
+    let destination = Pubkey::new_unique();
+    let owner = Pubkey::new_unique();
+    let transaction = lookup_table_transfer(42);
+
+    let parsed = parse_transaction_info(
+        encode(
+            &transaction,
+            vec![source, destination],
+            [Some(owner), Some(owner)],
+        ),
+        1,
+        1,
+    )
+    .unwrap()
+    .expect("transfer should be parsed");
+    let transfer = parsed.instruction_groups[0]
+        .outer_instruction
+        .as_ref()
+        .unwrap();
+    assert_ne!(transfer.source_address, transfer.destination_address);
+    assert_eq!(transfer.source_owner, Some(owner.to_bytes().to_vec()));
+    assert_eq!(transfer.destination_owner, Some(owner.to_bytes().to_vec()));
+    assert!(transfer.is_self_transfer());
+
+    let parsed = parse_transaction_info(
+        encode(
+            &transaction,
+            vec![source, destination],
+            [Some(owner), Some(Pubkey::new_unique())],
+        ),
+        1,
+        1,
+    )
+    .unwrap()
+    .expect("transfer should be parsed");
+    assert!(!parsed.instruction_groups[0]
+        .outer_instruction
+        .as_ref()
+        .unwrap()
+        .is_self_transfer());
+
+    // Without owners in the token balances the parser can't tell.
+    let parsed = parse_transaction_info(
+        encode(&transaction, vec![source, destination], [None, None]),
+        1,
+        1,
+    )
+    .unwrap()
+    .expect("transfer should be parsed");
+    let transfer = parsed.instruction_groups[0]
+        .outer_instruction
+        .as_ref()
+        .unwrap();
+    assert_eq!(transfer.source_owner, None);
+    assert!(!transfer.is_self_transfer());
+}
+
+#[test]
+fn test_identical_transfers_have_distinct_indexes() {
+    let source = Pubkey::new_unique();
+    let destination = Pubkey::new_unique();
+    let transaction = lookup_table_transfers(42, 2);
+
+    let parsed = parse_transaction_info(
+        encode(&transaction, vec![source, destination], [None, None]),
+        1,
+        1,
+    )
+    .unwrap()
+    .expect("transfers should be parsed");
+    let transfers = parsed
+        .instruction_groups
+        .iter()
+        .flat_map(|instruction_group| instruction_group.outer_instruction.as_ref())
+        .collect::<Vec<_>>();
+    assert_eq!(transfers.len(), 2);
+    assert_eq!(transfers[0].source_address, transfers[1].source_address);
+    assert_eq!(transfers[0].amount, transfers[1].amount);
+    assert_eq!(transfers[0].instruction_index, 0);
+    assert_eq!(transfers[1].instruction_index, 1);
+    assert!(transfers
+        .iter()
+        .all(|transfer| transfer.inner_instruction_index.is_none()));
+}
+
+#[test]
+fn test_cpi_transfer_is_parsed() {
+    let source = Pubkey::new_unique();
+    let destination = Pubkey::new_unique();
+
+    let parsed = parse_transaction_info(
+        encode_with_inner_instructions(
+            &router_transfer(),
+            vec![source, destination],
+            [None, None],
+            cpi_transfer(42),
+        ),
+        1,
+        1,
+    )
+    .unwrap()
+    .expect("the CPI transfer should be parsed");
+    let instruction_group = &parsed.instruction_groups[0];
+    assert!(instruction_group.outer_instruction.is_none());
+    let transfers = instruction_group.transfers().collect::<Vec<_>>();
+    assert_eq!(transfers.len(), 1);
+    assert_eq!(transfers[0].source_address, source.to_bytes().to_vec());
+    assert_eq!(
+        transfers[0].destination_address,
+        destination.to_bytes().to_vec()
+    );
+    assert_eq!(transfers[0].amount, 42);
+    assert_eq!(transfers[0].instruction_index, 0);
+    assert_eq!(transfers[0].inner_instruction_index, Some(0));
+}
+
+#[test]
+fn test_token_type_round_trips() {
+    for (variant, value) in TokenType::iter().zip(["spl-token", "token-2022", "native"]) {
+        assert_eq!(variant.to_value(), value);
+        assert_eq!(
+            TokenType::try_from_value(&value.to_string()).unwrap(),
+            variant
+        );
+        assert_eq!(serde_json::to_value(variant).unwrap(), value);
+        assert_eq!(
+            serde_json::from_value::<TokenType>(value.into()).unwrap(),
+            variant
+        );
+    }
+    assert!(TokenType::try_from_value(&"spl".to_string()).is_err());
+
+    assert_eq!(token_type(&spl_token::id()), TokenType::SplToken);
+    assert_eq!(token_type(&spl_token_2022::id()), TokenType::Token2022);
+    assert_eq!(token_type(&Pubkey::new_unique()), TokenType::Native);
+}
+
+#[test]
+fn test_allowlists_filter_transfers() {
+    let transaction = parse_transaction_info(
+        encode(
+            &lookup_table_transfer(42),
+            vec![Pubkey::new_unique(), Pubkey::new_unique()],
+            [None, None],
+        ),
+        1,
+        1,
+    )
+    .unwrap()
+    .unwrap();
+    let block = BlockInfo {
+        transactions: vec![transaction],
+        ..Default::default()
+    };
+    let filtered = |filter: IndexFilter| {
+        let mut block = block.clone();
+        filter_block(&mut block, &filter);
+        block.transactions.len()
+    };
+
+    assert_eq!(filtered(IndexFilter::default()), 1);
+    let mint = Pubkey::from_str(MINT).unwrap().to_bytes().to_vec();
+    assert_eq!(
+        filtered(IndexFilter {
+            mints: HashSet::from([mint.clone()]),
+            ..Default::default()
+        }),
+        1
+    );
+    assert_eq!(
+        filtered(IndexFilter {
+            mints: HashSet::from([Pubkey::new_unique().to_bytes().to_vec()]),
+            ..Default::default()
+        }),
+        0
+    );
+    assert_eq!(
+        filtered(IndexFilter {
+            mints: HashSet::from([mint]),
+            programs: HashSet::from([spl_token_2022::id()]),
+            ..Default::default()
+        }),
+        0
+    );
+    assert_eq!(
+        filtered(IndexFilter {
+            programs: HashSet::from([spl_token::id()]),
+            ..Default::default()
+        }),
+        1
+    );
+}
+
+#[test]
+fn test_merged_state_updates_keep_slot_order() {
+    let transaction = |slot: u64, signature: u8| {
+        let mut transaction = parse_transaction_info(
+            encode(
+                &lookup_table_transfer(42),
+                vec![Pubkey::new_unique(), Pubkey::new_unique()],
+                [None, None],
+            ),
+            slot,
+            1,
+        )
+        .unwrap()
+        .unwrap();
+        transaction.signature = Signature::from([signature; 64]);
+        transaction
+    };
+    let block = |slot: u64, transactions: Vec<_>| BlockInfo {
+        metadata: BlockMetadata {
+            slot,
+            ..Default::default()
+        },
+        transactions,
+        ..Default::default()
+    };
+    // Signature 1 is in slot 100 and, on another fork, in slot 101. Signature 3 is sent twice.
+    let blocks = [
+        block(100, vec![transaction(100, 3), transaction(100, 1)]),
+        block(101, vec![transaction(101, 2), transaction(101, 1)]),
+        block(102, vec![transaction(102, 4)]),
+        block(100, vec![transaction(100, 3)]),
+    ];
+
+    for _ in 0..10 {
+        let merged = StateUpdate::merge_updates(
+            blocks
+                .iter()
+                .map(|block| parse_block_state_update(block).unwrap())
+                .collect(),
+        );
+        let keys = merged
+            .transactions
+            .values()
+            .map(|transaction| (transaction.slot, transaction.signature.as_ref()[0]))
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec![(100, 3), (100, 1), (101, 2), (101, 1), (102, 4)]);
+    }
+}
+
+#[test]
+fn test_failed_transactions_are_not_indexed_by_default() {
+    let mut failed = encode(
+        &lookup_table_transfer(42),
+        vec![Pubkey::new_unique(), Pubkey::new_unique()],
+        [None, None],
+    );
+    let error = TransactionError::InstructionError(0, InstructionError::Custom(1));
+    let meta = failed.meta.as_mut().unwrap();
+    meta.err = Some(error.clone());
+    meta.status = Err(error);
+    let transaction = parse_transaction_info(failed, 1, 1).unwrap().unwrap();
+    assert!(transaction.error.is_some());
+    let block = BlockInfo {
+        transactions: vec![transaction],
+        ..Default::default()
+    };
+
+    let mut filtered = block.clone();
+    filter_block(&mut filtered, &IndexFilter::default());
+    assert!(filtered.transactions.is_empty());
+
+    let mut filtered = block.clone();
+    filter_block(
+        &mut filtered,
+        &IndexFilter {
+            failed_transactions: true,
+            ..Default::default()
+        },
+    );
+    assert_eq!(filtered, block);
+}
+
+#[test]
+fn test_dry_run_describes_the_parsed_transfers() {
+    let source = Pubkey::new_unique();
+    let destination = Pubkey::new_unique();
+    let transaction = parse_transaction_info(
+        encode(
+            &lookup_table_transfer(42),
+            vec![source, destination],
+            [None, None],
+        ),
+        1,
+        1,
+    )
+    .unwrap()
+    .unwrap();
+    let block = BlockInfo {
+        metadata: BlockMetadata {
+            slot: 1,
+            block_time: 1,
+            ..Default::default()
+        },
+        transactions: vec![transaction],
+        parse_failures: vec![ParseFailure {
+            signature: None,
+            slot: 1,
+            error: "Missing metadata".to_string(),
+        }],
+        ..Default::default()
+    };
+
+    assert_eq!(
+        describe_block(&block).unwrap(),
+        format!(
+            "Slot 1 (parent 0, block time 1): 1 transfers in 1 transactions, 1 unparseable\n\
+             {}\n  42 of {} from {} to {} by {}, instruction 0\n\
+             Unparseable undecodable transaction: Missing metadata\n",
+            Signature::from([7u8; 64]),
+            MINT,
+            source,
+            destination,
+            spl_token::id()
+        )
+    );
+}
+
+#[test]
+fn test_unparseable_transaction_is_skipped() {
+    let transaction = encode(
+        &lookup_table_transfer(42),
+        vec![Pubkey::new_unique(), Pubkey::new_unique()],
+        [None, None],
+    );
+    let mut unparseable = serde_json::to_value(&transaction).unwrap();
+    unparseable["meta"] = serde_json::Value::Null;
+    let block: UiConfirmedBlock = serde_json::from_value(serde_json::json!({
+        "previousBlockhash": "11111111111111111111111111111111",
+        "blockhash": "11111111111111111111111111111111",
+        "parentSlot": 99,
+        "transactions": [unparseable, transaction],
+        "blockTime": 1_700_000_000,
+        "blockHeight": 90,
+    }))
+    .unwrap();
+
+    let block_info = parse_block_info(block, 100).unwrap();
+    assert_eq!(block_info.transactions.len(), 1);
+    assert_eq!(block_info.parse_failures.len(), 1);
+    let failure = &block_info.parse_failures[0];
+    assert_eq!(
+        failure.signature,
+        Some(block_info.transactions[0].signature)
+    );
+    assert_eq!(failure.slot, 100);
+    assert!(failure.error.contains("Missing metadata"));
+}
+
+#[test]
+fn test_parser_errors_point_at_the_instruction() {
+    let mut transaction = lookup_table_transfers(42, 2);
+    // The second transfer moves tokens between accounts without token balances, so it has no
+    // mint.
+    if let VersionedMessage::V0(message) = &mut transaction.message {
+        message.instructions[1].accounts = vec![0, 1, 0];
+    }
+
+    let error = parse_transaction_info(
+        encode(
+            &transaction,
+            vec![Pubkey::new_unique(), Pubkey::new_unique()],
+            [None, None],
+        ),
+        1,
+        1,
+    )
+    .unwrap_err()
+    .to_string();
+    assert!(error.contains("Token balance not found"));
+    assert!(error.contains(&format!(
+        "transaction {}, instruction 1",
+        Signature::from([7u8; 64])
+    )));
+}
+
+#[test]
+fn test_balance_changes_are_parsed() {
+    let source = Pubkey::new_unique();
+    let destination = Pubkey::new_unique();
+    let owner = Pubkey::new_unique();
+    let mut transaction = serde_json::to_value(encode(
+        &lookup_table_transfer(42),
+        vec![source, destination],
+        [Some(owner), None],
+    ))
+    .unwrap();
+    // The source held 1000042 before the transfer, the destination already held 1000000.
+    let post_token_balances = transaction["meta"]["postTokenBalances"].clone();
+    let mut source_balance = post_token_balances[0].clone();
+    source_balance["uiTokenAmount"]["amount"] = serde_json::json!("1000042");
+    transaction["meta"]["preTokenBalances"] =
+        serde_json::json!([source_balance, post_token_balances[1]]);
+    transaction["meta"]["postTokenBalances"][1]["uiTokenAmount"]["amount"] =
+        serde_json::json!("1000042");
+    let block: UiConfirmedBlock = serde_json::from_value(serde_json::json!({
+        "previousBlockhash": "11111111111111111111111111111111",
+        "blockhash": "11111111111111111111111111111111",
+        "parentSlot": 99,
+        "transactions": [transaction],
+        "blockTime": 1_700_000_000,
+        "blockHeight": 90,
+    }))
+    .unwrap();
+
+    let block_info = parse_block_info(block, 100).unwrap();
+    let changes = block_info
+        .balance_changes
+        .iter()
+        .map(|change| {
+            (
+                Pubkey::try_from(change.account.as_slice()).unwrap(),
+                change.owner.clone(),
+                change.pre_amount,
+                change.post_amount,
+                change.delta(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        changes,
+        vec![
+            (
+                source,
+                Some(owner.to_bytes().to_vec()),
+                1_000_042,
+                1_000_000,
+                -42
+            ),
+            (destination, None, 1_000_000, 1_000_042, 42),
+        ]
+    );
+    let change = &block_info.balance_changes[0];
+    assert_eq!(change.signature, Signature::from([7u8; 64]));
+    assert_eq!((change.slot, change.block_time), (100, 1_700_000_000));
+    assert_eq!(
+        change.mint,
+        Pubkey::from_str(MINT).unwrap().to_bytes().to_vec()
+    );
+    assert_eq!(change.decimals, 6);
+
+    // They are only kept when enabled, and for the allowed mints.
+    let filtered = |filter: IndexFilter| {
+        let mut block = block_info.clone();
+        filter_block(&mut block, &filter);
+        block.balance_changes.len()
+    };
+    assert_eq!(filtered(IndexFilter::default()), 0);
+    assert_eq!(
+        filtered(IndexFilter {
+            balance_changes: true,
+            ..Default::default()
+        }),
+        2
+    );
+    assert_eq!(
+        filtered(IndexFilter {
+            balance_changes: true,
+            mints: HashSet::from([Pubkey::new_unique().to_bytes().to_vec()]),
+            ..Default::default()
+        }),
+        0
+    );
+}
+
+// A v0 Token-2022 transaction moving tokens of the mint (index 2) between the two looked up
+// token accounts (indexes 3 and 4), with the accounts laid out as `TransferChecked` expects.
+fn token_2022_transfer(data: Vec<u8>) -> VersionedTransaction {
+    let fee_payer = Pubkey::new_unique();
+    let message = v0::Message {
+        header: MessageHeader {
+            num_required_signatures: 1,
+            num_readonly_signed_accounts: 0,
+            num_readonly_unsigned_accounts: 2,
+        },
+        account_keys: vec![
+            fee_payer,
+            spl_token_2022::id(),
+            Pubkey::from_str(MINT).unwrap(),
+        ],
+        recent_blockhash: Hash::new_unique(),
+        instructions: vec![CompiledInstruction {
+            program_id_index: 1,
+            accounts: vec![3, 2, 4, 0],
+            data,
+        }],
+        address_table_lookups: vec![MessageAddressTableLookup {
+            account_key: Pubkey::new_unique(),
+            writable_indexes: vec![0, 1],
+            readonly_indexes: vec![],
+        }],
+    };
+    VersionedTransaction {
+        signatures: vec![Signature::from([9u8; 64])],
+        message: VersionedMessage::V0(message),
+    }
+}
+
+#[test]
+fn test_token_2022_transfer_fees_are_parsed() {
+    let source = Pubkey::new_unique();
+    let destination = Pubkey::new_unique();
+    let mint = Pubkey::from_str(MINT).unwrap();
+    let parse = |data: Vec<u8>| {
+        parse_transaction_info(
+            encode(
+                &token_2022_transfer(data),
+                vec![source, destination],
+                [None, None],
+            ),
+            1,
+            1,
+        )
+        .unwrap()
+        .expect("transfer should be parsed")
+        .instruction_groups[0]
+            .outer_instruction
+            .clone()
+            .unwrap()
+    };
+
+    // The fee is stated by the instruction.
+    let data = spl_token_2022::extension::transfer_fee::instruction::transfer_checked_with_fee(
+        &spl_token_2022::id(),
+        &source,
+        &mint,
+        &destination,
+        &Pubkey::new_unique(),
+        &[],
+        1_005_000,
+        6,
+        5_000,
+    )
+    .unwrap()
+    .data;
+    let transfer = parse(data);
+    assert_eq!(transfer.source_address, source.to_bytes().to_vec());
+    assert_eq!(
+        transfer.destination_address,
+        destination.to_bytes().to_vec()
+    );
+    assert_eq!(transfer.mint, Some(mint.to_bytes().to_vec()));
+    assert_eq!(transfer.amount, 1_005_000);
+    assert_eq!(transfer.fee, Some(5_000));
+
+    // The token balances show the destination was credited 1_000_000 of the 1_005_000 sent.
+    let data = spl_token::instruction::TokenInstruction::TransferChecked {
+        amount: 1_005_000,
+        decimals: 6,
+    }
+    .pack();
+    let transfer = parse(data);
+    assert_eq!(transfer.amount, 1_005_000);
+    assert_eq!(transfer.decimals, Some(6));
+    assert_eq!(transfer.fee, Some(5_000));
+
+    // A mint without the transfer fee extension credits everything that was sent.
+    let data = spl_token::instruction::TokenInstruction::TransferChecked {
+        amount: 1_000_000,
+        decimals: 6,
+    }
+    .pack();
+    let transfer = parse(data);
+    assert_eq!(transfer.amount, 1_000_000);
+    assert_eq!(transfer.fee, None);
+
+    // The legacy token program has no transfer fees.
+    let transaction = lookup_table_transfer(42);
+    let parsed = parse_transaction_info(
+        encode(&transaction, vec![source, destination], [None, None]),
+        1,
+        1,
+    )
+    .unwrap()
+    .expect("transfer should be parsed");
+    let transfer = parsed.instruction_groups[0]
+        .outer_instruction
+        .as_ref()
+        .unwrap();
+    assert_eq!(transfer.fee, None);
+}
+
+#[test]
+fn test_token_2022_atas_are_derived_from_the_token_2022_program() {
+    let source = Pubkey::new_unique();
+    let destination = Pubkey::new_unique();
+    let mint = Pubkey::from_str(MINT).unwrap();
+    let associated_token_program =
+        Pubkey::from_str("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL").unwrap();
+    let ata = |owner: Pubkey, token_program: Pubkey| {
+        Pubkey::find_program_address(
+            &[owner.as_ref(), token_program.as_ref(), mint.as_ref()],
+            &associated_token_program,
+        )
+        .0
+        .to_bytes()
+        .to_vec()
+    };
+
+    let data = spl_token::instruction::TokenInstruction::TransferChecked {
+        amount: 42,
+        decimals: 6,
+    }
+    .pack();
+    let parsed = parse_transaction_info(
+        encode(
+            &token_2022_transfer(data),
+            vec![source, destination],
+            [None, None],
+        ),
+        1,
+        1,
+    )
+    .unwrap()
+    .expect("transfer should be parsed");
+    let transfer = parsed.instruction_groups[0]
+        .outer_instruction
+        .as_ref()
+        .unwrap();
+    assert_eq!(transfer.source_ata, Some(ata(source, spl_token_2022::id())));
+    assert_eq!(
+        transfer.destination_ata,
+        Some(ata(destination, spl_token_2022::id()))
+    );
+    assert_ne!(transfer.source_ata, Some(ata(source, spl_token::id())));
+}
+
+#[test]
+fn test_lookup_table_readonly_accounts_follow_all_writable_ones() {
+    let source = Pubkey::new_unique();
+    let destination = Pubkey::new_unique();
+    let mint = Pubkey::from_str(MINT).unwrap();
+    // The first lookup loads the source and the mint, the second the destination. The runtime
+    // resolves the writable addresses of both lookups before any readonly one, so the
+    // destination is index 3 and the mint index 4.
+    let message = v0::Message {
+        header: MessageHeader {
+            num_required_signatures: 1,
+            num_readonly_signed_accounts: 0,
+            num_readonly_unsigned_accounts: 1,
+        },
+        account_keys: vec![Pubkey::new_unique(), spl_token::id()],
+        recent_blockhash: Hash::new_unique(),
+        instructions: vec![CompiledInstruction {
+            program_id_index: 1,
+            accounts: vec![2, 4, 3, 0],
+            data: spl_token::instruction::TokenInstruction::TransferChecked {
+                amount: 42,
+                decimals: 6,
+            }
+            .pack(),
+        }],
+        address_table_lookups: vec![
+            MessageAddressTableLookup {
+                account_key: Pubkey::new_unique(),
+                writable_indexes: vec![0],
+                readonly_indexes: vec![1],
+            },
+            MessageAddressTableLookup {
+                account_key: Pubkey::new_unique(),
+                writable_indexes: vec![0],
+                readonly_indexes: vec![],
+            },
+        ],
+    };
+    let transaction = VersionedTransaction {
+        signatures: vec![Signature::from([10u8; 64])],
+        message: VersionedMessage::V0(message),
+    };
+    let encode_loaded = |writable: Vec<Pubkey>, readonly: Vec<Pubkey>| {
+        let mut encoded =
+            serde_json::to_value(encode(&transaction, writable, [None, None])).unwrap();
+        encoded["meta"]["loadedAddresses"]["readonly"] =
+            serde_json::json!(readonly.iter().map(Pubkey::to_string).collect::<Vec<_>>());
+        serde_json::from_value::<EncodedTransactionWithStatusMeta>(encoded).unwrap()
+    };
+
+    let parsed = parse_transaction_info(encode_loaded(vec![source, destination], vec![mint]), 1, 1)
+        .unwrap()
+        .expect("transfer should be parsed");
+    let transfer = parsed.instruction_groups[0]
+        .outer_instruction
+        .as_ref()
+        .unwrap();
+    assert_eq!(transfer.source_address, source.to_bytes().to_vec());
+    assert_eq!(
+        transfer.destination_address,
+        destination.to_bytes().to_vec()
+    );
+    assert_eq!(transfer.mint, Some(mint.to_bytes().to_vec()));
+
+    // The same number of addresses, split differently between writable and readonly, would
+    // shift the indexes.
+    let parsed =
+        parse_transaction_info(encode_loaded(vec![source], vec![destination, mint]), 1, 1).unwrap();
+    assert!(parsed.is_none());
+}
+
+// The mainnet block behind the `get_transaction_by_address` snapshots, parsed without setting up
+// a database.
+#[test]
+fn test_recorded_block_transfers_are_parsed() {
+    let signature = Signature::from_str(
+        "5u2MkL1atE8ZY2XHEE8PJwxnP69mzLSTfaTrnHu6HFt3KgjuEHJADbCR9dn2o7cJDVbDenvY1PrdPTCVfCNSfLEA",
+    )
+    .unwrap();
+    let transaction = load_block_fixture("get_transaction_by_address", 285941932)
+        .transactions
+        .unwrap()
+        .into_iter()
+        .find(|transaction| {
+            transaction
+                .transaction
+                .decode()
+                .is_some_and(|decoded| decoded.signatures.first() == Some(&signature))
+        })
+        .expect("the transaction should be in the block");
+
+    let instruction_groups = PollerParser::parse_instruction_groups(
+        transaction.transaction.decode().unwrap(),
+        transaction.meta.unwrap(),
+    )
+    .unwrap();
+    let source = Pubkey::from_str("BhW85ig2dHu5tV6sCs7ps5UyPCLSjfXgEsfVAX82yXnb").unwrap();
+    let transfers = instruction_groups
+        .iter()
+        .flat_map(|instruction_group| instruction_group.transfers())
+        .filter(|transfer| transfer.source_address == source.to_bytes().to_vec())
+        .collect::<Vec<_>>();
+    let mint = Pubkey::from_str(MINT).unwrap().to_bytes().to_vec();
+    assert!(transfers
+        .iter()
+        .all(|transfer| transfer.mint.as_ref() == Some(&mint)));
+
+    let mut amounts = transfers
+        .iter()
+        .map(|transfer| transfer.amount)
+        .collect::<Vec<_>>();
+    amounts.sort();
+    assert_eq!(
+        amounts,
+        vec![
+            4186000000000,
+            4204000000000,
+            4318000000000,
+            4414000000000,
+            4424000000000,
+            4608000000000,
+            4629000000000,
+            4740000000000,
+            4985000000000,
+            4999000000000,
+        ]
+    );
+}
+
+#[test]
+fn test_fetched_transaction_keeps_its_slot_and_block_time() {
+    let source = Pubkey::new_unique();
+    let destination = Pubkey::new_unique();
+    let fetched = |block_time| EncodedConfirmedTransactionWithStatusMeta {
+        slot: 100,
+        transaction: encode(
+            &lookup_table_transfer(42),
+            vec![source, destination],
+            [None, None],
+        ),
+        block_time,
+    };
+
+    let transaction = Transaction::try_from(fetched(Some(1_700_000_000))).unwrap();
+    assert_eq!(transaction.slot, 100);
+    assert_eq!(transaction.block_time, 1_700_000_000);
+
+    let error = Transaction::try_from(fetched(None)).unwrap_err();
+    assert!(error.to_string().contains("Missing block_time"));
+}
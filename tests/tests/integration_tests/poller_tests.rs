@@ -0,0 +1,141 @@
+use std::{
+    convert::Infallible,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures_util::StreamExt;
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server,
+};
+use indexer::{
+    poller::{PollerStreamer, SlotReorderBuffer},
+    rpc::FailoverRpcClient,
+};
+use serde_json::{json, Value};
+use solana_client::nonblocking::rpc_client::RpcClient;
+
+use crate::setup::{block, slots};
+
+#[tokio::test]
+async fn test_unavailable_block_is_given_up_on() {
+    // Nothing listens on this port, so every fetch fails.
+    let client = Arc::new(RpcClient::new("http://127.0.0.1:1".to_string()));
+    let client = Arc::new(FailoverRpcClient::from(client));
+    let stream = PollerStreamer::get_poller_block_stream(client, 99, 1, 2, 0, Some(100));
+    let blocks = tokio::time::timeout(Duration::from_secs(10), stream.collect::<Vec<_>>())
+        .await
+        .expect("the poller should give up on the block");
+    assert!(blocks.is_empty());
+}
+
+// Serves an RPC node whose tip stays at `tip` and whose slots are all skipped, recording the
+// slots blocks are requested for.
+fn serve_rpc(tip: u64, requested_slots: Arc<Mutex<Vec<u64>>>) -> String {
+    let make_service = make_service_fn(move |_| {
+        let requested_slots = requested_slots.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let requested_slots = requested_slots.clone();
+                async move {
+                    let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                    let request: Value = serde_json::from_slice(&body).unwrap();
+                    let response = match request["method"].as_str() {
+                        Some("getSlot") => {
+                            json!({ "jsonrpc": "2.0", "id": request["id"], "result": tip })
+                        }
+                        // The client checks the node's version before its first block request.
+                        Some("getVersion") => json!({
+                            "jsonrpc": "2.0",
+                            "id": request["id"],
+                            "result": { "solana-core": "1.17.34", "feature-set": 0 },
+                        }),
+                        _ => {
+                            requested_slots
+                                .lock()
+                                .unwrap()
+                                .push(request["params"][0].as_u64().unwrap());
+                            json!({
+                                "jsonrpc": "2.0",
+                                "id": request["id"],
+                                "error": { "code": -32007, "message": "Slot was skipped" },
+                            })
+                        }
+                    };
+                    Ok::<_, Infallible>(Response::new(Body::from(response.to_string())))
+                }
+            }))
+        }
+    });
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+    let url = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+    url
+}
+
+#[tokio::test]
+async fn test_polling_stays_confirmation_depth_behind_the_tip() {
+    let requested_slots = Arc::new(Mutex::new(vec![]));
+    let url = serve_rpc(110, requested_slots.clone());
+    let client = Arc::new(FailoverRpcClient::from(Arc::new(RpcClient::new(url))));
+    let stream = PollerStreamer::get_poller_block_stream(client, 100, 20, 0, 5, None);
+    // The stream follows the tip forever, so it only ends with the timeout.
+    assert!(
+        tokio::time::timeout(Duration::from_millis(500), stream.collect::<Vec<_>>())
+            .await
+            .is_err()
+    );
+
+    let mut requested_slots = requested_slots.lock().unwrap().clone();
+    requested_slots.sort();
+    assert_eq!(requested_slots, (101..=105).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_blocks_are_held_back_until_lower_slots_are_resolved() {
+    let mut buffer = SlotReorderBuffer::new(100);
+
+    // Slot 100 is still being fetched when the slots after it, fetched later, complete.
+    assert!(buffer.push(102, Some(block(102, 101))).is_empty());
+    // 101 was skipped.
+    assert!(buffer.push(101, None).is_empty());
+    assert!(buffer.push(104, Some(block(104, 103))).is_empty());
+    assert_eq!(
+        slots(buffer.push(100, Some(block(100, 99)))),
+        vec![100, 102]
+    );
+    assert_eq!(buffer.next_slot(), 103);
+
+    // A block that couldn't be fetched resolves its slot too.
+    assert_eq!(slots(buffer.push(103, None)), vec![104]);
+    assert_eq!(slots(buffer.push(105, Some(block(105, 104)))), vec![105]);
+    // Resolved slots aren't handed out again.
+    assert!(buffer.push(102, Some(block(102, 101))).is_empty());
+    assert_eq!(buffer.next_slot(), 106);
+}
+
+#[test]
+fn test_rpc_endpoint_is_switched_after_repeated_failures() {
+    let client = FailoverRpcClient::new(vec![
+        Arc::new(RpcClient::new("http://127.0.0.1:1".to_string())),
+        Arc::new(RpcClient::new("http://127.0.0.1:2".to_string())),
+    ]);
+    let url = || client.client().url();
+
+    client.record_failure();
+    client.record_failure();
+    // A success resets the count.
+    client.record_success();
+    client.record_failure();
+    client.record_failure();
+    assert_eq!(url(), "http://127.0.0.1:1");
+
+    client.record_failure();
+    assert_eq!(url(), "http://127.0.0.1:2");
+
+    for _ in 0..3 {
+        client.record_failure();
+    }
+    assert_eq!(url(), "http://127.0.0.1:1");
+}
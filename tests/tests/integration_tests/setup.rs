@@ -1,11 +1,14 @@
 use std::{
     env,
     path::{Path, PathBuf},
-    sync::Mutex,
 };
 
 use api::{api::Api, config::setup_config};
-use indexer::{db::Dao, parser::PollerParser, types::BlockInfo};
+use indexer::{
+    db::Dao,
+    parser::PollerParser,
+    types::{BlockInfo, BlockMetadata},
+};
 
 use migrator::{Migrator, MigratorTrait};
 use once_cell::sync::Lazy;
@@ -24,6 +27,7 @@ use sqlx::{
     PgPool,
 };
 use std::sync::Arc;
+use tokio::sync::Mutex;
 
 const RPC_CONFIG: RpcTransactionConfig = RpcTransactionConfig {
     encoding: Some(UiTransactionEncoding::Base64),
@@ -49,7 +53,7 @@ async fn run_migrations(db: &DatabaseConnection) {
 }
 
 async fn run_one_time_setup(db: &DatabaseConnection) {
-    let mut init = INIT.lock().unwrap();
+    let mut init = INIT.lock().await;
     if init.is_none() {
         setup_logging();
         Migrator::fresh(db).await.unwrap();
@@ -143,6 +147,29 @@ pub async fn cached_fetch_block(setup: &TestSetup, slot: Slot) -> BlockInfo {
     PollerParser::parse_ui_confirmed_block(block, slot).unwrap()
 }
 
+/// Reads a block recorded by `cached_fetch_block`, for tests that only parse it and need
+/// neither an RPC endpoint nor a database.
+pub fn load_block_fixture(name: &str, slot: Slot) -> UiConfirmedBlock {
+    let file_path = relative_project_path(&format!("tests/data/blocks/{}/{}", name, slot));
+    serde_json::from_slice(&std::fs::read(file_path).unwrap()).unwrap()
+}
+
+/// An empty block at `slot`, for tests that only look at how blocks are ordered.
+pub fn block(slot: Slot, parent_slot: Slot) -> BlockInfo {
+    BlockInfo {
+        metadata: BlockMetadata {
+            slot,
+            parent_slot,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+pub fn slots(blocks: Vec<BlockInfo>) -> Vec<Slot> {
+    blocks.iter().map(|block| block.metadata.slot).collect()
+}
+
 pub fn trim_test_name(name: &str) -> String {
     // Remove the test_ prefix and the case suffix
     name.replace("test_", "")
@@ -152,6 +179,6 @@ pub fn trim_test_name(name: &str) -> String {
         .to_string()
 }
 
-fn relative_project_path(path: &str) -> PathBuf {
+pub fn relative_project_path(path: &str) -> PathBuf {
     PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(path)
 }
@@ -0,0 +1,399 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use api::spec::{ApiContract, GetBlock, GetTransactionsByAddress};
+use async_trait::async_trait;
+use function_name::named;
+use indexer::{
+    config::IndexerConfig,
+    db::Dao,
+    error::IndexerError,
+    messenger::Messenger,
+    parser::parse_transaction_info,
+    sink::TransferSink,
+    types::{BlockInfo, BlockMetadata, Transaction},
+};
+use rstest::rstest;
+use serial_test::serial;
+use solana_sdk::{clock::Slot, pubkey::Pubkey};
+
+use crate::parser_tests::{encode, lookup_table_transfers, MINT};
+
+fn block_metadata() -> BlockMetadata {
+    BlockMetadata {
+        slot: 100,
+        parent_slot: 99,
+        block_time: 1_700_000_000,
+        block_height: 90,
+        ..Default::default()
+    }
+}
+
+fn transfers(source: Pubkey, destination: Pubkey) -> Vec<Transaction> {
+    let transaction = parse_transaction_info(
+        encode(
+            &lookup_table_transfers(42, 2),
+            vec![source, destination],
+            [None, None],
+        ),
+        100,
+        1_700_000_000,
+    )
+    .unwrap()
+    .unwrap();
+    vec![transaction]
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_postgres_sink() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let source = Pubkey::new_unique();
+    let sink: Arc<dyn TransferSink> = Arc::new(setup.dao.clone());
+    sink.index_block_metadatas(vec![&block_metadata()])
+        .await
+        .unwrap();
+    sink.index_transaction(&transfers(source, Pubkey::new_unique()))
+        .await
+        .unwrap();
+
+    let block = setup.api.get_block(GetBlock { slot: 100 }).await.unwrap();
+    assert_eq!(block.parent_slot, 99);
+    let transactions = setup
+        .api
+        .get_transactions_by_address(GetTransactionsByAddress {
+            source: Some(source.to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(transactions.count, 2);
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_concurrent_inserts() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    // Run with `--nocapture` to compare the insert times.
+    for concurrency in [1, 4] {
+        let transactions = (0..200)
+            .flat_map(|_| transfers(Pubkey::new_unique(), Pubkey::new_unique()))
+            .collect::<Vec<_>>();
+        let start = std::time::Instant::now();
+        setup
+            .dao
+            .index_transactions_concurrently(&transactions, concurrency)
+            .await
+            .unwrap();
+        println!(
+            "Inserted {} transactions on {} connections in {:?}",
+            transactions.len(),
+            concurrency,
+            start.elapsed()
+        );
+    }
+
+    let transactions = setup
+        .api
+        .get_transactions_by_address(GetTransactionsByAddress {
+            mint: Some(MINT.to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    // Each transaction holds two transfers.
+    assert_eq!(transactions.count, 800);
+}
+
+// Writes to Postgres, but panics the first time it is handed transfers.
+struct PanickingSink {
+    dao: Dao,
+    panicked: AtomicBool,
+}
+
+#[async_trait]
+impl TransferSink for PanickingSink {
+    async fn index_block_metadatas(
+        &self,
+        blocks: Vec<&BlockMetadata>,
+    ) -> Result<Vec<Slot>, IndexerError> {
+        self.dao.index_block_metadatas(blocks).await
+    }
+
+    async fn index_transaction(&self, transactions: &[Transaction]) -> Result<(), IndexerError> {
+        if !self.panicked.swap(true, Ordering::SeqCst) {
+            panic!("sink failed while writing transfers");
+        }
+        TransferSink::index_transaction(&self.dao, transactions).await
+    }
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_batches_survive_a_panicking_worker() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let messenger = Arc::new(Messenger::new(IndexerConfig {
+        workers: 1,
+        ..Default::default()
+    }));
+    let sink = Arc::new(PanickingSink {
+        dao: setup.dao.clone(),
+        panicked: AtomicBool::new(false),
+    });
+    messenger.clone().run(sink.clone());
+
+    let source = Pubkey::new_unique();
+    messenger
+        .send_block_batches(vec![BlockInfo {
+            metadata: block_metadata(),
+            transactions: transfers(source, Pubkey::new_unique()),
+            ..Default::default()
+        }])
+        .await;
+    messenger.wait_until_idle().await;
+
+    // The restarted worker wrote the transfers the panicked one was holding.
+    assert!(sink.panicked.load(Ordering::SeqCst));
+    let transactions = setup
+        .api
+        .get_transactions_by_address(GetTransactionsByAddress {
+            source: Some(source.to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(transactions.count, 2);
+}
+
+// Writes blocks to Postgres, but never manages to write transfers.
+struct FailingSink {
+    dao: Dao,
+}
+
+#[async_trait]
+impl TransferSink for FailingSink {
+    async fn index_block_metadatas(
+        &self,
+        blocks: Vec<&BlockMetadata>,
+    ) -> Result<Vec<Slot>, IndexerError> {
+        self.dao.index_block_metadatas(blocks).await
+    }
+
+    async fn index_transaction(&self, _transactions: &[Transaction]) -> Result<(), IndexerError> {
+        Err(IndexerError::DatabaseError("connection lost".to_string()))
+    }
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_batch_commit_waits_for_every_write() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let block = |source: Pubkey| BlockInfo {
+        metadata: block_metadata(),
+        transactions: transfers(source, Pubkey::new_unique()),
+        ..Default::default()
+    };
+    let config = IndexerConfig {
+        workers: 1,
+        ..Default::default()
+    };
+
+    let messenger = Arc::new(Messenger::new(config.clone()));
+    messenger.clone().run(Arc::new(setup.dao.clone()));
+    let source = Pubkey::new_unique();
+    let commit = messenger.send_block_batches(vec![block(source)]).await;
+    assert_eq!(commit.await, Ok(true));
+    // Confirmed without waiting for the workers, so the transfers must be written already.
+    let transactions = setup
+        .api
+        .get_transactions_by_address(GetTransactionsByAddress {
+            source: Some(source.to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(transactions.count, 2);
+
+    let messenger = Arc::new(Messenger::new(config));
+    messenger.clone().run(Arc::new(FailingSink {
+        dao: setup.dao.clone(),
+    }));
+    let commit = messenger
+        .send_block_batches(vec![block(Pubkey::new_unique())])
+        .await;
+    assert_eq!(commit.await, Ok(false));
+}
+
+// Records the order of the writes, each of which takes a while so that concurrent ones would
+// interleave.
+#[derive(Default)]
+struct RecordingSink {
+    writes: std::sync::Mutex<Vec<(&'static str, Slot)>>,
+}
+
+#[async_trait]
+impl TransferSink for RecordingSink {
+    async fn index_block_metadatas(
+        &self,
+        blocks: Vec<&BlockMetadata>,
+    ) -> Result<Vec<Slot>, IndexerError> {
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let mut writes = self.writes.lock().unwrap();
+        writes.extend(blocks.iter().map(|block| ("blocks", block.slot)));
+        Ok(vec![])
+    }
+
+    async fn index_transaction(&self, transactions: &[Transaction]) -> Result<(), IndexerError> {
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let mut writes = self.writes.lock().unwrap();
+        writes.extend(
+            transactions
+                .iter()
+                .map(|transaction| ("transfers", transaction.slot)),
+        );
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_blocks_are_written_before_their_transfers_and_after_earlier_batches() {
+    let messenger = Arc::new(Messenger::new(IndexerConfig {
+        workers: 2,
+        ..Default::default()
+    }));
+    let sink = Arc::new(RecordingSink::default());
+    messenger.clone().run(sink.clone());
+
+    let block = |slot: Slot| {
+        let mut transactions = transfers(Pubkey::new_unique(), Pubkey::new_unique());
+        for transaction in &mut transactions {
+            transaction.slot = slot;
+        }
+        BlockInfo {
+            metadata: BlockMetadata {
+                slot,
+                parent_slot: slot - 1,
+                ..block_metadata()
+            },
+            transactions,
+            ..Default::default()
+        }
+    };
+    let first = messenger.send_block_batches(vec![block(100)]).await;
+    let second = messenger.send_block_batches(vec![block(101)]).await;
+    assert_eq!(first.await, Ok(true));
+    assert_eq!(second.await, Ok(true));
+
+    // Removing the blocks of an abandoned fork can't race with the writes of their slots.
+    assert_eq!(
+        *sink.writes.lock().unwrap(),
+        vec![
+            ("blocks", 100),
+            ("transfers", 100),
+            ("blocks", 101),
+            ("transfers", 101),
+        ]
+    );
+}
+
+// Needs a running ClickHouse server, e.g.
+// `TEST_CLICKHOUSE_URL=http://localhost:8123 cargo test --features clickhouse`.
+#[cfg(feature = "clickhouse")]
+#[tokio::test]
+#[serial]
+async fn test_clickhouse_sink() {
+    use indexer::{clickhouse::ClickHouseSink, config::ClickHouseConfig};
+
+    let url = std::env::var("TEST_CLICKHOUSE_URL").expect("TEST_CLICKHOUSE_URL must be set");
+    if !(url.contains("127.0.0.1") || url.contains("localhost")) {
+        panic!("Refusing to run tests on non-local database out of caution");
+    }
+    let sink = ClickHouseSink::new(&ClickHouseConfig {
+        url,
+        ..Default::default()
+    });
+    sink.create_tables().await.unwrap();
+    for table in ["token_transfers", "blocks"] {
+        sink.client()
+            .query(&format!("TRUNCATE TABLE {}", table))
+            .execute()
+            .await
+            .unwrap();
+    }
+
+    let source = Pubkey::new_unique();
+    let transactions = transfers(source, Pubkey::new_unique());
+    sink.index_block_metadatas(vec![&block_metadata()])
+        .await
+        .unwrap();
+    // Indexing the same transfers again must not duplicate them.
+    for _ in 0..2 {
+        sink.index_transaction(&transactions).await.unwrap();
+    }
+
+    let blocks = sink
+        .client()
+        .query("SELECT count() FROM blocks FINAL WHERE slot = 100")
+        .fetch_one::<u64>()
+        .await
+        .unwrap();
+    assert_eq!(blocks, 1);
+    let transfers = sink
+        .client()
+        .query("SELECT count() FROM token_transfers FINAL WHERE source_address = ?")
+        .bind(source.to_string())
+        .fetch_one::<u64>()
+        .await
+        .unwrap();
+    assert_eq!(transfers, 2);
+}
@@ -1,10 +1,44 @@
-use api::spec::{ApiContract, GetTransactionsByAddress};
+use std::{collections::BTreeSet, convert::Infallible, time::Duration};
+
+use api::{
+    api::MAX_MINTS_PER_REQUEST,
+    db::TransactionSorting,
+    export::{csv_row, ExportLayer, EXPORT_PATH},
+    spec::{
+        ApiContract, GetActivityHistogram, GetCounterparties, GetTransactionsByAddress,
+        GetTransactionsByAddresses, GetTransactionsByMint, GetTransactionsByProgram,
+        GetTransferVolume,
+    },
+    types::{DateBound, Transaction},
+};
+use chrono::{DateTime, Utc};
+use dao::generated::{blocks, sea_orm_active_enums::TokenType, token_transfers};
 use function_name::named;
+use hyper::{header::CONTENT_TYPE, Body, Method, Request, Response, StatusCode};
+use indexer::{
+    config::ConflictStrategy,
+    parser::parse_transaction_info,
+    types::{BlockInfo, BlockMetadata},
+};
 use rstest::rstest;
+use sea_orm::{
+    prelude::Decimal, ConnectionTrait, DbBackend, EntityTrait, Statement, TransactionTrait,
+};
 
 use insta::assert_json_snapshot;
+use serde_json::Value;
 use serial_test::serial;
+use solana_sdk::{
+    instruction::InstructionError, pubkey::Pubkey, signature::Signature,
+    transaction::TransactionError,
+};
+use sqlx::postgres::PgListener;
+use tower::{service_fn, Layer, Service};
 
+use crate::parser_tests::{
+    cpi_transfer, encode, encode_with_inner_instructions, lookup_table_transfer,
+    lookup_table_transfers, router_transfer, MINT,
+};
 use crate::setup::cached_fetch_block;
 
 #[named]
@@ -28,12 +62,7 @@ async fn test_get_transaction_by_address() {
     let payload = GetTransactionsByAddress {
         source: Some("BhW85ig2dHu5tV6sCs7ps5UyPCLSjfXgEsfVAX82yXnb".to_string()),
         page: Some(1),
-        destination: None,
-        mint: None,
-        before: None,
-        after: None,
-        limit: None,
-        sort_by: None,
+        ..Default::default()
     };
 
     let parsed_transaction = setup
@@ -49,12 +78,8 @@ async fn test_get_transaction_by_address() {
     let payload = GetTransactionsByAddress {
         source: Some("BhW85ig2dHu5tV6sCs7ps5UyPCLSjfXgEsfVAX82yXnb".to_string()),
         destination: Some("4HHZV2LRBQD5CJnYgMTzPeKcS2nnTT8szeh2svBWQ89m".to_string()),
-        mint: None,
         page: Some(1),
-        before: None,
-        after: None,
-        limit: None,
-        sort_by: None,
+        ..Default::default()
     };
 
     let parsed_transaction = setup
@@ -69,13 +94,9 @@ async fn test_get_transaction_by_address() {
 
     let payload = GetTransactionsByAddress {
         source: Some("BhW85ig2dHu5tV6sCs7ps5UyPCLSjfXgEsfVAX82yXnb".to_string()),
-        destination: None,
-        mint: None,
         page: Some(2),
-        before: None,
-        after: None,
         limit: Some(5),
-        sort_by: None,
+        ..Default::default()
     };
 
     let parsed_transaction = setup
@@ -90,13 +111,8 @@ async fn test_get_transaction_by_address() {
 
     let payload = GetTransactionsByAddress {
         source: Some("HUe9Gfu8DMhY4Dj9A56N9muZg7euoFcXQskVAAfJpgEw".to_string()),
-        destination: None,
-        mint: None,
         page: Some(1),
-        before: None,
-        after: None,
-        limit: None,
-        sort_by: None,
+        ..Default::default()
     };
 
     let parsed_transaction = setup
@@ -110,14 +126,9 @@ async fn test_get_transaction_by_address() {
     );
 
     let payload = GetTransactionsByAddress {
-        source: None,
-        destination: None,
         mint: Some("AmeroCaeKg55p6J8d1y2R4t9taqgn3TH4BARgzQJyHvd".to_string()),
         page: Some(1),
-        before: None,
-        after: None,
-        limit: None,
-        sort_by: None,
+        ..Default::default()
     };
 
     let parsed_transaction = setup
@@ -130,3 +141,2558 @@ async fn test_get_transaction_by_address() {
         parsed_transaction
     );
 }
+
+// Looks `name` up among the schema definitions of the document, wherever they were placed.
+fn find_definition<'a>(value: &'a Value, name: &str) -> Option<&'a Value> {
+    match value {
+        Value::Object(map) => ["definitions", "schemas"]
+            .iter()
+            .find_map(|key| map.get(*key).and_then(|definitions| definitions.get(name)))
+            .or_else(|| map.values().find_map(|value| find_definition(value, name))),
+        Value::Array(values) => values.iter().find_map(|value| find_definition(value, name)),
+        _ => None,
+    }
+}
+
+fn resolve_schema<'a>(document: &'a Value, schema: &'a Value) -> &'a Value {
+    match schema.get("$ref").and_then(Value::as_str) {
+        Some(reference) => {
+            let name = reference.rsplit('/').next().unwrap();
+            find_definition(document, name)
+                .unwrap_or_else(|| panic!("{} is not defined in the document", reference))
+        }
+        None => schema,
+    }
+}
+
+fn json_types(value: &Value) -> &'static [&'static str] {
+    match value {
+        Value::Null => &["null"],
+        Value::Bool(_) => &["boolean"],
+        Value::Number(number) if number.is_f64() => &["number"],
+        Value::Number(_) => &["integer", "number"],
+        Value::String(_) => &["string"],
+        Value::Array(_) => &["array"],
+        Value::Object(_) => &["object"],
+    }
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_schema_documents_get_transactions_by_address() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let document = serde_json::to_value(setup.api.schema()).unwrap();
+    let method = document["methods"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|method| method["name"] == "getTransactionsByAddress")
+        .unwrap();
+    let properties = method["params"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|param| {
+            resolve_schema(&document, &param["schema"])["properties"]
+                .as_object()
+                .cloned()
+        })
+        .flatten()
+        .collect::<serde_json::Map<_, _>>();
+
+    // Every field is set, so that a new field has to be added here and gets checked too.
+    let payload = GetTransactionsByAddress {
+        source: Some("BhW85ig2dHu5tV6sCs7ps5UyPCLSjfXgEsfVAX82yXnb".to_string()),
+        destination: Some("4HHZV2LRBQD5CJnYgMTzPeKcS2nnTT8szeh2svBWQ89m".to_string()),
+        participant_address: Some("BhW85ig2dHu5tV6sCs7ps5UyPCLSjfXgEsfVAX82yXnb".to_string()),
+        mint: Some(MINT.to_string()),
+        mint_addresses: Some(vec![MINT.to_string()]),
+        limit: Some(10),
+        page: Some(1),
+        before: Some("2024-08-27".to_string()),
+        after: Some("2024-08-26".to_string()),
+        sort_by: Some(TransactionSorting::default()),
+        count_total: Some(true),
+        success_only: Some(true),
+        from_height: Some(90),
+        to_height: Some(100),
+        include_raw: Some(true),
+        token_type: Some(TokenType::Token2022),
+    };
+    let payload = serde_json::to_value(&payload).unwrap();
+    let payload = payload.as_object().unwrap();
+    assert_eq!(
+        properties.keys().collect::<BTreeSet<_>>(),
+        payload.keys().collect::<BTreeSet<_>>()
+    );
+    for (field, value) in payload {
+        let schema = resolve_schema(&document, &properties[field]);
+        let documented = match schema.get("type") {
+            Some(Value::String(documented)) => vec![documented.as_str()],
+            Some(Value::Array(documented)) => documented.iter().filter_map(Value::as_str).collect(),
+            // References and unions of them are checked by the names above.
+            _ => continue,
+        };
+        assert!(
+            json_types(value)
+                .iter()
+                .any(|json_type| documented.contains(json_type)),
+            "{} is documented as {:?} but sent as {}",
+            field,
+            documented,
+            value
+        );
+    }
+}
+
+#[test]
+fn test_address_field_names_are_still_accepted() {
+    let payload: GetTransactionsByAddress = serde_json::from_value(serde_json::json!({
+        "sourceAddress": "BhW85ig2dHu5tV6sCs7ps5UyPCLSjfXgEsfVAX82yXnb",
+        "destinationAddress": "4HHZV2LRBQD5CJnYgMTzPeKcS2nnTT8szeh2svBWQ89m",
+        "mintAddress": MINT,
+    }))
+    .unwrap();
+    assert_eq!(
+        payload,
+        GetTransactionsByAddress {
+            source: Some("BhW85ig2dHu5tV6sCs7ps5UyPCLSjfXgEsfVAX82yXnb".to_string()),
+            destination: Some("4HHZV2LRBQD5CJnYgMTzPeKcS2nnTT8szeh2svBWQ89m".to_string()),
+            mint: Some(MINT.to_string()),
+            ..Default::default()
+        }
+    );
+}
+
+#[test]
+fn test_transfer_amount_is_a_string() {
+    // 2^53 + 1 can't be represented exactly by a JavaScript number.
+    let transfer = Transaction {
+        signature: "5u2MkL1atE8ZY2XHEE8PJwxnP69mzLSTfaTrnHu6HFt3KgjuEHJADbCR9dn2o7cJDVbDenvY1PrdPTCVfCNSfLEA".to_string(),
+        program_address: "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA".to_string(),
+        mint_address: Some(MINT.to_string()),
+        source_address: "BhW85ig2dHu5tV6sCs7ps5UyPCLSjfXgEsfVAX82yXnb".to_string(),
+        destination_address: "FDtzHvo68TiRiWB7sU5KkEGy8HGnNcYS8qL7pGqqPmm8".to_string(),
+        source_ata: None,
+        destination_ata: None,
+        amount: 9_007_199_254_740_993,
+        decimals: None,
+        ui_amount: None,
+        fee: None,
+        net_amount: None,
+        fee_payer: None,
+        transaction_fee: None,
+        memo: None,
+        slot: 285941932,
+        error: None,
+        block_time: "2024-08-26T12:02:50Z".parse().unwrap(),
+        block_time_unix: 1724673770,
+        inner_instruction_index: None,
+        data: None,
+    };
+    assert_json_snapshot!("transfer-amount-as-string", transfer);
+
+    let json = serde_json::to_value(&transfer).unwrap();
+    assert_eq!(
+        serde_json::from_value::<Transaction>(json).unwrap(),
+        transfer
+    );
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_get_transaction_with_slot_safety_margin() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let mut setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let block = cached_fetch_block(&setup, 285941932).await;
+    let _ = setup.dao.index_block(&block).await;
+    let payload = GetTransactionsByAddress {
+        source: Some("BhW85ig2dHu5tV6sCs7ps5UyPCLSjfXgEsfVAX82yXnb".to_string()),
+        ..Default::default()
+    };
+
+    // The only indexed block is the tip, so any margin hides its transfers.
+    setup.api.config.slot_safety_margin = Some(1);
+    let transactions = setup
+        .api
+        .get_transactions_by_address(payload.clone())
+        .await
+        .unwrap();
+    assert_eq!(transactions.count, 0);
+
+    setup.api.config.slot_safety_margin = Some(0);
+    let transactions = setup
+        .api
+        .get_transactions_by_address(payload.clone())
+        .await
+        .unwrap();
+    assert_eq!(transactions.count, 10);
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_identical_transfers_are_all_indexed() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let source = Pubkey::new_unique();
+    let destination = Pubkey::new_unique();
+    let transaction = parse_transaction_info(
+        encode(
+            &lookup_table_transfers(42, 2),
+            vec![source, destination],
+            [None, None],
+        ),
+        100,
+        1_700_000_000,
+    )
+    .unwrap()
+    .unwrap();
+    let block = BlockInfo {
+        metadata: BlockMetadata {
+            slot: 100,
+            parent_slot: 99,
+            block_time: 1_700_000_000,
+            block_height: 90,
+            ..Default::default()
+        },
+        transactions: vec![transaction],
+        ..Default::default()
+    };
+    setup.dao.index_block(&block).await.unwrap();
+    // Indexing the same block again must not duplicate the transfers.
+    setup.dao.index_block(&block).await.unwrap();
+
+    let payload = GetTransactionsByAddress {
+        source: Some(source.to_string()),
+        ..Default::default()
+    };
+    let transactions = setup
+        .api
+        .get_transactions_by_address(payload)
+        .await
+        .unwrap();
+    assert_eq!(transactions.count, 2);
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_unseen_address_returns_an_empty_list() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let address = Pubkey::new_unique().to_string();
+    for payload in [
+        GetTransactionsByAddress {
+            source: Some(address.clone()),
+            ..Default::default()
+        },
+        GetTransactionsByAddress {
+            participant_address: Some(address.clone()),
+            page: Some(1),
+            ..Default::default()
+        },
+        GetTransactionsByAddress {
+            destination: Some(address.clone()),
+            count_total: Some(true),
+            ..Default::default()
+        },
+    ] {
+        let transactions = setup
+            .api
+            .get_transactions_by_address(payload)
+            .await
+            .unwrap();
+        assert_eq!(transactions.count, 0);
+        assert_eq!(transactions.total, Some(0));
+        assert!(transactions.items.is_empty());
+    }
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_malformed_address_is_rejected() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+    use api::error::ApiError;
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    for address in [
+        "",
+        "not-a-pubkey",
+        "11111111111111111111111111111111111111111111111",
+    ] {
+        let result = setup
+            .api
+            .get_transactions_by_address(GetTransactionsByAddress {
+                source: Some(address.to_string()),
+                ..Default::default()
+            })
+            .await;
+        match result {
+            Err(ApiError::PubkeyValidationError(rejected)) => assert_eq!(rejected, address),
+            other => panic!("{:?} should be rejected, got {:?}", address, other),
+        }
+    }
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_get_transactions_by_address_within_block_heights() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let source = Pubkey::new_unique();
+    // One transfer per block, its amount being the block height.
+    for slot in 100..103 {
+        let block_time = 1_700_000_000 + slot as i64;
+        let transaction = parse_transaction_info(
+            encode(
+                &lookup_table_transfers(slot - 10, 1),
+                vec![source, Pubkey::new_unique()],
+                [None, None],
+            ),
+            slot,
+            block_time,
+        )
+        .unwrap()
+        .unwrap();
+        let block = BlockInfo {
+            metadata: BlockMetadata {
+                slot,
+                parent_slot: slot - 1,
+                block_time,
+                block_height: slot - 10,
+                ..Default::default()
+            },
+            transactions: vec![transaction],
+            ..Default::default()
+        };
+        setup.dao.index_block(&block).await.unwrap();
+    }
+
+    let amounts = |from_height: Option<u64>, to_height: Option<u64>| {
+        let api = &setup.api;
+        async move {
+            let mut amounts = api
+                .get_transactions_by_address(GetTransactionsByAddress {
+                    source: Some(source.to_string()),
+                    from_height,
+                    to_height,
+                    ..Default::default()
+                })
+                .await
+                .unwrap()
+                .items
+                .iter()
+                .map(|transaction| transaction.amount)
+                .collect::<Vec<_>>();
+            amounts.sort();
+            amounts
+        }
+    };
+    assert_eq!(amounts(None, None).await, vec![90, 91, 92]);
+    assert_eq!(amounts(Some(91), None).await, vec![91, 92]);
+    assert_eq!(amounts(None, Some(91)).await, vec![90, 91]);
+    assert_eq!(amounts(Some(91), Some(91)).await, vec![91]);
+    assert!(amounts(Some(93), None).await.is_empty());
+
+    assert!(setup
+        .api
+        .get_transactions_by_address(GetTransactionsByAddress {
+            source: Some(source.to_string()),
+            from_height: Some(92),
+            to_height: Some(91),
+            ..Default::default()
+        })
+        .await
+        .is_err());
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_inner_transfers_are_indexed() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let source = Pubkey::new_unique();
+    let destination = Pubkey::new_unique();
+    let transaction = parse_transaction_info(
+        encode_with_inner_instructions(
+            &router_transfer(),
+            vec![source, destination],
+            [None, None],
+            cpi_transfer(42),
+        ),
+        100,
+        1_700_000_000,
+    )
+    .unwrap()
+    .unwrap();
+    let block = BlockInfo {
+        metadata: BlockMetadata {
+            slot: 100,
+            parent_slot: 99,
+            block_time: 1_700_000_000,
+            block_height: 90,
+            ..Default::default()
+        },
+        transactions: vec![transaction],
+        ..Default::default()
+    };
+    setup.dao.index_block(&block).await.unwrap();
+
+    let payload = GetTransactionsByAddress {
+        destination: Some(destination.to_string()),
+        ..Default::default()
+    };
+    let transactions = setup
+        .api
+        .get_transactions_by_address(payload)
+        .await
+        .unwrap();
+    assert_eq!(transactions.count, 1);
+    let transfer = &transactions.items[0];
+    assert_eq!(transfer.source_address, source.to_string());
+    assert_eq!(transfer.amount, 42);
+    assert_eq!(transfer.inner_instruction_index, Some(0));
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_indexed_transfers_are_notified() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let database_url = std::env::var("TEST_DATABASE_URL").unwrap();
+    let mut listener = PgListener::connect(&database_url).await.unwrap();
+    listener.listen("transfers").await.unwrap();
+
+    let transaction = parse_transaction_info(
+        encode(
+            &lookup_table_transfers(42, 2),
+            vec![Pubkey::new_unique(), Pubkey::new_unique()],
+            [None, None],
+        ),
+        100,
+        1_700_000_000,
+    )
+    .unwrap()
+    .unwrap();
+    let signature = transaction.signature.to_string();
+    let block = BlockInfo {
+        metadata: BlockMetadata {
+            slot: 100,
+            parent_slot: 99,
+            block_time: 1_700_000_000,
+            block_height: 90,
+            ..Default::default()
+        },
+        transactions: vec![transaction],
+        ..Default::default()
+    };
+    let dao = setup
+        .dao
+        .clone()
+        .with_listener_channel(Some("transfers".to_string()));
+    dao.index_block(&block).await.unwrap();
+
+    let notification = tokio::time::timeout(Duration::from_secs(5), listener.recv())
+        .await
+        .expect("a notification should be sent on commit")
+        .unwrap();
+    let payload: serde_json::Value = serde_json::from_str(notification.payload()).unwrap();
+    assert_eq!(
+        payload,
+        serde_json::json!([{
+            "signature": signature,
+            "mint": MINT,
+            "slot": 100,
+        }])
+    );
+    // Both transfers are of the same mint, so they share a notification.
+    assert!(
+        tokio::time::timeout(Duration::from_millis(500), listener.recv())
+            .await
+            .is_err()
+    );
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_conflict_strategy_decides_whether_reindexing_updates() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let source = Pubkey::new_unique();
+    let transaction = parse_transaction_info(
+        encode(
+            &lookup_table_transfer(42),
+            vec![source, Pubkey::new_unique()],
+            [None, None],
+        ),
+        100,
+        1_700_000_000,
+    )
+    .unwrap()
+    .unwrap();
+    let block = |amount: u64, memo: Option<&str>| {
+        let mut transaction = transaction.clone();
+        transaction.instruction_groups[0]
+            .outer_instruction
+            .as_mut()
+            .unwrap()
+            .amount = amount;
+        transaction.memo = memo.map(str::to_string);
+        BlockInfo {
+            metadata: BlockMetadata {
+                slot: 100,
+                parent_slot: 99,
+                block_time: 1_700_000_000,
+                block_height: 90,
+                ..Default::default()
+            },
+            transactions: vec![transaction],
+            ..Default::default()
+        }
+    };
+    let stored = || async {
+        let transactions = setup
+            .api
+            .get_transactions_by_address(GetTransactionsByAddress {
+                source: Some(source.to_string()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(transactions.count, 1);
+        let transfer = transactions.items[0].clone();
+        (transfer.amount, transfer.memo)
+    };
+
+    setup.dao.index_block(&block(42, None)).await.unwrap();
+    // By default the stored rows are kept.
+    setup
+        .dao
+        .index_block(&block(43, Some("fixed")))
+        .await
+        .unwrap();
+    assert_eq!(stored().await, (42, None));
+
+    let dao = setup
+        .dao
+        .clone()
+        .with_conflict_strategy(ConflictStrategy::Update);
+    dao.index_block(&block(43, Some("fixed"))).await.unwrap();
+    assert_eq!(stored().await, (43, Some("fixed".to_string())));
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_raw_instruction_data_is_returned_on_request() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let source = Pubkey::new_unique();
+    let transaction = parse_transaction_info(
+        encode(
+            &lookup_table_transfer(42),
+            vec![source, Pubkey::new_unique()],
+            [None, None],
+        ),
+        100,
+        1_700_000_000,
+    )
+    .unwrap()
+    .unwrap();
+    let data = transaction.instruction_groups[0]
+        .outer_instruction
+        .as_ref()
+        .unwrap()
+        .data
+        .clone();
+    let block = BlockInfo {
+        metadata: BlockMetadata {
+            slot: 100,
+            parent_slot: 99,
+            block_time: 1_700_000_000,
+            block_height: 90,
+            ..Default::default()
+        },
+        transactions: vec![transaction],
+        ..Default::default()
+    };
+    setup.dao.index_block(&block).await.unwrap();
+
+    let transfers = |include_raw| {
+        setup
+            .api
+            .get_transactions_by_address(GetTransactionsByAddress {
+                source: Some(source.to_string()),
+                include_raw,
+                ..Default::default()
+            })
+    };
+    let transactions = transfers(None).await.unwrap();
+    assert_eq!(transactions.items[0].data, None);
+    let transactions = transfers(Some(true)).await.unwrap();
+    assert_eq!(
+        transactions.items[0].data,
+        Some(solana_sdk::bs58::encode(data).into_string())
+    );
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_notifications_are_split_to_fit_the_payload_limit() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let database_url = std::env::var("TEST_DATABASE_URL").unwrap();
+    let mut listener = PgListener::connect(&database_url).await.unwrap();
+    listener.listen("transfers").await.unwrap();
+
+    let transactions = (0..5)
+        .map(|_| {
+            let mut transaction = parse_transaction_info(
+                encode(
+                    &lookup_table_transfer(42),
+                    vec![Pubkey::new_unique(), Pubkey::new_unique()],
+                    [None, None],
+                ),
+                100,
+                1_700_000_000,
+            )
+            .unwrap()
+            .unwrap();
+            transaction.signature = Signature::new_unique();
+            transaction
+        })
+        .collect::<Vec<_>>();
+    let signatures = transactions
+        .iter()
+        .map(|transaction| transaction.signature.to_string())
+        .collect::<BTreeSet<_>>();
+    let block = BlockInfo {
+        metadata: BlockMetadata {
+            slot: 100,
+            parent_slot: 99,
+            block_time: 1_700_000_000,
+            block_height: 90,
+            ..Default::default()
+        },
+        transactions,
+        ..Default::default()
+    };
+    // Room for two of the ~170 byte entries per notification.
+    let dao = setup
+        .dao
+        .clone()
+        .with_listener_channel(Some("transfers".to_string()))
+        .with_notify_max_payload_bytes(400);
+    dao.index_block(&block).await.unwrap();
+
+    let mut notified = BTreeSet::new();
+    for _ in 0..3 {
+        let notification = tokio::time::timeout(Duration::from_secs(5), listener.recv())
+            .await
+            .expect("a notification should be sent on commit")
+            .unwrap();
+        assert!(notification.payload().len() <= 400);
+        let payload: Vec<Value> = serde_json::from_str(notification.payload()).unwrap();
+        notified.extend(
+            payload
+                .iter()
+                .map(|entry| entry["signature"].as_str().unwrap().to_string()),
+        );
+    }
+    assert_eq!(notified, signatures);
+    assert!(
+        tokio::time::timeout(Duration::from_millis(500), listener.recv())
+            .await
+            .is_err()
+    );
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_get_transfer_volume() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    // 2023-11-14 22:13:20 UTC and a day later.
+    for (slot, block_time, amount, count) in
+        [(100, 1_700_000_000, 42, 2), (101, 1_700_086_400, 10, 1)]
+    {
+        let transaction = parse_transaction_info(
+            encode(
+                &lookup_table_transfers(amount, count),
+                vec![Pubkey::new_unique(), Pubkey::new_unique()],
+                [None, None],
+            ),
+            slot,
+            block_time,
+        )
+        .unwrap()
+        .unwrap();
+        let block = BlockInfo {
+            metadata: BlockMetadata {
+                slot,
+                parent_slot: slot - 1,
+                block_time,
+                block_height: slot - 10,
+                ..Default::default()
+            },
+            transactions: vec![transaction],
+            ..Default::default()
+        };
+        setup.dao.index_block(&block).await.unwrap();
+    }
+
+    let payload = GetTransferVolume {
+        mint_address: MINT.to_string(),
+        start: "14/11/2023".to_string(),
+        end: "15/11/2023".to_string(),
+        interval: None,
+    };
+    let volume = setup
+        .api
+        .get_transfer_volume(payload.clone())
+        .await
+        .unwrap();
+    let buckets = volume
+        .buckets
+        .iter()
+        .map(|bucket| {
+            (
+                bucket.bucket.to_rfc3339(),
+                bucket.amount.as_str(),
+                bucket.count,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        buckets,
+        vec![
+            ("2023-11-14T00:00:00+00:00".to_string(), "84", 2),
+            ("2023-11-15T00:00:00+00:00".to_string(), "10", 1),
+        ]
+    );
+
+    let volume = setup
+        .api
+        .get_transfer_volume(GetTransferVolume {
+            interval: Some("1 week".to_string()),
+            ..payload.clone()
+        })
+        .await
+        .unwrap();
+    assert_eq!(volume.buckets.len(), 1);
+    assert_eq!(volume.buckets[0].amount, "94");
+    assert_eq!(volume.buckets[0].count, 3);
+
+    let volume = setup
+        .api
+        .get_transfer_volume(GetTransferVolume {
+            start: "01/01/2024".to_string(),
+            end: "02/01/2024".to_string(),
+            ..payload.clone()
+        })
+        .await
+        .unwrap();
+    assert!(volume.buckets.is_empty());
+
+    assert!(setup
+        .api
+        .get_transfer_volume(GetTransferVolume {
+            start: "16/11/2023".to_string(),
+            ..payload.clone()
+        })
+        .await
+        .is_err());
+    assert!(setup
+        .api
+        .get_transfer_volume(GetTransferVolume {
+            interval: Some("1 day'; --".to_string()),
+            ..payload
+        })
+        .await
+        .is_err());
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_get_activity_histogram() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    // Two transfers on 2023-11-14 and one two days later, all from the same address.
+    let source = Pubkey::new_unique();
+    for (slot, block_time, count) in [(100, 1_700_000_000, 2), (101, 1_700_172_800, 1)] {
+        let transaction = parse_transaction_info(
+            encode(
+                &lookup_table_transfers(1, count),
+                vec![source, Pubkey::new_unique()],
+                [None, None],
+            ),
+            slot,
+            block_time,
+        )
+        .unwrap()
+        .unwrap();
+        let block = BlockInfo {
+            metadata: BlockMetadata {
+                slot,
+                parent_slot: slot - 1,
+                block_time,
+                block_height: slot - 10,
+                ..Default::default()
+            },
+            transactions: vec![transaction],
+            ..Default::default()
+        };
+        setup.dao.index_block(&block).await.unwrap();
+    }
+
+    let payload = GetActivityHistogram {
+        address: source.to_string(),
+        start: "2023-11-13".to_string(),
+        end: "2023-11-16".to_string(),
+    };
+    let histogram = setup
+        .api
+        .get_activity_histogram(payload.clone())
+        .await
+        .unwrap();
+    let buckets = histogram
+        .buckets
+        .iter()
+        .map(|bucket| (bucket.bucket.to_rfc3339(), bucket.count))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        buckets,
+        vec![
+            ("2023-11-13T00:00:00+00:00".to_string(), 0),
+            ("2023-11-14T00:00:00+00:00".to_string(), 2),
+            ("2023-11-15T00:00:00+00:00".to_string(), 0),
+            ("2023-11-16T00:00:00+00:00".to_string(), 1),
+        ]
+    );
+
+    assert!(setup
+        .api
+        .get_activity_histogram(GetActivityHistogram {
+            start: "2023-11-17".to_string(),
+            ..payload.clone()
+        })
+        .await
+        .is_err());
+    assert!(setup
+        .api
+        .get_activity_histogram(GetActivityHistogram {
+            start: "2020-01-01".to_string(),
+            ..payload
+        })
+        .await
+        .is_err());
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_date_range_is_validated() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let validate = |before: &str, after: &str| {
+        setup.api.validate_pagination(
+            &None,
+            &None,
+            &Some(before.to_string()),
+            &Some(after.to_string()),
+        )
+    };
+
+    // A single day.
+    let page_opt = validate("26/08/2024", "26/08/2024").unwrap();
+    assert_eq!(page_opt.before, page_opt.after);
+    assert!(validate("25/08/2024", "26/08/2024").is_err());
+    assert!(validate("31/12/9999", "01/01/9999").is_err());
+    assert!(validate("26/08/2024", "2024-13-01").is_err());
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_pagination_limits_are_configurable() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let mut setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+    setup.api.config.max_limit = Some(10);
+    setup.api.config.default_limit = Some(5);
+    setup.api.config.max_offset = Some(20);
+
+    let page_opt = setup
+        .api
+        .validate_pagination(&None, &None, &None, &None)
+        .unwrap();
+    assert_eq!(page_opt.limit, 5);
+    assert!(setup
+        .api
+        .validate_pagination(&Some(11), &None, &None, &None)
+        .is_err());
+    // Page 5 starts 20 transfers in, page 6 beyond the offset limit.
+    assert!(setup
+        .api
+        .validate_pagination(&None, &Some(5), &None, &None)
+        .is_ok());
+    assert!(setup
+        .api
+        .validate_pagination(&None, &Some(6), &None, &None)
+        .is_err());
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_get_transactions_by_mint_pages_like_by_address() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let transaction = parse_transaction_info(
+        encode(
+            &lookup_table_transfers(1, 3),
+            vec![Pubkey::new_unique(), Pubkey::new_unique()],
+            [None, None],
+        ),
+        100,
+        1_700_000_000,
+    )
+    .unwrap()
+    .unwrap();
+    let block = BlockInfo {
+        metadata: BlockMetadata {
+            slot: 100,
+            parent_slot: 99,
+            block_time: 1_700_000_000,
+            block_height: 90,
+            ..Default::default()
+        },
+        transactions: vec![transaction],
+        ..Default::default()
+    };
+    setup.dao.index_block(&block).await.unwrap();
+
+    let by_mint = setup
+        .api
+        .get_transactions_by_mint(GetTransactionsByMint {
+            mint_address: MINT.to_string(),
+            limit: Some(2),
+            page: Some(2),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(by_mint.count, 1);
+    assert_eq!(by_mint.limit, 2);
+    assert_eq!(by_mint.page, Some(2));
+    assert_eq!(by_mint.before, None);
+
+    let by_address = setup
+        .api
+        .get_transactions_by_address(GetTransactionsByAddress {
+            mint: Some(MINT.to_string()),
+            limit: Some(2),
+            page: Some(2),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    // The transfers share a slot and block time, so only the page itself is comparable.
+    assert_eq!(
+        (by_mint.count, by_mint.limit, by_mint.page),
+        (by_address.count, by_address.limit, by_address.page)
+    );
+
+    let by_mint = setup
+        .api
+        .get_transactions_by_mint(GetTransactionsByMint {
+            mint_address: MINT.to_string(),
+            after: Some("2023-11-14".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(by_mint.count, 3);
+    assert_eq!(by_mint.page, None);
+    assert_eq!(by_mint.after, Some("2023-11-14".to_string()));
+
+    // Page and date pagination can't be combined, for either endpoint.
+    assert!(setup
+        .api
+        .get_transactions_by_mint(GetTransactionsByMint {
+            mint_address: MINT.to_string(),
+            page: Some(1),
+            after: Some("2023-11-14".to_string()),
+            ..Default::default()
+        })
+        .await
+        .is_err());
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_get_transactions_by_program() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    // Two SPL Token transfers in slot 100, and one that Token-2022 executed in slot 101.
+    for (slot, count, program_id) in [(100, 2, spl_token::id()), (101, 1, spl_token_2022::id())] {
+        let block_time = 1_700_000_000 + slot as i64;
+        let mut transfers = lookup_table_transfers(slot, count);
+        transfers.signatures = vec![Signature::from([slot as u8; 64])];
+        let mut transaction = parse_transaction_info(
+            encode(
+                &transfers,
+                vec![Pubkey::new_unique(), Pubkey::new_unique()],
+                [None, None],
+            ),
+            slot,
+            block_time,
+        )
+        .unwrap()
+        .unwrap();
+        for instruction_group in &mut transaction.instruction_groups {
+            instruction_group
+                .outer_instruction
+                .as_mut()
+                .unwrap()
+                .program_id = program_id;
+        }
+        let block = BlockInfo {
+            metadata: BlockMetadata {
+                slot,
+                parent_slot: slot - 1,
+                block_time,
+                block_height: slot - 10,
+                ..Default::default()
+            },
+            transactions: vec![transaction],
+            ..Default::default()
+        };
+        setup.dao.index_block(&block).await.unwrap();
+    }
+
+    let by_program = |program_id: String, limit: Option<u32>| {
+        setup
+            .api
+            .get_transactions_by_program(GetTransactionsByProgram {
+                program_id,
+                limit,
+                page: Some(1),
+                ..Default::default()
+            })
+    };
+    let transactions = by_program(spl_token::id().to_string(), None).await.unwrap();
+    assert_eq!(transactions.count, 2);
+    assert!(transactions
+        .items
+        .iter()
+        .all(|transaction| transaction.amount == 100
+            && transaction.program_address == spl_token::id().to_string()));
+
+    let transactions = by_program(spl_token_2022::id().to_string(), None)
+        .await
+        .unwrap();
+    assert_eq!(transactions.count, 1);
+    assert_eq!(transactions.items[0].amount, 101);
+
+    // The shared pagination applies.
+    let transactions = by_program(spl_token::id().to_string(), Some(1))
+        .await
+        .unwrap();
+    assert_eq!((transactions.count, transactions.limit), (1, 1));
+
+    assert!(by_program("not-a-program".to_string(), None).await.is_err());
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_amounts_above_i64_max_are_kept() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let amount = i64::MAX as u64 + 1;
+    let source = Pubkey::new_unique();
+    let transaction = parse_transaction_info(
+        encode(
+            &lookup_table_transfers(amount, 2),
+            vec![source, Pubkey::new_unique()],
+            [None, None],
+        ),
+        100,
+        1_700_000_000,
+    )
+    .unwrap()
+    .unwrap();
+    let block = BlockInfo {
+        metadata: BlockMetadata {
+            slot: 100,
+            parent_slot: 99,
+            block_time: 1_700_000_000,
+            block_height: 90,
+            ..Default::default()
+        },
+        transactions: vec![transaction],
+        ..Default::default()
+    };
+    setup.dao.index_block(&block).await.unwrap();
+
+    let transactions = setup
+        .api
+        .get_transactions_by_address(GetTransactionsByAddress {
+            source: Some(source.to_string()),
+            page: Some(1),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(transactions.count, 2);
+    assert!(transactions
+        .items
+        .iter()
+        .all(|transaction| transaction.amount == amount));
+
+    // Their sum doesn't fit in 64 bits either.
+    let volume = setup
+        .api
+        .get_transfer_volume(GetTransferVolume {
+            mint_address: MINT.to_string(),
+            start: "14/11/2023".to_string(),
+            end: "15/11/2023".to_string(),
+            interval: None,
+        })
+        .await
+        .unwrap();
+    assert_eq!(volume.buckets.len(), 1);
+    assert_eq!(volume.buckets[0].amount, "18446744073709551616");
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_u64_max_amount_round_trips() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let column_type = setup
+        .dao
+        .db
+        .query_one(Statement::from_string(
+            DbBackend::Postgres,
+            "SELECT domain_name FROM information_schema.columns
+                WHERE table_name = 'token_transfers' AND column_name = 'amount'"
+                .to_string(),
+        ))
+        .await
+        .unwrap()
+        .unwrap()
+        .try_get::<String>("", "domain_name")
+        .unwrap();
+    assert_eq!(column_type, "bigint2");
+
+    let source = Pubkey::new_unique();
+    let transaction = parse_transaction_info(
+        encode(
+            &lookup_table_transfer(u64::MAX),
+            vec![source, Pubkey::new_unique()],
+            [None, None],
+        ),
+        100,
+        1_700_000_000,
+    )
+    .unwrap()
+    .unwrap();
+    let block = BlockInfo {
+        metadata: BlockMetadata {
+            slot: 100,
+            parent_slot: 99,
+            block_time: 1_700_000_000,
+            block_height: 90,
+            ..Default::default()
+        },
+        transactions: vec![transaction],
+        ..Default::default()
+    };
+    setup.dao.index_block(&block).await.unwrap();
+
+    let transfers = token_transfers::Entity::find()
+        .all(setup.dao.db.as_ref())
+        .await
+        .unwrap();
+    assert_eq!(transfers.len(), 1);
+    assert_eq!(transfers[0].amount, Decimal::from(u64::MAX));
+
+    let transactions = setup
+        .api
+        .get_transactions_by_address(GetTransactionsByAddress {
+            source: Some(source.to_string()),
+            page: Some(1),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(transactions.items[0].amount, u64::MAX);
+}
+
+#[test]
+fn test_date_bound_formats() {
+    let day = DateBound::parse("2024-08-26").unwrap();
+    assert_eq!(
+        day.start(),
+        "2024-08-26T00:00:00Z".parse::<DateTime<Utc>>().unwrap()
+    );
+    assert_eq!(
+        day.end(),
+        "2024-08-26T23:59:59Z".parse::<DateTime<Utc>>().unwrap()
+    );
+    assert_eq!(day.to_string(), "2024-08-26");
+
+    let legacy = DateBound::parse("26/08/2024").unwrap();
+    assert_eq!(legacy.start(), day.start());
+    assert_eq!(legacy.to_string(), "26/08/2024");
+
+    let timestamp = DateBound::parse("2024-08-26T14:02:50+02:00").unwrap();
+    assert_eq!(
+        timestamp.start(),
+        "2024-08-26T12:02:50Z".parse::<DateTime<Utc>>().unwrap()
+    );
+    assert_eq!(timestamp.start(), timestamp.end());
+    assert_eq!(timestamp.to_string(), "2024-08-26T12:02:50+00:00");
+
+    assert!(DateBound::parse("08/26/2024").is_none());
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_get_transactions_by_participant() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let wallet = Pubkey::new_unique();
+    let transactions = [
+        (wallet, Pubkey::new_unique(), 1),
+        (Pubkey::new_unique(), wallet, 2),
+        (Pubkey::new_unique(), Pubkey::new_unique(), 3),
+    ]
+    .into_iter()
+    .map(|(source, destination, amount)| {
+        let mut transaction = parse_transaction_info(
+            encode(
+                &lookup_table_transfers(amount, 1),
+                vec![source, destination],
+                [None, None],
+            ),
+            100,
+            1_700_000_000,
+        )
+        .unwrap()
+        .unwrap();
+        transaction.signature = Signature::new_unique();
+        transaction
+    })
+    .collect();
+    let block = BlockInfo {
+        metadata: BlockMetadata {
+            slot: 100,
+            parent_slot: 99,
+            block_time: 1_700_000_000,
+            block_height: 90,
+            ..Default::default()
+        },
+        transactions,
+        ..Default::default()
+    };
+    setup.dao.index_block(&block).await.unwrap();
+
+    let payload = GetTransactionsByAddress {
+        participant_address: Some(wallet.to_string()),
+        ..Default::default()
+    };
+    let transactions = setup
+        .api
+        .get_transactions_by_address(payload.clone())
+        .await
+        .unwrap();
+    let mut amounts = transactions
+        .items
+        .iter()
+        .map(|transfer| transfer.amount)
+        .collect::<Vec<_>>();
+    amounts.sort();
+    assert_eq!(amounts, vec![1, 2]);
+    assert_eq!(transactions.total, None);
+    assert!(transactions.items.iter().all(|transfer| {
+        transfer.fee_payer.is_some() && transfer.transaction_fee == Some(5000)
+    }));
+
+    // `total` counts the matches beyond the page.
+    let transactions = setup
+        .api
+        .get_transactions_by_address(GetTransactionsByAddress {
+            limit: Some(1),
+            count_total: Some(true),
+            ..payload.clone()
+        })
+        .await
+        .unwrap();
+    assert_eq!(transactions.count, 1);
+    assert_eq!(transactions.total, Some(2));
+
+    assert!(setup
+        .api
+        .get_transactions_by_address(GetTransactionsByAddress {
+            source: Some(wallet.to_string()),
+            ..payload
+        })
+        .await
+        .is_err());
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_get_transactions_by_address_success_only() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let wallet = Pubkey::new_unique();
+    let succeeded = encode(
+        &lookup_table_transfers(1, 1),
+        vec![wallet, Pubkey::new_unique()],
+        [None, None],
+    );
+    let mut failed_transfer = lookup_table_transfers(2, 1);
+    failed_transfer.signatures = vec![Signature::from([9u8; 64])];
+    let mut failed = encode(
+        &failed_transfer,
+        vec![wallet, Pubkey::new_unique()],
+        [None, None],
+    );
+    let error = TransactionError::InstructionError(0, InstructionError::Custom(1));
+    let meta = failed.meta.as_mut().unwrap();
+    meta.err = Some(error.clone());
+    meta.status = Err(error);
+    let transactions = [succeeded, failed]
+        .into_iter()
+        .map(|transaction| {
+            parse_transaction_info(transaction, 100, 1_700_000_000)
+                .unwrap()
+                .unwrap()
+        })
+        .collect();
+    let block = BlockInfo {
+        metadata: BlockMetadata {
+            slot: 100,
+            parent_slot: 99,
+            block_time: 1_700_000_000,
+            block_height: 90,
+            ..Default::default()
+        },
+        transactions,
+        ..Default::default()
+    };
+    setup.dao.index_block(&block).await.unwrap();
+
+    for (success_only, suffix) in [(true, "successful"), (false, "failed")] {
+        let transactions = setup
+            .api
+            .get_transactions_by_address(GetTransactionsByAddress {
+                source: Some(wallet.to_string()),
+                success_only: Some(success_only),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let transfers = transactions
+            .items
+            .iter()
+            .map(|transfer| {
+                serde_json::json!({
+                    "amount": transfer.amount.to_string(),
+                    "error": transfer.error,
+                })
+            })
+            .collect::<Vec<_>>();
+        assert_json_snapshot!(format!("{}-{}", name, suffix), transfers);
+    }
+
+    let transactions = setup
+        .api
+        .get_transactions_by_address(GetTransactionsByAddress {
+            source: Some(wallet.to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(transactions.count, 2);
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_get_transactions_by_addresses() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+    let idle = Pubkey::new_unique();
+    let transactions = [
+        (alice, Pubkey::new_unique(), 1),
+        (Pubkey::new_unique(), bob, 2),
+        (alice, bob, 3),
+        (Pubkey::new_unique(), Pubkey::new_unique(), 4),
+    ]
+    .into_iter()
+    .map(|(source, destination, amount)| {
+        let mut transaction = parse_transaction_info(
+            encode(
+                &lookup_table_transfers(amount, 1),
+                vec![source, destination],
+                [None, None],
+            ),
+            100,
+            1_700_000_000,
+        )
+        .unwrap()
+        .unwrap();
+        transaction.signature = Signature::new_unique();
+        transaction
+    })
+    .collect();
+    let block = BlockInfo {
+        metadata: BlockMetadata {
+            slot: 100,
+            parent_slot: 99,
+            block_time: 1_700_000_000,
+            block_height: 90,
+            ..Default::default()
+        },
+        transactions,
+        ..Default::default()
+    };
+    setup.dao.index_block(&block).await.unwrap();
+
+    let payload = GetTransactionsByAddresses {
+        addresses: vec![alice.to_string(), bob.to_string(), idle.to_string()],
+        ..Default::default()
+    };
+    let transactions = setup
+        .api
+        .get_transactions_by_addresses(payload.clone())
+        .await
+        .unwrap();
+    assert_eq!(transactions.count, 3);
+    let amounts = |address: &Pubkey| {
+        let mut amounts = transactions.items[&address.to_string()]
+            .iter()
+            .map(|transfer| transfer.amount)
+            .collect::<Vec<_>>();
+        amounts.sort();
+        amounts
+    };
+    assert_eq!(amounts(&alice), vec![1, 3]);
+    assert_eq!(amounts(&bob), vec![2, 3]);
+    assert!(amounts(&idle).is_empty());
+
+    let too_many = (0..101).map(|_| Pubkey::new_unique().to_string()).collect();
+    assert!(setup
+        .api
+        .get_transactions_by_addresses(GetTransactionsByAddresses {
+            addresses: too_many,
+            ..payload
+        })
+        .await
+        .is_err());
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_address_lookups_use_indices() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let source = Pubkey::new_unique();
+    let destination = Pubkey::new_unique();
+    let transaction = parse_transaction_info(
+        encode(
+            &lookup_table_transfers(42, 1),
+            vec![source, destination],
+            [None, None],
+        ),
+        100,
+        1_700_000_000,
+    )
+    .unwrap()
+    .unwrap();
+    let block = BlockInfo {
+        metadata: BlockMetadata {
+            slot: 100,
+            parent_slot: 99,
+            block_time: 1_700_000_000,
+            block_height: 90,
+            ..Default::default()
+        },
+        transactions: vec![transaction],
+        ..Default::default()
+    };
+    setup.dao.index_block(&block).await.unwrap();
+
+    // The test table is tiny, so sequential scans are switched off to see which indices the
+    // planner can use.
+    for (query, address, index) in [
+        (
+            "SELECT * FROM token_transfers WHERE source_address = $1",
+            source,
+            "idx_token_transfers_source_address",
+        ),
+        (
+            "SELECT * FROM token_transfers WHERE destination_address = $1",
+            destination,
+            "idx_token_transfers_destination_address",
+        ),
+        (
+            "SELECT * FROM token_transfers WHERE source_address = $1 ORDER BY block_time DESC LIMIT 10",
+            source,
+            "idx_token_transfers_source_address_block_time",
+        ),
+        (
+            "SELECT * FROM token_transfers WHERE destination_address = $1 ORDER BY block_time DESC LIMIT 10",
+            destination,
+            "idx_token_transfers_destination_address_block_time",
+        ),
+    ] {
+        let txn = setup.dao.db.begin().await.unwrap();
+        txn.execute(Statement::from_string(
+            DbBackend::Postgres,
+            "SET LOCAL enable_seqscan = off".to_string(),
+        ))
+        .await
+        .unwrap();
+        let plan = txn
+            .query_all(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                &format!("EXPLAIN {}", query),
+                vec![address.to_bytes().to_vec().into()],
+            ))
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.try_get::<String>("", "QUERY PLAN").unwrap())
+            .collect::<Vec<_>>()
+            .join("\n");
+        txn.rollback().await.unwrap();
+        assert!(plan.contains(index), "{} does not use {}:\n{}", query, index, plan);
+    }
+}
+
+#[test]
+fn test_pool_exhaustion_is_reported_as_retryable() {
+    use api::error::{ApiError, POOL_EXHAUSTED_CODE};
+    use jsonrpsee::{core::Error as RpcError, types::error::CallError};
+    use sea_orm::{DbErr, RuntimeErr};
+
+    for err in [
+        DbErr::ConnectionAcquire,
+        DbErr::Conn(RuntimeErr::SqlxError(sqlx::Error::PoolTimedOut)),
+    ] {
+        assert!(matches!(ApiError::from(err), ApiError::PoolExhausted));
+    }
+    assert!(matches!(
+        ApiError::from(DbErr::Custom("boom".to_string())),
+        ApiError::DatabaseError(_)
+    ));
+
+    match RpcError::from(ApiError::PoolExhausted) {
+        RpcError::Call(CallError::Custom(error)) => {
+            assert_eq!(error.code(), POOL_EXHAUSTED_CODE);
+            let data: serde_json::Value =
+                serde_json::from_str(error.data().unwrap().get()).unwrap();
+            assert_eq!(data["retryAfter"], 1);
+        }
+        err => panic!("unexpected error: {:?}", err),
+    }
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_statement_timeout_is_reported_as_query_timeout() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+    use api::error::ApiError;
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let txn = setup.dao.db.begin().await.unwrap();
+    txn.execute(Statement::from_string(
+        DbBackend::Postgres,
+        "SET LOCAL statement_timeout = 10".to_string(),
+    ))
+    .await
+    .unwrap();
+    let err = txn
+        .query_all(Statement::from_string(
+            DbBackend::Postgres,
+            "SELECT pg_sleep(1)".to_string(),
+        ))
+        .await
+        .unwrap_err();
+    txn.rollback().await.unwrap();
+    assert!(matches!(ApiError::from(err), ApiError::QueryTimeout));
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_get_stats() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let mut setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let block = |slot: u64, block_time: i64| {
+        let transaction = parse_transaction_info(
+            encode(
+                &lookup_table_transfers(42, 2),
+                vec![Pubkey::new_unique(), Pubkey::new_unique()],
+                [None, None],
+            ),
+            slot,
+            block_time,
+        )
+        .unwrap()
+        .unwrap();
+        BlockInfo {
+            metadata: BlockMetadata {
+                slot,
+                parent_slot: slot - 1,
+                block_time,
+                block_height: slot - 10,
+                ..Default::default()
+            },
+            transactions: vec![transaction],
+            ..Default::default()
+        }
+    };
+    setup
+        .dao
+        .index_block(&block(100, 1_700_000_000))
+        .await
+        .unwrap();
+
+    let stats = setup.api.get_stats().await.unwrap();
+    assert_eq!(stats.total_transfers, 2);
+    assert_eq!(stats.distinct_mints, 1);
+    assert_eq!(stats.earliest_block_time, Some(1_700_000_000));
+    assert_eq!(stats.latest_block_time, Some(1_700_000_000));
+    assert_eq!(stats.last_indexed_slot, Some(100));
+
+    // The transfer counts are cached, the block stats are not.
+    setup
+        .dao
+        .index_block(&block(101, 1_700_000_400))
+        .await
+        .unwrap();
+    let stats = setup.api.get_stats().await.unwrap();
+    assert_eq!(stats.total_transfers, 2);
+    assert_eq!(stats.latest_block_time, Some(1_700_000_400));
+    assert_eq!(stats.last_indexed_slot, Some(101));
+
+    setup.api.config.stats_cache_ttl_seconds = Some(0);
+    let stats = setup.api.get_stats().await.unwrap();
+    assert_eq!(stats.total_transfers, 4);
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_get_counterparties_groups_transfers_per_direction() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let transaction = parse_transaction_info(
+        encode(
+            &lookup_table_transfers(5, 3),
+            vec![Pubkey::new_unique(), Pubkey::new_unique()],
+            [None, None],
+        ),
+        100,
+        1_700_000_000,
+    )
+    .unwrap()
+    .unwrap();
+    let block = BlockInfo {
+        metadata: BlockMetadata {
+            slot: 100,
+            parent_slot: 99,
+            block_time: 1_700_000_000,
+            block_height: 90,
+            ..Default::default()
+        },
+        transactions: vec![transaction],
+        ..Default::default()
+    };
+    setup.dao.index_block(&block).await.unwrap();
+
+    let transfer = setup
+        .api
+        .get_transactions_by_mint(GetTransactionsByMint {
+            mint_address: MINT.to_string(),
+            limit: Some(1),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .items
+        .remove(0);
+
+    let sender = setup
+        .api
+        .get_counterparties(GetCounterparties {
+            address: transfer.source_address.clone(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(sender.count, 1);
+    assert_eq!(sender.page, 1);
+    let counterparty = &sender.items[0];
+    assert_eq!(counterparty.address, transfer.destination_address);
+    assert_eq!(counterparty.mint_address, Some(MINT.to_string()));
+    assert_eq!(counterparty.outgoing_count, 3);
+    assert_eq!(counterparty.outgoing_amount, "15");
+    assert_eq!(counterparty.incoming_count, 0);
+    assert_eq!(counterparty.incoming_amount, "0");
+
+    let receiver = setup
+        .api
+        .get_counterparties(GetCounterparties {
+            address: transfer.destination_address.clone(),
+            mint_address: Some(MINT.to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let counterparty = &receiver.items[0];
+    assert_eq!(counterparty.address, transfer.source_address);
+    assert_eq!(counterparty.incoming_count, 3);
+    assert_eq!(counterparty.incoming_amount, "15");
+    assert_eq!(counterparty.outgoing_count, 0);
+
+    let next_page = setup
+        .api
+        .get_counterparties(GetCounterparties {
+            address: transfer.source_address,
+            limit: Some(1),
+            page: Some(2),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(next_page.count, 0);
+    assert!(next_page.items.is_empty());
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_block_time_is_returned_as_on_chain() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let source = Pubkey::new_unique();
+    let destination = Pubkey::new_unique();
+    let block_time = 1_724_673_770;
+    let transaction = parse_transaction_info(
+        encode(
+            &lookup_table_transfer(42),
+            vec![source, destination],
+            [None, None],
+        ),
+        100,
+        block_time,
+    )
+    .unwrap()
+    .unwrap();
+    let block = BlockInfo {
+        metadata: BlockMetadata {
+            slot: 100,
+            parent_slot: 99,
+            block_time,
+            block_height: 90,
+            ..Default::default()
+        },
+        transactions: vec![transaction],
+        ..Default::default()
+    };
+    setup.dao.index_block(&block).await.unwrap();
+
+    let payload = GetTransactionsByAddress {
+        source: Some(source.to_string()),
+        ..Default::default()
+    };
+    let transactions = setup
+        .api
+        .get_transactions_by_address(payload)
+        .await
+        .unwrap();
+    let transfer = &transactions.items[0];
+    assert_eq!(transfer.block_time_unix, block_time);
+    assert_eq!(transfer.block_time.timestamp(), block_time);
+    assert_eq!(
+        transfer.block_time,
+        "2024-08-26T12:02:50Z".parse::<DateTime<Utc>>().unwrap()
+    );
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_transactions_without_a_block_time_are_not_indexed() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let source = Pubkey::new_unique();
+    let destination = Pubkey::new_unique();
+    let transaction = parse_transaction_info(
+        encode(
+            &lookup_table_transfer(42),
+            vec![source, destination],
+            [None, None],
+        ),
+        100,
+        0,
+    )
+    .unwrap()
+    .unwrap();
+    let block = BlockInfo {
+        metadata: BlockMetadata {
+            slot: 100,
+            parent_slot: 99,
+            block_height: 90,
+            ..Default::default()
+        },
+        transactions: vec![transaction],
+        ..Default::default()
+    };
+    setup.dao.index_block(&block).await.unwrap();
+
+    let payload = GetTransactionsByAddress {
+        source: Some(source.to_string()),
+        ..Default::default()
+    };
+    let transactions = setup
+        .api
+        .get_transactions_by_address(payload)
+        .await
+        .unwrap();
+    assert!(transactions.items.is_empty());
+    assert!(blocks::Entity::find()
+        .all(setup.dao.db.as_ref())
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_get_transactions_by_token_type() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let source = Pubkey::new_unique();
+    let destination = Pubkey::new_unique();
+    let transaction = parse_transaction_info(
+        encode(
+            &lookup_table_transfer(42),
+            vec![source, destination],
+            [None, None],
+        ),
+        100,
+        1_700_000_000,
+    )
+    .unwrap()
+    .unwrap();
+    let block = BlockInfo {
+        metadata: BlockMetadata {
+            slot: 100,
+            parent_slot: 99,
+            block_time: 1_700_000_000,
+            block_height: 90,
+            ..Default::default()
+        },
+        transactions: vec![transaction],
+        ..Default::default()
+    };
+    setup.dao.index_block(&block).await.unwrap();
+
+    for (token_type, expected_count) in [
+        (None, 1),
+        (Some(TokenType::SplToken), 1),
+        (Some(TokenType::Token2022), 0),
+        (Some(TokenType::Native), 0),
+    ] {
+        let payload = GetTransactionsByAddress {
+            source: Some(source.to_string()),
+            token_type,
+            ..Default::default()
+        };
+        let transactions = setup
+            .api
+            .get_transactions_by_address(payload)
+            .await
+            .unwrap();
+        assert_eq!(transactions.items.len(), expected_count, "{:?}", token_type);
+    }
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_get_transactions_by_several_mints() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let first_mint = Pubkey::new_unique();
+    let second_mint = Pubkey::new_unique();
+    // One transfer per slot, the mints alternating, and one of a mint that isn't asked for.
+    for (slot, mint) in [
+        (100, first_mint),
+        (101, second_mint),
+        (102, first_mint),
+        (103, Pubkey::new_unique()),
+    ] {
+        let block_time = 1_700_000_000 + slot as i64;
+        let mut transfer = lookup_table_transfer(slot);
+        transfer.signatures = vec![Signature::from([slot as u8; 64])];
+        let mut transaction = parse_transaction_info(
+            encode(
+                &transfer,
+                vec![Pubkey::new_unique(), Pubkey::new_unique()],
+                [None, None],
+            ),
+            slot,
+            block_time,
+        )
+        .unwrap()
+        .unwrap();
+        transaction.instruction_groups[0]
+            .outer_instruction
+            .as_mut()
+            .unwrap()
+            .mint = Some(mint.to_bytes().to_vec());
+        let block = BlockInfo {
+            metadata: BlockMetadata {
+                slot,
+                parent_slot: slot - 1,
+                block_time,
+                block_height: slot - 10,
+                ..Default::default()
+            },
+            transactions: vec![transaction],
+            ..Default::default()
+        };
+        setup.dao.index_block(&block).await.unwrap();
+    }
+
+    let slots = |transactions: Vec<Transaction>| {
+        transactions
+            .iter()
+            .map(|transaction| transaction.slot)
+            .collect::<Vec<_>>()
+    };
+    let mint_addresses = vec![first_mint.to_string(), second_mint.to_string()];
+    // Both mints in a single page, newest first.
+    let by_address = setup
+        .api
+        .get_transactions_by_address(GetTransactionsByAddress {
+            mint_addresses: Some(mint_addresses.clone()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(slots(by_address.items), vec![102, 101, 100]);
+
+    let by_mint = setup
+        .api
+        .get_transactions_by_mint(GetTransactionsByMint {
+            mint_address: second_mint.to_string(),
+            mint_addresses: Some(vec![first_mint.to_string()]),
+            limit: Some(2),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(slots(by_mint.items), vec![102, 101]);
+
+    // One way of filtering by mint at a time, and a bounded number of mints.
+    assert!(setup
+        .api
+        .get_transactions_by_address(GetTransactionsByAddress {
+            mint: Some(first_mint.to_string()),
+            mint_addresses: Some(mint_addresses),
+            ..Default::default()
+        })
+        .await
+        .is_err());
+    assert!(setup
+        .api
+        .get_transactions_by_address(GetTransactionsByAddress {
+            mint_addresses: Some(
+                (0..=MAX_MINTS_PER_REQUEST)
+                    .map(|_| Pubkey::new_unique().to_string())
+                    .collect()
+            ),
+            ..Default::default()
+        })
+        .await
+        .is_err());
+    assert!(setup
+        .api
+        .get_transactions_by_address(GetTransactionsByAddress {
+            mint_addresses: Some(vec!["not a pubkey".to_string()]),
+            ..Default::default()
+        })
+        .await
+        .is_err());
+}
+
+#[test]
+fn test_csv_export_quotes_fields() {
+    let transfer = Transaction {
+        signature: "5u2MkL1atE8ZY2XHEE8PJwxnP69mzLSTfaTrnHu6HFt3KgjuEHJADbCR9dn2o7cJDVbDenvY1PrdPTCVfCNSfLEA".to_string(),
+        program_address: "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA".to_string(),
+        mint_address: Some(MINT.to_string()),
+        source_address: "BhW85ig2dHu5tV6sCs7ps5UyPCLSjfXgEsfVAX82yXnb".to_string(),
+        destination_address: "FDtzHvo68TiRiWB7sU5KkEGy8HGnNcYS8qL7pGqqPmm8".to_string(),
+        source_ata: None,
+        destination_ata: None,
+        amount: 42,
+        decimals: Some(6),
+        ui_amount: None,
+        fee: None,
+        net_amount: None,
+        fee_payer: None,
+        transaction_fee: Some(5000),
+        memo: Some("invoice 7, \"march\"".to_string()),
+        slot: 285941932,
+        error: None,
+        block_time: "2024-08-26T12:02:50Z".parse().unwrap(),
+        block_time_unix: 1724673770,
+        inner_instruction_index: None,
+        data: None,
+    };
+    assert_eq!(
+        csv_row(&transfer),
+        format!(
+            "{},285941932,2024-08-26T12:02:50+00:00,1724673770,{},{},{},{},,,42,6,,,,,5000,\"invoice 7, \"\"march\"\"\",,\n",
+            transfer.signature,
+            transfer.program_address,
+            MINT,
+            transfer.source_address,
+            transfer.destination_address,
+        )
+    );
+}
+
+// An export layer in front of a service that answers "next".
+fn export_service(
+    api: &api::api::Api,
+    max_concurrent_exports: u32,
+) -> impl Service<
+    Request<Body>,
+    Response = Response<Body>,
+    Error = Box<dyn std::error::Error + Send + Sync>,
+> {
+    ExportLayer::new(
+        api.dao.clone(),
+        None,
+        Duration::ZERO,
+        max_concurrent_exports,
+    )
+    .layer(service_fn(|_| async {
+        Ok::<_, Infallible>(Response::new(Body::from("next")))
+    }))
+}
+
+async fn send_through_export(api: &api::api::Api, request: Request<Body>) -> Response<Body> {
+    export_service(api, 1).call(request).await.unwrap()
+}
+
+fn export_request(payload: Value) -> Request<Body> {
+    Request::builder()
+        .method(Method::POST)
+        .uri(EXPORT_PATH)
+        .body(Body::from(payload.to_string()))
+        .unwrap()
+}
+
+async fn export(api: &api::api::Api, payload: Value) -> Response<Body> {
+    send_through_export(api, export_request(payload)).await
+}
+
+async fn response_text(response: Response<Body>) -> String {
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_export_streams_every_matching_transfer() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    let source = Pubkey::new_unique();
+    let destination = Pubkey::new_unique();
+    let transaction = parse_transaction_info(
+        encode(
+            &lookup_table_transfers(42, 3),
+            vec![source, destination],
+            [None, None],
+        ),
+        100,
+        1_700_000_000,
+    )
+    .unwrap()
+    .unwrap();
+    let block = BlockInfo {
+        metadata: BlockMetadata {
+            slot: 100,
+            parent_slot: 99,
+            block_time: 1_700_000_000,
+            block_height: 90,
+            ..Default::default()
+        },
+        transactions: vec![transaction],
+        ..Default::default()
+    };
+    setup.dao.index_block(&block).await.unwrap();
+
+    let response = export(
+        &setup.api,
+        serde_json::json!({"sourceAddress": source.to_string()}),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_TYPE], "application/x-ndjson");
+    let lines = response_text(response).await;
+    let transfers = lines
+        .lines()
+        .map(|line| serde_json::from_str::<Transaction>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(transfers.len(), 3);
+    assert!(transfers
+        .iter()
+        .all(|transfer| transfer.source_address == source.to_string() && transfer.amount == 42));
+
+    let response = export(
+        &setup.api,
+        serde_json::json!({"sourceAddress": source.to_string(), "format": "csv"}),
+    )
+    .await;
+    assert_eq!(response.headers()[CONTENT_TYPE], "text/csv");
+    let csv = response_text(response).await;
+    let mut rows = csv.lines();
+    assert!(rows
+        .next()
+        .unwrap()
+        .starts_with("signature,slot,block_time,"));
+    assert_eq!(rows.count(), 3);
+
+    // Filters are checked before anything is streamed.
+    let response = export(&setup.api, serde_json::json!({"format": "csv"})).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let request = Request::builder()
+        .uri("/health")
+        .body(Body::empty())
+        .unwrap();
+    let response = send_through_export(&setup.api, request).await;
+    assert_eq!(response_text(response).await, "next");
+}
+
+#[named]
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_exports_are_capped_and_not_bound_by_the_query_timeout() {
+    use crate::setup::{setup, trim_test_name, Network, TestSetupOptions};
+
+    let name = trim_test_name(function_name!());
+    let setup = setup(
+        name.clone(),
+        TestSetupOptions {
+            network: Network::Mainnet,
+        },
+    )
+    .await;
+
+    for (timeout, expected) in [(Duration::ZERO, "0"), (Duration::from_secs(5), "5s")] {
+        let txn = setup.api.dao.begin_export(timeout).await.unwrap();
+        let statement_timeout = txn
+            .query_one(Statement::from_string(
+                DbBackend::Postgres,
+                "SHOW statement_timeout".to_string(),
+            ))
+            .await
+            .unwrap()
+            .unwrap()
+            .try_get::<String>("", "statement_timeout")
+            .unwrap();
+        txn.rollback().await.unwrap();
+        assert_eq!(statement_timeout, expected);
+    }
+
+    let source = Pubkey::new_unique();
+    let transaction = parse_transaction_info(
+        encode(
+            &lookup_table_transfers(42, 3),
+            vec![source, Pubkey::new_unique()],
+            [None, None],
+        ),
+        100,
+        1_700_000_000,
+    )
+    .unwrap()
+    .unwrap();
+    let block = BlockInfo {
+        metadata: BlockMetadata {
+            slot: 100,
+            parent_slot: 99,
+            block_time: 1_700_000_000,
+            block_height: 90,
+            ..Default::default()
+        },
+        transactions: vec![transaction],
+        ..Default::default()
+    };
+    setup.dao.index_block(&block).await.unwrap();
+
+    let payload = serde_json::json!({"sourceAddress": source.to_string()});
+    let mut service = export_service(&setup.api, 1);
+    // Nothing reads the first export, so it keeps its place until its response is dropped.
+    let unread = service.call(export_request(payload.clone())).await.unwrap();
+    assert_eq!(unread.status(), StatusCode::OK);
+    let rejected = service.call(export_request(payload.clone())).await.unwrap();
+    assert_eq!(rejected.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    drop(unread);
+    let mut status = StatusCode::SERVICE_UNAVAILABLE;
+    for _ in 0..50 {
+        let response = service.call(export_request(payload.clone())).await.unwrap();
+        status = response.status();
+        if status == StatusCode::OK {
+            assert_eq!(response_text(response).await.lines().count(), 3);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(status, StatusCode::OK);
+}